@@ -0,0 +1,652 @@
+use std::{path::Path, rc::Rc};
+
+use anyhow::Context;
+
+use crate::{cube_map_renderer::CubeMapRenderer, cube_mipmap_renderer::CubeMipmapRenderer, equirect_renderer::EquirectRenderer, ibl_renderer::IBLRenderer, output::{OutputFormat, OutputImage}, profiler::{GpuProfiler, StageTiming}, progressive::{ProgressiveSettings, Refinement}, readback::{self, RawImage, ReadbackBuffer}, sky::SkyUniform, sky_renderer::SkyRenderer, texture::Texture, tiling::{Submitter, Tiling}};
+
+/// Sizes and formats of everything a bake produces. All inputs of a batch share them,
+/// which is what allows the pipelines to be reused.
+#[ derive( Clone, Copy, Debug ) ]
+pub struct BakeSettings
+{
+  pub cube_size : u32,
+  /// Resampling of the equirectangular source to the environment cube
+  pub cube_resample : CubeResample,
+  /// Samples along each axis of a cube texel with `CubeResample::Supersample`
+  pub cube_samples : u32,
+  /// Filter of the environment cube mips the specular prefilter samples
+  pub mip_filter : MipFilter,
+  /// Formats the outputs are rendered and read back in, see [`crate::gpu::check_output_formats`]
+  pub diffuse_format : wgpu::TextureFormat,
+  pub specular_format : wgpu::TextureFormat,
+  pub lut_format : wgpu::TextureFormat,
+  pub diffuse_width : u32,
+  pub diffuse_height : u32,
+  pub specular_1_width : u32,
+  pub specular_1_height : u32,
+  pub specular_2_width : u32,
+  pub specular_2_height : u32,
+  /// Samples along each axis of the hemisphere, the irradiance integral takes the square of it
+  pub diffuse_samples : u32,
+  pub specular_samples : u32,
+  pub lut_samples : u32,
+  /// Runs the diffuse, specular and LUT passes as compute shaders instead of render pipelines
+  pub compute : bool,
+  /// Bakes with the CPU reference of [`crate::cpu_baker`] instead of the GPU
+  pub cpu : bool,
+  /// Mapping of the diffuse and specular maps
+  pub layout : OutputLayout,
+  /// File format of every output image
+  pub output_format : OutputFormat,
+  /// Face size of the prefiltered specular cube, which is only rendered when set
+  pub specular_cube_size : Option< u32 >,
+  pub gltf : Option< GltfSettings >,
+  /// Size of the equirectangular image the environment cube is rendered back to, when set
+  pub equirect_size : Option< ( u32, u32 ) >,
+  /// Writes the environment cube itself, unfiltered, when set
+  pub environment_cube : Option< EnvironmentCubeSettings >,
+  /// Split of the filtering passes into tiles and submissions, left out of the cache key
+  pub tiling : Tiling,
+  /// Refines the irradiance and specular in batches until they converge, needs `compute`
+  pub progressive : Option< ProgressiveSettings >
+}
+
+/// Resampling of the source to the environment cube. The order matches `RESAMPLE` in `cube_map.wgsl`.
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum CubeResample
+{
+  /// One bilinear sample at the texel center
+  Bilinear,
+  /// Bilinear samples on a grid over the texel
+  Supersample,
+  /// Average of the source texels the texel covers, weighted by the covered area.
+  /// Avoids aliasing when the cube is smaller than the source
+  Area
+}
+
+impl CubeResample
+{
+  pub fn name( self ) -> &'static str
+  {
+    match self
+    {
+      Self::Bilinear => "bilinear",
+      Self::Supersample => "supersample",
+      Self::Area => "area"
+    }
+  }
+}
+
+/// Filter of the environment cube mip chain. The order matches `FILTER` in `mipmap.wgsl`.
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum MipFilter
+{
+  /// Average of 2x2 texels, which never reaches past the face
+  Box,
+  /// Kaiser windowed sinc over 4x4 texels, sharper and with less aliasing of bright features.
+  /// Footprints that cross a face edge continue on the neighboring face, so it is the default.
+  Kaiser,
+  /// Average of 2x2 texels weighted by their solid angle on the sphere, within the face like `Box`
+  SolidAngle
+}
+
+impl MipFilter
+{
+  pub fn name( self ) -> &'static str
+  {
+    match self
+    {
+      Self::Box => "box",
+      Self::Kaiser => "kaiser",
+      Self::SolidAngle => "solid-angle"
+    }
+  }
+}
+
+/// How directions are mapped to the texels of the diffuse and specular maps.
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum OutputLayout
+{
+  Equirect,
+  /// Octahedral map with +Y at the center and a border of duplicated texels on every mip.
+  /// Needs square maps.
+  Octahedral
+}
+
+impl OutputLayout
+{
+  pub fn name( self ) -> &'static str
+  {
+    match self
+    {
+      Self::Equirect => "equirect",
+      Self::Octahedral => "octahedral"
+    }
+  }
+
+  /// Texels added around every edge, see `OCTAHEDRAL_BORDER` in `ibl.wgsl`
+  pub fn border( self ) -> u32
+  {
+    match self
+    {
+      Self::Equirect => 0,
+      Self::Octahedral => 1
+    }
+  }
+}
+
+/// Light parameters of the `EXT_lights_image_based` export.
+#[ derive( Clone, Copy, Debug ) ]
+pub struct GltfSettings
+{
+  pub intensity : f32,
+  /// Rotation of the environment around the up axis, in degrees
+  pub rotation : f32
+}
+
+impl Default for GltfSettings
+{
+  fn default() -> Self
+  {
+    Self { intensity : 1.0, rotation : 0.0 }
+  }
+}
+
+/// Files the environment cube is written to.
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum CubeContainer
+{
+  /// One image per face in the output format
+  Faces,
+  /// One horizontal cross per mip in the output format, see [`crate::cube_file::cross`]
+  Cross,
+  /// A single KTX2 cube with 32-bit float RGBA texels
+  Ktx2,
+  /// A single DDS cube with 32-bit float RGBA texels
+  Dds
+}
+
+impl CubeContainer
+{
+  pub fn name( self ) -> &'static str
+  {
+    match self
+    {
+      Self::Faces => "faces",
+      Self::Cross => "cross",
+      Self::Ktx2 => "ktx2",
+      Self::Dds => "dds"
+    }
+  }
+}
+
+/// Export of the environment cube the filtering passes sample, for rendering the background.
+#[ derive( Clone, Copy, Debug ) ]
+pub struct EnvironmentCubeSettings
+{
+  pub container : CubeContainer,
+  /// Writes its whole mip chain instead of the first mip only
+  pub mips : bool
+}
+
+impl Default for EnvironmentCubeSettings
+{
+  fn default() -> Self
+  {
+    Self { container : CubeContainer::Ktx2, mips : false }
+  }
+}
+
+impl Default for BakeSettings
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      cube_size : 1024,
+      cube_resample : CubeResample::Bilinear,
+      cube_samples : 4,
+      mip_filter : MipFilter::Kaiser,
+      diffuse_format : wgpu::TextureFormat::Rgba32Float,
+      specular_format : wgpu::TextureFormat::Rgba32Float,
+      lut_format : wgpu::TextureFormat::Rgba32Float,
+      diffuse_width : 512,
+      diffuse_height : 512,
+      specular_1_width : 512,
+      specular_1_height : 512,
+      specular_2_width : 512,
+      specular_2_height : 512,
+      diffuse_samples : 50,
+      specular_samples : 512,
+      lut_samples : 1024,
+      compute : false,
+      cpu : false,
+      layout : OutputLayout::Equirect,
+      output_format : OutputFormat::Hdr,
+      specular_cube_size : None,
+      gltf : None,
+      equirect_size : None,
+      environment_cube : None,
+      tiling : Tiling::default(),
+      progressive : None
+    }
+  }
+}
+
+/// Decoded equirectangular input, RGBA 32-bit float texels.
+pub struct SourceImage
+{
+  pub width : u32,
+  pub height : u32,
+  pub pixels : Vec< f32 >
+}
+
+impl SourceImage
+{
+  pub fn load( path : &Path ) -> anyhow::Result< Self >
+  {
+    let bytes = std::fs::read( path ).with_context( || format!( "Failed to open {}", path.display() ) )?;
+    Self::decode( &bytes ).with_context( || format!( "Failed to decode {}", path.display() ) )
+  }
+
+  /// Decodes an image file already read to memory, the format is guessed from its content.
+  pub fn decode( bytes : &[ u8 ] ) -> anyhow::Result< Self >
+  {
+    let image = image::load_from_memory( bytes )?.to_rgba32f();
+    let ( width, height ) = image.dimensions();
+
+    Ok( Self { width, height, pixels : image.into_vec() } )
+  }
+
+  pub fn to_texture( &self, device : &wgpu::Device, queue : &wgpu::Queue ) -> Texture
+  {
+    let texture = Texture::builder( self.width, self.height ).label( "HDR_TEXTURE" ).build( device );
+    texture.write_mip( queue, 0, bytemuck::cast_slice( &self.pixels ) );
+    texture
+  }
+}
+
+/// Everything a bake reads back. `specular` holds one image per prefiltered mip.
+/// They are [`RawImage`] as long as they are in the GPU formats.
+pub struct BakeOutputs< I = OutputImage >
+{
+  pub diffuse : I,
+  pub specular : Vec< I >,
+  pub brdf_lut : I,
+  /// Faces of every mip of the specular cube, when `specular_cube_size` is set
+  pub specular_cube : Option< Vec< [ I; 6 ] > >,
+  /// Faces of the exported mips of the environment cube, when `environment_cube` is set
+  pub environment_cube : Option< Vec< [ I; 6 ] > >,
+  /// The environment cube as an equirectangular image, when `equirect_size` is set
+  pub environment : Option< I >
+}
+
+impl BakeOutputs< RawImage >
+{
+  /// Converts every image to RGB floats.
+  pub fn decode( self ) -> BakeOutputs
+  {
+    BakeOutputs
+    {
+      diffuse : self.diffuse.decode(),
+      specular : self.specular.into_iter().map( RawImage::decode ).collect(),
+      brdf_lut : self.brdf_lut.decode(),
+      specular_cube : self.specular_cube.map( decode_cube ),
+      environment_cube : self.environment_cube.map( decode_cube ),
+      environment : self.environment.map( RawImage::decode )
+    }
+  }
+}
+
+fn decode_cube( mips : Vec< [ RawImage; 6 ] > ) -> Vec< [ OutputImage; 6 ] >
+{
+  mips.into_iter().map( | faces | faces.map( RawImage::decode ) ).collect()
+}
+
+impl BakeOutputs
+{
+  pub fn images( &self ) -> impl Iterator< Item = &OutputImage >
+  {
+    std::iter::once( &self.diffuse )
+    .chain( self.specular.iter() )
+    .chain( std::iter::once( &self.brdf_lut ) )
+    .chain( self.specular_cube.iter().flatten().flatten() )
+    .chain( self.environment.iter() )
+  }
+}
+
+/// Owns the environment cube and every renderer of the bake, so a batch can run
+/// many inputs through the same pipelines on one device.
+pub struct Baker
+{
+  cube_texture : Rc< Texture >,
+  cm_renderer : CubeMapRenderer,
+  sky_renderer : SkyRenderer,
+  /// The cube is rendered from the sky of [`Baker::set_sky`] instead of the source image
+  sky : bool,
+  cube_mipmap_renderer : CubeMipmapRenderer,
+  ibl_renderer : IBLRenderer,
+  /// Renders the cube back to an equirect, when `BakeSettings::equirect_size` is set
+  equirect_renderer : Option< EquirectRenderer >,
+  /// One per face of every exported mip of the cube, when `BakeSettings::environment_cube` is set
+  environment_buffers : Vec< ReadbackBuffer >,
+  profiler : GpuProfiler,
+  tiling : Tiling,
+  /// The BRDF LUT only depends on its size and sample count, so it is rendered
+  /// by the first bake and reused by every following one.
+  brdf_lut : Option< RawImage >
+}
+
+impl Baker
+{
+  /// With `profile` set, every pass is timed on the GPU when the device supports timestamp queries.
+  /// The cube is rendered from the source of [`Baker::set_source`] or [`Baker::set_sky`], one of them is called before baking.
+  pub fn new( device : &wgpu::Device, queue : &wgpu::Queue, settings : &BakeSettings, profile : bool ) -> Self
+  {
+    let cube_texture = Rc::new
+    (
+      Texture::builder( settings.cube_size, settings.cube_size )
+      .label( "CUBE_TEXTURE" )
+      .cube()
+      .full_mips()
+      .usage( wgpu::TextureUsages::STORAGE_BINDING )
+      .build( device )
+    );
+    let cm_renderer = CubeMapRenderer::new( cube_texture.clone(), device, settings.cube_resample, settings.cube_samples );
+    let sky_renderer = SkyRenderer::new( cube_texture.clone(), device );
+    let cube_mipmap_renderer = CubeMipmapRenderer::new( device, &cube_texture, settings.mip_filter );
+    let ibl_renderer = IBLRenderer::new( device, &cube_texture, settings );
+    let equirect_renderer = settings.equirect_size.map( | ( width, height ) | EquirectRenderer::new( device, &cube_texture, width, height ) );
+    let environment_mips = match settings.environment_cube
+    {
+      Some( export ) if export.mips => cube_texture.mip_count(),
+      Some( _ ) => 1,
+      None => 0
+    };
+    let environment_buffers = ( 0..environment_mips )
+    .flat_map( | mip_level | std::iter::repeat_n( mip_level, 6 ) )
+    .map( | mip_level | cube_texture.readback_buffer( device, mip_level ) )
+    .collect();
+
+    Self
+    {
+      cube_texture,
+      cm_renderer,
+      sky_renderer,
+      sky : false,
+      cube_mipmap_renderer,
+      ibl_renderer,
+      equirect_renderer,
+      environment_buffers,
+      profiler : GpuProfiler::new( device, queue, profile ),
+      tiling : settings.tiling,
+      brdf_lut : None
+    }
+  }
+
+  pub fn cube_texture( &self ) -> &Texture
+  {
+    &self.cube_texture
+  }
+
+  /// Converts `hdr_texture` to the cube in the following bakes.
+  pub fn set_source( &mut self, device : &wgpu::Device, hdr_texture : &Texture )
+  {
+    self.cm_renderer.set_hdr_texture( device, hdr_texture );
+    self.sky = false;
+  }
+
+  /// Renders `sky` to the cube in the following bakes.
+  pub fn set_sky( &mut self, queue : &wgpu::Queue, sky : &SkyUniform )
+  {
+    self.sky_renderer.set_sky( queue, sky );
+    self.sky = true;
+  }
+
+  pub fn specular_cube_texture( &self ) -> Option< &Texture >
+  {
+    self.ibl_renderer.specular_cube_texture()
+  }
+
+  /// Converts the source or renders the sky to the cube, filters it and copies every output to its readback buffer.
+  /// The work is split over as many submissions as `BakeSettings::tiling` asks for.
+  ///
+  /// With `BakeSettings::progressive` set only the cube and the LUT are rendered, and the returned
+  /// [`Refinement`] is passed to [`Baker::refine`] until it is done, then to [`Baker::finish_refinement`].
+  pub fn bake( &self, device : &wgpu::Device, queue : &wgpu::Queue ) -> Option< Refinement >
+  {
+    let mut submitter = Submitter::new( device, queue, &self.tiling );
+    let refinement = self.ibl_renderer.refinement( device );
+
+    if self.sky
+    {
+      self.sky_renderer.render( submitter.encoder(), &self.profiler );
+    }
+    else
+    {
+      self.cm_renderer.render( submitter.encoder(), &self.profiler );
+    }
+    self.cube_mipmap_renderer.generate_mipmaps( submitter.encoder(), &self.profiler );
+    for ( index, buffer ) in self.environment_buffers.iter().enumerate()
+    {
+      buffer.copy_from( submitter.encoder(), self.cube_texture.texture(), index as u32 / 6, index as u32 % 6 );
+    }
+    if let Some( equirect_renderer ) = &self.equirect_renderer
+    {
+      equirect_renderer.render( submitter.encoder(), &self.profiler );
+    }
+    if refinement.is_none()
+    {
+      self.ibl_renderer.render_diffuse( &mut submitter, &self.profiler );
+      self.ibl_renderer.render_specular_1( &mut submitter, &self.profiler );
+      self.ibl_renderer.render_specular_cube( &mut submitter, &self.profiler );
+      self.ibl_renderer.copy_outputs( submitter.encoder() );
+    }
+    if self.brdf_lut.is_none()
+    {
+      self.ibl_renderer.render_specular_2( &mut submitter, &self.profiler );
+    }
+    if refinement.is_none()
+    {
+      self.profiler.resolve( submitter.encoder() );
+    }
+
+    submitter.finish();
+    refinement
+  }
+
+  /// Adds a batch to the outputs that haven't converged yet. Waits for it to finish.
+  pub async fn refine( &self, device : &wgpu::Device, queue : &wgpu::Queue, refinement : &mut Refinement ) -> anyhow::Result< () >
+  {
+    self.ibl_renderer.refine( device, queue, &self.profiler, refinement ).await
+  }
+
+  /// Copies the refined outputs to their readback buffers, like the end of a [`Baker::bake`] that isn't progressive.
+  pub fn finish_refinement( &self, device : &wgpu::Device, queue : &wgpu::Queue )
+  {
+    let mut encoder = device.create_command_encoder( &wgpu::CommandEncoderDescriptor::default() );
+    self.ibl_renderer.copy_outputs( &mut encoder );
+    self.profiler.resolve( &mut encoder );
+    queue.submit( std::iter::once( encoder.finish() ) );
+  }
+
+  /// Reads back the results of the last [`Baker::bake`], left in the GPU formats.
+  /// Every readback buffer is mapped at once and waited for with a single poll.
+  pub async fn read_outputs( &mut self, device : &wgpu::Device ) -> anyhow::Result< BakeOutputs< RawImage > >
+  {
+    let mut buffers = self.ibl_renderer.readback_buffers( self.brdf_lut.is_none() );
+    buffers.extend( self.environment_buffers.iter().map( ReadbackBuffer::buffer ) );
+    buffers.extend( self.equirect_renderer.iter().map( | renderer | renderer.buffer().buffer() ) );
+    readback::map_buffers( device, &buffers ).await?;
+
+    let outputs = BakeOutputs
+    {
+      environment_cube : ( !self.environment_buffers.is_empty() ).then( || readback::take_cube( &self.environment_buffers, "environment_cube" ) ),
+      environment : self.equirect_renderer.as_ref().map( | renderer | renderer.buffer().take( "environment" ) ),
+      ..self.ibl_renderer.take_outputs( self.brdf_lut.as_ref() )
+    };
+    self.brdf_lut.get_or_insert_with( || outputs.brdf_lut.clone() );
+    Ok( outputs )
+  }
+
+  /// GPU time of every stage of the last [`Baker::bake`], empty when profiling is off or unsupported.
+  pub async fn read_profile( &self, device : &wgpu::Device ) -> anyhow::Result< Vec< StageTiming > >
+  {
+    self.profiler.read( device ).await
+  }
+}
+
+/// Analytic results every backend has to reproduce: the CPU reference always, and the render and
+/// compute passes when an adapter is available.
+#[ cfg( test ) ]
+mod tests
+{
+  use std::f32::consts::PI;
+
+  use super::*;
+  use crate::{cpu_baker, gpu};
+
+  fn settings() -> BakeSettings
+  {
+    BakeSettings
+    {
+      cube_size : 32,
+      diffuse_width : 32,
+      diffuse_height : 16,
+      specular_1_width : 32,
+      specular_1_height : 16,
+      specular_2_width : 8,
+      specular_2_height : 8,
+      diffuse_samples : 32,
+      specular_samples : 256,
+      lut_samples : 1024,
+      specular_cube_size : Some( 8 ),
+      ..BakeSettings::default()
+    }
+  }
+
+  /// Equirect of `width` x `height` whose texels are `radiance( elevation )`, in radians.
+  fn source( width : u32, height : u32, radiance : impl Fn( f32 ) -> f32 ) -> SourceImage
+  {
+    let pixels = ( 0..width * height ).flat_map( | i |
+    {
+      let value = radiance( ( 0.5 - ( ( i / width ) as f32 + 0.5 ) / height as f32 ) * PI );
+      [ value, value, value, 1.0 ]
+    })
+    .collect();
+    SourceImage { width, height, pixels }
+  }
+
+  /// Outputs of `source` on every backend, named for the messages.
+  fn bake_all( source : &SourceImage, settings : &BakeSettings ) -> Vec< ( &'static str, BakeOutputs ) >
+  {
+    let mut outputs = vec![ ( "cpu", cpu_baker::bake( source, settings ) ) ];
+    let Some( ( device, queue ) ) = gpu::test_device() else
+    {
+      eprintln!( "No suitable GPU adapter, only the CPU reference is tested" );
+      return outputs;
+    };
+
+    let hdr_texture = source.to_texture( &device, &queue );
+    for ( name, compute ) in [ ( "render", false ), ( "compute", true ) ]
+    {
+      let mut baker = Baker::new( &device, &queue, &BakeSettings { compute, ..*settings }, false );
+      baker.set_source( &device, &hdr_texture );
+      baker.bake( &device, &queue );
+      device.poll( wgpu::PollType::wait() ).unwrap();
+      outputs.push( ( name, pollster::block_on( baker.read_outputs( &device ) ).unwrap().decode() ) );
+    }
+    outputs
+  }
+
+  fn texel( image : &OutputImage, x : u32, y : u32 ) -> [ f32; 3 ]
+  {
+    let index = ( ( y * image.width + x ) * 3 ) as usize;
+    [ image.data[ index ], image.data[ index + 1 ], image.data[ index + 2 ] ]
+  }
+
+  #[ test ]
+  fn white_furnace()
+  {
+    // The irradiance is normalized by PI, so a constant environment gives back its radiance
+    for ( backend, outputs ) in bake_all( &source( 64, 32, | _ | 1.0 ), &settings() )
+    {
+      let filtered = std::iter::once( &outputs.diffuse ).chain( &outputs.specular ).chain( outputs.specular_cube.iter().flatten().flatten() );
+      for image in filtered
+      {
+        for value in &image.data
+        {
+          assert!( ( value - 1.0 ).abs() < 0.005, "{} {}: {} instead of 1", backend, image.name, value );
+        }
+      }
+    }
+  }
+
+  #[ test ]
+  fn hemisphere_irradiance()
+  {
+    // Unit radiance above the horizon: the irradiance over PI at a normal of elevation e is ( 1 + sin( e ) ) / 2
+    let settings = settings();
+    for ( backend, outputs ) in bake_all( &source( 64, 32, | elevation | if elevation > 0.0 { 1.0 } else { 0.0 } ), &settings )
+    {
+      for y in 0..settings.diffuse_height
+      {
+        let elevation = ( 0.5 - ( y as f32 + 0.5 ) / settings.diffuse_height as f32 ) * PI;
+        let expected = ( 1.0 + elevation.sin() ) / 2.0;
+        for x in 0..settings.diffuse_width
+        {
+          let value = texel( &outputs.diffuse, x, y )[ 0 ];
+          assert!( ( value - expected ).abs() < 0.01, "{} at ({}, {}): {} instead of {}", backend, x, y, value, expected );
+        }
+      }
+    }
+  }
+
+  #[ test ]
+  fn brdf_lut()
+  {
+    // On a mirror the split sum's scale and bias are the weights of F0 and 1 in Schlick's Fresnel,
+    // 1 - ( 1 - NdotV )^5 and ( 1 - NdotV )^5 (Schlick, "An Inexpensive BRDF Model for Physically-based Rendering", 1994,
+    // and Karis, "Real Shading in Unreal Engine 4", 2013)
+    let schlick = | dot_nv : f32 | ( 1.0 - ( 1.0 - dot_nv ).powi( 5 ), ( 1.0 - dot_nv ).powi( 5 ) );
+
+    // Exactly at a roughness of 0, so that a smooth surface seen head on reflects everything
+    for dot_nv in [ 1.0, 0.75, 0.5, 0.25, 0.1 ]
+    {
+      let [ scale, bias, _ ] = cpu_baker::integrate_brdf( dot_nv, 0.0, 64 ).to_array();
+      let ( expected_scale, expected_bias ) = schlick( dot_nv );
+      assert!
+      (
+        ( scale - expected_scale ).abs() < 1e-4 && ( bias - expected_bias ).abs() < 1e-4,
+        "({}, {}) instead of ({}, {}) at NdotV {}", scale, bias, expected_scale, expected_bias, dot_nv
+      );
+    }
+
+    // At a roughness of 1 the GGX distribution is 1 / pi and the height-correlated visibility of Heitz,
+    // "Understanding the Masking-Shadowing Function in Microfacet-Based BRDFs", 2014, is 1 / ( 2 ( NdotV + NdotL ) ),
+    // so scale + bias, the directional albedo, integrates to 1 - NdotV ln( 1 + 1 / NdotV )
+    for dot_nv in [ 1.0, 0.75, 0.5, 0.25, 0.1 ]
+    {
+      let [ scale, bias, _ ] = cpu_baker::integrate_brdf( dot_nv, 1.0, 4096 ).to_array();
+      let expected = 1.0 - dot_nv * ( 1.0 + 1.0 / dot_nv ).ln();
+      assert!( ( scale + bias - expected ).abs() < 0.005, "{} instead of {} at NdotV {}", scale + bias, expected, dot_nv );
+    }
+
+    // Every backend bakes the integral above at the texel centers, NdotV along X and the roughness along Y
+    let settings = settings();
+    for ( backend, outputs ) in bake_all( &source( 8, 4, | _ | 1.0 ), &settings )
+    {
+      for ( x, y ) in ( 0..8 ).flat_map( | y | ( 0..8 ).map( move | x | ( x, y ) ) )
+      {
+        let ( dot_nv, roughness ) = ( ( x as f32 + 0.5 ) / 8.0, ( y as f32 + 0.5 ) / 8.0 );
+        let [ scale, bias, _ ] = texel( &outputs.brdf_lut, x, y );
+        let [ expected_scale, expected_bias, _ ] = cpu_baker::integrate_brdf( dot_nv, roughness, settings.lut_samples ).to_array();
+        assert!
+        (
+          ( scale - expected_scale ).abs() < 0.01 && ( bias - expected_bias ).abs() < 0.01,
+          "{} at NdotV {} and roughness {}: ({}, {}) instead of ({}, {})", backend, dot_nv, roughness, scale, bias, expected_scale, expected_bias
+        );
+      }
+    }
+  }
+}
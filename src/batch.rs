@@ -0,0 +1,780 @@
+use std::{any::Any, collections::BTreeMap, fmt, panic::{self, AssertUnwindSafe}, path::{Path, PathBuf}, thread, time::{Duration, Instant}};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::{baker::{BakeOutputs, BakeSettings, Baker, CubeContainer, GltfSettings, SourceImage}, cache, cpu_baker, cube_file, gltf, manifest::{FileEntry, Manifest, SkyEntry, SourceEntry}, output::OutputFormat, profiler::StageTiming, readback::RawImage, sh::{self, ShCoefficients}, sky::{SkySettings, SkyUniform, SKY_EQUIRECT_SIZE}};
+
+/// What the environment cube of a job is made from.
+#[ derive( Clone, Debug ) ]
+pub enum Input
+{
+  /// Equirectangular image
+  File( PathBuf ),
+  /// Analytic sky, evaluated in place of an image
+  Sky( SkySettings )
+}
+
+impl Input
+{
+  /// File name of the image or name of the sky, for the report table.
+  pub fn name( &self ) -> String
+  {
+    match self
+    {
+      Self::File( path ) => path.file_name().map( | n | n.to_string_lossy().into_owned() ).unwrap_or_default(),
+      Self::Sky( sky ) => sky.name()
+    }
+  }
+}
+
+impl fmt::Display for Input
+{
+  fn fmt( &self, f : &mut fmt::Formatter< '_ > ) -> fmt::Result
+  {
+    match self
+    {
+      Self::File( path ) => write!( f, "{}", path.display() ),
+      Self::Sky( sky ) => write!( f, "{}", sky.name() )
+    }
+  }
+}
+
+/// One input of a batch and the directory its outputs are written to.
+pub struct BatchJob
+{
+  pub input : Input,
+  pub output_dir : PathBuf
+}
+
+/// Wall-clock times of the stages of one job. `bake` lasts until the GPU is done,
+/// `readback` is the copy of the outputs out of the mapped buffers and `encode` their conversion
+/// from the GPU formats and the writing of the files.
+#[ derive( Default ) ]
+pub struct JobTimings
+{
+  pub load : Duration,
+  pub bake : Duration,
+  pub readback : Duration,
+  pub encode : Duration
+}
+
+/// Where `--profile` reports the timings of every job.
+pub enum ProfileOutput
+{
+  Table,
+  Json( PathBuf )
+}
+
+pub struct JobReport
+{
+  pub job : BatchJob,
+  pub timings : JobTimings,
+  pub num_files : usize,
+  pub output_bytes : u64,
+  /// The outputs were up to date and the bake was skipped.
+  pub cached : bool,
+  pub error : Option< String >,
+  /// GPU time of every stage of the bake, when profiled
+  pub stages : Vec< StageTiming >,
+  /// How the progressive refinement ended, see [`crate::progressive::Refinement::summary`]
+  pub refinement : Option< String >
+}
+
+pub struct BatchReport
+{
+  pub jobs : Vec< JobReport >,
+  pub total_time : Duration,
+  /// The device timed the passes, otherwise the profile only has wall-clock times
+  pub gpu_timestamps : bool
+}
+
+/// Decoded image or evaluated sky of one job.
+enum Source
+{
+  Image( SourceImage ),
+  Sky( Box< SkyUniform > )
+}
+
+/// What the loader thread found for one job. The irradiance SH is only projected for the glTF export.
+enum LoadResult
+{
+  Source( Source, blake3::Hash, String, Option< ShCoefficients > ),
+  Cached,
+  Failed( anyhow::Error )
+}
+
+/// Read back outputs of one job, handed to the write workers.
+struct WriteJob
+{
+  index : usize,
+  output_dir : PathBuf,
+  source : SourceEntry,
+  cache_key : String,
+  outputs : BakeOutputs< RawImage >,
+  irradiance : Option< ShCoefficients >
+}
+
+/// Extensions the `image` crate is built to decode.
+const INPUT_EXTENSIONS : [ &str; 3 ] = [ "hdr", "png", "avif" ];
+
+/// Expands a directory or a glob pattern into the list of images to bake, sorted by path.
+pub fn collect_inputs( pattern : &str ) -> anyhow::Result< Vec< PathBuf > >
+{
+  let path = Path::new( pattern );
+  let mut inputs = if path.is_dir()
+  {
+    std::fs::read_dir( path )
+    .with_context( || format!( "Failed to read directory {}", path.display() ) )?
+    .filter_map( | entry | entry.ok().map( | e | e.path() ) )
+    .collect::< Vec< _ > >()
+  }
+  else
+  {
+    glob::glob( pattern )
+    .with_context( || format!( "Invalid glob pattern {}", pattern ) )?
+    .filter_map( Result::ok )
+    .collect::< Vec< _ > >()
+  };
+
+  inputs.retain( | p |
+  {
+    p.is_file() && p.extension()
+    .and_then( | e | e.to_str() )
+    .is_some_and( | e | INPUT_EXTENSIONS.iter().any( | ext | e.eq_ignore_ascii_case( ext ) ) )
+  });
+  inputs.sort();
+
+  Ok( inputs )
+}
+
+/// One job per input, writing to `<output>/<file stem>`. Inputs with the same stem, like `sky.hdr` and `sky.png`
+/// or the same name in two directories of a recursive glob, would overwrite each other's outputs and fail instead.
+pub fn file_jobs( inputs : Vec< PathBuf >, output : &Path ) -> anyhow::Result< Vec< BatchJob > >
+{
+  let mut by_stem = BTreeMap::< _, Vec< &PathBuf > >::new();
+  for input in &inputs
+  {
+    by_stem.entry( input.file_stem().unwrap_or_default() ).or_default().push( input );
+  }
+  let clashes = by_stem.values()
+  .filter( | inputs | inputs.len() > 1 )
+  .map( | inputs | inputs.iter().map( | input | input.display().to_string() ).collect::< Vec< _ > >().join( ", " ) )
+  .collect::< Vec< _ > >();
+  if !clashes.is_empty()
+  {
+    anyhow::bail!( "Inputs with the same file stem would be written to the same folder: {}", clashes.join( "; " ) );
+  }
+
+  Ok( inputs.into_iter().map( | input |
+  {
+    let output_dir = output.join( input.file_stem().unwrap_or_default() );
+    BatchJob { input : Input::File( input ), output_dir }
+  })
+  .collect() )
+}
+
+/// Text of the payload of a caught panic.
+fn panic_message( payload : Box< dyn Any + Send > ) -> String
+{
+  payload.downcast_ref::< &str >().map( | s | s.to_string() )
+  .or_else( || payload.downcast_ref::< String >().cloned() )
+  .unwrap_or_else( || "unknown panic".into() )
+}
+
+/// Bakes every job on one device. Decoding of the next input runs on its own thread, and the
+/// outputs are converted and encoded by a pool of workers, so both overlap with the GPU work of the current one.
+/// Jobs whose outputs were produced from the same input and settings are skipped unless `force` is set.
+/// A panic while loading or writing a job fails that job only, it is recorded in its report like any other error,
+/// and so does an error of the GPU while baking or reading it back.
+/// With `profile` set, the passes of every bake are timed on the GPU when the device supports it.
+pub async fn run
+(
+  device : &wgpu::Device,
+  queue : &wgpu::Queue,
+  jobs : Vec< BatchJob >,
+  settings : &BakeSettings,
+  force : bool,
+  profile : bool
+) -> BatchReport
+{
+  let start = Instant::now();
+
+  let ( load_sender, load_receiver ) = flume::bounded( 1 );
+  let num_workers = thread::available_parallelism().map_or( 1, | n | n.get() ).min( jobs.len() ).max( 1 );
+  let ( write_sender, write_receiver ) = flume::bounded::< WriteJob >( num_workers );
+
+  let inputs = jobs.iter().map( | job | ( job.input.clone(), job.output_dir.clone() ) ).collect::< Vec< _ > >();
+  let loader_settings = *settings;
+  let max_size = device.limits().max_texture_dimension_2d;
+  let loader = thread::spawn( move ||
+  {
+    for ( index, ( input, output_dir ) ) in inputs.iter().enumerate()
+    {
+      let now = Instant::now();
+      let result = panic::catch_unwind( AssertUnwindSafe( || load( input, output_dir, &loader_settings, force, max_size ) ) )
+      .unwrap_or_else( | payload | LoadResult::Failed( anyhow::anyhow!( "Loading panicked: {}", panic_message( payload ) ) ) );
+      if load_sender.send( ( index, result, now.elapsed() ) ).is_err()
+      {
+        break;
+      }
+    }
+  });
+
+  let writers = ( 0..num_workers ).map( | _ |
+  {
+    let write_receiver = write_receiver.clone();
+    let settings = *settings;
+    thread::spawn( move ||
+    {
+      let mut results = Vec::new();
+      for job in write_receiver.iter()
+      {
+        let now = Instant::now();
+        let result = panic::catch_unwind( AssertUnwindSafe( ||
+        {
+          save_job( &job.output_dir, job.source, job.cache_key, &settings, &job.outputs.decode(), job.irradiance )
+        }))
+        .unwrap_or_else( | payload | Err( anyhow::anyhow!( "Writing the outputs panicked: {}", panic_message( payload ) ) ) );
+        results.push( ( job.index, result, now.elapsed() ) );
+      }
+      results
+    })
+  })
+  .collect::< Vec< _ > >();
+  drop( write_receiver );
+
+  let mut reports = jobs.into_iter().map( JobReport::new ).collect::< Vec< _ > >();
+  // Jobs the loader or a writer still owes a result, failed if their thread dies
+  let mut pending = vec![ true; reports.len() ];
+
+  let mut baker : Option< Baker > = None;
+  for ( index, result, load_time ) in load_receiver.iter()
+  {
+    let report = &mut reports[ index ];
+    report.timings.load = load_time;
+
+    let ( source, hash, key, irradiance ) = match result
+    {
+      LoadResult::Source( source, hash, key, irradiance ) => ( source, hash, key, irradiance ),
+      LoadResult::Cached =>
+      {
+        report.cached = true;
+        pending[ index ] = false;
+        continue;
+      },
+      LoadResult::Failed( e ) =>
+      {
+        report.error = Some( format!( "{:#}", e ) );
+        pending[ index ] = false;
+        continue;
+      }
+    };
+
+    let mut source_entry = source_entry( &report.job.input, &hash );
+    if let Source::Image( image ) = &source
+    {
+      ( source_entry.width, source_entry.height ) = ( image.width, image.height );
+    }
+
+    // A validation error, an allocation failure or a lost device fails this job only. The baker may hold
+    // invalid resources after one, so it is created again for the next job
+    for filter in GPU_ERROR_FILTERS
+    {
+      device.push_error_scope( filter );
+    }
+    let result = bake_job( device, queue, &mut baker, settings, profile, source, report ).await;
+    let mut gpu_errors = Vec::new();
+    for _ in GPU_ERROR_FILTERS
+    {
+      gpu_errors.extend( device.pop_error_scope().await );
+    }
+    let result = match gpu_errors.first()
+    {
+      Some( e ) => Err( anyhow::anyhow!( "The bake failed on the GPU: {}", e ) ),
+      None => result
+    };
+    let outputs = match result
+    {
+      Ok( outputs ) => outputs,
+      Err( e ) =>
+      {
+        report.error = Some( format!( "{:#}", e ) );
+        pending[ index ] = false;
+        baker = None;
+        continue;
+      }
+    };
+
+    let write_job = WriteJob
+    {
+      index,
+      output_dir : report.job.output_dir.clone(),
+      source : source_entry,
+      cache_key : key,
+      outputs,
+      irradiance
+    };
+    if write_sender.send( write_job ).is_err()
+    {
+      report.error = Some( "Every write worker stopped, the outputs weren't written".into() );
+      pending[ index ] = false;
+    }
+  }
+  drop( write_sender );
+
+  let mut thread_failures = Vec::new();
+  if let Err( payload ) = loader.join()
+  {
+    thread_failures.push( format!( "The loader thread panicked: {}", panic_message( payload ) ) );
+  }
+  let mut results = Vec::new();
+  for writer in writers
+  {
+    match writer.join()
+    {
+      Ok( writer_results ) => results.extend( writer_results ),
+      Err( payload ) => thread_failures.push( format!( "A write worker panicked: {}", panic_message( payload ) ) )
+    }
+  }
+  for ( index, result, encode_time ) in results
+  {
+    let report = &mut reports[ index ];
+    pending[ index ] = false;
+    report.timings.encode = encode_time;
+    match result
+    {
+      Ok( sizes ) =>
+      {
+        report.num_files = sizes.len();
+        report.output_bytes = sizes.iter().sum();
+      },
+      Err( e ) => report.error = Some( format!( "{:#}", e ) )
+    }
+  }
+  for ( report, _ ) in reports.iter_mut().zip( pending ).filter( | ( _, pending ) | *pending )
+  {
+    report.error = Some( match thread_failures.is_empty()
+    {
+      true => "The job was never finished".into(),
+      false => thread_failures.join( ", " )
+    });
+  }
+
+  BatchReport
+  {
+    jobs : reports,
+    total_time : start.elapsed(),
+    gpu_timestamps : profile && device.features().contains( wgpu::Features::TIMESTAMP_QUERY )
+  }
+}
+
+/// Error scopes every job is baked in, see [`run`].
+const GPU_ERROR_FILTERS : [ wgpu::ErrorFilter; 3 ] = [ wgpu::ErrorFilter::Validation, wgpu::ErrorFilter::OutOfMemory, wgpu::ErrorFilter::Internal ];
+
+/// Bakes `source` on the GPU and reads the outputs back, recording the timings in `report`.
+async fn bake_job
+(
+  device : &wgpu::Device,
+  queue : &wgpu::Queue,
+  baker : &mut Option< Baker >,
+  settings : &BakeSettings,
+  profile : bool,
+  source : Source,
+  report : &mut JobReport
+) -> anyhow::Result< BakeOutputs< RawImage > >
+{
+  let now = Instant::now();
+  let baker = baker.get_or_insert_with( || Baker::new( device, queue, settings, profile ) );
+  match source
+  {
+    Source::Image( image ) => baker.set_source( device, &image.to_texture( device, queue ) ),
+    Source::Sky( sky ) => baker.set_sky( queue, &sky )
+  }
+  if let Some( mut refinement ) = baker.bake( device, queue )
+  {
+    while !refinement.is_done()
+    {
+      baker.refine( device, queue, &mut refinement ).await?;
+    }
+    baker.finish_refinement( device, queue );
+    report.refinement = Some( refinement.summary() );
+  }
+  device.poll( wgpu::PollType::wait() ).context( "Failed to wait for the bake" )?;
+  report.timings.bake = now.elapsed();
+
+  let now = Instant::now();
+  let outputs = baker.read_outputs( device ).await?;
+  report.timings.readback = now.elapsed();
+  report.stages = baker.read_profile( device ).await?;
+  Ok( outputs )
+}
+
+/// Bakes every job with the CPU reference of [`cpu_baker`], one after the other. The outputs match
+/// the ones of [`run`] with `BakeSettings::compute` set.
+pub fn run_cpu( jobs : Vec< BatchJob >, settings : &BakeSettings, force : bool ) -> BatchReport
+{
+  let start = Instant::now();
+  let reports = jobs.into_iter().map( | job |
+  {
+    let mut report = JobReport::new( job );
+    let now = Instant::now();
+    let result = load( &report.job.input, &report.job.output_dir, settings, force, u32::MAX );
+    report.timings.load = now.elapsed();
+
+    match result
+    {
+      LoadResult::Source( source, hash, key, irradiance ) =>
+      {
+        let now = Instant::now();
+        let mut source_entry = source_entry( &report.job.input, &hash );
+        let outputs = match source
+        {
+          Source::Image( image ) =>
+          {
+            ( source_entry.width, source_entry.height ) = ( image.width, image.height );
+            cpu_baker::bake( &image, settings )
+          },
+          Source::Sky( sky ) => cpu_baker::bake_sky( &sky, settings )
+        };
+        report.timings.bake = now.elapsed();
+
+        let now = Instant::now();
+        match save_job( &report.job.output_dir, source_entry, key, settings, &outputs, irradiance )
+        {
+          Ok( sizes ) =>
+          {
+            report.num_files = sizes.len();
+            report.output_bytes = sizes.iter().sum();
+          },
+          Err( e ) => report.error = Some( format!( "{:#}", e ) )
+        }
+        report.timings.encode = now.elapsed();
+      },
+      LoadResult::Cached => report.cached = true,
+      LoadResult::Failed( e ) => report.error = Some( format!( "{:#}", e ) )
+    }
+    report
+  })
+  .collect();
+
+  BatchReport
+  {
+    jobs : reports,
+    total_time : start.elapsed(),
+    gpu_timestamps : false
+  }
+}
+
+/// Manifest entry of the source, the size of an image is filled in once it is decoded.
+fn source_entry( input : &Input, hash : &blake3::Hash ) -> SourceEntry
+{
+  SourceEntry
+  {
+    path : input.to_string(),
+    hash : hash.to_hex().to_string(),
+    width : 0,
+    height : 0,
+    sky : match input
+    {
+      Input::Sky( sky ) => Some( SkyEntry::new( sky ) ),
+      Input::File( _ ) => None
+    }
+  }
+}
+
+/// Images wider or higher than `max_size`, the largest texture of the device, fail to load.
+fn load( input : &Input, output_dir : &Path, settings : &BakeSettings, force : bool, max_size : u32 ) -> LoadResult
+{
+  match input
+  {
+    Input::File( path ) => load_file( path, output_dir, settings, force, max_size ),
+    Input::Sky( sky ) => load_sky( sky, output_dir, settings, force )
+  }
+}
+
+fn load_file( input : &Path, output_dir : &Path, settings : &BakeSettings, force : bool, max_size : u32 ) -> LoadResult
+{
+  let bytes = match std::fs::read( input )
+  {
+    Ok( bytes ) => bytes,
+    Err( e ) => return LoadResult::Failed( anyhow::Error::new( e ).context( format!( "Failed to open {}", input.display() ) ) )
+  };
+
+  let hash = blake3::hash( &bytes );
+  let key = cache::cache_key( &hash, settings );
+  if !force && cache::is_cached( output_dir, &key )
+  {
+    return LoadResult::Cached;
+  }
+
+  match SourceImage::decode( &bytes )
+  {
+    Ok( source ) if source.width.max( source.height ) > max_size =>
+    {
+      LoadResult::Failed( anyhow::anyhow!( "{} is {}x{}, larger than the {} texels of the largest texture of the GPU", input.display(), source.width, source.height, max_size ) )
+    },
+    Ok( source ) =>
+    {
+      let irradiance = settings.gltf.map( | _ | sh::radiance_to_irradiance( sh::project_radiance( &source ) ) );
+      LoadResult::Source( Source::Image( source ), hash, key, irradiance )
+    },
+    Err( e ) => LoadResult::Failed( e.context( format!( "Failed to decode {}", input.display() ) ) )
+  }
+}
+
+/// The sky is hashed from its settings, and from the Hosek-Wilkie data when it is read.
+fn load_sky( sky : &SkySettings, output_dir : &Path, settings : &BakeSettings, force : bool ) -> LoadResult
+{
+  let uniform = match sky.uniform()
+  {
+    Ok( uniform ) => uniform,
+    Err( e ) => return LoadResult::Failed( e )
+  };
+
+  let mut hasher = blake3::Hasher::new();
+  hasher.update( format!( "{:?}", sky ).as_bytes() );
+  hasher.update( bytemuck::bytes_of( &uniform ) );
+  let hash = hasher.finalize();
+  let key = cache::cache_key( &hash, settings );
+  if !force && cache::is_cached( output_dir, &key )
+  {
+    return LoadResult::Cached;
+  }
+  let irradiance = settings.gltf.map( | _ |
+  {
+    let ( width, height ) = SKY_EQUIRECT_SIZE;
+    sh::radiance_to_irradiance( sh::project_radiance( &uniform.render_equirect( width, height ) ) )
+  });
+  LoadResult::Source( Source::Sky( Box::new( uniform ) ), hash, key, irradiance )
+}
+
+/// Writes the outputs of one job with their manifest, and the glTF and environment cube files the settings ask for.
+pub fn save_job
+(
+  output_dir : &Path,
+  source : SourceEntry,
+  cache_key : String,
+  settings : &BakeSettings,
+  outputs : &BakeOutputs,
+  irradiance : Option< ShCoefficients >
+) -> anyhow::Result< Vec< u64 > >
+{
+  let manifest = Manifest::new( source, cache_key, settings, outputs );
+  let gltf = settings.gltf.zip( irradiance );
+  let environment_cube = settings.environment_cube.map( | export | export.container );
+  write_outputs( output_dir, manifest, outputs, settings.output_format, gltf.as_ref(), environment_cube )
+}
+
+fn write_outputs
+( 
+  output_dir : &Path, 
+  mut manifest : Manifest, 
+  outputs : &BakeOutputs, 
+  format : OutputFormat, 
+  gltf : Option< &( GltfSettings, ShCoefficients ) >,
+  environment_cube : Option< CubeContainer >
+) -> anyhow::Result< Vec< u64 > >
+{
+  std::fs::create_dir_all( output_dir )
+  .with_context( || format!( "Failed to create {}", output_dir.display() ) )?;
+  cache::invalidate( output_dir )?;
+  let mut files = outputs.images().map( | image | FileEntry::image( output_dir, image, format ) ).collect::< anyhow::Result< Vec< _ > > >()?;
+  if let ( Some( ( settings, irradiance ) ), Some( specular_cube ) ) = ( gltf, &outputs.specular_cube )
+  {
+    files.extend( gltf::save( output_dir, settings, irradiance, specular_cube )? );
+    manifest.add_gltf( settings );
+  }
+  if let ( Some( container ), Some( mips ) ) = ( environment_cube, &outputs.environment_cube )
+  {
+    files.extend( cube_file::save( output_dir, container, mips, format )? );
+  }
+  let mut sizes = files.iter().map( | file | file.bytes ).collect::< Vec< _ > >();
+  manifest.files = files;
+  sizes.push( manifest.save( output_dir )? );
+  Ok( sizes )
+}
+
+impl JobReport
+{
+  fn new( job : BatchJob ) -> Self
+  {
+    Self
+    {
+      job,
+      timings : JobTimings::default(),
+      num_files : 0,
+      output_bytes : 0,
+      cached : false,
+      error : None,
+      stages : Vec::new(),
+      refinement : None
+    }
+  }
+
+  pub fn status( &self ) -> &'static str
+  {
+    if self.error.is_some() { "FAILED" }
+    else if self.cached { "cached" }
+    else { "ok" }
+  }
+}
+
+impl BatchReport
+{
+  pub fn num_failed( &self ) -> usize
+  {
+    self.jobs.iter().filter( | j | j.error.is_some() ).count()
+  }
+
+  pub fn print( &self )
+  {
+    println!
+    (
+      "{:<40} {:>8} {:>10} {:>10} {:>11} {:>10} {:>6} {:>10}",
+      "input", "status", "load ms", "bake ms", "readback ms", "encode ms", "files", "size MiB"
+    );
+    for report in &self.jobs
+    {
+      let name = report.job.input.name();
+      println!
+      (
+        "{:<40} {:>8} {:>10.1} {:>10.1} {:>11.1} {:>10.1} {:>6} {:>10.2}",
+        name,
+        report.status(),
+        report.timings.load.as_secs_f64() * 1000.0,
+        report.timings.bake.as_secs_f64() * 1000.0,
+        report.timings.readback.as_secs_f64() * 1000.0,
+        report.timings.encode.as_secs_f64() * 1000.0,
+        report.num_files,
+        report.output_bytes as f64 / ( 1024.0 * 1024.0 )
+      );
+    }
+
+    let total_bytes = self.jobs.iter().map( | j | j.output_bytes ).sum::< u64 >();
+    println!
+    (
+      "{} inputs, {} cached, {} failed, {:.2} MiB written in {:.2} s",
+      self.jobs.len(),
+      self.jobs.iter().filter( | j | j.cached ).count(),
+      self.num_failed(),
+      total_bytes as f64 / ( 1024.0 * 1024.0 ),
+      self.total_time.as_secs_f64()
+    );
+
+    for report in self.jobs.iter().filter( | j | j.refinement.is_some() )
+    {
+      println!( "{}: {}", report.job.input, report.refinement.as_deref().unwrap_or_default() );
+    }
+    for report in self.jobs.iter().filter( | j | j.error.is_some() )
+    {
+      println!( "{}: {}", report.job.input, report.error.as_deref().unwrap_or_default() );
+    }
+  }
+
+  /// Prints the GPU time of every stage of every baked job.
+  pub fn print_profile( &self )
+  {
+    if !self.gpu_timestamps
+    {
+      println!( "The device doesn't support timestamp queries, only wall-clock times are reported" );
+      return;
+    }
+
+    for report in self.jobs.iter().filter( | j | !j.stages.is_empty() )
+    {
+      println!();
+      println!( "{}", report.job.input );
+      println!( "  {:<30} {:>6} {:>10} {:>8}", "stage", "passes", "gpu ms", "untimed" );
+      for stage in &report.stages
+      {
+        println!( "  {:<30} {:>6} {:>10.3} {:>8}", stage.stage, stage.passes, stage.ms, stage.untimed );
+      }
+      let untimed = report.stages.iter().map( | s | s.untimed ).sum::< u32 >();
+      println!( "  {:<30} {:>6} {:>10.3} {:>8}", "total", "", report.stages.iter().map( | s | s.ms ).sum::< f64 >(), untimed );
+      if untimed > 0
+      {
+        println!( "  {} passes ran once every query set was full, their time is missing from the totals", untimed );
+      }
+    }
+  }
+
+  /// Writes the timings of every job as JSON.
+  pub fn save_profile( &self, path : &Path ) -> anyhow::Result< () >
+  {
+    let profile = Profile
+    {
+      gpu_timestamps : self.gpu_timestamps,
+      jobs : self.jobs.iter().map( | report | JobProfile
+      {
+        input : report.job.input.to_string(),
+        status : report.status(),
+        load_ms : report.timings.load.as_secs_f64() * 1000.0,
+        bake_ms : report.timings.bake.as_secs_f64() * 1000.0,
+        readback_ms : report.timings.readback.as_secs_f64() * 1000.0,
+        encode_ms : report.timings.encode.as_secs_f64() * 1000.0,
+        stages : &report.stages
+      })
+      .collect()
+    };
+
+    let json = serde_json::to_string_pretty( &profile )?;
+    std::fs::write( path, json ).with_context( || format!( "Failed to write {}", path.display() ) )
+  }
+}
+
+#[ derive( Serialize ) ]
+struct Profile< 'a >
+{
+  gpu_timestamps : bool,
+  jobs : Vec< JobProfile< 'a > >
+}
+
+#[ derive( Serialize ) ]
+struct JobProfile< 'a >
+{
+  input : String,
+  status : &'static str,
+  load_ms : f64,
+  bake_ms : f64,
+  readback_ms : f64,
+  encode_ms : f64,
+  stages : &'a [ StageTiming ]
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+  use crate::output::OutputImage;
+
+  #[ test ]
+  fn inputs_with_the_same_stem_fail()
+  {
+    let output = Path::new( "result" );
+    let jobs = file_jobs( vec![ "a/sky.hdr".into(), "a/sun.hdr".into() ], output ).unwrap();
+    assert_eq!( jobs[ 1 ].output_dir, output.join( "sun" ) );
+
+    for inputs in [ vec![ "a/sky.hdr".into(), "a/sky.png".into() ], vec![ "a/x.hdr".into(), "b/x.hdr".into(), "b/y.hdr".into() ] ]
+    {
+      let error = file_jobs( inputs, output ).err().expect( "Clashing stems are accepted" ).to_string();
+      assert!( error.contains( "x.hdr" ) || error.contains( "sky.hdr" ) );
+      assert!( !error.contains( "b/y.hdr" ), "{}", error );
+    }
+  }
+
+  #[ test ]
+  fn images_larger_than_the_gpu_textures_fail_to_load()
+  {
+    let dir = std::env::temp_dir().join( format!( "IBLConverter-batch-{}", std::process::id() ) );
+    std::fs::create_dir_all( &dir ).unwrap();
+    OutputImage::new( "wide", 16, 8, vec![ 1.0; 16 * 8 * 3 ] ).save( &dir, OutputFormat::Hdr ).unwrap();
+    let input = Input::File( dir.join( "wide.hdr" ) );
+    let output_dir = dir.join( "wide" );
+
+    let LoadResult::Failed( error ) = load( &input, &output_dir, &BakeSettings::default(), true, 8 ) else { panic!( "A 16x8 image loads for textures of 8" ) };
+    assert!( error.to_string().contains( "16x8" ), "{}", error );
+    assert!( matches!( load( &input, &output_dir, &BakeSettings::default(), true, 16 ), LoadResult::Source( .. ) ) );
+    std::fs::remove_dir_all( &dir ).unwrap();
+  }
+}
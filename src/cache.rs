@@ -0,0 +1,66 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::{baker::BakeSettings, cube_map_renderer, cube_mipmap_renderer, equirect_renderer, ibl_compute, ibl_renderer, manifest::{Manifest, MANIFEST_FILE}, sky_renderer, tiling::Tiling};
+
+/// Hash of everything a bake depends on: the input file or sky, the settings, the shaders and the tool itself.
+/// Any change to one of them produces a different key.
+pub fn cache_key( input_hash : &blake3::Hash, settings : &BakeSettings ) -> String
+{
+  let mut hasher = blake3::Hasher::new();
+  hasher.update( env!( "CARGO_PKG_VERSION" ).as_bytes() );
+  // The tiling doesn't change the outputs
+  let settings = BakeSettings { tiling : Tiling::default(), ..*settings };
+  hasher.update( format!( "{:?}", settings ).as_bytes() );
+  for source in [ cube_map_renderer::SHADER_SOURCE, cube_mipmap_renderer::SHADER_SOURCE, equirect_renderer::SHADER_SOURCE, ibl_renderer::SHADER_SOURCE, ibl_compute::SHADER_SOURCE, sky_renderer::SHADER_SOURCE ]
+  {
+    hasher.update( blake3::hash( source.as_bytes() ).as_bytes() );
+  }
+  hasher.update( input_hash.as_bytes() );
+  hasher.finalize().to_hex().to_string()
+}
+
+/// The manifest is written after every other output, so a manifest with a matching key
+/// means the outputs in `output_dir` were complete and up to date. They still have to be there,
+/// with the sizes the manifest lists, for the bake to be skipped.
+pub fn is_cached( output_dir : &Path, key : &str ) -> bool
+{
+  Manifest::load( output_dir ).is_ok_and( | manifest | manifest.cache_key == key && !manifest.files.is_empty() && manifest.files_intact( output_dir ) )
+}
+
+/// Drops the cache entry of `output_dir`, so outputs that are about to be overwritten are never
+/// mistaken for a finished bake.
+pub fn invalidate( output_dir : &Path ) -> anyhow::Result< () >
+{
+  match std::fs::remove_file( output_dir.join( MANIFEST_FILE ) )
+  {
+    Err( e ) if e.kind() != std::io::ErrorKind::NotFound => Err( e.into() ),
+    _ => Ok( () )
+  }
+}
+
+/// Removes every cache entry under `root` and returns how many were found.
+pub fn clean( root : &Path ) -> anyhow::Result< usize >
+{
+  let mut removed = 0;
+  if root.join( MANIFEST_FILE ).is_file()
+  {
+    invalidate( root )?;
+    removed += 1;
+  }
+
+  if root.is_dir()
+  {
+    for entry in std::fs::read_dir( root ).with_context( || format!( "Failed to read directory {}", root.display() ) )?
+    {
+      let path = entry?.path();
+      if path.is_dir()
+      {
+        removed += clean( &path )?;
+      }
+    }
+  }
+
+  Ok( removed )
+}
@@ -0,0 +1,391 @@
+use std::path::PathBuf;
+
+use anyhow::{bail, Context};
+
+use crate::{baker::{BakeSettings, CubeContainer, CubeResample, EnvironmentCubeSettings, GltfSettings, MipFilter, OutputLayout}, batch::{Input, ProfileOutput}, golden, output::OutputFormat, packing::Packing, progressive::ProgressiveSettings, sky::{SkyModel, SkySettings, TimeOfDay}};
+
+pub const USAGE : &str = "\
+Usage:
+  IBLConverter bake <input>|--sky <model> [--output <dir>] [options]
+  IBLConverter batch <dir|glob> [--output <dir>] [options]
+  IBLConverter view <input>|--sky <model> [options]
+  IBLConverter golden [<dir>] [--bless] [--output <heatmap dir>] [--compute|--cpu]
+  IBLConverter compare <dir|manifest> <dir|manifest> [--output <diff dir>]
+  IBLConverter inspect <input>|--sky <model> [options]
+  IBLConverter cache-clean [<dir>]
+
+Options:
+  --output <dir>           Output directory, `result` by default. Batch writes one subfolder per input
+  --force                  Bake even when the outputs are up to date
+  --bless                  Replace the goldens with the current outputs instead of comparing them
+  --profile                Print the GPU time of every stage, when the device supports timestamp queries
+  --profile-json <file>    Write the wall-clock and GPU timings of every job as JSON
+  --cube-size <n>          Size of the environment cube faces
+  --cube-resample <name>   Resampling of the source to the cube: `bilinear`, `supersample` or `area`
+  --cube-samples <n>       Samples along each axis of a cube texel when supersampling, 4 by default
+  --mip-filter <name>      Filter of the environment cube mips: `kaiser` by default, which filters across face edges, `box` or `solid-angle`
+  --diffuse-size <n|WxH>   Size of the irradiance map
+  --specular-size <n|WxH>  Size of the first prefiltered specular mip
+  --lut-size <n|WxH>       Size of the BRDF LUT
+  --diffuse-samples <n>    Samples per hemisphere axis of the irradiance integral
+  --specular-samples <n>   Samples per texel of the specular prefilter
+  --lut-samples <n>        Samples per texel of the BRDF LUT
+  --diffuse-format <name>  GPU format of the irradiance map: `rgba32f`, `rgba16f` or `rg11b10f`
+  --specular-format <name> GPU format of the specular mips and cube, same choices as the irradiance
+  --lut-format <name>      GPU format of the BRDF LUT: `rgba32f`, `rgba16f` or `rg16f`
+  --tile-size <n>          Render the filtering passes in tiles of n x n texels, to stay under the GPU watchdog
+  --submit-budget <n>      Millions of environment samples per submission, the bake is split across submissions
+  --compute                Run the filtering passes as compute shaders, needs rgba32f or rgba16f outputs
+  --cpu                    Bake with the CPU reference implementation, also used when no GPU adapter is found
+  --progressive            Refine the irradiance and specular in batches until they converge, implies --compute
+  --batch-samples <n>      Specular samples per texel of each progressive batch, 64 by default
+  --target-noise <f>       Relative noise at which a progressive output stops refining, 0.01 by default
+  --time-budget <s>        Seconds after which the progressive refinement stops, converged or not
+  --layout <name>          Mapping of the diffuse and specular maps, `equirect` or `octahedral`
+  --format <name>          Format of the output images: `hdr`, `exr`, or PNG packed as `rgbm`, `rgbd` or `rgbe`
+  --range <f>              Range multiplier of RGBM and RGBD, 8 and 255 by default. RGBE takes none
+  --specular-cube-size <n> Also prefilter the specular to a cube with faces of this size
+  --gltf                   Write environment.gltf using EXT_lights_image_based, implies a 256 specular cube
+  --gltf-intensity <f>     Intensity of the glTF light, 1 by default
+  --gltf-rotation <deg>    Rotation of the glTF light around the up axis
+  --equirect-size <w|WxH>  Also render the environment cube back to an equirect, half as high as wide unless given
+  --env-cube <name>        Also write the unfiltered environment cube as `faces`, a `cross`, `ktx2` or `dds`
+  --env-cube-mips          Write the mips of the environment cube too, implies --env-cube ktx2
+  --sky <model>            Render an analytic sky instead of an input: `preetham`, `hosek-wilkie` or `gradient`
+  --time-of-day <name>     Sun position of the sky: `sunrise`, `morning`, `noon`, `afternoon` or `sunset`
+  --sun-elevation <deg>    Degrees of the sun above the horizon, 45 by default
+  --sun-azimuth <deg>      Degrees of the sun around the up axis, 0 is the center of an equirect
+  --turbidity <f>          Haze of the sky, from 1 for a clear sky to 10, 3 by default
+  --ground-albedo <f>      Reflectance of the ground below the horizon, 0.3 by default
+  --hosek-data <file>      ArHosekSkyModelData_RGB.h of the reference implementation, needed by hosek-wilkie
+";
+
+pub enum Command
+{
+  Bake { input : Input, output : PathBuf },
+  Batch { pattern : String, output : PathBuf },
+  View { input : Input },
+  /// Bakes the inputs under `dir`, `tests/golden` by default, with fixed settings and compares the outputs
+  /// to their goldens, see [`crate::golden`]. Only the backend options apply. The heatmaps of the outputs that
+  /// differ go to `diff_dir`, `target/golden-diff` by default.
+  Golden { dir : PathBuf, diff_dir : PathBuf, bless : bool },
+  /// Compares every output and mip of two bakes, see [`crate::compare`]. False color images of the differences
+  /// are written to `diff_dir` when it is set.
+  Compare { a : PathBuf, b : PathBuf, diff_dir : Option< PathBuf > },
+  /// Prints the statistics of an input before baking it, see [`crate::inspect`].
+  Inspect { input : Input },
+  /// Removes the cache entries under a directory, so the next bake redoes everything.
+  CacheClean { dir : PathBuf }
+}
+
+pub struct Cli
+{
+  pub command : Command,
+  pub settings : BakeSettings,
+  pub force : bool,
+  pub profile : Option< ProfileOutput >
+}
+
+impl Cli
+{
+  pub fn parse( mut args : impl Iterator< Item = String > ) -> anyhow::Result< Self >
+  {
+    let Some( command ) = args.next() else { bail!( "Missing command" ) };
+
+    let mut settings = BakeSettings::default();
+    let mut output = None;
+    let mut force = false;
+    let mut bless = false;
+    let mut profile = None;
+    let mut gltf = None;
+    let mut format = None;
+    let mut range = None;
+    let mut sky_model = None;
+    let mut sky = SkySettings::default();
+    let mut positional = Vec::new();
+
+    while let Some( arg ) = args.next()
+    {
+      let mut value = | | args.next().with_context( || format!( "Missing value for {}", arg ) );
+      match arg.as_str()
+      {
+        "--output" | "-o" => output = Some( PathBuf::from( value()? ) ),
+        "--force" => force = true,
+        "--bless" => bless = true,
+        "--profile" => profile = Some( ProfileOutput::Table ),
+        "--profile-json" => profile = Some( ProfileOutput::Json( PathBuf::from( value()? ) ) ),
+        "--compute" => settings.compute = true,
+        "--cpu" => settings.cpu = true,
+        "--progressive" => { settings.progressive.get_or_insert_with( ProgressiveSettings::default ); },
+        "--batch-samples" => settings.progressive.get_or_insert_with( ProgressiveSettings::default ).batch_samples = parse_count( &value()? )?,
+        "--target-noise" => settings.progressive.get_or_insert_with( ProgressiveSettings::default ).target_noise = parse_float( &value()? )?,
+        "--time-budget" => settings.progressive.get_or_insert_with( ProgressiveSettings::default ).time_budget = Some( parse_float( &value()? )? ),
+        "--tile-size" => settings.tiling.tile_size = Some( parse_count( &value()? )? ),
+        "--submit-budget" => settings.tiling.samples_per_submit = Some( parse_count( &value()? )? as u64 * 1_000_000 ),
+        "--cube-size" => settings.cube_size = parse_count( &value()? )?,
+        "--cube-resample" => settings.cube_resample = parse_cube_resample( &value()? )?,
+        "--cube-samples" => settings.cube_samples = parse_count( &value()? )?,
+        "--mip-filter" => settings.mip_filter = parse_mip_filter( &value()? )?,
+        "--diffuse-size" => ( settings.diffuse_width, settings.diffuse_height ) = parse_extent( &value()? )?,
+        "--specular-size" => ( settings.specular_1_width, settings.specular_1_height ) = parse_extent( &value()? )?,
+        "--lut-size" => ( settings.specular_2_width, settings.specular_2_height ) = parse_extent( &value()? )?,
+        "--diffuse-samples" => settings.diffuse_samples = parse_count( &value()? )?,
+        "--specular-samples" => settings.specular_samples = parse_count( &value()? )?,
+        "--lut-samples" => settings.lut_samples = parse_count( &value()? )?,
+        "--diffuse-format" => settings.diffuse_format = parse_texture_format( &value()?, COLOR_FORMATS )?,
+        "--specular-format" => settings.specular_format = parse_texture_format( &value()?, COLOR_FORMATS )?,
+        "--lut-format" => settings.lut_format = parse_texture_format( &value()?, LUT_FORMATS )?,
+        "--layout" => settings.layout = parse_layout( &value()? )?,
+        "--format" => format = Some( value()? ),
+        "--range" => range = Some( parse_float( &value()? )? ),
+        "--specular-cube-size" => settings.specular_cube_size = Some( parse_count( &value()? )? ),
+        "--gltf" => { gltf.get_or_insert_with( GltfSettings::default ); },
+        "--gltf-intensity" => gltf.get_or_insert_with( GltfSettings::default ).intensity = parse_float( &value()? )?,
+        "--gltf-rotation" => gltf.get_or_insert_with( GltfSettings::default ).rotation = parse_float( &value()? )?,
+        "--equirect-size" => settings.equirect_size = Some( parse_equirect_size( &value()? )? ),
+        "--env-cube" => settings.environment_cube.get_or_insert_with( EnvironmentCubeSettings::default ).container = parse_cube_container( &value()? )?,
+        "--env-cube-mips" => settings.environment_cube.get_or_insert_with( EnvironmentCubeSettings::default ).mips = true,
+        "--sky" => sky_model = Some( parse_sky_model( &value()? )? ),
+        "--time-of-day" => ( sky.sun_elevation, sky.sun_azimuth ) = parse_time_of_day( &value()? )?.sun(),
+        "--sun-elevation" => sky.sun_elevation = parse_float( &value()? )?,
+        "--sun-azimuth" => sky.sun_azimuth = parse_float( &value()? )?,
+        "--turbidity" => sky.turbidity = parse_float( &value()? )?,
+        "--ground-albedo" => sky.ground_albedo = parse_float( &value()? )?,
+        "--hosek-data" => sky.hosek_data = Some( PathBuf::from( value()? ) ),
+        _ if arg.starts_with( '-' ) => bail!( "Unknown option {}", arg ),
+        _ => positional.push( arg )
+      }
+    }
+
+    settings.output_format = parse_format( format.as_deref().unwrap_or( "hdr" ), range )?;
+
+    if settings.layout == OutputLayout::Octahedral
+    && ( settings.diffuse_width != settings.diffuse_height || settings.specular_1_width != settings.specular_1_height )
+    {
+      bail!( "The octahedral layout needs square diffuse and specular maps" );
+    }
+
+    if settings.progressive.is_some()
+    {
+      if settings.cpu
+      {
+        bail!( "--progressive refines on the GPU, it can't be used with --cpu" );
+      }
+      settings.compute = true;
+    }
+    if settings.cpu && command == "view"
+    {
+      bail!( "The viewer renders on the GPU, --cpu only applies to bake and batch" );
+    }
+
+    if bless && command != "golden"
+    {
+      bail!( "--bless only applies to golden" );
+    }
+
+    if gltf.is_some()
+    {
+      settings.gltf = gltf;
+      settings.specular_cube_size.get_or_insert( 256 );
+    }
+
+    let sky = match sky_model
+    {
+      Some( model ) => Some( check_sky( SkySettings { model, ..sky } )? ),
+      None if sky != SkySettings::default() => bail!( "The sky options need --sky" ),
+      None => None
+    };
+    if sky.is_some()
+    {
+      if command == "batch"
+      {
+        bail!( "--sky can't be used with batch" );
+      }
+      if !positional.is_empty()
+      {
+        bail!( "--sky takes the place of the input" );
+      }
+    }
+
+    let mut positional = positional.into_iter();
+    let mut input = | | positional.next().context( "Missing input" );
+    let source = | path : anyhow::Result< String > | match &sky
+    {
+      Some( sky ) => Ok( Input::Sky( sky.clone() ) ),
+      None => path.map( | path | Input::File( PathBuf::from( path ) ) )
+    };
+    let output_or = | default : &str | output.clone().unwrap_or_else( || PathBuf::from( default ) );
+    let command = match command.as_str()
+    {
+      "bake" => Command::Bake { input : source( input() )?, output : output_or( "result" ) },
+      "batch" => Command::Batch { pattern : input()?, output : output_or( "result" ) },
+      "view" => Command::View { input : source( input() )? },
+      "golden" => Command::Golden
+      {
+        dir : input().map( PathBuf::from ).unwrap_or_else( | _ | PathBuf::from( golden::GOLDEN_DIR ) ),
+        diff_dir : output_or( golden::DIFF_DIR ),
+        bless
+      },
+      "compare" => Command::Compare { a : PathBuf::from( input()? ), b : PathBuf::from( input()? ), diff_dir : output },
+      "inspect" => Command::Inspect { input : source( input() )? },
+      "cache-clean" => Command::CacheClean { dir : input().map( PathBuf::from ).unwrap_or_else( | _ | output_or( "result" ) ) },
+      _ => bail!( "Unknown command {}", command )
+    };
+
+    Ok( Self { command, settings, force, profile } )
+  }
+}
+
+fn parse_count( value : &str ) -> anyhow::Result< u32 >
+{
+  let size = value.parse::< u32 >().with_context( || format!( "Invalid number {}", value ) )?;
+  if size == 0
+  {
+    bail!( "Expected a number greater than zero, got {}", value );
+  }
+  Ok( size )
+}
+
+fn parse_float( value : &str ) -> anyhow::Result< f32 >
+{
+  value.parse::< f32 >().ok().filter( | v | v.is_finite() ).with_context( || format!( "Invalid number {}", value ) )
+}
+
+/// Formats of the environment maps, which need the three color channels.
+const COLOR_FORMATS : &[ ( &str, wgpu::TextureFormat ) ] = &
+[
+  ( "rgba32f", wgpu::TextureFormat::Rgba32Float ),
+  ( "rgba16f", wgpu::TextureFormat::Rgba16Float ),
+  ( "rg11b10f", wgpu::TextureFormat::Rg11b10Ufloat )
+];
+
+/// The BRDF LUT only has two channels.
+const LUT_FORMATS : &[ ( &str, wgpu::TextureFormat ) ] = &
+[
+  ( "rgba32f", wgpu::TextureFormat::Rgba32Float ),
+  ( "rgba16f", wgpu::TextureFormat::Rgba16Float ),
+  ( "rg16f", wgpu::TextureFormat::Rg16Float )
+];
+
+fn parse_texture_format( value : &str, formats : &[ ( &str, wgpu::TextureFormat ) ] ) -> anyhow::Result< wgpu::TextureFormat >
+{
+  match formats.iter().find( | ( name, _ ) | *name == value )
+  {
+    Some( ( _, format ) ) => Ok( *format ),
+    None => bail!
+    (
+      "Unknown format {}, expected one of {}",
+      value,
+      formats.iter().map( | ( name, _ ) | *name ).collect::< Vec< _ > >().join( ", " )
+    )
+  }
+}
+
+/// The range is only allowed with RGBM and RGBD, RGBE has a shared exponent instead.
+fn parse_format( value : &str, range : Option< f32 > ) -> anyhow::Result< OutputFormat >
+{
+  let packing = match value
+  {
+    "hdr" | "exr" if range.is_some() => bail!( "--range only applies to the PNG formats" ),
+    "rgbe" if range.is_some() => bail!( "--range doesn't apply to rgbe, its shared exponent covers any range" ),
+    "hdr" => return Ok( OutputFormat::Hdr ),
+    "exr" => return Ok( OutputFormat::Exr ),
+    "rgbm" => Packing::Rgbm,
+    "rgbd" => Packing::Rgbd,
+    "rgbe" => Packing::Rgbe,
+    _ => bail!( "Unknown format {}, expected hdr, exr, rgbm, rgbd or rgbe", value )
+  };
+
+  let range = range.unwrap_or( packing.default_range() );
+  if range <= 0.0
+  {
+    bail!( "Expected a range greater than zero, got {}", range );
+  }
+  Ok( OutputFormat::Png { packing, range } )
+}
+
+fn parse_layout( value : &str ) -> anyhow::Result< OutputLayout >
+{
+  match value
+  {
+    "equirect" => Ok( OutputLayout::Equirect ),
+    "octahedral" => Ok( OutputLayout::Octahedral ),
+    _ => bail!( "Unknown layout {}, expected equirect or octahedral", value )
+  }
+}
+
+/// Parses either `<n>` for a square or `<width>x<height>`.
+fn parse_extent( value : &str ) -> anyhow::Result< ( u32, u32 ) >
+{
+  match value.split_once( 'x' )
+  {
+    Some( ( width, height ) ) => Ok( ( parse_count( width )?, parse_count( height )? ) ),
+    None => parse_count( value ).map( | size | ( size, size ) )
+  }
+}
+
+fn parse_cube_resample( value : &str ) -> anyhow::Result< CubeResample >
+{
+  [ CubeResample::Bilinear, CubeResample::Supersample, CubeResample::Area ].into_iter()
+  .find( | resample | resample.name() == value )
+  .with_context( || format!( "Unknown cube resampling {}, expected bilinear, supersample or area", value ) )
+}
+
+/// Parses either `<width>`, for an image half as high, or `<width>x<height>`.
+fn parse_equirect_size( value : &str ) -> anyhow::Result< ( u32, u32 ) >
+{
+  if value.contains( 'x' )
+  {
+    return parse_extent( value );
+  }
+  parse_count( value ).map( | width | ( width, ( width / 2 ).max( 1 ) ) )
+}
+
+fn parse_mip_filter( value : &str ) -> anyhow::Result< MipFilter >
+{
+  [ MipFilter::Box, MipFilter::Kaiser, MipFilter::SolidAngle ].into_iter()
+  .find( | filter | filter.name() == value )
+  .with_context( || format!( "Unknown mip filter {}, expected box, kaiser or solid-angle", value ) )
+}
+
+fn parse_cube_container( value : &str ) -> anyhow::Result< CubeContainer >
+{
+  [ CubeContainer::Faces, CubeContainer::Cross, CubeContainer::Ktx2, CubeContainer::Dds ].into_iter()
+  .find( | container | container.name() == value )
+  .with_context( || format!( "Unknown cube container {}, expected faces, cross, ktx2 or dds", value ) )
+}
+
+fn parse_sky_model( value : &str ) -> anyhow::Result< SkyModel >
+{
+  [ SkyModel::Preetham, SkyModel::HosekWilkie, SkyModel::Gradient ].into_iter()
+  .find( | model | model.name() == value )
+  .with_context( || format!( "Unknown sky {}, expected preetham, hosek-wilkie or gradient", value ) )
+}
+
+fn parse_time_of_day( value : &str ) -> anyhow::Result< TimeOfDay >
+{
+  [ TimeOfDay::Sunrise, TimeOfDay::Morning, TimeOfDay::Noon, TimeOfDay::Afternoon, TimeOfDay::Sunset ].into_iter()
+  .find( | time | time.name() == value )
+  .with_context( || format!( "Unknown time of day {}, expected sunrise, morning, noon, afternoon or sunset", value ) )
+}
+
+fn check_sky( sky : SkySettings ) -> anyhow::Result< SkySettings >
+{
+  if !( -90.0..=90.0 ).contains( &sky.sun_elevation )
+  {
+    bail!( "Expected a sun elevation from -90 to 90 degrees, got {}", sky.sun_elevation );
+  }
+  if !( 1.0..=10.0 ).contains( &sky.turbidity )
+  {
+    bail!( "Expected a turbidity from 1 to 10, got {}", sky.turbidity );
+  }
+  if !( 0.0..=1.0 ).contains( &sky.ground_albedo )
+  {
+    bail!( "Expected a ground albedo from 0 to 1, got {}", sky.ground_albedo );
+  }
+  if sky.model == SkyModel::HosekWilkie && sky.hosek_data.is_none()
+  {
+    bail!( "The Hosek-Wilkie sky needs --hosek-data" );
+  }
+  Ok( sky )
+}
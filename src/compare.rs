@@ -0,0 +1,362 @@
+use std::{f32::consts::PI, path::{Path, PathBuf}};
+
+use anyhow::Context;
+use glam::{Vec2, Vec3};
+
+use crate::{baker::CubeContainer, cube_file, image_diff::{self, ImageDiff}, manifest::{ImageEntry, Manifest, MANIFEST_FILE}, output::OutputImage};
+
+/// Relative error shown in full red by the difference images.
+const HEATMAP_SCALE : f32 = 0.1;
+
+const FACE_NAMES : [ &str; 6 ] = [ "+X", "-X", "+Y", "-Y", "+Z", "-Z" ];
+
+/// How the texels of a compared output map to directions, which the energy is integrated over.
+#[ derive( Clone, Copy, Debug, PartialEq ) ]
+enum Mapping
+{
+  Equirect,
+  Octahedral { border : u32 },
+  /// The six faces of a cube mip stacked from top to bottom, in the manifest face order
+  Cube,
+  /// Not a map of directions
+  Lut
+}
+
+/// One output or mip of a bake.
+struct Output
+{
+  name : String,
+  mapping : Mapping,
+  image : OutputImage
+}
+
+/// Statistics of one output on its own.
+#[ derive( Clone, Copy, Debug ) ]
+pub struct OutputStats
+{
+  pub width : u32,
+  pub height : u32,
+  pub mean_luminance : f32,
+  /// Luminance integrated over the sphere, `None` for the LUT
+  pub energy : Option< f32 >
+}
+
+/// An output of either bake, with the differences of `b` from `a` when both have it at the same size.
+pub struct OutputComparison
+{
+  pub name : String,
+  pub a : Option< OutputStats >,
+  pub b : Option< OutputStats >,
+  pub diff : Option< ImageDiff >,
+  /// Texel of the largest relative error, with the face of the cubes
+  pub max_error_at : Option< String >
+}
+
+/// Compares every output and mip of the bakes at `a` and `b`, output directories or manifests. The relative
+/// errors are relative to `a`, and are written as false color images to `diff_dir` when it is set.
+pub fn compare( a : &Path, b : &Path, diff_dir : Option< &Path > ) -> anyhow::Result< Vec< OutputComparison > >
+{
+  let ( outputs_a, outputs_b ) = ( load_bake( a )?, load_bake( b )? );
+  if let Some( dir ) = diff_dir
+  {
+    std::fs::create_dir_all( dir ).with_context( || format!( "Failed to create {}", dir.display() ) )?;
+  }
+
+  let mut names : Vec< &str > = outputs_a.iter().map( | output | output.name.as_str() ).collect();
+  names.extend( outputs_b.iter().map( | output | output.name.as_str() ).filter( | name | !outputs_a.iter().any( | output | output.name == *name ) ) );
+
+  names.into_iter().map( | name |
+  {
+    let ( a, b ) = ( outputs_a.iter().find( | output | output.name == name ), outputs_b.iter().find( | output | output.name == name ) );
+    let same_size = a.zip( b ).filter( | ( a, b ) | ( a.image.width, a.image.height ) == ( b.image.width, b.image.height ) );
+
+    let diff = same_size.map( | ( a, b ) | ImageDiff::new( &b.image, &a.image ) ).transpose()?;
+    if let Some( ( ( a, b ), dir ) ) = same_size.zip( diff_dir )
+    {
+      image_diff::save_heatmap( &dir.join( format!( "{}.png", name ) ), &b.image, &a.image, HEATMAP_SCALE )?;
+    }
+
+    Ok( OutputComparison
+    {
+      name : name.into(),
+      a : a.map( stats ),
+      b : b.map( stats ),
+      diff,
+      max_error_at : diff.zip( a ).map( | ( diff, a ) | texel_name( a, diff.max_error_at ) )
+    })
+  })
+  .collect()
+}
+
+/// Prints one line per output, `a` and `b` stand for the first and second bake.
+pub fn print( comparisons : &[ OutputComparison ] )
+{
+  println!
+  (
+    "{:<22} {:>15} {:>10} {:>8} {:>10} {:>10} {:>10} {:>10} {:>9}  at",
+    "output", "size", "rmse", "psnr", "mean a", "mean b", "energy a", "energy b", "max rel"
+  );
+  let number = | value : Option< f32 > | value.map_or( "-".into(), | value | format!( "{:.4}", value ) );
+  for comparison in comparisons
+  {
+    let size = match ( comparison.a, comparison.b )
+    {
+      ( Some( a ), Some( b ) ) if ( a.width, a.height ) == ( b.width, b.height ) => format!( "{}x{}", a.width, a.height ),
+      ( Some( a ), Some( b ) ) => format!( "{}x{}/{}x{}", a.width, a.height, b.width, b.height ),
+      ( Some( a ), None ) => format!( "{}x{}/-", a.width, a.height ),
+      ( None, Some( b ) ) => format!( "-/{}x{}", b.width, b.height ),
+      ( None, None ) => String::new()
+    };
+    println!
+    (
+      "{:<22} {:>15} {:>10} {:>8} {:>10} {:>10} {:>10} {:>10} {:>9}  {}",
+      comparison.name,
+      size,
+      comparison.diff.map_or( "-".into(), | diff | format!( "{:.3e}", diff.rmse ) ),
+      comparison.diff.map_or( "-".into(), | diff | format!( "{:.1}", diff.psnr ) ),
+      number( comparison.a.map( | a | a.mean_luminance ) ),
+      number( comparison.b.map( | b | b.mean_luminance ) ),
+      number( comparison.a.and_then( | a | a.energy ) ),
+      number( comparison.b.and_then( | b | b.energy ) ),
+      number( comparison.diff.map( | diff | diff.max_relative_error ) ),
+      comparison.max_error_at.as_deref().unwrap_or( "-" )
+    );
+  }
+}
+
+/// Reads every image the manifest at `path`, or in the directory `path`, lists.
+fn load_bake( path : &Path ) -> anyhow::Result< Vec< Output > >
+{
+  let ( manifest_path, dir ) = match path.is_dir()
+  {
+    true => ( path.join( MANIFEST_FILE ), path.to_owned() ),
+    false => ( path.to_owned(), path.parent().map_or_else( PathBuf::new, Path::to_owned ) )
+  };
+  let manifest = Manifest::read( &manifest_path )?;
+
+  let image = | name : String, entry : &ImageEntry | -> anyhow::Result< Output >
+  {
+    let mapping = match entry.layout.as_str()
+    {
+      "equirect" => Mapping::Equirect,
+      "octahedral" => Mapping::Octahedral { border : entry.border },
+      _ => Mapping::Lut
+    };
+    Ok( Output { name, mapping, image : entry.load( &dir )? } )
+  };
+  let cube = | name : String, faces : &[ OutputImage ] | Output { name, mapping : Mapping::Cube, image : stack( faces ) };
+  let load_faces = | entries : &[ ImageEntry ] | entries.iter().map( | entry | entry.load( &dir ) ).collect::< anyhow::Result< Vec< _ > > >();
+
+  let mut outputs = vec![ image( "diffuse".into(), &manifest.diffuse )? ];
+  for mip in &manifest.specular.mips
+  {
+    outputs.push( image( format!( "specular_{}", mip.mip ), &mip.image )? );
+  }
+  outputs.push( image( "brdf_lut".into(), &manifest.brdf_lut.image )? );
+  for ( mip, faces ) in manifest.specular_cube.iter().flat_map( | cube | cube.mips.iter().enumerate() )
+  {
+    outputs.push( cube( format!( "specular_cube_{}", mip ), &load_faces( faces )? ) );
+  }
+  if let Some( environment_cube ) = &manifest.environment_cube
+  {
+    match &environment_cube.file
+    {
+      Some( file ) =>
+      {
+        let container = if environment_cube.container == CubeContainer::Dds.name() { CubeContainer::Dds } else { CubeContainer::Ktx2 };
+        for ( mip, faces ) in cube_file::load( &dir.join( &file.path ), container, "environment_cube" )?.iter().enumerate()
+        {
+          outputs.push( cube( format!( "environment_cube_{}", mip ), faces ) );
+        }
+      },
+      None => for ( mip, entries ) in environment_cube.mips.iter().enumerate()
+      {
+        let faces = match entries.as_slice()
+        {
+          [ cross ] => cube_file::split_cross( &cross.load( &dir )?, "environment_cube" ).to_vec(),
+          faces => load_faces( faces )?
+        };
+        outputs.push( cube( format!( "environment_cube_{}", mip ), &faces ) );
+      }
+    }
+  }
+  if let Some( environment ) = &manifest.environment
+  {
+    outputs.push( image( "environment".into(), environment )? );
+  }
+  Ok( outputs )
+}
+
+/// The faces one above the other.
+fn stack( faces : &[ OutputImage ] ) -> OutputImage
+{
+  let ( width, height ) = ( faces[ 0 ].width, faces[ 0 ].height );
+  OutputImage::new( "cube", width, height * faces.len() as u32, faces.iter().flat_map( | face | face.data.iter().copied() ).collect() )
+}
+
+fn stats( output : &Output ) -> OutputStats
+{
+  let image = &output.image;
+  let luminance = image.data.chunks_exact( 3 ).map( | rgb | image_diff::luminance( [ rgb[ 0 ], rgb[ 1 ], rgb[ 2 ] ] ) as f64 );
+  let energy = ( output.mapping != Mapping::Lut ).then( ||
+  {
+    luminance.clone().enumerate().map( | ( i, luminance ) |
+    {
+      luminance * solid_angle( output.mapping, image.width, image.height, i as u32 % image.width, i as u32 / image.width ) as f64
+    })
+    .sum::< f64 >() as f32
+  });
+
+  OutputStats
+  {
+    width : image.width,
+    height : if output.mapping == Mapping::Cube { image.width } else { image.height },
+    mean_luminance : ( luminance.sum::< f64 >() / ( image.width * image.height ).max( 1 ) as f64 ) as f32,
+    energy
+  }
+}
+
+/// Solid angle the texel at `x`, `y` covers. The texels of all the mappings add up to the whole sphere,
+/// the duplicated border of the octahedral maps is left out. The last octahedral mips, no wider than
+/// their border, have no texel left then.
+fn solid_angle( mapping : Mapping, width : u32, height : u32, x : u32, y : u32 ) -> f32
+{
+  match mapping
+  {
+    Mapping::Equirect =>
+    {
+      let latitude = ( 0.5 - ( y as f32 + 0.5 ) / height as f32 ) * PI;
+      latitude.cos() * ( 2.0 * PI / width as f32 ) * ( PI / height as f32 )
+    },
+    Mapping::Octahedral { border } =>
+    {
+      let size = width.saturating_sub( 2 * border );
+      let ( x, y ) = ( x.wrapping_sub( border ), y.wrapping_sub( border ) );
+      if x >= size || y >= size
+      {
+        return 0.0;
+      }
+      // Point of the octahedron |x| + |y| + |z| = 1 the texel maps to, the lower half folded over the corners.
+      // Its solid angle is the area of the texel over the cube of the distance to the point
+      let uv = ( Vec2::new( x as f32, y as f32 ) + 0.5 ) / size as f32 * 2.0 - 1.0;
+      let mut point = Vec3::new( uv.x, 1.0 - uv.x.abs() - uv.y.abs(), uv.y );
+      if point.y < 0.0
+      {
+        point = Vec3::new( ( 1.0 - uv.y.abs() ) * uv.x.signum(), point.y, ( 1.0 - uv.x.abs() ) * uv.y.signum() );
+      }
+      ( 2.0 / size as f32 ).powi( 2 ) / point.length().powi( 3 )
+    },
+    Mapping::Cube =>
+    {
+      let uv = ( Vec2::new( x as f32, ( y % width ) as f32 ) + 0.5 ) / width as f32 * 2.0 - 1.0;
+      ( 2.0 / width as f32 ).powi( 2 ) / ( 1.0 + uv.length_squared() ).powf( 1.5 )
+    },
+    Mapping::Lut => 0.0
+  }
+}
+
+fn texel_name( output : &Output, ( x, y ) : ( u32, u32 ) ) -> String
+{
+  match output.mapping
+  {
+    Mapping::Cube => format!( "{} ({}, {})", FACE_NAMES[ ( y / output.image.width ) as usize ], x, y % output.image.width ),
+    _ => format!( "({}, {})", x, y )
+  }
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+  use crate::{baker::{BakeOutputs, BakeSettings, EnvironmentCubeSettings, OutputLayout, SourceImage}, batch, cpu_baker, manifest::SourceEntry};
+
+  /// Writes `outputs` to `dir` with their manifest, like a batch job.
+  fn save( dir : &Path, settings : &BakeSettings, outputs : &BakeOutputs )
+  {
+    let source = SourceEntry { path : "source.hdr".into(), hash : String::new(), width : 32, height : 16, sky : None };
+    batch::save_job( dir, source, String::new(), settings, outputs, None ).unwrap();
+  }
+
+  #[ test ]
+  fn saved_bakes_pair_up()
+  {
+    let dir = std::env::temp_dir().join( format!( "IBLConverter-compare-{}", std::process::id() ) );
+    let pixels = ( 0..32 * 16 ).flat_map( | i | [ 1.0 + ( i % 32 ) as f32 / 8.0, 1.0, 0.5 + ( i / 32 ) as f32 / 4.0, 1.0 ] ).collect();
+    let source = SourceImage { width : 32, height : 16, pixels };
+
+    // The octahedral maps have a border, and the environment cube is read from a KTX2, DDS or cross
+    for ( layout, container ) in [ ( OutputLayout::Octahedral, CubeContainer::Ktx2 ), ( OutputLayout::Equirect, CubeContainer::Dds ), ( OutputLayout::Equirect, CubeContainer::Cross ) ]
+    {
+      let settings = BakeSettings
+      {
+        cube_size : 16,
+        diffuse_width : 16,
+        diffuse_height : 16,
+        specular_1_width : 16,
+        specular_1_height : 16,
+        specular_2_width : 8,
+        specular_2_height : 8,
+        diffuse_samples : 8,
+        specular_samples : 32,
+        lut_samples : 32,
+        layout,
+        specular_cube_size : Some( 4 ),
+        equirect_size : Some( ( 16, 8 ) ),
+        environment_cube : Some( EnvironmentCubeSettings { container, mips : true } ),
+        ..BakeSettings::default()
+      };
+      // Fewer samples, and a smaller irradiance map that can't be compared texel by texel
+      let other_settings = BakeSettings { specular_samples : 8, diffuse_width : 8, diffuse_height : 8, ..settings };
+      let outputs = cpu_baker::bake( &source, &settings );
+      let ( a, copy, b ) = ( dir.join( "a" ), dir.join( "copy" ), dir.join( "b" ) );
+      save( &a, &settings, &outputs );
+      save( &copy, &settings, &outputs );
+      save( &b, &other_settings, &cpu_baker::bake( &source, &other_settings ) );
+
+      let mut expected = vec![ "diffuse".to_string() ];
+      expected.extend( ( 0..outputs.specular.len() ).map( | mip | format!( "specular_{}", mip ) ) );
+      expected.push( "brdf_lut".into() );
+      expected.extend( ( 0..outputs.specular_cube.as_ref().unwrap().len() ).map( | mip | format!( "specular_cube_{}", mip ) ) );
+      expected.extend( ( 0..outputs.environment_cube.as_ref().unwrap().len() ).map( | mip | format!( "environment_cube_{}", mip ) ) );
+      expected.push( "environment".into() );
+
+      let identical = compare( &a, &copy, None ).unwrap();
+      assert_eq!( identical.iter().map( | c | c.name.clone() ).collect::< Vec< _ > >(), expected, "{}", container.name() );
+      for comparison in &identical
+      {
+        let diff = comparison.diff.expect( "Identical outputs aren't compared" );
+        assert!( diff.psnr.is_infinite() && diff.rmse == 0.0, "{} of {}: {:?}", comparison.name, container.name(), diff.psnr );
+      }
+
+      let different = compare( &a, &b, None ).unwrap();
+      assert_eq!( different.iter().map( | c | c.name.clone() ).collect::< Vec< _ > >(), expected, "{}", container.name() );
+      for comparison in &different
+      {
+        let ( stats_a, stats_b ) = ( comparison.a.unwrap(), comparison.b.unwrap() );
+        match comparison.name.as_str()
+        {
+          "diffuse" =>
+          {
+            assert_eq!( ( stats_a.width, stats_b.width ), ( 16, 8 ) );
+            assert!( comparison.diff.is_none() && comparison.max_error_at.is_none() );
+          },
+          // The first mip is a mirror whatever the samples, and the last octahedral ones are all border
+          "specular_1" => assert!( comparison.diff.unwrap().psnr.is_finite(), "specular_1 of {} is identical with fewer samples", container.name() ),
+          _ => assert!( comparison.diff.is_some(), "{} of {}", comparison.name, container.name() )
+        }
+      }
+      std::fs::remove_dir_all( &dir ).unwrap();
+    }
+  }
+
+  #[ test ]
+  fn solid_angles_cover_the_sphere()
+  {
+    let mappings = [ ( Mapping::Equirect, 64, 32 ), ( Mapping::Octahedral { border : 1 }, 66, 66 ), ( Mapping::Cube, 16, 96 ) ];
+    for ( mapping, width, height ) in mappings
+    {
+      let total = ( 0..width * height ).map( | i | solid_angle( mapping, width, height, i % width, i / width ) ).sum::< f32 >();
+      assert!( ( total - 4.0 * PI ).abs() < 4.0 * PI * 0.002, "{:?}: {}", mapping, total );
+    }
+  }
+}
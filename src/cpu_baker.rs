@@ -0,0 +1,964 @@
+use std::{f32::consts::PI, sync::Mutex, thread};
+
+use glam::{IVec2, Mat3, Vec2, Vec3};
+
+use crate::{baker::{BakeOutputs, BakeSettings, CubeResample, MipFilter, OutputLayout, SourceImage}, ibl_compute, ibl_renderer, output::OutputImage, sky::SkyUniform};
+
+/// `tangent_normalizer` in `cube_map.wgsl`, rounded the same way so the lookups land on the same texels
+#[ allow( clippy::approx_constant ) ]
+const TANGENT_NORMALIZER : Vec2 = Vec2::new( 0.15915, 0.3183 );
+/// `MAX_AREA_TEXELS` in `cube_map.wgsl`
+const MAX_AREA_TEXELS : f32 = 16.0;
+/// `KAISER_WEIGHTS` in `mipmap.wgsl`
+const KAISER_WEIGHTS : [ f32; 4 ] = [ 0.054027, 0.445973, 0.445973, 0.054027 ];
+/// `OCTAHEDRAL_BORDER` in `ibl.wgsl`
+const OCTAHEDRAL_BORDER : f32 = 1.0;
+/// Most prefiltered mips of the specular map and cube, as in [`ibl_renderer::IBLRenderer`]
+const SPECULAR_MIPS : u32 = 5;
+
+/// Bakes `source` on the CPU with the same math as the shaders: the conversion of `cube_map.wgsl`,
+/// the mips of `mipmap.wgsl`, the passes of `ibl_compute.wgsl` and the equirect of `equirect.wgsl`.
+/// The results match a GPU bake with `compute` set within the precision of the sampler.
+///
+/// It is the reference the GPU outputs are tested against, and the backend of machines without an adapter.
+/// The outputs are left in 32-bit floats whatever the GPU formats of `settings`, and its tiling and
+/// progressive refinement don't apply.
+pub fn bake( source : &SourceImage, settings : &BakeSettings ) -> BakeOutputs
+{
+  bake_cube( EnvironmentCube::new( source, settings ), settings )
+}
+
+/// Bakes `sky` like [`bake`] does an image, with the environment cube rendered like `sky.wgsl` does.
+pub fn bake_sky( sky : &SkyUniform, settings : &BakeSettings ) -> BakeOutputs
+{
+  bake_cube( EnvironmentCube::from_sky( sky, settings ), settings )
+}
+
+fn bake_cube( cube : EnvironmentCube, settings : &BakeSettings ) -> BakeOutputs
+{
+  let ( width, height ) = ( settings.diffuse_width, settings.diffuse_height );
+  let diffuse = render( width, height, | x, y | irradiance( &cube, output_direction( x, y, width, height, settings.layout ), settings.diffuse_samples ) );
+  let diffuse = OutputImage::new( "diffuse", width, height, to_rgb( &diffuse ) );
+
+  let total_mips = mip_count( settings.specular_1_width.max( settings.specular_1_height ) ).min( SPECULAR_MIPS );
+  let specular = ( 0..total_mips ).map( | mip_level |
+  {
+    let ( width, height ) = ( ( settings.specular_1_width >> mip_level ).max( 1 ), ( settings.specular_1_height >> mip_level ).max( 1 ) );
+    let roughness = ibl_renderer::mip_roughness( mip_level, total_mips );
+    let texels = render( width, height, | x, y | prefilter( &cube, output_direction( x, y, width, height, settings.layout ), roughness, settings.specular_samples ) );
+    OutputImage::new( format!( "specular_1_{}", mip_level ), width, height, to_rgb( &texels ) )
+  })
+  .collect();
+
+  let ( width, height ) = ( settings.specular_2_width, settings.specular_2_height );
+  let brdf_lut = render( width, height, | x, y |
+  {
+    let uv = ( Vec2::new( x as f32, y as f32 ) + 0.5 ) / Vec2::new( width as f32, height as f32 );
+    integrate_brdf( uv.x, uv.y, settings.lut_samples )
+  });
+  let brdf_lut = OutputImage::new( "specular_2", width, height, to_rgb( &brdf_lut ) );
+
+  let specular_cube = settings.specular_cube_size.map( | face_size |
+  {
+    let total_mips = mip_count( face_size ).min( SPECULAR_MIPS );
+    ( 0..total_mips ).map( | mip_level |
+    {
+      let size = ( face_size >> mip_level ).max( 1 );
+      let roughness = ibl_renderer::mip_roughness( mip_level, total_mips );
+      std::array::from_fn( | face |
+      {
+        let texels = render( size, size, | x, y |
+        {
+          let uv = ( Vec2::new( x as f32, y as f32 ) + 0.5 ) / size as f32;
+          prefilter( &cube, face_direction( face, uv * 2.0 - 1.0 ).normalize(), roughness, settings.specular_samples )
+        });
+        OutputImage::new( format!( "specular_cube_{}_{}", mip_level, face ), size, size, to_rgb( &texels ) )
+      })
+    })
+    .collect()
+  });
+
+  let environment_cube = settings.environment_cube.map( | export |
+  {
+    let mips = if export.mips { cube.mips.len() } else { 1 };
+    cube.mips[ ..mips ].iter().enumerate().map( | ( mip_level, mip ) |
+    {
+      std::array::from_fn( | face | OutputImage::new( format!( "environment_cube_{}_{}", mip_level, face ), mip.size, mip.size, to_rgb( &mip.faces[ face ] ) ) )
+    })
+    .collect()
+  });
+
+  let environment = settings.equirect_size.map( | ( width, height ) |
+  {
+    OutputImage::new( "environment", width, height, to_rgb( &render( width, height, | x, y | equirect_texel( &cube, x, y, width, height ) ) ) )
+  });
+
+  BakeOutputs { diffuse, specular, brdf_lut, specular_cube, environment_cube, environment }
+}
+
+/// Evaluates `texel` at every texel of a `width` x `height` image. The rows are handed out to one thread per core.
+fn render( width : u32, height : u32, texel : impl Fn( u32, u32 ) -> Vec3 + Sync ) -> Vec< Vec3 >
+{
+  let mut texels = vec![ Vec3::ZERO; width as usize * height as usize ];
+  let rows = Mutex::new( texels.chunks_mut( width as usize ).enumerate() );
+  let num_threads = thread::available_parallelism().map_or( 1, | n | n.get() ).min( height as usize );
+  thread::scope( | scope |
+  {
+    for _ in 0..num_threads
+    {
+      scope.spawn( ||
+      {
+        while let Some( ( y, row ) ) = rows.lock().unwrap().next()
+        {
+          for ( x, value ) in row.iter_mut().enumerate()
+          {
+            *value = texel( x as u32, y as u32 );
+          }
+        }
+      });
+    }
+  });
+  texels
+}
+
+fn to_rgb( texels : &[ Vec3 ] ) -> Vec< f32 >
+{
+  texels.iter().flat_map( Vec3::to_array ).collect()
+}
+
+/// Mips of a texture whose largest side is `size`, down to 1x1.
+fn mip_count( size : u32 ) -> u32
+{
+  32 - size.leading_zeros()
+}
+
+/// One mip of the environment cube, the texels of every face row by row.
+struct CubeMip
+{
+  size : u32,
+  faces : [ Vec< Vec3 >; 6 ]
+}
+
+/// The environment cube and its mips, sampled like the cube sampler of the passes.
+struct EnvironmentCube
+{
+  mips : Vec< CubeMip >
+}
+
+impl EnvironmentCube
+{
+  fn new( source : &SourceImage, settings : &BakeSettings ) -> Self
+  {
+    let size = settings.cube_size;
+    let base = CubeMip
+    {
+      size,
+      faces : std::array::from_fn( | face | render( size, size, | x, y | convert_texel( source, face, x, y, size, settings.cube_resample, settings.cube_samples ) ) )
+    };
+    Self::with_mips( base, settings.mip_filter )
+  }
+
+  /// Mirrors `main` in `sky.wgsl`, which writes the faces with Y flipped.
+  fn from_sky( sky : &SkyUniform, settings : &BakeSettings ) -> Self
+  {
+    let size = settings.cube_size;
+    let base = CubeMip
+    {
+      size,
+      faces : std::array::from_fn( | face | render( size, size, | x, y |
+      {
+        let dir = texel_direction( face, ( Vec2::new( x as f32, y as f32 ) + 0.5 ) / size as f32 );
+        sky.radiance( dir * Vec3::new( 1.0, -1.0, 1.0 ), 2.0 / size as f32 )
+      }))
+    };
+    Self::with_mips( base, settings.mip_filter )
+  }
+
+  /// The cube of `base` with its mips down to 1x1.
+  fn with_mips( base : CubeMip, mip_filter : MipFilter ) -> Self
+  {
+    let count = mip_count( base.size ) as usize;
+    let mut mips = vec![ base ];
+    while mips.len() < count
+    {
+      let source = mips.last().unwrap();
+      // Like the dispatches of `CubeMipmapRenderer`, the box filters reduce up to four mips from the same source
+      let next = match mip_filter
+      {
+        MipFilter::Kaiser => vec![ source.downsample_kaiser() ],
+        filter => ( 1..=( count - mips.len() ).min( 4 ) ).map( | level | source.downsample_block( level as u32, filter == MipFilter::SolidAngle ) ).collect()
+      };
+      mips.extend( next );
+    }
+
+    Self { mips }
+  }
+
+  fn size( &self ) -> u32
+  {
+    self.mips[ 0 ].size
+  }
+
+  /// Trilinear sample in `dir` at `lod`, which is clamped to the mips.
+  fn sample( &self, dir : Vec3, lod : f32 ) -> Vec3
+  {
+    let lod = lod.clamp( 0.0, ( self.mips.len() - 1 ) as f32 );
+    let low = lod.floor() as usize;
+    let high = ( low + 1 ).min( self.mips.len() - 1 );
+    self.mips[ low ].sample( dir ).lerp( self.mips[ high ].sample( dir ), lod - low as f32 )
+  }
+}
+
+impl CubeMip
+{
+  fn texel( &self, face : usize, texel : IVec2 ) -> Vec3
+  {
+    self.faces[ face ][ ( texel.y as u32 * self.size + texel.x as u32 ) as usize ]
+  }
+
+  /// Mirrors `fetch` in `mipmap.wgsl`, texels past an edge of `face` are read on the neighboring face.
+  fn fetch( &self, face : usize, texel : IVec2 ) -> Vec3
+  {
+    let size = self.size as i32;
+    if texel.cmpge( IVec2::ZERO ).all() && texel.cmplt( IVec2::splat( size ) ).all()
+    {
+      return self.texel( face, texel );
+    }
+
+    let st = ( texel.as_vec2() + 0.5 ) / size as f32 * 2.0 - 1.0;
+    let ( neighbor, st ) = direction_face( face_direction( face, st ) );
+    let neighbor_texel = ( ( st * 0.5 + 0.5 ) * size as f32 ).floor().as_ivec2();
+    self.texel( neighbor, neighbor_texel.clamp( IVec2::ZERO, IVec2::splat( size - 1 ) ) )
+  }
+
+  /// Bilinear sample in `dir`, blending with the neighboring faces across the edges as a seamless cube sampler does.
+  fn sample( &self, dir : Vec3 ) -> Vec3
+  {
+    let ( face, st ) = direction_face( dir );
+    let position = ( st * 0.5 + 0.5 ) * self.size as f32 - 0.5;
+    let cell = position.floor();
+    let offset = position - cell;
+    let fetch = | x : i32, y : i32 | self.fetch( face, cell.as_ivec2() + IVec2::new( x, y ) );
+
+    fetch( 0, 0 ).lerp( fetch( 1, 0 ), offset.x ).lerp( fetch( 0, 1 ).lerp( fetch( 1, 1 ), offset.x ), offset.y )
+  }
+
+  /// Mip `level` below this one, every texel is the average of the block of texels it covers.
+  /// With `solid_angle` the texels are weighted like `texel_weight` in `mipmap.wgsl`.
+  fn downsample_block( &self, level : u32, solid_angle : bool ) -> Self
+  {
+    let size = self.size >> level;
+    let block = 1 << level;
+    let weight = | texel : IVec2 |
+    {
+      if !solid_angle
+      {
+        return 1.0;
+      }
+      let uv = ( texel.as_vec2() + 0.5 ) / self.size as f32 * 2.0 - 1.0;
+      let d = 1.0 + uv.dot( uv );
+      1.0 / ( d * d.sqrt() )
+    };
+
+    let faces = std::array::from_fn( | face | render( size, size, | x, y |
+    {
+      let ( mut sum, mut total ) = ( Vec3::ZERO, 0.0 );
+      for j in 0..block
+      {
+        for i in 0..block
+        {
+          let texel = IVec2::new( ( x * block + i ) as i32, ( y * block + j ) as i32 );
+          sum += self.texel( face, texel ) * weight( texel );
+          total += weight( texel );
+        }
+      }
+      sum / total
+    }));
+    Self { size, faces }
+  }
+
+  /// Mirrors `downsample_kaiser_main` in `mipmap.wgsl`.
+  fn downsample_kaiser( &self ) -> Self
+  {
+    let size = ( self.size / 2 ).max( 1 );
+    let faces = std::array::from_fn( | face | render( size, size, | x, y |
+    {
+      let origin = IVec2::new( x as i32, y as i32 ) * 2 - 1;
+      let mut sum = Vec3::ZERO;
+      for ( dy, weight_y ) in KAISER_WEIGHTS.iter().enumerate()
+      {
+        for ( dx, weight_x ) in KAISER_WEIGHTS.iter().enumerate()
+        {
+          sum += self.fetch( face, origin + IVec2::new( dx as i32, dy as i32 ) ) * weight_x * weight_y;
+        }
+      }
+      sum
+    }));
+    Self { size, faces }
+  }
+}
+
+/// Mirrors `face_direction` in `mipmap.wgsl` and `cube_direction` in `ibl.wgsl`, before normalization.
+fn face_direction( face : usize, st : Vec2 ) -> Vec3
+{
+  match face
+  {
+    0 => Vec3::new( 1.0, -st.y, -st.x ),
+    1 => Vec3::new( -1.0, -st.y, st.x ),
+    2 => Vec3::new( st.x, 1.0, st.y ),
+    3 => Vec3::new( st.x, -1.0, -st.y ),
+    4 => Vec3::new( st.x, -st.y, 1.0 ),
+    _ => Vec3::new( -st.x, -st.y, -1.0 )
+  }
+}
+
+/// Mirrors `direction_face` in `mipmap.wgsl`.
+fn direction_face( dir : Vec3 ) -> ( usize, Vec2 )
+{
+  let a = dir.abs();
+  if a.x >= a.y && a.x >= a.z
+  {
+    if dir.x > 0.0 { ( 0, Vec2::new( -dir.z, -dir.y ) / a.x ) } else { ( 1, Vec2::new( dir.z, -dir.y ) / a.x ) }
+  }
+  else if a.y >= a.z
+  {
+    if dir.y > 0.0 { ( 2, Vec2::new( dir.x, dir.z ) / a.y ) } else { ( 3, Vec2::new( dir.x, -dir.z ) / a.y ) }
+  }
+  else if dir.z > 0.0 { ( 4, Vec2::new( dir.x, -dir.y ) / a.z ) } else { ( 5, Vec2::new( -dir.x, -dir.y ) / a.z ) }
+}
+
+/// Texel of the environment cube converted from the source, as `main` in `cube_map.wgsl`.
+fn convert_texel( source : &SourceImage, face : usize, x : u32, y : u32, size : u32, resample : CubeResample, samples : u32 ) -> Vec3
+{
+  let texel = Vec2::new( x as f32, y as f32 );
+  match resample
+  {
+    CubeResample::Bilinear => sample_source( source, equirect_uv( texel_direction( face, ( texel + 0.5 ) / size as f32 ) ) ),
+    CubeResample::Supersample =>
+    {
+      let sum = ( 0..samples * samples ).map( | i |
+      {
+        let offset = ( Vec2::new( ( i % samples ) as f32, ( i / samples ) as f32 ) + 0.5 ) / samples as f32;
+        sample_source( source, equirect_uv( texel_direction( face, ( texel + offset ) / size as f32 ) ) )
+      })
+      .sum::< Vec3 >();
+      sum / ( samples * samples ) as f32
+    },
+    CubeResample::Area => sample_area( source, face, texel, size as f32 )
+  }
+}
+
+/// Mirrors `texel_direction` in `cube_map.wgsl`, whose faces are written with Y flipped.
+fn texel_direction( face : usize, face_uv : Vec2 ) -> Vec3
+{
+  // Forward, up and right of every face
+  const FACES : [ [ Vec3; 3 ]; 6 ] =
+  [
+    [ Vec3::X, Vec3::NEG_Z, Vec3::Y ],
+    [ Vec3::NEG_X, Vec3::Z, Vec3::Y ],
+    [ Vec3::NEG_Y, Vec3::X, Vec3::Z ],
+    [ Vec3::Y, Vec3::X, Vec3::NEG_Z ],
+    [ Vec3::Z, Vec3::X, Vec3::Y ],
+    [ Vec3::NEG_Z, Vec3::NEG_X, Vec3::Y ]
+  ];
+
+  let uv2 = face_uv * 2.0 - 1.0;
+  let [ forward, up, right ] = FACES[ face ];
+  ( forward + up * uv2.x + right * uv2.y ).normalize()
+}
+
+/// Mirrors `equirect_uv` in `cube_map.wgsl`.
+fn equirect_uv( dir : Vec3 ) -> Vec2
+{
+  let longitude = dir.y.asin();
+  let latitude = dir.z.atan2( dir.x );
+  Vec2::new( latitude, longitude ) * TANGENT_NORMALIZER + 0.5
+}
+
+/// WGSL `fract`, which is positive for negative values too.
+fn fract( value : f32 ) -> f32
+{
+  value - value.floor()
+}
+
+/// Mirrors `load_wrapped` in `cube_map.wgsl`.
+fn load_wrapped( source : &SourceImage, cell : IVec2 ) -> Vec3
+{
+  let x = cell.x.rem_euclid( source.width as i32 ) as usize;
+  let y = cell.y.clamp( 0, source.height as i32 - 1 ) as usize;
+  let index = ( y * source.width as usize + x ) * 4;
+  Vec3::from_slice( &source.pixels[ index..index + 3 ] )
+}
+
+/// Mirrors `sampleHDR` in `cube_map.wgsl`.
+fn sample_source( source : &SourceImage, uv : Vec2 ) -> Vec3
+{
+  let big_uv = uv * Vec2::new( source.width as f32, source.height as f32 ) - 0.5;
+  let cell = big_uv.floor().as_ivec2();
+  let offset = big_uv - big_uv.floor();
+  let load = | x : i32, y : i32 | load_wrapped( source, cell + IVec2::new( x, y ) );
+
+  load( 0, 0 ).lerp( load( 1, 0 ), offset.x ).lerp( load( 0, 1 ).lerp( load( 1, 1 ), offset.x ), offset.y )
+}
+
+/// Mirrors `sample_area` in `cube_map.wgsl`.
+fn sample_area( source : &SourceImage, face : usize, texel : Vec2, size : f32 ) -> Vec3
+{
+  let source_size = Vec2::new( source.width as f32, source.height as f32 );
+  let center = equirect_uv( texel_direction( face, ( texel + 0.5 ) / size ) );
+  let ( mut low, mut high ) = ( center, center );
+  for corner in 0..4
+  {
+    let uv = equirect_uv( texel_direction( face, ( texel + Vec2::new( ( corner & 1 ) as f32, ( corner >> 1 ) as f32 ) ) / size ) );
+    let u = center.x + fract( uv.x - center.x + 0.5 ) - 0.5;
+    low = low.min( Vec2::new( u, uv.y ) );
+    high = high.max( Vec2::new( u, uv.y ) );
+  }
+
+  let st = ( texel + 0.5 ) / size * 2.0 - 1.0;
+  if ( face == 2 || face == 3 ) && st.abs().cmple( Vec2::splat( 1.0 / size ) ).all()
+  {
+    low.x = center.x - 0.5;
+    high.x = center.x + 0.5;
+    if center.y < 0.5 { low.y = 0.0; } else { high.y = 1.0; }
+  }
+
+  low *= source_size;
+  high *= source_size;
+  if ( high - low ).cmple( Vec2::ONE ).all()
+  {
+    return sample_source( source, center );
+  }
+
+  let step = ( ( high - low ) / MAX_AREA_TEXELS ).ceil().max( Vec2::ONE );
+  let ( mut sum, mut total ) = ( Vec3::ZERO, 0.0 );
+  let mut y = low.y.floor();
+  while y < high.y
+  {
+    let weight_y = ( y + step.y ).min( high.y ) - y.max( low.y );
+    let mut x = low.x.floor();
+    while x < high.x
+    {
+      let weight = weight_y * ( ( x + step.x ).min( high.x ) - x.max( low.x ) );
+      sum += load_wrapped( source, ( Vec2::new( x, y ) + ( step * 0.5 ).floor() ).as_ivec2() ) * weight;
+      total += weight;
+      x += step.x;
+    }
+    y += step.y;
+  }
+  sum / total
+}
+
+/// Mirrors `texel_direction` in `ibl.wgsl` for the texel `x`, `y` of a `width` x `height` output.
+fn output_direction( x : u32, y : u32, width : u32, height : u32, layout : OutputLayout ) -> Vec3
+{
+  let pos = Vec2::new( x as f32, y as f32 ) + 0.5;
+  let size = Vec2::new( width as f32, height as f32 );
+  if layout == OutputLayout::Octahedral
+  {
+    return octahedral_direction( pos, size );
+  }
+
+  let mut uv = pos / size;
+  uv.y = 1.0 - uv.y;
+  let angles = ( uv * 2.0 - 1.0 ) * Vec2::new( PI, PI / 2.0 );
+  Vec3::new( angles.x.cos() * angles.y.cos(), angles.y.sin(), angles.x.sin() * angles.y.cos() ).normalize()
+}
+
+/// Mirrors `octahedral_direction` in `ibl.wgsl`.
+fn octahedral_direction( pos : Vec2, size : Vec2 ) -> Vec3
+{
+  let interior = size - 2.0 * OCTAHEDRAL_BORDER;
+  let mut texel = pos.floor() - OCTAHEDRAL_BORDER;
+  // WGSL `clamp`, which doesn't panic on the maps smaller than their border
+  let clamp = | value : f32, high : f32 | value.max( 0.0 ).min( high );
+  if texel.x < 0.0 || texel.x >= interior.x
+  {
+    texel = Vec2::new( clamp( texel.x, interior.x - 1.0 ), interior.y - 1.0 - texel.y );
+  }
+  if texel.y < 0.0 || texel.y >= interior.y
+  {
+    texel = Vec2::new( interior.x - 1.0 - texel.x, clamp( texel.y, interior.y - 1.0 ) );
+  }
+
+  let e = ( texel + 0.5 ) / interior * 2.0 - 1.0;
+  let mut dir = Vec3::new( e.x, 1.0 - e.x.abs() - e.y.abs(), e.y );
+  if dir.y < 0.0
+  {
+    let sign = | v : f32 | if v >= 0.0 { 1.0 } else { -1.0 };
+    dir = Vec3::new( ( 1.0 - dir.z.abs() ) * sign( dir.x ), dir.y, ( 1.0 - dir.x.abs() ) * sign( dir.z ) );
+  }
+  dir.normalize()
+}
+
+/// Mirrors `tangent_frame` in `ibl_compute.wgsl`, tangent space directions have the normal in Y.
+fn tangent_frame( n : Vec3 ) -> Mat3
+{
+  let up = if n.y.abs() > 0.999 { Vec3::X } else { Vec3::Y };
+  let forward = up.cross( n ).normalize();
+  let right = n.cross( forward ).normalize();
+  Mat3::from_cols( right, n, forward )
+}
+
+/// Mirrors `RadicalInverse_VdC` in `ibl.wgsl`.
+fn radical_inverse( bits : u32 ) -> f32
+{
+  let bits = bits.rotate_left( 16 );
+  let bits = ( ( bits & 0x5555_5555 ) << 1 ) | ( ( bits & 0xAAAA_AAAA ) >> 1 );
+  let bits = ( ( bits & 0x3333_3333 ) << 2 ) | ( ( bits & 0xCCCC_CCCC ) >> 2 );
+  let bits = ( ( bits & 0x0F0F_0F0F ) << 4 ) | ( ( bits & 0xF0F0_F0F0 ) >> 4 );
+  let bits = ( ( bits & 0x00FF_00FF ) << 8 ) | ( ( bits & 0xFF00_FF00 ) >> 8 );
+  bits as f32 * 2.328_306_4e-10
+}
+
+/// Mirrors `Hammersley` in `ibl.wgsl`.
+fn hammersley( i : u32, n : u32 ) -> Vec2
+{
+  Vec2::new( i as f32 / n as f32, radical_inverse( i ) )
+}
+
+/// Mirrors `importance_sample` in `ibl.wgsl`, a GGX half vector around `n`.
+fn importance_sample( xi : Vec2, n : Vec3, alpha : f32 ) -> Vec3
+{
+  let phi = 2.0 * PI * xi.x;
+  let cos_theta = ( ( 1.0 - xi.y ) / ( 1.0 + ( alpha * alpha - 1.0 ) * xi.y ) ).sqrt();
+  let sin_theta = ( 1.0 - cos_theta * cos_theta ).sqrt();
+  let sample_dir = Vec3::new( phi.sin() * sin_theta, cos_theta, phi.cos() * sin_theta );
+  ( tangent_frame( n ) * sample_dir ).normalize()
+}
+
+/// Mirrors `D_GGX` in `ibl.wgsl`.
+fn d_ggx( alpha : f32, dot_nh : f32 ) -> f32
+{
+  let a2 = alpha * alpha;
+  let denom = dot_nh * dot_nh * ( a2 - 1.0 ) + 1.0;
+  a2 / ( PI * denom * denom )
+}
+
+/// Mirrors `V_GGX_SmithCorrelated` in `ibl.wgsl`.
+fn v_ggx_smith_correlated( alpha : f32, dot_nl : f32, dot_nv : f32 ) -> f32
+{
+  let a2 = alpha * alpha;
+  let gv = dot_nl * ( a2 + ( 1.0 - a2 ) * dot_nv * dot_nv ).sqrt();
+  let gl = dot_nv * ( a2 + ( 1.0 - a2 ) * dot_nl * dot_nl ).sqrt();
+  0.5 / ( gv + gl ).max( 1e-6 )
+}
+
+/// Mirrors `compute_diffuse_main` in `ibl_compute.wgsl` with the grid of `ibl_compute::diffuse_samples`.
+fn irradiance( cube : &EnvironmentCube, n : Vec3, samples_per_axis : u32 ) -> Vec3
+{
+  let frame = tangent_frame( n );
+  let count = samples_per_axis as f32;
+  let mut sum = Vec3::ZERO;
+  for x in 0..samples_per_axis
+  {
+    for y in 0..samples_per_axis
+    {
+      let phi = x as f32 / count * 2.0 * PI;
+      let theta = y as f32 / count * PI / 2.0;
+      let weight = theta.cos() * theta.sin();
+      if weight > 0.0
+      {
+        let solid_angle = ( 2.0 * PI / count ) * ( PI / 2.0 / count ) * theta.sin();
+        let dir = Vec3::new( phi.sin() * theta.sin(), theta.cos(), phi.cos() * theta.sin() );
+        sum += cube.sample( frame * dir, ibl_compute::sample_lod( solid_angle, cube.size() ) ) * weight;
+      }
+    }
+  }
+  PI * sum / ( count * count )
+}
+
+/// Mirrors `prefilter` in `ibl.wgsl`, the GGX prefiltered radiance around `n` for `roughness`.
+fn prefilter( cube : &EnvironmentCube, n : Vec3, roughness : f32, samples : u32 ) -> Vec3
+{
+  let v = n;
+  let alpha = roughness * roughness;
+  let env_size = cube.size() as f32;
+
+  let ( mut result, mut total_weight ) = ( Vec3::ZERO, 0.0 );
+  for i in 0..samples
+  {
+    let h = importance_sample( hammersley( i, samples ), n, alpha );
+    let dot_vh = v.dot( h ).clamp( 0.0, 1.0 );
+    let l = ( 2.0 * dot_vh * h - v ).normalize();
+    let dot_nl = n.dot( l ).clamp( 0.0, 1.0 );
+    let dot_nh = n.dot( h ).clamp( 0.0, 1.0 );
+
+    if dot_nl > 0.0
+    {
+      let pdf = d_ggx( alpha, dot_nh ) * dot_nh / ( 4.0 * dot_vh );
+      let sa_texel = 4.0 * PI / ( 6.0 * env_size * env_size );
+      let sa_sample = 1.0 / ( samples as f32 * pdf );
+      let lod = if roughness != 0.0 { 0.5 * ( sa_sample / sa_texel ).log2() } else { 0.0 };
+
+      result += cube.sample( l, lod ) * dot_nl;
+      total_weight += dot_nl;
+    }
+  }
+  result / total_weight
+}
+
+/// Mirrors `fragment_specular_2_main` in `ibl.wgsl`, the scale and bias of the split sum in X and Y.
+pub fn integrate_brdf( dot_nv : f32, roughness : f32, samples : u32 ) -> Vec3
+{
+  let alpha = roughness * roughness;
+  let n = Vec3::Y;
+  let v = Vec3::new( 0.0, dot_nv, ( 1.0 - dot_nv * dot_nv ).sqrt() );
+
+  let mut result = Vec2::ZERO;
+  for i in 0..samples
+  {
+    let h = importance_sample( hammersley( i, samples ), n, alpha );
+    let dot_vh = v.dot( h ).clamp( 0.0, 1.0 );
+    let l = ( 2.0 * dot_vh * h - v ).normalize();
+    let dot_nl = l.y.clamp( 0.0, 1.0 );
+    let dot_nh = h.y.clamp( 0.0, 1.0 );
+
+    if dot_nl > 0.0
+    {
+      // D * Vis * NdotL over the pdf of L, D * NdotH / ( 4 * VdotH )
+      let brdf = 4.0 * v_ggx_smith_correlated( alpha, dot_nl, dot_nv ) * dot_nl * dot_vh / dot_nh;
+      let fresnel = ( 1.0 - dot_vh ).powi( 5 );
+      result += Vec2::new( brdf * ( 1.0 - fresnel ), brdf * fresnel );
+    }
+  }
+  ( result / samples as f32 ).extend( 0.0 )
+}
+
+/// Mirrors `main` in `equirect.wgsl`.
+fn equirect_texel( cube : &EnvironmentCube, x : u32, y : u32, width : u32, height : u32 ) -> Vec3
+{
+  let mut uv = ( Vec2::new( x as f32, y as f32 ) + 0.5 ) / Vec2::new( width as f32, height as f32 );
+  uv.y = 1.0 - uv.y;
+  let angles = ( uv * 2.0 - 1.0 ) * Vec2::new( PI, PI / 2.0 );
+  let dir = Vec3::new( angles.x.cos() * angles.y.cos(), angles.y.sin(), angles.x.sin() * angles.y.cos() );
+
+  let cube_size = cube.size() as f32;
+  let texel_angle = ( 2.0 * PI * angles.y.cos() / width as f32 ).max( PI / height as f32 );
+  let lod = ( texel_angle * cube_size * 2.0 / PI ).max( 1.0 ).log2();
+  cube.sample( dir, lod )
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+  use crate::{baker::{Baker, EnvironmentCubeSettings}, gpu, sky::{self, SkyModel, SkySettings}};
+
+  /// Every output at a size the tests run through quickly. The octahedral maps are square,
+  /// and the last of their five specular mips keeps texels inside the border.
+  fn small_settings( layout : OutputLayout ) -> BakeSettings
+  {
+    let octahedral = layout == OutputLayout::Octahedral;
+    BakeSettings
+    {
+      cube_size : 16,
+      diffuse_width : 16,
+      diffuse_height : if octahedral { 16 } else { 8 },
+      specular_1_width : if octahedral { 64 } else { 16 },
+      specular_1_height : if octahedral { 64 } else { 8 },
+      specular_2_width : 8,
+      specular_2_height : 8,
+      diffuse_samples : 16,
+      specular_samples : 64,
+      lut_samples : 64,
+      layout,
+      specular_cube_size : Some( 4 ),
+      equirect_size : Some( ( 16, 8 ) ),
+      environment_cube : Some( EnvironmentCubeSettings { mips : true, ..Default::default() } ),
+      ..BakeSettings::default()
+    }
+  }
+
+  fn source( width : u32, height : u32, radiance : impl Fn( f32, f32 ) -> [ f32; 3 ] ) -> SourceImage
+  {
+    let pixels = ( 0..width * height ).flat_map( | i |
+    {
+      let [ r, g, b ] = radiance( ( i % width ) as f32 / width as f32, ( i / width ) as f32 / height as f32 );
+      [ r, g, b, 1.0 ]
+    })
+    .collect();
+    SourceImage { width, height, pixels }
+  }
+
+  /// Every image but the LUT, with the faces of the environment cube.
+  fn environment_images( outputs : &BakeOutputs ) -> impl Iterator< Item = &OutputImage >
+  {
+    outputs.images().filter( | image | image.name != "specular_2" ).chain( outputs.environment_cube.iter().flatten().flatten() )
+  }
+
+  #[ test ]
+  fn constant_environment_stays_constant()
+  {
+    let radiance = [ 0.5, 1.0, 2.0 ];
+    let source = source( 32, 16, | _, _ | radiance );
+    for layout in [ OutputLayout::Equirect, OutputLayout::Octahedral ]
+    {
+      for cube_resample in [ CubeResample::Bilinear, CubeResample::Supersample, CubeResample::Area ]
+      {
+        for mip_filter in [ MipFilter::Box, MipFilter::Kaiser, MipFilter::SolidAngle ]
+        {
+          let outputs = bake( &source, &BakeSettings { cube_resample, mip_filter, ..small_settings( layout ) } );
+          for image in environment_images( &outputs )
+          {
+            for texel in image.data.chunks_exact( 3 )
+            {
+              for ( value, expected ) in texel.iter().zip( radiance )
+              {
+                // The irradiance grid underestimates the cosine integral slightly
+                assert!( ( value - expected ).abs() < 0.01 * expected, "{} of {:?} {:?}: {} instead of {}", image.name, cube_resample, mip_filter, value, expected );
+              }
+            }
+          }
+        }
+      }
+    }
+  }
+
+  /// Smooth radiance with a bright lobe close to the corner of +X, +Y and +Z.
+  fn corner_lobe( dir : Vec3 ) -> Vec3
+  {
+    let lobe = 20.0 * ( 8.0 * ( dir.dot( Vec3::ONE ) / 3f32.sqrt() - 1.0 ) ).exp();
+    Vec3::new( 1.0 + 0.5 * dir.x + lobe, 1.0 + 0.5 * dir.y + lobe, 1.0 + 0.5 * dir.z )
+  }
+
+  fn texel_center( face : usize, texel : IVec2, size : u32 ) -> Vec3
+  {
+    face_direction( face, ( texel.as_vec2() + 0.5 ) / size as f32 * 2.0 - 1.0 ).normalize()
+  }
+
+  impl CubeMip
+  {
+    /// `downsample_kaiser` with the footprints clamped to the face instead of continued past its edges.
+    fn downsample_kaiser_clamped( &self ) -> Self
+    {
+      let size = ( self.size / 2 ).max( 1 );
+      let faces = std::array::from_fn( | face | render( size, size, | x, y |
+      {
+        let origin = IVec2::new( x as i32, y as i32 ) * 2 - 1;
+        let mut sum = Vec3::ZERO;
+        for ( dy, weight_y ) in KAISER_WEIGHTS.iter().enumerate()
+        {
+          for ( dx, weight_x ) in KAISER_WEIGHTS.iter().enumerate()
+          {
+            let texel = ( origin + IVec2::new( dx as i32, dy as i32 ) ).clamp( IVec2::ZERO, IVec2::splat( self.size as i32 - 1 ) );
+            sum += self.texel( face, texel ) * weight_x * weight_y;
+          }
+        }
+        sum
+      }));
+      Self { size, faces }
+    }
+
+    /// Mean error of the step between the texels on both sides of every face edge, against the step
+    /// of `reference` between their centers, relative to the mean step of `reference`.
+    fn seam_error( &self, reference : impl Fn( Vec3 ) -> Vec3 ) -> f32
+    {
+      let size = self.size as i32;
+      let ( mut error, mut step ) = ( 0.0, 0.0 );
+      for face in 0..6
+      {
+        for i in 0..size
+        {
+          for ( texel, offset ) in [ ( IVec2::new( 0, i ), IVec2::NEG_X ), ( IVec2::new( size - 1, i ), IVec2::X ), ( IVec2::new( i, 0 ), IVec2::NEG_Y ), ( IVec2::new( i, size - 1 ), IVec2::Y ) ]
+          {
+            // The texel `fetch` reads past the edge, found back from its direction
+            let outside = ( texel + offset ).as_vec2() + 0.5;
+            let ( neighbor, st ) = direction_face( face_direction( face, outside / size as f32 * 2.0 - 1.0 ) );
+            let neighbor_texel = ( ( st * 0.5 + 0.5 ) * size as f32 ).floor().as_ivec2().clamp( IVec2::ZERO, IVec2::splat( size - 1 ) );
+
+            let texel_step = self.texel( face, texel ) - self.fetch( face, texel + offset );
+            let reference_step = reference( texel_center( face, texel, self.size ) ) - reference( texel_center( neighbor, neighbor_texel, self.size ) );
+            error += ( texel_step - reference_step ).abs().element_sum();
+            step += reference_step.abs().element_sum();
+          }
+        }
+      }
+      error / step
+    }
+  }
+
+  #[ test ]
+  fn fetch_continues_on_the_adjacent_texel()
+  {
+    // Every texel holds its face and position, stepping past an edge and back lands on the starting texel
+    let size = 8;
+    let mip = CubeMip { size, faces : std::array::from_fn( | face | render( size, size, | x, y | Vec3::new( face as f32, x as f32, y as f32 ) ) ) };
+    for face in 0..6
+    {
+      for i in 0..size as i32
+      {
+        for ( texel, offset ) in [ ( IVec2::new( 0, i ), IVec2::NEG_X ), ( IVec2::new( 7, i ), IVec2::X ), ( IVec2::new( i, 0 ), IVec2::NEG_Y ), ( IVec2::new( i, 7 ), IVec2::Y ) ]
+        {
+          let neighbor = mip.fetch( face, texel + offset );
+          assert_ne!( neighbor.x as usize, face );
+          let back = [ IVec2::NEG_X, IVec2::X, IVec2::NEG_Y, IVec2::Y ].iter()
+          .any( | step | mip.fetch( neighbor.x as usize, IVec2::new( neighbor.y as i32, neighbor.z as i32 ) + *step ) == Vec3::new( face as f32, texel.x as f32, texel.y as f32 ) );
+          assert!( back, "face {} texel {} reaches {}", face, texel, neighbor );
+        }
+      }
+    }
+  }
+
+  #[ test ]
+  fn default_mips_filter_across_the_seams()
+  {
+    let source = source( 256, 128, | u, v |
+    {
+      // Direction of the texel center, row 0 looking up as the cube sampler sees the conversion
+      let ( azimuth, elevation ) = ( ( u + 0.5 / 256.0 - 0.5 ) * 2.0 * PI, ( 0.5 - v - 0.5 / 128.0 ) * PI );
+      corner_lobe( Vec3::new( azimuth.cos() * elevation.cos(), elevation.sin(), azimuth.sin() * elevation.cos() ) ).to_array()
+    });
+    let cube = EnvironmentCube::new( &source, &BakeSettings { cube_size : 64, ..BakeSettings::default() } );
+
+    let mut clamped = CubeMip { size : 64, faces : cube.mips[ 0 ].faces.clone() };
+    for ( level, mip ) in cube.mips.iter().enumerate().skip( 1 ).take( 4 )
+    {
+      clamped = clamped.downsample_kaiser_clamped();
+      let ( error, clamped_error ) = ( mip.seam_error( corner_lobe ), clamped.seam_error( corner_lobe ) );
+      assert!( error < 0.75 * clamped_error, "mip {}: {} against {} when clamped", level, error, clamped_error );
+    }
+  }
+
+  #[ test ]
+  fn radical_inverse_reverses_the_bits()
+  {
+    for i in ( 0..4096 ).chain( [ u32::MAX / 3, u32::MAX ] )
+    {
+      assert_eq!( radical_inverse( i ), i.reverse_bits() as f32 * 2.328_306_4e-10 );
+    }
+  }
+
+  #[ test ]
+  fn smooth_surface_reflects_everything_at_normal_incidence()
+  {
+    let lut = integrate_brdf( 1.0, 0.0, 64 );
+    assert!( ( lut - Vec3::X ).abs().max_element() < 1e-5, "{}", lut );
+  }
+
+  #[ test ]
+  fn importance_samples_center_on_the_normal()
+  {
+    for n in [ Vec3::Y, Vec3::NEG_Y, Vec3::X, Vec3::new( 1.0, 2.0, -3.0 ).normalize() ]
+    {
+      for i in 0..64
+      {
+        // A smooth surface only reflects around the normal
+        assert!( importance_sample( hammersley( i, 64 ), n, 0.0 ).dot( n ) > 0.9999 );
+      }
+
+      let mean = ( 0..256 ).map( | i | importance_sample( hammersley( i, 256 ), n, 0.5 ) ).sum::< Vec3 >();
+      assert!( mean.normalize().dot( n ) > 0.999, "mean half vector {} around {}", mean.normalize(), n );
+    }
+  }
+
+  /// Skies whose sun is off the axes, so a mirrored or rotated sky shows.
+  fn test_skies() -> Vec< SkySettings >
+  {
+    [ SkyModel::Preetham, SkyModel::HosekWilkie, SkyModel::Gradient ].map( | model | SkySettings
+    {
+      model,
+      sun_elevation : 30.0,
+      sun_azimuth : 60.0,
+      hosek_data : Some( sky::test_hosek_data() ),
+      ..SkySettings::default()
+    })
+    .to_vec()
+  }
+
+  #[ test ]
+  fn sky_equirect_bakes_like_the_sky()
+  {
+    // The equirect of `inspect` and of the glTF SH has to face the same way as the cube of the sky, the mean
+    // of every face is compared. The glow of Preetham and Hosek-Wilkie around the sun shows a mirrored or rotated
+    // equirect, while the sun disk of the gradient covers too few texels of the cube to keep its energy when point sampled
+    let settings = BakeSettings
+    {
+      cube_resample : CubeResample::Area,
+      mip_filter : MipFilter::Box,
+      environment_cube : Some( EnvironmentCubeSettings { mips : true, ..Default::default() } ),
+      ..small_settings( OutputLayout::Equirect )
+    };
+    for sky in test_skies().into_iter().filter( | sky | sky.model != SkyModel::Gradient )
+    {
+      let uniform = sky.uniform().unwrap();
+      let from_sky = bake_sky( &uniform, &settings ).environment_cube.unwrap();
+      let from_equirect = bake( &uniform.render_equirect( 256, 128 ), &settings ).environment_cube.unwrap();
+
+      for ( sky_face, equirect_face ) in from_sky.last().unwrap().iter().zip( from_equirect.last().unwrap() )
+      {
+        let ( expected, actual ) = ( Vec3::from_slice( &sky_face.data ), Vec3::from_slice( &equirect_face.data ) );
+        assert!( ( expected - actual ).abs().max_element() < 0.05 * expected.max_element(), "{} of {}: {} instead of {}", sky_face.name, sky.name(), actual, expected );
+      }
+    }
+  }
+
+  /// Bakes the same source on the GPU with the compute passes and on the CPU, when an adapter is available.
+  #[ test ]
+  fn matches_gpu()
+  {
+    let Some( ( device, queue ) ) = gpu::test_device() else
+    {
+      eprintln!( "No suitable GPU adapter, the comparison with the GPU is skipped" );
+      return;
+    };
+
+    let source = source( 64, 32, | u, v |
+    {
+      let lobe = 20.0 * ( -200.0 * ( ( u - 0.3 ).powi( 2 ) + ( v - 0.35 ).powi( 2 ) ) ).exp();
+      [ 1.0 + ( u * 2.0 * PI ).sin() * 0.5 + lobe, 1.0 + v, 0.5 + lobe ]
+    });
+    for layout in [ OutputLayout::Equirect, OutputLayout::Octahedral ]
+    {
+      let settings = BakeSettings { compute : true, ..small_settings( layout ) };
+      let mut baker = Baker::new( &device, &queue, &settings, false );
+      baker.set_source( &device, &source.to_texture( &device, &queue ) );
+      baker.bake( &device, &queue );
+      device.poll( wgpu::PollType::wait() ).unwrap();
+      let gpu = pollster::block_on( baker.read_outputs( &device ) ).unwrap().decode();
+      let cpu = bake( &source, &settings );
+
+      let gpu_images = gpu.images().chain( gpu.environment_cube.iter().flatten().flatten() );
+      let cpu_images = cpu.images().chain( cpu.environment_cube.iter().flatten().flatten() );
+      for ( gpu_image, cpu_image ) in gpu_images.zip( cpu_images )
+      {
+        assert_eq!( gpu_image.name, cpu_image.name );
+        let difference = gpu_image.data.iter().zip( &cpu_image.data ).map( | ( g, c ) | ( g - c ).abs() ).sum::< f32 >();
+        let total = cpu_image.data.iter().map( | c | c.abs() ).sum::< f32 >();
+        assert!( difference < 0.01 * total, "{} differs by {} of its total", gpu_image.name, difference / total );
+      }
+    }
+  }
+
+  /// Renders the skies to the cube on the GPU and on the CPU, when an adapter is available.
+  #[ test ]
+  fn sky_matches_gpu()
+  {
+    let Some( ( device, queue ) ) = gpu::test_device() else
+    {
+      eprintln!( "No suitable GPU adapter, the comparison of the skies with the GPU is skipped" );
+      return;
+    };
+
+    let settings = BakeSettings { compute : true, ..small_settings( OutputLayout::Equirect ) };
+    let mut baker = Baker::new( &device, &queue, &settings, false );
+    for sky in test_skies()
+    {
+      let uniform = sky.uniform().unwrap();
+      baker.set_sky( &queue, &uniform );
+      baker.bake( &device, &queue );
+      device.poll( wgpu::PollType::wait() ).unwrap();
+      let gpu = pollster::block_on( baker.read_outputs( &device ) ).unwrap().decode();
+      let cpu = bake_sky( &uniform, &settings );
+
+      let gpu_faces = gpu.environment_cube.iter().flatten().flatten();
+      let cpu_faces = cpu.environment_cube.iter().flatten().flatten();
+      for ( gpu_face, cpu_face ) in gpu_faces.zip( cpu_faces )
+      {
+        let difference = gpu_face.data.iter().zip( &cpu_face.data ).map( | ( g, c ) | ( g - c ).abs() ).sum::< f32 >();
+        let total = cpu_face.data.iter().map( | c | c.abs() ).sum::< f32 >();
+        assert!( difference < 0.01 * total, "{} of {} differs by {} of its total", gpu_face.name, sky.name(), difference / total );
+      }
+    }
+  }
+}
@@ -0,0 +1,355 @@
+use std::{fs::File, io::{BufWriter, Write}, path::Path};
+
+use anyhow::Context;
+
+use crate::{baker::CubeContainer, manifest::FileEntry, output::{OutputFormat, OutputImage}};
+
+pub const KTX2_FILE : &str = "environment_cube.ktx2";
+pub const DDS_FILE : &str = "environment_cube.dds";
+
+/// Both containers hold 32-bit float RGBA texels, the alpha is always 1.
+const TEXEL_SIZE : u64 = 16;
+
+/// Column and row of every face in the horizontal cross, in the WebGPU face order.
+/// The faces keep the orientation they have in the cube.
+const CROSS_CELLS : [ ( u32, u32 ); 6 ] = [ ( 2, 1 ), ( 0, 1 ), ( 1, 0 ), ( 1, 2 ), ( 1, 1 ), ( 3, 1 ) ];
+
+/// Name of the cross of mip `mip_level`.
+pub fn cross_name( mip_level : usize ) -> String
+{
+  format!( "environment_cross_{}", mip_level )
+}
+
+/// Lays the six faces out as a horizontal cross, four faces wide and three high:
+///
+/// ```text
+///       +Y
+///   -X  +Z  +X  -Z
+///       -Y
+/// ```
+///
+/// The cells without a face are black.
+pub fn cross( faces : &[ OutputImage; 6 ], name : impl Into< String > ) -> OutputImage
+{
+  let size = faces[ 0 ].width as usize;
+  let width = size * 4;
+  let mut data = vec![ 0.0; width * size * 3 * 3 ];
+  for ( face, ( column, row ) ) in faces.iter().zip( CROSS_CELLS )
+  {
+    for ( y, texels ) in face.data.chunks_exact( size * 3 ).enumerate()
+    {
+      let start = ( ( row as usize * size + y ) * width + column as usize * size ) * 3;
+      data[ start..start + size * 3 ].copy_from_slice( texels );
+    }
+  }
+  OutputImage::new( name, width as u32, size as u32 * 3, data )
+}
+
+/// Inverse of [`cross`], cuts the six faces out of a horizontal cross. They are named `<name>_<face>`.
+pub fn split_cross( cross : &OutputImage, name : &str ) -> [ OutputImage; 6 ]
+{
+  let size = ( cross.width / 4 ) as usize;
+  let width = cross.width as usize;
+  std::array::from_fn( | face |
+  {
+    let ( column, row ) = CROSS_CELLS[ face ];
+    let data = ( 0..size ).flat_map( | y |
+    {
+      let start = ( ( row as usize * size + y ) * width + column as usize * size ) * 3;
+      cross.data[ start..start + size * 3 ].iter().copied()
+    })
+    .collect();
+    OutputImage::new( format!( "{}_{}", name, face ), size as u32, size as u32, data )
+  })
+}
+
+/// Writes the faces of every mip of `mips` in `container` and returns the written files.
+/// The faces and the crosses are written in `format`, the other containers always hold floats.
+pub fn save( dir : &Path, container : CubeContainer, mips : &[ [ OutputImage; 6 ] ], format : OutputFormat ) -> anyhow::Result< Vec< FileEntry > >
+{
+  let container_file = | name : &str, write : fn( &mut BufWriter< File >, &[ [ OutputImage; 6 ] ] ) -> std::io::Result< () > |
+  {
+    Ok( vec![ FileEntry { path : name.into(), bytes : save_with( &dir.join( name ), | writer | write( writer, mips ) )? } ] )
+  };
+  match container
+  {
+    CubeContainer::Faces => mips.iter().flatten().map( | face | FileEntry::image( dir, face, format ) ).collect(),
+    CubeContainer::Cross => mips.iter().enumerate().map( | ( mip_level, faces ) | FileEntry::image( dir, &cross( faces, cross_name( mip_level ) ), format ) ).collect(),
+    CubeContainer::Ktx2 => container_file( KTX2_FILE, write_ktx2 ),
+    CubeContainer::Dds => container_file( DDS_FILE, write_dds )
+  }
+}
+
+/// Reads the faces of every mip back from a KTX2 or DDS cube written by [`save`], other files than the
+/// 32-bit float cubes it writes fail. The faces are named `<name>_<mip>_<face>`.
+pub fn load( path : &Path, container : CubeContainer, name : &str ) -> anyhow::Result< Vec< [ OutputImage; 6 ] > >
+{
+  let bytes = std::fs::read( path ).with_context( || format!( "Failed to open {}", path.display() ) )?;
+  let mips = match container
+  {
+    CubeContainer::Ktx2 => read_ktx2( &bytes, name ),
+    CubeContainer::Dds => read_dds( &bytes, name ),
+    CubeContainer::Faces | CubeContainer::Cross => anyhow::bail!( "The {} of a cube are separate images", container.name() )
+  };
+  mips.with_context( || format!( "Failed to read {}", path.display() ) )
+}
+
+fn save_with( path : &Path, write : impl FnOnce( &mut BufWriter< File > ) -> std::io::Result< () > ) -> anyhow::Result< u64 >
+{
+  let file = File::create( path ).with_context( || format!( "Failed to create {}", path.display() ) )?;
+  let mut writer = BufWriter::new( file );
+  write( &mut writer ).and_then( | _ | writer.flush() ).with_context( || format!( "Failed to write {}", path.display() ) )?;
+
+  Ok( std::fs::metadata( path )?.len() )
+}
+
+fn write_texels( writer : &mut impl Write, image : &OutputImage ) -> std::io::Result< () >
+{
+  let rgba = image.data.chunks_exact( 3 ).flat_map( | texel | [ texel[ 0 ], texel[ 1 ], texel[ 2 ], 1.0 ] ).collect::< Vec< f32 > >();
+  writer.write_all( bytemuck::cast_slice( &rgba ) )
+}
+
+fn u32_at( bytes : &[ u8 ], offset : usize ) -> anyhow::Result< u32 >
+{
+  let word = bytes.get( offset..offset + 4 ).context( "Truncated header" )?;
+  Ok( u32::from_le_bytes( [ word[ 0 ], word[ 1 ], word[ 2 ], word[ 3 ] ] ) )
+}
+
+/// Face of `size` x `size` RGBA float texels starting at `offset`, without the alpha.
+fn read_texels( bytes : &[ u8 ], offset : usize, size : u32, name : String ) -> anyhow::Result< OutputImage >
+{
+  let length = ( size * size ) as usize * TEXEL_SIZE as usize;
+  let texels = bytes.get( offset..offset + length ).context( "Truncated texels" )?;
+  let data = texels.chunks_exact( TEXEL_SIZE as usize ).flat_map( | texel |
+  {
+    std::array::from_fn::< f32, 3, _ >( | c | f32::from_le_bytes( [ texel[ c * 4 ], texel[ c * 4 + 1 ], texel[ c * 4 + 2 ], texel[ c * 4 + 3 ] ] ) )
+  })
+  .collect();
+  Ok( OutputImage::new( name, size, size, data ) )
+}
+
+fn level_size( faces : &[ OutputImage; 6 ] ) -> u64
+{
+  6 * faces[ 0 ].width as u64 * faces[ 0 ].height as u64 * TEXEL_SIZE
+}
+
+/// `VK_FORMAT_R32G32B32A32_SFLOAT`
+const VK_FORMAT_RGBA32F : u32 = 109;
+
+const KTX2_IDENTIFIER : [ u8; 12 ] = [ 0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a ];
+
+/// Basic data format descriptor of linear BT.709 RGBA 32-bit floats, with its total size first.
+fn ktx2_format_descriptor() -> Vec< u8 >
+{
+  let mut words = vec!
+  [
+    4 + 24 + 4 * 16,
+    // Khronos vendor and basic descriptor type, version 2 and block size
+    0,
+    2 | ( ( 24 + 4 * 16 ) << 16 ),
+    // RGBSDA color model, BT.709 primaries, linear transfer and straight alpha
+    1 | ( 1 << 8 ) | ( 1 << 16 ),
+    // One texel per block
+    0,
+    TEXEL_SIZE as u32,
+    0
+  ];
+  for ( index, channel ) in [ 0, 1, 2, 15 ].into_iter().enumerate()
+  {
+    // Signed float channel of 32 bits at `index * 32`, from -1 to 1
+    words.extend_from_slice( &[ ( index as u32 * 32 ) | ( 31 << 16 ) | ( ( 0xc0 | channel ) << 24 ), 0, 0xbf80_0000, 0x3f80_0000 ] );
+  }
+  words.into_iter().flat_map( u32::to_le_bytes ).collect()
+}
+
+/// KTX2 cube of every mip of `mips`. The levels are stored from the smallest to the largest, as the format requires.
+fn write_ktx2( writer : &mut impl Write, mips : &[ [ OutputImage; 6 ] ] ) -> std::io::Result< () >
+{
+  let level_count = mips.len() as u64;
+  let format_descriptor = ktx2_format_descriptor();
+  let format_descriptor_offset = 12 + 9 * 4 + 4 * 4 + 2 * 8 + level_count * 3 * 8;
+  let data_offset = ( format_descriptor_offset + format_descriptor.len() as u64 ).next_multiple_of( TEXEL_SIZE );
+
+  let mut level_offsets = vec![ 0; mips.len() ];
+  let mut offset = data_offset;
+  for ( level, faces ) in mips.iter().enumerate().rev()
+  {
+    level_offsets[ level ] = offset;
+    offset += level_size( faces );
+  }
+
+  let mut header = KTX2_IDENTIFIER.to_vec();
+  let base = &mips[ 0 ][ 0 ];
+  for value in [ VK_FORMAT_RGBA32F, 4, base.width, base.height, 0, 0, 6, level_count as u32, 0 ]
+  {
+    header.extend_from_slice( &value.to_le_bytes() );
+  }
+  // Format descriptor, then the empty key/value and supercompression data
+  for value in [ format_descriptor_offset as u32, format_descriptor.len() as u32, 0, 0 ]
+  {
+    header.extend_from_slice( &value.to_le_bytes() );
+  }
+  header.extend_from_slice( &[ 0; 16 ] );
+  for ( faces, offset ) in mips.iter().zip( level_offsets )
+  {
+    for value in [ offset, level_size( faces ), level_size( faces ) ]
+    {
+      header.extend_from_slice( &value.to_le_bytes() );
+    }
+  }
+  header.extend_from_slice( &format_descriptor );
+  header.resize( data_offset as usize, 0 );
+  writer.write_all( &header )?;
+
+  for faces in mips.iter().rev()
+  {
+    for face in faces
+    {
+      write_texels( writer, face )?;
+    }
+  }
+  Ok( () )
+}
+
+fn read_ktx2( bytes : &[ u8 ], name : &str ) -> anyhow::Result< Vec< [ OutputImage; 6 ] > >
+{
+  if !bytes.starts_with( &KTX2_IDENTIFIER )
+  {
+    anyhow::bail!( "Not a KTX2 file" );
+  }
+  let ( format, size, faces, level_count ) = ( u32_at( bytes, 12 )?, u32_at( bytes, 20 )?, u32_at( bytes, 36 )?, u32_at( bytes, 40 )? );
+  if format != VK_FORMAT_RGBA32F || faces != 6
+  {
+    anyhow::bail!( "Expected a cube of RGBA 32-bit floats, got format {} with {} faces", format, faces );
+  }
+
+  // The level index follows the header, 3 64-bit words per level of which the first is the offset
+  ( 0..level_count ).map( | level |
+  {
+    let offset = u32_at( bytes, 80 + level as usize * 24 )? as usize;
+    let face_size = ( size >> level ).max( 1 );
+    let face_length = ( face_size * face_size ) as usize * TEXEL_SIZE as usize;
+    let faces = ( 0..6 ).map( | face | read_texels( bytes, offset + face * face_length, face_size, format!( "{}_{}_{}", name, level, face ) ) )
+    .collect::< anyhow::Result< Vec< _ > > >()?;
+    Ok( faces.try_into().unwrap_or_else( | _ | unreachable!() ) )
+  })
+  .collect()
+}
+
+/// `DXGI_FORMAT_R32G32B32A32_FLOAT`
+const DXGI_FORMAT_RGBA32F : u32 = 2;
+
+/// DDS cube with a DX10 header, every mip of a face is stored before the next face.
+fn write_dds( writer : &mut impl Write, mips : &[ [ OutputImage; 6 ] ] ) -> std::io::Result< () >
+{
+  // Caps, height, width, pitch, pixel format and mip count are set
+  const FLAGS : u32 = 0x1 | 0x2 | 0x4 | 0x8 | 0x1000 | 0x20000;
+  const PIXEL_FORMAT_FOURCC : u32 = 0x4;
+  // Complex, texture and mipmap
+  const CAPS : u32 = 0x8 | 0x1000 | 0x40_0000;
+  // Cube map with all six faces
+  const CAPS2 : u32 = 0x200 | 0xfc00;
+  const RESOURCE_DIMENSION_TEXTURE2D : u32 = 3;
+  const MISC_TEXTURECUBE : u32 = 0x4;
+
+  let base = &mips[ 0 ][ 0 ];
+  let mut words = vec!
+  [
+    124,
+    FLAGS,
+    base.height,
+    base.width,
+    base.width * TEXEL_SIZE as u32,
+    0,
+    mips.len() as u32
+  ];
+  words.extend_from_slice( &[ 0; 11 ] );
+  words.extend_from_slice( &[ 32, PIXEL_FORMAT_FOURCC, u32::from_le_bytes( *b"DX10" ), 0, 0, 0, 0, 0 ] );
+  words.extend_from_slice( &[ CAPS, CAPS2, 0, 0, 0 ] );
+  words.extend_from_slice( &[ DXGI_FORMAT_RGBA32F, RESOURCE_DIMENSION_TEXTURE2D, MISC_TEXTURECUBE, 1, 0 ] );
+
+  writer.write_all( b"DDS " )?;
+  writer.write_all( &words.into_iter().flat_map( u32::to_le_bytes ).collect::< Vec< _ > >() )?;
+  for face in 0..6
+  {
+    for faces in mips
+    {
+      write_texels( writer, &faces[ face ] )?;
+    }
+  }
+  Ok( () )
+}
+
+fn read_dds( bytes : &[ u8 ], name : &str ) -> anyhow::Result< Vec< [ OutputImage; 6 ] > >
+{
+  if !bytes.starts_with( b"DDS " )
+  {
+    anyhow::bail!( "Not a DDS file" );
+  }
+  // The DX10 header follows the 124 bytes of the header
+  let ( size, level_count, format ) = ( u32_at( bytes, 16 )?, u32_at( bytes, 28 )?, u32_at( bytes, 128 )? );
+  if format != DXGI_FORMAT_RGBA32F
+  {
+    anyhow::bail!( "Expected RGBA 32-bit floats, got DXGI format {}", format );
+  }
+
+  let level_sizes = ( 0..level_count ).map( | level | ( size >> level ).max( 1 ) ).collect::< Vec< _ > >();
+  let face_length = level_sizes.iter().map( | size | ( size * size ) as usize * TEXEL_SIZE as usize ).sum::< usize >();
+  let mut mips = Vec::new();
+  for ( level, face_size ) in level_sizes.iter().enumerate()
+  {
+    let level_offset = level_sizes[ ..level ].iter().map( | size | ( size * size ) as usize * TEXEL_SIZE as usize ).sum::< usize >();
+    let faces = ( 0..6 ).map( | face | read_texels( bytes, 148 + face * face_length + level_offset, *face_size, format!( "{}_{}_{}", name, level, face ) ) )
+    .collect::< anyhow::Result< Vec< _ > > >()?;
+    mips.push( faces.try_into().unwrap_or_else( | _ | unreachable!() ) );
+  }
+  Ok( mips )
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+
+  /// Faces of two mips whose texels are all different.
+  fn mips() -> Vec< [ OutputImage; 6 ] >
+  {
+    [ 4, 2 ].into_iter().enumerate().map( | ( mip, size ) |
+    {
+      std::array::from_fn( | face |
+      {
+        let data = ( 0..size * size * 3 ).map( | i | ( mip * 1000 + face * 100 + i ) as f32 ).collect();
+        OutputImage::new( format!( "cube_{}_{}", mip, face ), size as u32, size as u32, data )
+      })
+    })
+    .collect()
+  }
+
+  #[ test ]
+  fn containers_read_back()
+  {
+    let dir = std::env::temp_dir().join( format!( "IBLConverter-cube-{}", std::process::id() ) );
+    std::fs::create_dir_all( &dir ).unwrap();
+    let mips = mips();
+    for ( container, file ) in [ ( CubeContainer::Ktx2, KTX2_FILE ), ( CubeContainer::Dds, DDS_FILE ) ]
+    {
+      save( &dir, container, &mips, OutputFormat::Hdr ).unwrap();
+      let loaded = load( &dir.join( file ), container, "cube" ).unwrap();
+      assert_eq!( loaded.len(), mips.len() );
+      for ( image, expected ) in loaded.iter().flatten().zip( mips.iter().flatten() )
+      {
+        assert_eq!( ( &image.name, &image.data ), ( &expected.name, &expected.data ), "{}", container.name() );
+      }
+    }
+    std::fs::remove_dir_all( &dir ).unwrap();
+  }
+
+  #[ test ]
+  fn cross_splits_into_the_faces()
+  {
+    for ( face, expected ) in split_cross( &cross( &mips()[ 0 ], "cross" ), "cube_0" ).iter().zip( &mips()[ 0 ] )
+    {
+      assert_eq!( ( &face.name, &face.data ), ( &expected.name, &expected.data ) );
+    }
+  }
+}
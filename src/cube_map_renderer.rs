@@ -1,20 +1,25 @@
 use std::rc::Rc;
 
-use crate::{cube_texture::CubeTexture, texture_2d::Texture2D};
+use crate::{baker::CubeResample, profiler::GpuProfiler, texture::Texture};
+
+pub const SHADER_SOURCE : &str = include_str!( "shaders/cube_map.wgsl" );
 
 
 
 pub struct CubeMapRenderer
 {
-  cube_texture : Rc< CubeTexture >,
-  hdr_texture : Rc< Texture2D >,
-  bind_group : wgpu::BindGroup,
+  cube_texture : Rc< Texture >,
+  bind_group_layout : wgpu::BindGroupLayout,
+  /// Set by [`CubeMapRenderer::set_hdr_texture`]
+  bind_group : Option< wgpu::BindGroup >,
   pipeline : wgpu::ComputePipeline
 }
 
 impl CubeMapRenderer
 {
-  pub fn new( cube_texture : Rc< CubeTexture >, hdr_texture : Rc< Texture2D >, device : &wgpu::Device ) -> Self
+  /// `samples` is the number of samples along each axis of a texel with `CubeResample::Supersample`.
+  /// The source is set by [`CubeMapRenderer::set_hdr_texture`] before the first render.
+  pub fn new( cube_texture : Rc< Texture >, device : &wgpu::Device, resample : CubeResample, samples : u32 ) -> Self
   {
     let bind_group_layout = device.create_bind_group_layout
     (
@@ -30,7 +35,7 @@ impl CubeMapRenderer
             ty: wgpu::BindingType::StorageTexture 
             { 
               access: wgpu::StorageTextureAccess::WriteOnly, 
-              format: wgpu::TextureFormat::Rgba32Float, 
+              format: cube_texture.format(), 
               view_dimension: wgpu::TextureViewDimension::D2Array 
             }, 
             count: None 
@@ -51,34 +56,12 @@ impl CubeMapRenderer
       }
     );
 
-    let bind_group = device.create_bind_group
-    (
-      &wgpu::BindGroupDescriptor
-      {
-        label : None,
-        layout : &bind_group_layout,
-        entries : &
-        [
-          wgpu::BindGroupEntry
-          {
-            binding : 0,
-            resource : wgpu::BindingResource::TextureView( cube_texture.view_2d() )
-          },
-          wgpu::BindGroupEntry
-          {
-            binding : 1,
-            resource : wgpu::BindingResource::TextureView( hdr_texture.view() )
-          },
-        ]
-      }
-    );
-
     let shader = device.create_shader_module
     ( 
       wgpu::ShaderModuleDescriptor 
       { 
         label: None, 
-        source: wgpu::ShaderSource::Wgsl( include_str!( "shaders/cube_map.wgsl" ).into() )
+        source: wgpu::ShaderSource::Wgsl( SHADER_SOURCE.into() )
       }
     );
 
@@ -103,7 +86,11 @@ impl CubeMapRenderer
         layout : Some( &pipeline_layout ),
         module : &shader,
         entry_point : None,
-        compilation_options : wgpu::PipelineCompilationOptions::default(),
+        compilation_options : wgpu::PipelineCompilationOptions
+        {
+          constants : &[ ( "RESAMPLE", resample as u32 as f64 ), ( "SAMPLES", samples as f64 ) ],
+          ..Default::default()
+        },
         cache : None
       }
     );
@@ -113,20 +100,65 @@ impl CubeMapRenderer
     Self 
     { 
       cube_texture,
-      hdr_texture,
-      bind_group,
+      bind_group_layout,
+      bind_group : None,
       pipeline
     }
   }  
 
-  pub fn render( &self, encoder : &mut wgpu::CommandEncoder )
+  /// Swaps the equirectangular source, so the same pipeline can convert another input.
+  pub fn set_hdr_texture( &mut self, device : &wgpu::Device, hdr_texture : &Texture )
+  {
+    self.bind_group = Some( Self::create_bind_group( device, &self.bind_group_layout, &self.cube_texture, hdr_texture ) );
+  }
+
+  fn create_bind_group
+  ( 
+    device : &wgpu::Device, 
+    bind_group_layout : &wgpu::BindGroupLayout, 
+    cube_texture : &Texture, 
+    hdr_texture : &Texture 
+  ) -> wgpu::BindGroup
+  {
+    let cube_view = cube_texture.create_mip_array_view( 0 );
+    device.create_bind_group
+    (
+      &wgpu::BindGroupDescriptor
+      {
+        label : None,
+        layout : bind_group_layout,
+        entries : &
+        [
+          wgpu::BindGroupEntry
+          {
+            binding : 0,
+            resource : wgpu::BindingResource::TextureView( &cube_view )
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 1,
+            resource : wgpu::BindingResource::TextureView( hdr_texture.view() )
+          },
+        ]
+      }
+    )
+  }
+
+  pub fn render( &self, encoder : &mut wgpu::CommandEncoder, profiler : &GpuProfiler )
   {
     let dst_size = self.cube_texture.size();
     let num_groups = dst_size.width.div_ceil( 16 );
     {
-      let mut compute_pass = encoder.begin_compute_pass( &wgpu::ComputePassDescriptor::default() );
+      let mut compute_pass = encoder.begin_compute_pass
+      (
+        &wgpu::ComputePassDescriptor
+        {
+          label : None,
+          timestamp_writes : profiler.compute_pass( "cube" )
+        }
+      );
       compute_pass.set_pipeline( &self.pipeline );
-      compute_pass.set_bind_group( 0, &self.bind_group, &[] );
+      compute_pass.set_bind_group( 0, self.bind_group.as_ref().expect( "The source is set before rendering" ), &[] );
       compute_pass.dispatch_workgroups( num_groups, num_groups, 6 );
     }
   }  
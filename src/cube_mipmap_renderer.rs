@@ -1,72 +1,92 @@
-use std::rc::Rc;
+use wgpu::util::DeviceExt;
 
-use crate::cube_texture::CubeTexture;
+use crate::{baker::MipFilter, profiler::GpuProfiler, texture::Texture};
 
+pub const SHADER_SOURCE : &str = include_str!( "shaders/mipmap.wgsl" );
 
+const WORKGROUP_SIZE : u32 = 8;
+/// Texels of the first written mip covered by a workgroup of `downsample_main`
+const TILE_SIZE : u32 = 16;
+/// Storage outputs of `mipmap.wgsl`
+const MAX_MIPS_PER_DISPATCH : u32 = 4;
 
+/// One dispatch, which reads one mip of the six faces and writes the following ones.
+struct Dispatch
+{
+  bind_group : wgpu::BindGroup,
+  workgroups : [ u32; 3 ]
+}
+
+/// Generates the mip chain of the environment cube with compute passes. The bind groups
+/// of every dispatch are created once, as the cube never changes.
 pub struct CubeMipmapRenderer
 {
-  cube_texture : Rc< CubeTexture >,
-  pipeline : wgpu::RenderPipeline,
-  sampler : wgpu::Sampler,
-  bind_group_layout : wgpu::BindGroupLayout
+  pipeline : wgpu::ComputePipeline,
+  dispatches : Vec< Dispatch >
 }
 
-impl CubeMipmapRenderer 
+impl CubeMipmapRenderer
 {
-  pub fn new( device : &wgpu::Device, cube_texture : Rc< CubeTexture > ) -> Self
+  pub fn new( device : &wgpu::Device, cube_texture : &Texture, filter : MipFilter ) -> Self
   {
-    let sampler = device.create_sampler(&wgpu::SamplerDescriptor 
+    let storage_entry = | binding : u32 | wgpu::BindGroupLayoutEntry
     {
-      label : None,
-      address_mode_u: wgpu::AddressMode::ClampToEdge,
-      address_mode_v: wgpu::AddressMode::ClampToEdge,
-      address_mode_w: wgpu::AddressMode::ClampToEdge,
-      mag_filter: wgpu::FilterMode::Linear,
-      min_filter: wgpu::FilterMode::Linear,
-      mipmap_filter: wgpu::FilterMode::Nearest,
-      ..Default::default()
-    });
+      binding,
+      visibility : wgpu::ShaderStages::COMPUTE,
+      ty : wgpu::BindingType::StorageTexture
+      {
+        access : wgpu::StorageTextureAccess::WriteOnly,
+        format : cube_texture.format(),
+        view_dimension : wgpu::TextureViewDimension::D2Array
+      },
+      count : None
+    };
 
     let bind_group_layout = device.create_bind_group_layout
     (
-      &wgpu::BindGroupLayoutDescriptor 
-      { 
-        label: None, 
+      &wgpu::BindGroupLayoutDescriptor
+      {
+        label: None,
         entries: &
         [
-          wgpu::BindGroupLayoutEntry 
-          { 
-            binding: 0, 
-            visibility: wgpu::ShaderStages::FRAGMENT, 
-            ty: wgpu::BindingType::Texture 
-            { 
-              sample_type: wgpu::TextureSampleType::Float { filterable: true }, 
-              view_dimension: wgpu::TextureViewDimension::D2, 
-              multisampled: false 
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture
+            {
+              sample_type: wgpu::TextureSampleType::Float { filterable: false },
+              view_dimension: wgpu::TextureViewDimension::D2Array,
+              multisampled: false
             },
-            count: None 
+            count: None
           },
-          wgpu::BindGroupLayoutEntry 
-          { 
-            binding: 1, 
-            visibility: wgpu::ShaderStages::FRAGMENT, 
-            ty: wgpu::BindingType::Sampler
-            (
-              wgpu::SamplerBindingType::Filtering
-            ), 
-            count: None 
-          }
-        ] 
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 1,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer
+            {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: false,
+              min_binding_size: None
+            },
+            count: None
+          },
+          storage_entry( 2 ),
+          storage_entry( 3 ),
+          storage_entry( 4 ),
+          storage_entry( 5 )
+        ]
       }
     );
 
     let shader = device.create_shader_module
-    ( 
-      wgpu::ShaderModuleDescriptor 
-      { 
-        label: None, 
-        source: wgpu::ShaderSource::Wgsl( include_str!( "shaders/mipmap.wgsl" ).into() )
+    (
+      wgpu::ShaderModuleDescriptor
+      {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl( SHADER_SOURCE.into() )
       }
     );
 
@@ -83,123 +103,114 @@ impl CubeMipmapRenderer
       }
     );
 
-    let pipeline = device.create_render_pipeline
+    let pipeline = device.create_compute_pipeline
     (
-      &wgpu::RenderPipelineDescriptor
+      &wgpu::ComputePipelineDescriptor
       {
         label : None,
         layout : Some( &pipeline_layout ),
-        vertex : wgpu::VertexState 
-        {
-          module: &shader, 
-          entry_point: None, 
-          compilation_options: wgpu::PipelineCompilationOptions::default(), 
-          buffers: &[] 
-        },
-        primitive : wgpu::PrimitiveState
+        module : &shader,
+        entry_point : Some( match filter { MipFilter::Kaiser => "downsample_kaiser_main", _ => "downsample_main" } ),
+        compilation_options : wgpu::PipelineCompilationOptions
         {
-          topology : wgpu::PrimitiveTopology::TriangleList,
+          constants : &[ ( "FILTER", filter as u32 as f64 ) ],
           ..Default::default()
         },
-        depth_stencil : None,
-        fragment : Some( wgpu::FragmentState 
-          { 
-            module: &shader, 
-            entry_point: None, 
-            compilation_options: wgpu::PipelineCompilationOptions::default(), 
-            targets: &[
-              Some( wgpu::ColorTargetState 
-                { 
-                  format: cube_texture.format(), 
-                  blend: None, 
-                  write_mask: wgpu::ColorWrites::all() 
-                }
-              )
-            ] 
-          }
-        ),
-        multisample : wgpu::MultisampleState::default(),
-        multiview : None,
         cache : None
       }
     );
 
-    Self 
-    { 
-      cube_texture, 
-      pipeline,
-      sampler,
-      bind_group_layout
-    }
-  }
-
-  pub fn generate_mipmaps( &self, device : &wgpu::Device, encoder : &mut wgpu::CommandEncoder )
-  {
-    let mip_levels = self.cube_texture.size().max_mips( wgpu::TextureDimension::D2 );
+    // Bound to the outputs a dispatch doesn't write, which the shader never stores to
+    let dummy_texture = Texture::builder( 1, 1 ).array( 6 ).format( cube_texture.format() ).usage( wgpu::TextureUsages::STORAGE_BINDING ).build( device );
+    let dummy_view = dummy_texture.create_mip_array_view( 0 );
 
-    let views = ( 0..mip_levels ).into_iter().flat_map( | mip_level | 
+    let mip_levels = cube_texture.texture().mip_level_count();
+    let mut dispatches = Vec::new();
+    let mut base_mip = 0;
+    while base_mip + 1 < mip_levels
     {
-      ( 0..6 ).into_iter().map( | array_level | 
+      let ( mip_count, texels_per_group ) = match filter
       {
-        self.cube_texture.create_mip_view( array_level, mip_level )
-      }).collect::< Vec< wgpu::TextureView > >()
-    })
-    .collect::< Vec< wgpu::TextureView > >();
+        MipFilter::Kaiser => ( 1, WORKGROUP_SIZE ),
+        _ => ( MAX_MIPS_PER_DISPATCH.min( mip_levels - base_mip - 1 ), TILE_SIZE )
+      };
 
+      let source_view = cube_texture.create_mip_array_view( base_mip );
+      let output_views = ( 0..MAX_MIPS_PER_DISPATCH )
+      .map( | i | ( i < mip_count ).then( || cube_texture.create_mip_array_view( base_mip + 1 + i ) ) )
+      .collect::< Vec< _ > >();
+      let uniform_buffer = device.create_buffer_init
+      (
+        &wgpu::util::BufferInitDescriptor
+        {
+          label : None,
+          contents : bytemuck::bytes_of( &mip_count ),
+          usage : wgpu::BufferUsages::UNIFORM
+        }
+      );
 
-    for mip_level in 1..mip_levels
-    {
-      for array_level in 0..6
+      let mut entries = vec!
+      [
+        wgpu::BindGroupEntry
+        {
+          binding : 0,
+          resource : wgpu::BindingResource::TextureView( &source_view )
+        },
+        wgpu::BindGroupEntry
+        {
+          binding : 1,
+          resource : uniform_buffer.as_entire_binding()
+        }
+      ];
+      entries.extend( output_views.iter().zip( 2.. ).map( | ( view, binding ) | wgpu::BindGroupEntry
       {
-        let src_view = &views[ ( ( mip_level - 1 ) * 6 + array_level ) as usize ];
-        let dst_view = &views[ ( mip_level * 6 + array_level ) as usize ];
+        binding,
+        resource : wgpu::BindingResource::TextureView( view.as_ref().unwrap_or( &dummy_view ) )
+      }));
 
-        let bind_group = device.create_bind_group
-        (
-          &wgpu::BindGroupDescriptor
-          {
-            label : None,
-            layout : &self.bind_group_layout,
-            entries : &[
-              wgpu::BindGroupEntry
-              {
-                binding : 0,
-                resource : wgpu::BindingResource::TextureView( src_view )
-              },
-              wgpu::BindGroupEntry
-              {
-                binding : 1,
-                resource : wgpu::BindingResource::Sampler( &self.sampler )
-              }
-            ]
-          }
-        );
-  
-        let mut render_pass = encoder.begin_render_pass
-        (
-          &wgpu::RenderPassDescriptor
-          {
-            label : None,
-            color_attachments : &
-            [
-              Some( wgpu::RenderPassColorAttachment
-              {
-                view : dst_view,
-                resolve_target : None,
-                ops : wgpu::Operations 
-                { 
-                  load: wgpu::LoadOp::Clear( wgpu::Color::WHITE ), 
-                  store: wgpu::StoreOp::Store
-                }
-              })
-            ],
-            ..Default::default()
-          }
-        );
-        render_pass.set_pipeline( &self.pipeline );
-        render_pass.set_bind_group( 0, &bind_group, &[] );
-        render_pass.draw( 0..3, 0..1 );
-      }
+      let bind_group = device.create_bind_group
+      (
+        &wgpu::BindGroupDescriptor
+        {
+          label : None,
+          layout : &bind_group_layout,
+          entries : &entries
+        }
+      );
+
+      let size = cube_texture.mip_level_size( base_mip + 1 );
+      dispatches.push( Dispatch
+      {
+        bind_group,
+        workgroups : [ size.width.div_ceil( texels_per_group ), size.height.div_ceil( texels_per_group ), 6 ]
+      });
+      base_mip += mip_count;
+    }
+
+    Self
+    {
+      pipeline,
+      dispatches
+    }
+  }
+
+  /// Each dispatch reads the last mip written by the previous one, so they run in separate passes.
+  pub fn generate_mipmaps( &self, encoder : &mut wgpu::CommandEncoder, profiler : &GpuProfiler )
+  {
+    for dispatch in &self.dispatches
+    {
+      let mut compute_pass = encoder.begin_compute_pass
+      (
+        &wgpu::ComputePassDescriptor
+        {
+          label : None,
+          timestamp_writes : profiler.compute_pass( "mips" )
+        }
+      );
+      compute_pass.set_pipeline( &self.pipeline );
+      compute_pass.set_bind_group( 0, &dispatch.bind_group, &[] );
+      let [ x, y, z ] = dispatch.workgroups;
+      compute_pass.dispatch_workgroups( x, y, z );
     }
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,164 @@
+use crate::{profiler::GpuProfiler, readback::ReadbackBuffer, texture::Texture};
+
+pub const SHADER_SOURCE : &str = include_str!( "shaders/equirect.wgsl" );
+
+/// Renders the environment cube back to an equirectangular image of any size, and copies it to its readback buffer.
+pub struct EquirectRenderer
+{
+  texture : Texture,
+  buffer : ReadbackBuffer,
+  bind_group : wgpu::BindGroup,
+  pipeline : wgpu::ComputePipeline
+}
+
+impl EquirectRenderer
+{
+  pub fn new( device : &wgpu::Device, cube_texture : &Texture, width : u32, height : u32 ) -> Self
+  {
+    let texture = Texture::builder( width, height )
+    .label( "EQUIRECT_TEXTURE" )
+    .usage( wgpu::TextureUsages::STORAGE_BINDING )
+    .build( device );
+    let buffer = texture.readback_buffer( device, 0 );
+
+    let bind_group_layout = device.create_bind_group_layout
+    (
+      &wgpu::BindGroupLayoutDescriptor
+      {
+        label: None,
+        entries: &
+        [
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture
+            {
+              sample_type: wgpu::TextureSampleType::Float { filterable: true },
+              view_dimension: wgpu::TextureViewDimension::Cube,
+              multisampled: false
+            },
+            count: None
+          },
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 1,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler
+            (
+              wgpu::SamplerBindingType::Filtering
+            ),
+            count: None
+          },
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture
+            {
+              access: wgpu::StorageTextureAccess::WriteOnly,
+              format: texture.format(),
+              view_dimension: wgpu::TextureViewDimension::D2
+            },
+            count: None
+          }
+        ]
+      }
+    );
+
+    let bind_group = device.create_bind_group
+    (
+      &wgpu::BindGroupDescriptor
+      {
+        label : None,
+        layout : &bind_group_layout,
+        entries : &
+        [
+          wgpu::BindGroupEntry
+          {
+            binding : 0,
+            resource : wgpu::BindingResource::TextureView( cube_texture.view() )
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 1,
+            resource : wgpu::BindingResource::Sampler( cube_texture.sampler() )
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 2,
+            resource : wgpu::BindingResource::TextureView( texture.view() )
+          }
+        ]
+      }
+    );
+
+    let shader = device.create_shader_module
+    (
+      wgpu::ShaderModuleDescriptor
+      {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl( SHADER_SOURCE.into() )
+      }
+    );
+
+    let pipeline_layout = device.create_pipeline_layout
+    (
+      &wgpu::PipelineLayoutDescriptor
+      {
+        label : None,
+        bind_group_layouts : &
+        [
+          &bind_group_layout
+        ],
+        push_constant_ranges : &[]
+      }
+    );
+
+    let pipeline = device.create_compute_pipeline
+    (
+      &wgpu::ComputePipelineDescriptor
+      {
+        label : None,
+        layout : Some( &pipeline_layout ),
+        module : &shader,
+        entry_point : None,
+        compilation_options : wgpu::PipelineCompilationOptions::default(),
+        cache : None
+      }
+    );
+
+    Self
+    {
+      texture,
+      buffer,
+      bind_group,
+      pipeline
+    }
+  }
+
+  /// Renders the image from the cube as it is when the encoder runs, and records its copy to the readback buffer.
+  pub fn render( &self, encoder : &mut wgpu::CommandEncoder, profiler : &GpuProfiler )
+  {
+    let size = self.texture.size();
+    {
+      let mut compute_pass = encoder.begin_compute_pass
+      (
+        &wgpu::ComputePassDescriptor
+        {
+          label : None,
+          timestamp_writes : profiler.compute_pass( "equirect" )
+        }
+      );
+      compute_pass.set_pipeline( &self.pipeline );
+      compute_pass.set_bind_group( 0, &self.bind_group, &[] );
+      compute_pass.dispatch_workgroups( size.width.div_ceil( 16 ), size.height.div_ceil( 16 ), 1 );
+    }
+    self.buffer.copy_from( encoder, self.texture.texture(), 0, 0 );
+  }
+
+  pub fn buffer( &self ) -> &ReadbackBuffer
+  {
+    &self.buffer
+  }
+}
@@ -0,0 +1,69 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde_json::json;
+
+use crate::{baker::GltfSettings, manifest::FileEntry, output::OutputImage, packing::Packing, sh::ShCoefficients};
+
+pub const GLTF_FILE : &str = "environment.gltf";
+
+/// Babylon.js decodes the specular images as RGBD over 0..255, whatever the other outputs use.
+const SPECULAR_PACKING : Packing = Packing::Rgbd;
+const SPECULAR_RANGE : f32 = 255.0;
+
+/// Writes a glTF asset whose scene uses an `EXT_lights_image_based` light, along with the
+/// specular cube faces it references. Returns every written file.
+pub fn save( dir : &Path, settings : &GltfSettings, irradiance : &ShCoefficients, specular_cube : &[ [ OutputImage; 6 ] ] ) -> anyhow::Result< Vec< FileEntry > >
+{
+  let mut files = Vec::new();
+  let mut images = Vec::new();
+  let mut specular_images = Vec::new();
+  for faces in specular_cube
+  {
+    let mut indices = Vec::with_capacity( 6 );
+    for face in faces
+    {
+      // Prefixed, so they don't collide with the cube faces written in the output format
+      let uri = format!( "gltf_{}.png", face.name );
+      let bytes = face.save_png( &dir.join( &uri ), SPECULAR_PACKING, SPECULAR_RANGE )?;
+      files.push( FileEntry { path : uri.clone(), bytes } );
+      indices.push( images.len() );
+      images.push( json!( { "uri" : uri, "mimeType" : "image/png" } ) );
+    }
+    specular_images.push( indices );
+  }
+
+  let half_angle = settings.rotation.to_radians() * 0.5;
+  let gltf = json!(
+  {
+    "asset" : { "version" : "2.0", "generator" : format!( "{} {}", env!( "CARGO_PKG_NAME" ), env!( "CARGO_PKG_VERSION" ) ) },
+    "extensionsUsed" : [ "EXT_lights_image_based" ],
+    "extensions" :
+    {
+      "EXT_lights_image_based" :
+      {
+        "lights" :
+        [
+          {
+            "name" : "environment",
+            "rotation" : [ 0.0, half_angle.sin(), 0.0, half_angle.cos() ],
+            "intensity" : settings.intensity,
+            "irradianceCoefficients" : irradiance,
+            "specularImageSize" : specular_cube.first().map_or( 0, | faces | faces[ 0 ].width ),
+            "specularImages" : specular_images
+          }
+        ]
+      }
+    },
+    "scene" : 0,
+    "scenes" : [ { "nodes" : [], "extensions" : { "EXT_lights_image_based" : { "light" : 0 } } } ],
+    "images" : images
+  });
+
+  let path = dir.join( GLTF_FILE );
+  let json = serde_json::to_string_pretty( &gltf )?;
+  std::fs::write( &path, &json ).with_context( || format!( "Failed to write {}", path.display() ) )?;
+  files.push( FileEntry { path : GLTF_FILE.into(), bytes : json.len() as u64 } );
+
+  Ok( files )
+}
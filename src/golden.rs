@@ -0,0 +1,338 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::{baker::{BakeOutputs, BakeSettings, Baker, CubeResample, MipFilter, OutputLayout, SourceImage}, image_diff::{self, ImageDiff}, output::{OutputFormat, OutputImage}};
+
+/// Goldens and their inputs, relative to the root of the repository. Every case has its input
+/// in `inputs/<case>.hdr` and its goldens in `<case>/`, as 32-bit float EXRs.
+pub const GOLDEN_DIR : &str = "tests/golden";
+
+/// Where the heatmaps of the outputs that differ from their goldens go by default.
+pub const DIFF_DIR : &str = "target/golden-diff";
+
+/// One checked-in input and the settings it is baked with. The settings vary between the cases
+/// to go through the resampling, mip filters and layouts of the shaders.
+struct Case
+{
+  name : &'static str,
+  settings : fn() -> BakeSettings
+}
+
+const CASES : [ Case; 3 ] =
+[
+  // A small sun much brighter than the sky, where the prefiltering and its mip selection show most
+  Case { name : "sun", settings : | | BakeSettings { mip_filter : MipFilter::Box, ..base_settings() } },
+  // Hard colored edges, for the area resampling and the Kaiser mips
+  Case
+  {
+    name : "checker",
+    settings : | | BakeSettings { cube_resample : CubeResample::Area, mip_filter : MipFilter::Kaiser, ..base_settings() }
+  },
+  // Area lights on a dark background, in the octahedral layout
+  Case
+  {
+    name : "studio",
+    settings : | | BakeSettings
+    {
+      cube_resample : CubeResample::Supersample,
+      mip_filter : MipFilter::SolidAngle,
+      layout : OutputLayout::Octahedral,
+      diffuse_height : 32,
+      specular_1_width : 64,
+      specular_1_height : 64,
+      ..base_settings()
+    }
+  }
+];
+
+/// Every output, small enough for the CPU reference to bake the cases in seconds.
+fn base_settings() -> BakeSettings
+{
+  BakeSettings
+  {
+    cube_size : 32,
+    diffuse_width : 32,
+    diffuse_height : 16,
+    specular_1_width : 32,
+    specular_1_height : 16,
+    specular_2_width : 16,
+    specular_2_height : 16,
+    diffuse_samples : 16,
+    specular_samples : 128,
+    lut_samples : 128,
+    specular_cube_size : Some( 8 ),
+    equirect_size : Some( ( 32, 16 ) ),
+    ..BakeSettings::default()
+  }
+}
+
+/// Largest differences an output may have from its golden. They leave room for the GPU backends to match
+/// goldens blessed on the CPU and the other way around, the Monte Carlo specular outputs the most.
+#[ derive( Clone, Copy, Debug ) ]
+pub struct Threshold
+{
+  pub min_psnr : f32,
+  pub max_relative_error : f32
+}
+
+pub fn threshold( output : &str ) -> Threshold
+{
+  match output
+  {
+    "diffuse" | "specular_2" | "environment" => Threshold { min_psnr : 40.0, max_relative_error : 0.02 },
+    _ => Threshold { min_psnr : 35.0, max_relative_error : 0.05 }
+  }
+}
+
+/// Comparison of one output of a case to its golden.
+pub struct OutputCheck
+{
+  pub backend : String,
+  pub case : String,
+  pub output : String,
+  /// `None` when the output couldn't be compared
+  pub diff : Option< ImageDiff >,
+  /// Why the check failed
+  pub failure : Option< String >
+}
+
+impl OutputCheck
+{
+  pub fn passed( &self ) -> bool
+  {
+    self.failure.is_none()
+  }
+}
+
+/// Bakes every case with `bake` and compares its outputs to the goldens under `dir`. Outputs without a golden
+/// and goldens without an output fail. Heatmaps of the relative error of the outputs that differ too much
+/// are written to `<diff_dir>/<backend>/<case>/<output>.png`.
+pub fn check
+(
+  dir : &Path,
+  diff_dir : &Path,
+  backend : &str,
+  mut bake : impl FnMut( &SourceImage, &BakeSettings ) -> BakeOutputs
+) -> anyhow::Result< Vec< OutputCheck > >
+{
+  let mut checks = Vec::new();
+  for case in &CASES
+  {
+    let source = SourceImage::load( &input_path( dir, case ) )?;
+    let outputs = bake( &source, &( case.settings )() );
+    let golden_dir = dir.join( case.name );
+    let new_check = | output : &str, diff, failure | OutputCheck
+    {
+      backend : backend.into(),
+      case : case.name.into(),
+      output : output.into(),
+      diff,
+      failure
+    };
+
+    for image in outputs.images()
+    {
+      let golden_path = golden_dir.join( image.file_name( OutputFormat::Exr ) );
+      if !golden_path.exists()
+      {
+        checks.push( new_check( &image.name, None, Some( "no golden, bless it with --bless".into() ) ) );
+        continue;
+      }
+
+      let threshold = threshold( &image.name );
+      let compared = OutputImage::load( &golden_path ).and_then( | golden | Ok( ( ImageDiff::new( image, &golden )?, golden ) ) );
+      let ( diff, golden ) = match compared
+      {
+        Ok( compared ) => compared,
+        Err( e ) =>
+        {
+          checks.push( new_check( &image.name, None, Some( format!( "{:#}", e ) ) ) );
+          continue;
+        }
+      };
+
+      let failure = if diff.psnr.is_nan() || diff.psnr < threshold.min_psnr
+      {
+        Some( format!( "PSNR of {:.1} dB under {:.1}", diff.psnr, threshold.min_psnr ) )
+      }
+      else if diff.max_relative_error > threshold.max_relative_error
+      {
+        Some( format!
+        (
+          "relative error of {:.4} at {:?} over {}",
+          diff.max_relative_error, diff.max_error_at, threshold.max_relative_error
+        ))
+      }
+      else
+      {
+        None
+      };
+
+      let failure = match failure
+      {
+        Some( failure ) =>
+        {
+          let heatmap = diff_dir.join( backend ).join( case.name ).join( format!( "{}.png", image.name ) );
+          Some( match image_diff::save_heatmap( &heatmap, image, &golden, threshold.max_relative_error )
+          {
+            Ok( () ) => format!( "{}, see {}", failure, heatmap.display() ),
+            Err( e ) => format!( "{}, {:#}", failure, e )
+          })
+        },
+        None => None
+      };
+      checks.push( new_check( &image.name, Some( diff ), failure ) );
+    }
+
+    for stale in golden_files( &golden_dir )?
+    {
+      if !outputs.images().any( | image | image.name == stale )
+      {
+        checks.push( new_check( &stale, None, Some( "golden of an output the bake no longer has, bless to remove it".into() ) ) );
+      }
+    }
+  }
+  Ok( checks )
+}
+
+/// Bakes every case with `bake` and replaces its goldens under `dir` with the outputs. Returns the number of files written.
+pub fn bless( dir : &Path, mut bake : impl FnMut( &SourceImage, &BakeSettings ) -> BakeOutputs ) -> anyhow::Result< usize >
+{
+  let mut count = 0;
+  for case in &CASES
+  {
+    let source = SourceImage::load( &input_path( dir, case ) )?;
+    let outputs = bake( &source, &( case.settings )() );
+
+    let golden_dir = dir.join( case.name );
+    std::fs::create_dir_all( &golden_dir ).with_context( || format!( "Failed to create {}", golden_dir.display() ) )?;
+    for stale in golden_files( &golden_dir )?
+    {
+      let path = golden_dir.join( format!( "{}.{}", stale, OutputFormat::Exr.extension() ) );
+      std::fs::remove_file( &path ).with_context( || format!( "Failed to remove {}", path.display() ) )?;
+    }
+    for image in outputs.images()
+    {
+      image.save( &golden_dir, OutputFormat::Exr )?;
+      count += 1;
+    }
+  }
+  Ok( count )
+}
+
+/// Blesses the goldens under `dir` with the outputs of `bake`, or compares them and prints the result,
+/// failing when an output differs.
+pub fn run
+(
+  dir : &Path,
+  diff_dir : &Path,
+  bless : bool,
+  backend : &str,
+  bake : impl FnMut( &SourceImage, &BakeSettings ) -> BakeOutputs
+) -> anyhow::Result< () >
+{
+  if bless
+  {
+    let count = self::bless( dir, bake )?;
+    println!( "Wrote {} goldens to {} with the {} backend", count, dir.display(), backend );
+    return Ok( () );
+  }
+
+  let checks = check( dir, diff_dir, backend, bake )?;
+  print( &checks );
+  let failed = checks.iter().filter( | check | !check.passed() ).count();
+  if failed > 0
+  {
+    anyhow::bail!( "{} of {} outputs differ from their goldens", failed, checks.len() );
+  }
+  Ok( () )
+}
+
+/// Bakes `source` on the GPU and reads the outputs back, for [`check`] and [`bless`].
+pub fn bake_gpu( device : &wgpu::Device, queue : &wgpu::Queue, source : &SourceImage, settings : &BakeSettings ) -> BakeOutputs
+{
+  let hdr_texture = source.to_texture( device, queue );
+  let mut baker = Baker::new( device, queue, settings, false );
+  baker.set_source( device, &hdr_texture );
+  baker.bake( device, queue );
+  device.poll( wgpu::PollType::wait() ).expect( "Failed to wait for the bake" );
+  pollster::block_on( baker.read_outputs( device ) ).expect( "Failed to read back the bake" ).decode()
+}
+
+/// Prints one line per output, with the failures last.
+pub fn print( checks : &[ OutputCheck ] )
+{
+  println!( "{:<8} {:<8} {:<22} {:>10} {:>8} {:>9}  result", "backend", "case", "output", "rmse", "psnr", "max rel" );
+  let ( passed, failed ) : ( Vec< _ >, Vec< _ > ) = checks.iter().partition( | check | check.passed() );
+  for check in passed.into_iter().chain( failed )
+  {
+    let ( rmse, psnr, max_relative_error ) = match check.diff
+    {
+      Some( diff ) => ( format!( "{:.3e}", diff.rmse ), format!( "{:.1}", diff.psnr ), format!( "{:.4}", diff.max_relative_error ) ),
+      None => ( "-".into(), "-".into(), "-".into() )
+    };
+    println!
+    (
+      "{:<8} {:<8} {:<22} {:>10} {:>8} {:>9}  {}",
+      check.backend, check.case, check.output, rmse, psnr, max_relative_error, check.failure.as_deref().unwrap_or( "ok" )
+    );
+  }
+}
+
+fn input_path( dir : &Path, case : &Case ) -> PathBuf
+{
+  dir.join( "inputs" ).join( format!( "{}.hdr", case.name ) )
+}
+
+/// Names of the goldens in `golden_dir`, empty when it doesn't exist yet.
+fn golden_files( golden_dir : &Path ) -> anyhow::Result< Vec< String > >
+{
+  if !golden_dir.exists()
+  {
+    return Ok( Vec::new() );
+  }
+
+  let mut names = Vec::new();
+  for entry in std::fs::read_dir( golden_dir ).with_context( || format!( "Failed to list {}", golden_dir.display() ) )?
+  {
+    let path = entry?.path();
+    if path.extension().is_some_and( | extension | extension == OutputFormat::Exr.extension() )
+    {
+      names.push( path.file_stem().unwrap_or_default().to_string_lossy().into_owned() );
+    }
+  }
+  Ok( names )
+}
+
+/// The CPU reference always has to match the goldens, and the render and compute passes too when an adapter is available.
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+  use crate::{cpu_baker, gpu};
+
+  #[ test ]
+  fn matches_goldens()
+  {
+    let root = Path::new( env!( "CARGO_MANIFEST_DIR" ) );
+    let ( dir, diff_dir ) = ( root.join( GOLDEN_DIR ), root.join( DIFF_DIR ) );
+
+    let mut checks = check( &dir, &diff_dir, "cpu", cpu_baker::bake ).unwrap();
+    match gpu::test_device()
+    {
+      Some( ( device, queue ) ) => for ( backend, compute ) in [ ( "render", false ), ( "compute", true ) ]
+      {
+        let bake = | source : &SourceImage, settings : &BakeSettings | bake_gpu( &device, &queue, source, &BakeSettings { compute, ..*settings } );
+        checks.extend( check( &dir, &diff_dir, backend, bake ).unwrap() );
+      },
+      None => eprintln!( "No suitable GPU adapter, only the CPU reference is compared to the goldens" )
+    }
+
+    let failures : Vec< _ > = checks.iter()
+    .filter( | check | !check.passed() )
+    .map( | check | format!( "{} {} {}: {}", check.backend, check.case, check.output, check.failure.as_deref().unwrap_or_default() ) )
+    .collect();
+    assert!( failures.is_empty(), "{} of {} outputs differ from their goldens:\n{}", failures.len(), checks.len(), failures.join( "\n" ) );
+  }
+}
@@ -0,0 +1,128 @@
+use anyhow::Context;
+
+use crate::baker::BakeSettings;
+
+/// Features every renderer in the tool relies on. The environment cube is `Rgba32Float`
+/// and is sampled with linear filtering.
+pub const REQUIRED_FEATURES : wgpu::Features = wgpu::Features::FLOAT32_FILTERABLE;
+
+/// Features enabled when the adapter has them. They extend the choice of output formats
+/// and let `--profile` time the passes on the GPU.
+pub const OPTIONAL_FEATURES : wgpu::Features = wgpu::Features::RG11B10UFLOAT_RENDERABLE
+.union( wgpu::Features::TIMESTAMP_QUERY );
+
+/// Name of an output format, as `--diffuse-format` and the others take it and the manifest records it.
+pub fn format_name( format : wgpu::TextureFormat ) -> &'static str
+{
+  match format
+  {
+    wgpu::TextureFormat::Rgba32Float => "rgba32f",
+    wgpu::TextureFormat::Rgba16Float => "rgba16f",
+    wgpu::TextureFormat::Rg11b10Ufloat => "rg11b10f",
+    wgpu::TextureFormat::Rg16Float => "rg16f",
+    _ => "unknown"
+  }
+}
+
+pub fn create_instance() -> wgpu::Instance
+{
+  wgpu::Instance::new
+  (
+    &wgpu::InstanceDescriptor
+    {
+      backends: wgpu::Backends::all(),
+      ..Default::default()
+    }
+  )
+}
+
+/// Requests an adapter and a device. When `compatible_surface` is `None` the device is headless,
+/// which is what batch baking uses.
+pub async fn create_device
+(
+  instance : &wgpu::Instance,
+  compatible_surface : Option< &wgpu::Surface< '_ > >
+) -> anyhow::Result< ( wgpu::Adapter, wgpu::Device, wgpu::Queue ) >
+{
+  let adapter = instance.request_adapter
+  (
+    &wgpu::RequestAdapterOptions
+    {
+      power_preference: wgpu::PowerPreference::default(),
+      compatible_surface,
+      force_fallback_adapter: false,
+    },
+  ).await.context( "No suitable GPU adapter found" )?;
+
+  let ( device, queue ) = adapter.request_device
+  (
+    &wgpu::DeviceDescriptor
+    {
+      required_features : REQUIRED_FEATURES | ( adapter.features() & OPTIONAL_FEATURES ),
+      ..Default::default()
+    }
+  ).await.context( "Failed to create the device" )?;
+
+  Ok( ( adapter, device, queue ) )
+}
+
+/// Headless device for the tests, on the software adapter when there is one and on any adapter otherwise.
+/// Panics when no adapter has the required features, unless `IBL_SKIP_GPU_TESTS` is set: `None` then,
+/// and the tests that need the GPU are skipped.
+#[ cfg( test ) ]
+pub fn test_device() -> Option< ( wgpu::Device, wgpu::Queue ) >
+{
+  let instance = create_instance();
+  let device = [ true, false ].into_iter().find_map( | force_fallback_adapter |
+  {
+    let adapter = pollster::block_on( instance.request_adapter
+    (
+      &wgpu::RequestAdapterOptions
+      {
+        power_preference : wgpu::PowerPreference::default(),
+        compatible_surface : None,
+        force_fallback_adapter
+      }
+    ))
+    .ok()?;
+    pollster::block_on( adapter.request_device
+    (
+      &wgpu::DeviceDescriptor
+      {
+        required_features : REQUIRED_FEATURES | ( adapter.features() & OPTIONAL_FEATURES ),
+        ..Default::default()
+      }
+    ))
+    .ok()
+  });
+  assert!
+  (
+    device.is_some() || std::env::var_os( "IBL_SKIP_GPU_TESTS" ).is_some(),
+    "No GPU adapter with the required features, set IBL_SKIP_GPU_TESTS to skip the tests that need one"
+  );
+  device
+}
+
+/// Fails when one of the output formats of `settings` can't be rendered to on `device`.
+pub fn check_output_formats( device : &wgpu::Device, settings : &BakeSettings ) -> anyhow::Result< () >
+{
+  let formats = [ settings.diffuse_format, settings.specular_format, settings.lut_format ];
+  for format in formats
+  {
+    let required = format.required_features();
+    if !device.features().contains( required )
+    {
+      anyhow::bail!( "The output format {:?} needs {:?}, which the adapter doesn't support", format, required );
+    }
+    if format == wgpu::TextureFormat::Rg11b10Ufloat && !device.features().contains( wgpu::Features::RG11B10UFLOAT_RENDERABLE )
+    {
+      anyhow::bail!( "The adapter can't render to {:?}", format );
+    }
+    let storage = format.guaranteed_format_features( device.features() ).allowed_usages.contains( wgpu::TextureUsages::STORAGE_BINDING );
+    if settings.compute && !storage
+    {
+      anyhow::bail!( "The compute passes can't write {:?}, use rgba32f or rgba16f", format );
+    }
+  }
+  Ok( () )
+}
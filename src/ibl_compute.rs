@@ -0,0 +1,759 @@
+use std::f32::consts::PI;
+
+use wgpu::util::DeviceExt;
+
+use crate::{baker::BakeSettings, ibl_renderer, profiler::GpuProfiler, progressive::{self, ProgressiveSettings, NOISE_SIZE}, texture::Texture, tiling::Tile};
+
+/// Appended to [`ibl_renderer::SHADER_SOURCE`], whose bindings and helpers it shares.
+pub const SHADER_SOURCE : &str = include_str!( "shaders/ibl_compute.wgsl" );
+
+const WORKGROUP_SIZE : u32 = 8;
+
+/// Mirrors `Sample` in `ibl_compute.wgsl`, which is padded to 32 bytes.
+#[ repr( C ) ]
+#[ derive( Clone, Copy, bytemuck::Pod, bytemuck::Zeroable ) ]
+struct Sample
+{
+  direction : [ f32; 3 ],
+  lod : f32,
+  weight : f32,
+  padding : [ f32; 3 ]
+}
+
+/// Textures the compute passes write to. They are owned by the [`ibl_renderer::IBLRenderer`].
+pub struct ComputeTargets< 'a >
+{
+  pub diffuse : &'a Texture,
+  pub specular : &'a Texture,
+  /// Mips of `specular` that are prefiltered
+  pub specular_mips : u32,
+  pub lut : &'a Texture,
+  pub specular_cube : Option< &'a Texture >
+}
+
+struct Output
+{
+  bind_group : wgpu::BindGroup,
+  size : wgpu::Extent3d,
+  /// Batch sums of the progressive passes
+  accumulator : Option< Accumulator >
+}
+
+struct Accumulator
+{
+  buffer : wgpu::Buffer,
+  /// Binds only the buffer, for `compute_noise_main`
+  bind_group : wgpu::BindGroup
+}
+
+/// Compute versions of the irradiance, specular prefilter and BRDF LUT passes. Sample directions,
+/// weights and mip LODs are computed once on the CPU, each workgroup stages them in shared memory.
+pub struct IBLCompute
+{
+  bind_group : wgpu::BindGroup,
+  diffuse_pipeline : wgpu::ComputePipeline,
+  specular_pipeline : wgpu::ComputePipeline,
+  lut_pipeline : wgpu::ComputePipeline,
+  specular_cube_pipeline : wgpu::ComputePipeline,
+  diffuse_output : Output,
+  specular_outputs : Vec< Output >,
+  lut_output : Output,
+  specular_cube_outputs : Vec< Output >,
+  noise_pipeline : wgpu::ComputePipeline,
+  /// Index of the batch the progressive passes add, see [`IBLCompute::set_batch`]
+  batch_buffer : wgpu::Buffer,
+  /// Tiles per row of the tile origin table, which holds one origin per dynamic offset
+  tile_columns : u32,
+  tile_stride : u32
+}
+
+impl IBLCompute
+{
+  /// The specular sample table holds one list of [`specular_table_len`] samples per mip, the mips of
+  /// `specular` first and then those of `specular_cube`. `uniforms.sample_offset` indexes it.
+  /// With `BakeSettings::progressive` the lists are made of batches, each one a shifted copy of the same pattern.
+  pub fn new
+  (
+    device : &wgpu::Device,
+    settings : &BakeSettings,
+    env_map : &Texture,
+    uniform_buffer : &wgpu::Buffer,
+    targets : ComputeTargets
+  ) -> Self
+  {
+    let env_size = env_map.size().width;
+    let cube_mips = targets.specular_cube.map_or( 0, | cube | cube.texture().mip_level_count() );
+    let roughness = ( 0..targets.specular_mips ).map( | mip | ibl_renderer::mip_roughness( mip, targets.specular_mips ) )
+    .chain( ( 0..cube_mips ).map( | mip | ibl_renderer::mip_roughness( mip, cube_mips ) ) );
+
+    let ( diffuse_samples, specular_samples ) = match &settings.progressive
+    {
+      Some( progressive ) =>
+      (
+        progressive_diffuse_samples( progressive, progressive.diffuse_batches( settings ), env_size ),
+        roughness.flat_map( | r | progressive_specular_samples( r, progressive, progressive.specular_batches( settings ), env_size ) ).collect::< Vec< _ > >()
+      ),
+      None =>
+      (
+        diffuse_samples( settings.diffuse_samples, env_size ),
+        roughness.flat_map( | r | specular_samples( r, settings.specular_samples, env_size ) ).collect::< Vec< _ > >()
+      )
+    };
+    let lut_samples = ( 0..settings.lut_samples ).map( | i | hammersley( i, settings.lut_samples ) ).collect::< Vec< _ > >();
+
+    let storage_buffer = | contents : &[ u8 ] | device.create_buffer_init
+    (
+      &wgpu::util::BufferInitDescriptor
+      {
+        label : None,
+        contents,
+        usage : wgpu::BufferUsages::STORAGE
+      }
+    );
+    let diffuse_buffer = storage_buffer( bytemuck::cast_slice( &diffuse_samples ) );
+    let specular_buffer = storage_buffer( bytemuck::cast_slice( &specular_samples ) );
+    let lut_buffer = storage_buffer( bytemuck::cast_slice( &lut_samples ) );
+
+    // Origins of every tile of the largest output, the dispatches of smaller outputs use the first ones
+    let max_size = [ targets.diffuse.size(), targets.specular.size(), targets.lut.size() ].into_iter()
+    .chain( targets.specular_cube.map( | cube | cube.size() ) )
+    .map( | size | size.width.max( size.height ) )
+    .max()
+    .unwrap_or( 1 );
+    let tile_columns = settings.tiling.columns( max_size );
+    let tile_size = settings.tiling.tile_size.unwrap_or( max_size );
+    let tile_stride = device.limits().min_uniform_buffer_offset_alignment;
+    let mut tile_data = vec![ 0u8; ( tile_columns * tile_columns * tile_stride ) as usize ];
+    for row in 0..tile_columns
+    {
+      for column in 0..tile_columns
+      {
+        let offset = ( ( row * tile_columns + column ) * tile_stride ) as usize;
+        tile_data[ offset..offset + 8 ].copy_from_slice( bytemuck::bytes_of( &[ column * tile_size, row * tile_size ] ) );
+      }
+    }
+    let tile_buffer = device.create_buffer_init
+    (
+      &wgpu::util::BufferInitDescriptor
+      {
+        label : None,
+        contents : &tile_data,
+        usage : wgpu::BufferUsages::UNIFORM
+      }
+    );
+
+    let batch_buffer = device.create_buffer
+    (
+      &wgpu::BufferDescriptor
+      {
+        label : None,
+        size : 16,
+        mapped_at_creation : false,
+        usage : wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST
+      }
+    );
+
+    let storage_entry = | binding : u32 | wgpu::BindGroupLayoutEntry
+    {
+      binding,
+      visibility : wgpu::ShaderStages::COMPUTE,
+      ty : wgpu::BindingType::Buffer
+      {
+        ty : wgpu::BufferBindingType::Storage { read_only : true },
+        has_dynamic_offset : false,
+        min_binding_size : None
+      },
+      count : None
+    };
+
+    let bind_group_layout = device.create_bind_group_layout
+    (
+      &wgpu::BindGroupLayoutDescriptor
+      {
+        label: None,
+        entries: &
+        [
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture
+            {
+              sample_type: wgpu::TextureSampleType::Float { filterable: true },
+              view_dimension: wgpu::TextureViewDimension::Cube,
+              multisampled: false
+            },
+            count: None
+          },
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 1,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Sampler
+            (
+              wgpu::SamplerBindingType::Filtering
+            ),
+            count: None
+          },
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer
+            {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: true,
+              min_binding_size: ibl_renderer::UNIFORM_SIZE
+            },
+            count: None
+          },
+          storage_entry( 3 ),
+          storage_entry( 4 ),
+          storage_entry( 5 ),
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 6,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer
+            {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: true,
+              min_binding_size: wgpu::BufferSize::new( 8 )
+            },
+            count: None
+          },
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 7,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer
+            {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: false,
+              min_binding_size: None
+            },
+            count: None
+          }
+        ]
+      }
+    );
+
+    let bind_group = device.create_bind_group
+    (
+      &wgpu::BindGroupDescriptor
+      {
+        label : None,
+        layout : &bind_group_layout,
+        entries : &[
+          wgpu::BindGroupEntry
+          {
+            binding : 0,
+            resource : wgpu::BindingResource::TextureView( env_map.view() )
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 1,
+            resource : wgpu::BindingResource::Sampler( env_map.sampler() )
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 2,
+            resource : wgpu::BindingResource::Buffer
+            (
+              wgpu::BufferBinding
+              {
+                buffer : uniform_buffer,
+                offset : 0,
+                size : ibl_renderer::UNIFORM_SIZE
+              }
+            )
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 3,
+            resource : diffuse_buffer.as_entire_binding()
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 4,
+            resource : specular_buffer.as_entire_binding()
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 5,
+            resource : lut_buffer.as_entire_binding()
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 6,
+            resource : wgpu::BindingResource::Buffer
+            (
+              wgpu::BufferBinding
+              {
+                buffer : &tile_buffer,
+                offset : 0,
+                size : wgpu::BufferSize::new( 8 )
+              }
+            )
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 7,
+            resource : batch_buffer.as_entire_binding()
+          },
+        ]
+      }
+    );
+
+    let source = format!( "{}\n{}", ibl_renderer::SHADER_SOURCE, SHADER_SOURCE )
+    .replace( "DIFFUSE_STORAGE_FORMAT", storage_format_name( settings.diffuse_format ) )
+    .replace( "SPECULAR_STORAGE_FORMAT", storage_format_name( settings.specular_format ) )
+    .replace( "LUT_STORAGE_FORMAT", storage_format_name( settings.lut_format ) );
+    let shader = device.create_shader_module
+    (
+      wgpu::ShaderModuleDescriptor
+      {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl( source.into() )
+      }
+    );
+
+    let progressive = settings.progressive.unwrap_or_default();
+    let constants =
+    [
+      ( "DIFFUSE_SAMPLES", settings.diffuse_samples as f64 ),
+      ( "SPECULAR_SAMPLES", settings.specular_samples as f64 ),
+      ( "LUT_SAMPLES", settings.lut_samples as f64 ),
+      ( "LAYOUT", settings.layout as u32 as f64 ),
+      ( "DIFFUSE_BATCH", progressive.diffuse_batch_axis().pow( 2 ) as f64 ),
+      ( "SPECULAR_BATCH", progressive.batch_samples as f64 )
+    ];
+
+    let accumulator_entry = wgpu::BindGroupLayoutEntry
+    {
+      binding : 4,
+      visibility : wgpu::ShaderStages::COMPUTE,
+      ty : wgpu::BindingType::Buffer
+      {
+        ty : wgpu::BufferBindingType::Storage { read_only : false },
+        has_dynamic_offset : false,
+        min_binding_size : None
+      },
+      count : None
+    };
+    let accumulator_layout = device.create_bind_group_layout
+    (
+      &wgpu::BindGroupLayoutDescriptor
+      {
+        label : None,
+        entries : &[ accumulator_entry ]
+      }
+    );
+
+    // Every pass writes to its own binding of group 1. The progressive passes also bind their accumulator.
+    let create_pass = | binding : u32, format : wgpu::TextureFormat, view_dimension : wgpu::TextureViewDimension, entry_point : &str, accumulate : bool |
+    {
+      let texture_entry = wgpu::BindGroupLayoutEntry
+      {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture
+        {
+          access: wgpu::StorageTextureAccess::WriteOnly,
+          format,
+          view_dimension
+        },
+        count: None
+      };
+      let entries = if accumulate { vec![ texture_entry, accumulator_entry ] } else { vec![ texture_entry ] };
+      let output_layout = device.create_bind_group_layout
+      (
+        &wgpu::BindGroupLayoutDescriptor
+        {
+          label: None,
+          entries: &entries
+        }
+      );
+
+      let pipeline_layout = device.create_pipeline_layout
+      (
+        &wgpu::PipelineLayoutDescriptor
+        {
+          label : None,
+          bind_group_layouts : &
+          [
+            &bind_group_layout,
+            &output_layout
+          ],
+          push_constant_ranges : &[]
+        }
+      );
+
+      let pipeline = device.create_compute_pipeline
+      (
+        &wgpu::ComputePipelineDescriptor
+        {
+          label : None,
+          layout : Some( &pipeline_layout ),
+          module : &shader,
+          entry_point : Some( entry_point ),
+          compilation_options : wgpu::PipelineCompilationOptions { constants : &constants, ..Default::default() },
+          cache : None
+        }
+      );
+
+      let accumulator_layout = &accumulator_layout;
+      let output = move | view : &wgpu::TextureView, size : wgpu::Extent3d |
+      {
+        // The even and odd sums of every texel after the noise
+        let accumulator = accumulate.then( || device.create_buffer
+        (
+          &wgpu::BufferDescriptor
+          {
+            label : None,
+            size : ( NOISE_SIZE as u32 + size.width * size.height * size.depth_or_array_layers * 2 * 16 ) as u64,
+            mapped_at_creation : false,
+            usage : wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC
+          }
+        ));
+
+        let mut entries = vec![
+          wgpu::BindGroupEntry
+          {
+            binding,
+            resource : wgpu::BindingResource::TextureView( view )
+          }
+        ];
+        entries.extend( accumulator.iter().map( | buffer | wgpu::BindGroupEntry { binding : 4, resource : buffer.as_entire_binding() } ) );
+
+        Output
+        {
+          bind_group : device.create_bind_group
+          (
+            &wgpu::BindGroupDescriptor
+            {
+              label : None,
+              layout : &output_layout,
+              entries : &entries
+            }
+          ),
+          size,
+          accumulator : accumulator.map( | buffer | Accumulator
+          {
+            bind_group : device.create_bind_group
+            (
+              &wgpu::BindGroupDescriptor
+              {
+                label : None,
+                layout : accumulator_layout,
+                entries : &[ wgpu::BindGroupEntry { binding : 4, resource : buffer.as_entire_binding() } ]
+              }
+            ),
+            buffer
+          })
+        }
+      };
+
+      ( pipeline, output )
+    };
+
+    let accumulate = settings.progressive.is_some();
+    let entry_point = | name : &str | if accumulate { format!( "{}_progressive_main", name ) } else { format!( "{}_main", name ) };
+    let ( diffuse_pipeline, diffuse_output ) = create_pass
+    (
+      0,
+      settings.diffuse_format,
+      wgpu::TextureViewDimension::D2,
+      &entry_point( "compute_diffuse" ),
+      accumulate
+    );
+    let ( specular_pipeline, specular_output ) = create_pass
+    (
+      1,
+      settings.specular_format,
+      wgpu::TextureViewDimension::D2,
+      &entry_point( "compute_specular" ),
+      accumulate
+    );
+    let ( lut_pipeline, lut_output ) = create_pass( 2, settings.lut_format, wgpu::TextureViewDimension::D2, "compute_lut_main", false );
+    let ( specular_cube_pipeline, specular_cube_output ) = create_pass
+    (
+      3,
+      settings.specular_format,
+      wgpu::TextureViewDimension::D2Array,
+      &entry_point( "compute_specular_cube" ),
+      accumulate
+    );
+
+    let noise_layout = device.create_pipeline_layout
+    (
+      &wgpu::PipelineLayoutDescriptor
+      {
+        label : None,
+        bind_group_layouts : &
+        [
+          &bind_group_layout,
+          &accumulator_layout
+        ],
+        push_constant_ranges : &[]
+      }
+    );
+    let noise_pipeline = device.create_compute_pipeline
+    (
+      &wgpu::ComputePipelineDescriptor
+      {
+        label : None,
+        layout : Some( &noise_layout ),
+        module : &shader,
+        entry_point : Some( "compute_noise_main" ),
+        compilation_options : wgpu::PipelineCompilationOptions { constants : &constants, ..Default::default() },
+        cache : None
+      }
+    );
+
+    let diffuse_output = diffuse_output( targets.diffuse.view(), targets.diffuse.size() );
+    let specular_outputs = ( 0..targets.specular_mips )
+    .map( | mip | specular_output( &targets.specular.create_mip_view( mip ), targets.specular.mip_level_size( mip ) ) )
+    .collect();
+    let lut_output = lut_output( targets.lut.view(), targets.lut.size() );
+    let specular_cube_outputs = targets.specular_cube.iter()
+    .flat_map( | cube | ( 0..cube_mips ).map( | mip | ( cube.create_mip_array_view( mip ), cube.mip_level_size( mip ) ) ) )
+    .map( | ( view, size ) | specular_cube_output( &view, wgpu::Extent3d { depth_or_array_layers : 6, ..size } ) )
+    .collect();
+
+    Self
+    {
+      bind_group,
+      diffuse_pipeline,
+      specular_pipeline,
+      lut_pipeline,
+      specular_cube_pipeline,
+      diffuse_output,
+      specular_outputs,
+      lut_output,
+      specular_cube_outputs,
+      noise_pipeline,
+      batch_buffer,
+      tile_columns,
+      tile_stride
+    }
+  }
+
+  pub fn diffuse( &self, encoder : &mut wgpu::CommandEncoder, profiler : &GpuProfiler, uniform_offset : u32, tile : &Tile )
+  {
+    self.dispatch( encoder, profiler.compute_pass( "diffuse" ), &self.diffuse_pipeline, &self.diffuse_output, uniform_offset, tile );
+  }
+
+  pub fn specular( &self, encoder : &mut wgpu::CommandEncoder, profiler : &GpuProfiler, mip_level : u32, uniform_offset : u32, tile : &Tile )
+  {
+    let timestamp_writes = profiler.compute_pass( &format!( "specular mip {}", mip_level ) );
+    self.dispatch( encoder, timestamp_writes, &self.specular_pipeline, &self.specular_outputs[ mip_level as usize ], uniform_offset, tile );
+  }
+
+  /// Writes `tile` of the six faces of `mip_level` in one dispatch.
+  pub fn specular_cube( &self, encoder : &mut wgpu::CommandEncoder, profiler : &GpuProfiler, mip_level : u32, uniform_offset : u32, tile : &Tile )
+  {
+    let timestamp_writes = profiler.compute_pass( &format!( "specular cube mip {}", mip_level ) );
+    self.dispatch( encoder, timestamp_writes, &self.specular_cube_pipeline, &self.specular_cube_outputs[ mip_level as usize ], uniform_offset, tile );
+  }
+
+  pub fn lut( &self, encoder : &mut wgpu::CommandEncoder, profiler : &GpuProfiler, uniform_offset : u32, tile : &Tile )
+  {
+    self.dispatch( encoder, profiler.compute_pass( "lut" ), &self.lut_pipeline, &self.lut_output, uniform_offset, tile );
+  }
+
+  /// Sets the batch the progressive passes add at the next submit.
+  pub fn set_batch( &self, queue : &wgpu::Queue, index : u32 )
+  {
+    queue.write_buffer( &self.batch_buffer, 0, bytemuck::bytes_of( &[ index, 0, 0, 0 ] ) );
+  }
+
+  /// Estimates the noise of the refined output `index` and copies it to `readback`, see [`progressive::Refinement`].
+  pub fn estimate_noise( &self, encoder : &mut wgpu::CommandEncoder, index : usize, readback : &wgpu::Buffer )
+  {
+    let output = std::iter::once( &self.diffuse_output )
+    .chain( &self.specular_outputs )
+    .chain( &self.specular_cube_outputs )
+    .nth( index )
+    .expect( "No refined output at this index" );
+    let Some( accumulator ) = &output.accumulator else { return };
+
+    {
+      let mut compute_pass = encoder.begin_compute_pass( &wgpu::ComputePassDescriptor::default() );
+      compute_pass.set_pipeline( &self.noise_pipeline );
+      compute_pass.set_bind_group( 0, &self.bind_group, &[ 0, 0 ] );
+      compute_pass.set_bind_group( 1, &accumulator.bind_group, &[] );
+      compute_pass.dispatch_workgroups( 1, 1, 1 );
+    }
+    encoder.copy_buffer_to_buffer( &accumulator.buffer, 0, readback, ( index * NOISE_SIZE ) as u64, NOISE_SIZE as u64 );
+  }
+
+  fn dispatch
+  (
+    &self,
+    encoder : &mut wgpu::CommandEncoder,
+    timestamp_writes : Option< wgpu::ComputePassTimestampWrites >,
+    pipeline : &wgpu::ComputePipeline,
+    output : &Output,
+    uniform_offset : u32,
+    tile : &Tile
+  )
+  {
+    let tile_offset = ( tile.row * self.tile_columns + tile.column ) * self.tile_stride;
+    let mut compute_pass = encoder.begin_compute_pass( &wgpu::ComputePassDescriptor { label : None, timestamp_writes } );
+    compute_pass.set_pipeline( pipeline );
+    compute_pass.set_bind_group( 0, &self.bind_group, &[ uniform_offset, tile_offset ] );
+    compute_pass.set_bind_group( 1, &output.bind_group, &[] );
+    compute_pass.dispatch_workgroups
+    (
+      tile.width.div_ceil( WORKGROUP_SIZE ),
+      tile.height.div_ceil( WORKGROUP_SIZE ),
+      output.size.depth_or_array_layers
+    );
+  }
+}
+
+/// Name of `format` in WGSL storage texture declarations. Only formats that pass
+/// [`crate::gpu::check_output_formats`] reach it.
+fn storage_format_name( format : wgpu::TextureFormat ) -> &'static str
+{
+  match format
+  {
+    wgpu::TextureFormat::Rgba32Float => "rgba32float",
+    wgpu::TextureFormat::Rgba16Float => "rgba16float",
+    _ => panic!( "{:?} can't be written by the compute passes", format )
+  }
+}
+
+// https://holger.dammertz.org/stuff/notes_HammersleyOnHemisphere.html
+fn hammersley( i : u32, n : u32 ) -> [ f32; 2 ]
+{
+  [ i as f32 / n as f32, i.reverse_bits() as f32 * 2.328_306_4e-10 ]
+}
+
+/// Solid angle of one texel of the base level of a cube with `size` texel faces.
+fn texel_solid_angle( size : u32 ) -> f32
+{
+  4.0 * PI / ( 6.0 * size as f32 * size as f32 )
+}
+
+/// Mip of a `env_size` cube whose texels cover `solid_angle`.
+pub fn sample_lod( solid_angle : f32, env_size : u32 ) -> f32
+{
+  ( 0.5 * ( solid_angle / texel_solid_angle( env_size ) ).log2() ).max( 0.0 )
+}
+
+/// The grid of `fragment_diffuse_main`, weighted by `cos( theta ) * sin( theta )`.
+fn diffuse_samples( samples_per_axis : u32, env_size : u32 ) -> Vec< Sample >
+{
+  let n = samples_per_axis as f32;
+  let mut samples = Vec::with_capacity( ( samples_per_axis * samples_per_axis ) as usize );
+  for x in 0..samples_per_axis
+  {
+    for y in 0..samples_per_axis
+    {
+      let phi = x as f32 / n * 2.0 * PI;
+      let theta = y as f32 / n * PI / 2.0;
+      let solid_angle = ( 2.0 * PI / n ) * ( PI / 2.0 / n ) * theta.sin();
+      samples.push( Sample
+      {
+        direction : [ phi.sin() * theta.sin(), theta.cos(), phi.cos() * theta.sin() ],
+        lod : sample_lod( solid_angle, env_size ),
+        weight : theta.cos() * theta.sin(),
+        padding : [ 0.0; 3 ]
+      });
+    }
+  }
+  samples
+}
+
+/// Entries of every mip in the specular sample table, a whole number of batches when progressive.
+pub fn specular_table_len( settings : &BakeSettings ) -> u32
+{
+  match &settings.progressive
+  {
+    Some( progressive ) => progressive.specular_batches( settings ) * progressive.batch_samples,
+    None => settings.specular_samples
+  }
+}
+
+/// Jittered versions of the grid of `diffuse_samples`, one per batch, each shifted by [`progressive::batch_offset`].
+fn progressive_diffuse_samples( progressive : &ProgressiveSettings, batches : u32, env_size : u32 ) -> Vec< Sample >
+{
+  let axis = progressive.diffuse_batch_axis();
+  let n = axis as f32;
+  let mut samples = Vec::with_capacity( ( batches * axis * axis ) as usize );
+  for batch in 0..batches
+  {
+    let [ du, dv ] = progressive::batch_offset( batch );
+    for x in 0..axis
+    {
+      for y in 0..axis
+      {
+        let phi = ( x as f32 + du ) / n * 2.0 * PI;
+        let theta = ( y as f32 + dv ) / n * PI / 2.0;
+        let solid_angle = ( 2.0 * PI / n ) * ( PI / 2.0 / n ) * theta.sin();
+        samples.push( Sample
+        {
+          direction : [ phi.sin() * theta.sin(), theta.cos(), phi.cos() * theta.sin() ],
+          lod : sample_lod( solid_angle, env_size ),
+          weight : theta.cos() * theta.sin(),
+          padding : [ 0.0; 3 ]
+        });
+      }
+    }
+  }
+  samples
+}
+
+/// Batches of `progressive.batch_samples` Hammersley points, each batch shifted by [`progressive::batch_offset`].
+fn progressive_specular_samples( roughness : f32, progressive : &ProgressiveSettings, batches : u32, env_size : u32 ) -> Vec< Sample >
+{
+  let count = progressive.batch_samples;
+  ( 0..batches ).flat_map( | batch |
+  {
+    let [ du, dv ] = progressive::batch_offset( batch );
+    ( 0..count ).map( move | i |
+    {
+      let [ u, v ] = hammersley( i, count );
+      specular_sample( roughness, ( u + du ).fract(), ( v + dv ).fract(), count, env_size )
+    })
+  })
+  .collect()
+}
+
+/// GGX importance samples of `prefilter` with `N = V = +Y`, weighted by `NdotL`.
+/// Samples below the horizon keep a weight of zero.
+fn specular_samples( roughness : f32, count : u32, env_size : u32 ) -> Vec< Sample >
+{
+  ( 0..count ).map( | i |
+  {
+    let [ u, v ] = hammersley( i, count );
+    specular_sample( roughness, u, v, count, env_size )
+  })
+  .collect()
+}
+
+/// Sample of the point `u`, `v` of a set of `count`, whose density sets the LOD.
+fn specular_sample( roughness : f32, u : f32, v : f32, count : u32, env_size : u32 ) -> Sample
+{
+  let alpha = roughness * roughness;
+  let a2 = alpha * alpha;
+  let phi = 2.0 * PI * u;
+  let cos_theta = ( ( 1.0 - v ) / ( 1.0 + ( a2 - 1.0 ) * v ) ).sqrt();
+  let sin_theta = ( 1.0 - cos_theta * cos_theta ).sqrt();
+  let h = [ phi.sin() * sin_theta, cos_theta, phi.cos() * sin_theta ];
+
+  // L = reflect( -V, H ) with V = +Y
+  let l = [ 2.0 * h[ 1 ] * h[ 0 ], 2.0 * h[ 1 ] * h[ 1 ] - 1.0, 2.0 * h[ 1 ] * h[ 2 ] ];
+  let dot_nl = l[ 1 ];
+  if dot_nl <= 0.0
+  {
+    return Sample { direction : l, lod : 0.0, weight : 0.0, padding : [ 0.0; 3 ] };
+  }
+
+  // With N = V the pdf D * NdotH / ( 4 * VdotH ) is D / 4
+  let d = a2 / ( PI * ( cos_theta * cos_theta * ( a2 - 1.0 ) + 1.0 ).powi( 2 ) );
+  let lod = if roughness == 0.0 { 0.0 } else { sample_lod( 4.0 / ( count as f32 * d ), env_size ) };
+  Sample { direction : l, lod, weight : dot_nl, padding : [ 0.0; 3 ] }
+}
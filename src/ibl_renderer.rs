@@ -1,65 +1,94 @@
-use std::{fs::File, rc::Rc};
+use wgpu::util::DeviceExt;
 
-use crate::{cube_texture::CubeTexture, texture_2d::Texture2D};
+use crate::{baker::{BakeOutputs, BakeSettings}, ibl_compute::{self, ComputeTargets, IBLCompute}, profiler::GpuProfiler, progressive::{OutputProgress, Refinement}, readback::{self, RawImage, ReadbackBuffer}, texture::Texture, tiling::{Submitter, Tile}};
 
+pub const SHADER_SOURCE : &str = include_str!( "shaders/ibl.wgsl" );
+
+/// Roughness `fragment_specular_1_main` prefilters mip `mip_level` for.
+pub fn mip_roughness( mip_level : u32, total_mips : u32 ) -> f32
+{
+  mip_level as f32 / total_mips as f32
+}
+
+#[ repr( C ) ]
+#[ derive( Clone, Copy, bytemuck::NoUninit ) ]
 struct UniformRaw
 {
   mip_level : u32,
-  total_mips : u32
+  total_mips : u32,
+  face : u32,
+  sample_offset : u32,
+  size : [ u32; 2 ]
 }
 
-struct BufferWrapper
+pub const UNIFORM_SIZE : Option< wgpu::BufferSize > = wgpu::BufferSize::new( std::mem::size_of::< UniformRaw >() as u64 );
+
+/// Prefiltered specular rendered to the six faces of a cube, for targets that want a cube map.
+struct SpecularCube
 {
-  pub buffer : wgpu::Buffer,
-  pub unpadded_bytes_per_row : u32,
-  pub padded_bytes_per_row : u32,
-  pub num_rows : u32
+  texture : Texture,
+  pipeline : wgpu::RenderPipeline,
+  /// One per face of every mip, indexed by `mip_level * 6 + face`
+  buffers : Vec< ReadbackBuffer >,
+  total_mips : u32
 }
 
 pub struct IBLRenderer
 {
-  env_map : Rc< CubeTexture >,
-  diffuse_texture : Texture2D,
-  specular_1_texture : Texture2D,
-  specular_2_texture : Texture2D,
+  diffuse_texture : Texture,
+  specular_1_texture : Texture,
+  specular_2_texture : Texture,
   bind_group : wgpu::BindGroup,
   diffuse_pipeline : wgpu::RenderPipeline,
   specular_1_pipeline : wgpu::RenderPipeline,
   specular_2_pipeline : wgpu::RenderPipeline,
-  diffuse_buffer : wgpu::Buffer,
-  specular_1_buffers : Vec< BufferWrapper >,
-  specular_2_buffer : wgpu::Buffer,
-  uniform_buffer : wgpu::Buffer,
-  total_mips : u32
+  diffuse_buffer : ReadbackBuffer,
+  specular_1_buffers : Vec< ReadbackBuffer >,
+  specular_2_buffer : ReadbackBuffer,
+  specular_cube : Option< SpecularCube >,
+  /// Replaces the render pipelines when `BakeSettings::compute` is set
+  compute : Option< IBLCompute >,
+  /// Every pass reads its `UniformRaw` at its own dynamic offset, since buffer writes
+  /// only land at the next submit
+  uniform_stride : u32,
+  total_mips : u32,
+  settings : BakeSettings
 }
 
 impl IBLRenderer 
 {
-  pub fn new
-  ( 
-    device : &wgpu::Device, 
-    env_map : Rc< CubeTexture >, 
-    format : wgpu::TextureFormat, 
-    diffuse_width : u32, 
-    diffuse_height : u32,
-    specular_1_width : u32,
-    specular_1_height : u32,
-    specular_2_width : u32,
-    specular_2_height : u32
-  ) -> Self
+  pub fn new( device : &wgpu::Device, env_map : &Texture, settings : &BakeSettings ) -> Self
   { 
-    let specular_1_format = wgpu::TextureFormat::Rgba32Float;
-    let specular_2_format = wgpu::TextureFormat::Rgba32Float;
-
-    let diffuse_size = wgpu::Extent3d{ width: diffuse_width, height: diffuse_height, depth_or_array_layers: 1 };
-    let specular_1_size = wgpu::Extent3d{ width: specular_1_width, height: specular_1_height, depth_or_array_layers: 1 };
-    let specular_2_size = wgpu::Extent3d{ width: specular_2_width, height: specular_2_height, depth_or_array_layers: 1 };
+    let format = settings.diffuse_format;
+    let specular_1_format = settings.specular_format;
+    let specular_2_format = settings.lut_format;
 
-    let diffuse_texture = Texture2D::new( device, format, diffuse_width, diffuse_height, false );
-    let specular_1_texture = Texture2D::new( device, specular_1_format, specular_1_width, specular_1_height, true );
-    let specular_2_texture = Texture2D::new( device, specular_2_format, specular_2_width, specular_2_height, false );
-
-    let total_mips = specular_1_size.max_mips( wgpu::TextureDimension::D2 ).min( 5 );
+    // Render targets the compute passes can also write to
+    let output_texture = | format : wgpu::TextureFormat, width : u32, height : u32 |
+    {
+      Texture::builder( width, height )
+      .label( "2D_TEXTURE" )
+      .format( format )
+      .usage( wgpu::TextureUsages::RENDER_ATTACHMENT )
+      .storage_if_supported()
+    };
+    let diffuse_texture = output_texture( format, settings.diffuse_width, settings.diffuse_height ).build( device );
+    let specular_1_texture = output_texture( specular_1_format, settings.specular_1_width, settings.specular_1_height ).full_mips().build( device );
+    let specular_2_texture = output_texture( specular_2_format, settings.specular_2_width, settings.specular_2_height ).build( device );
+
+    let total_mips = specular_1_texture.mip_count().min( 5 );
+    let specular_cube_texture = settings.specular_cube_size.map( | size |
+    {
+      Texture::builder( size, size )
+      .label( "SPECULAR_CUBE_TEXTURE" )
+      .format( specular_1_format )
+      .cube()
+      .mips( 5 )
+      .usage( wgpu::TextureUsages::RENDER_ATTACHMENT )
+      .storage_if_supported()
+      .build( device )
+    });
+    let cube_total_mips = specular_cube_texture.as_ref().map_or( 0, Texture::mip_count );
 
     let bind_group_layout = device.create_bind_group_layout
     (
@@ -97,8 +126,8 @@ impl IBLRenderer
             ty: wgpu::BindingType::Buffer 
             { 
               ty: wgpu::BufferBindingType::Uniform, 
-              has_dynamic_offset: false, 
-              min_binding_size: None 
+              has_dynamic_offset: true, 
+              min_binding_size: UNIFORM_SIZE
             }, 
             count: None 
           },
@@ -106,14 +135,40 @@ impl IBLRenderer
       }
     );
 
-    let uniform_buffer = device.create_buffer
+    // The equirect mips come first, followed by every face of every cube mip, the diffuse and the LUT.
+    // The sample table of the compute passes has one entry per mip, in the same order
+    let uniform_stride = device.limits().min_uniform_buffer_offset_alignment;
+    let samples = ibl_compute::specular_table_len( settings );
+    let target_size = | texture : &Texture, mip_level : u32 |
+    {
+      let size = texture.mip_level_size( mip_level );
+      [ size.width, size.height ]
+    };
+    let uniforms = ( 0..total_mips ).map( | mip_level | ( mip_level, total_mips, 0, mip_level * samples, target_size( &specular_1_texture, mip_level ) ) )
+    .chain
     (
-      &wgpu::BufferDescriptor
+      specular_cube_texture.iter().flat_map( | texture | ( 0..cube_total_mips ).flat_map( move | mip_level |
+      {
+        ( 0..6 ).map( move | face | ( mip_level, cube_total_mips, face, ( total_mips + mip_level ) * samples, target_size( texture, mip_level ) ) )
+      }))
+    )
+    .chain( [ ( 0, 0, 0, 0, target_size( &diffuse_texture, 0 ) ), ( 0, 0, 0, 0, target_size( &specular_2_texture, 0 ) ) ] )
+    .collect::< Vec< _ > >();
+    let mut uniform_data = vec![ 0u8; uniforms.len() * uniform_stride as usize ];
+    for ( i, ( mip_level, total_mips, face, sample_offset, size ) ) in uniforms.into_iter().enumerate()
+    {
+      let raw = UniformRaw { mip_level, total_mips, face, sample_offset, size };
+      let offset = i * uniform_stride as usize;
+      uniform_data[ offset..offset + std::mem::size_of::< UniformRaw >() ].copy_from_slice( bytemuck::bytes_of( &raw ) );
+    }
+
+    let uniform_buffer = device.create_buffer_init
+    (
+      &wgpu::util::BufferInitDescriptor
       {
         label : None,
-        size : std::mem::size_of::< UniformRaw >() as u64,
-        mapped_at_creation : false,
-        usage : wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM
+        contents : &uniform_data,
+        usage : wgpu::BufferUsages::UNIFORM
       }
     );
 
@@ -127,17 +182,25 @@ impl IBLRenderer
           wgpu::BindGroupEntry
           {
             binding : 0,
-            resource : wgpu::BindingResource::TextureView( &env_map.view_cube() )
+            resource : wgpu::BindingResource::TextureView( env_map.view() )
           },
           wgpu::BindGroupEntry
           {
             binding : 1,
-            resource : wgpu::BindingResource::Sampler( &env_map.sampler() )
+            resource : wgpu::BindingResource::Sampler( env_map.sampler() )
           },
           wgpu::BindGroupEntry
           {
             binding : 2,
-            resource : wgpu::BindingResource::Buffer( uniform_buffer.as_entire_buffer_binding() )
+            resource : wgpu::BindingResource::Buffer
+            ( 
+              wgpu::BufferBinding
+              {
+                buffer : &uniform_buffer,
+                offset : 0,
+                size : UNIFORM_SIZE
+              }
+            )
           },
         ]
       }
@@ -148,7 +211,7 @@ impl IBLRenderer
       wgpu::ShaderModuleDescriptor 
       { 
         label: None, 
-        source: wgpu::ShaderSource::Wgsl( include_str!( "shaders/ibl.wgsl" ).into() )
+        source: wgpu::ShaderSource::Wgsl( SHADER_SOURCE.into() )
       }
     );
 
@@ -165,6 +228,14 @@ impl IBLRenderer
       }
     );
 
+    let constants = 
+    [
+      ( "DIFFUSE_SAMPLES", settings.diffuse_samples as f64 ),
+      ( "SPECULAR_SAMPLES", settings.specular_samples as f64 ),
+      ( "LUT_SAMPLES", settings.lut_samples as f64 ),
+      ( "LAYOUT", settings.layout as u32 as f64 )
+    ];
+
     let diffuse_pipeline = device.create_render_pipeline
     (
       &wgpu::RenderPipelineDescriptor
@@ -189,7 +260,7 @@ impl IBLRenderer
           { 
             module: &shader, 
             entry_point: Some( "fragment_diffuse_main" ), 
-            compilation_options: wgpu::PipelineCompilationOptions::default(), 
+            compilation_options: wgpu::PipelineCompilationOptions { constants : &constants, ..Default::default() }, 
             targets: &[
               Some( wgpu::ColorTargetState 
                 { 
@@ -231,11 +302,11 @@ impl IBLRenderer
           { 
             module: &shader, 
             entry_point: Some( "fragment_specular_1_main" ), 
-            compilation_options: wgpu::PipelineCompilationOptions::default(), 
+            compilation_options: wgpu::PipelineCompilationOptions { constants : &constants, ..Default::default() }, 
             targets: &[
               Some( wgpu::ColorTargetState 
                 { 
-                  format: specular_2_format, 
+                  format: specular_1_format, 
                   blend: None, 
                   write_mask: wgpu::ColorWrites::all() 
                 }
@@ -273,7 +344,7 @@ impl IBLRenderer
           { 
             module: &shader, 
             entry_point: Some( "fragment_specular_2_main" ), 
-            compilation_options: wgpu::PipelineCompilationOptions::default(), 
+            compilation_options: wgpu::PipelineCompilationOptions { constants : &constants, ..Default::default() }, 
             targets: &[
               Some( wgpu::ColorTargetState 
                 { 
@@ -291,59 +362,86 @@ impl IBLRenderer
       }
     );
 
-    let diffuse_buffer = device.create_buffer
-    (
-      &wgpu::BufferDescriptor
-      {
-        label : None,
-        size : diffuse_texture.mip_memory_size( 0 ) as u64,
-        mapped_at_creation : false,
-        usage : wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST
-      }
-    );
+    let diffuse_buffer = diffuse_texture.readback_buffer( device, 0 );
+    let specular_1_buffers = ( 0..total_mips )
+    .map( | i | specular_1_texture.readback_buffer( device, i ) )
+    .collect();
 
-    let bytes_per_texel = specular_1_format.block_copy_size( None ).unwrap();
-    let mut specular_1_buffers = Vec::new();
-    for i in 0..total_mips
+    let specular_cube = specular_cube_texture.map( | texture |
     {
-      let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
-      let size = specular_1_texture.mip_level_size( i );
-      let padded_bytes_per_row = ( bytes_per_texel * size.width ).div_ceil( alignment ) * alignment;
-      let buffer = device.create_buffer
+      let pipeline = device.create_render_pipeline
       (
-        &wgpu::BufferDescriptor
+        &wgpu::RenderPipelineDescriptor
         {
           label : None,
-          size : ( padded_bytes_per_row * size.height  ) as u64,
-          mapped_at_creation : false,
-          usage : wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST
+          layout : Some( &pipeline_layout ),
+          vertex : wgpu::VertexState 
+          {
+            module: &shader, 
+            entry_point: None, 
+            compilation_options: wgpu::PipelineCompilationOptions::default(), 
+            buffers: &[] 
+          },
+          primitive : wgpu::PrimitiveState
+          {
+            topology : wgpu::PrimitiveTopology::TriangleList,
+            ..Default::default()
+          },
+          depth_stencil : None,
+          fragment : Some( 
+            wgpu::FragmentState 
+            { 
+              module: &shader, 
+              entry_point: Some( "fragment_specular_cube_main" ), 
+              compilation_options: wgpu::PipelineCompilationOptions { constants : &constants, ..Default::default() }, 
+              targets: &[
+                Some( wgpu::ColorTargetState 
+                  { 
+                    format: texture.format(), 
+                    blend: None, 
+                    write_mask: wgpu::ColorWrites::all() 
+                  }
+                )
+              ] 
+            }
+          ),
+          multisample : wgpu::MultisampleState::default(),
+          multiview : None,
+          cache : None
         }
       );
 
-      let wrapper = BufferWrapper
-      {
-        buffer,
-        unpadded_bytes_per_row : size.width * bytes_per_texel,
-        padded_bytes_per_row,
-        num_rows : size.height
-      };
-      specular_1_buffers.push( wrapper );
-    }
+      let buffers = ( 0..cube_total_mips )
+      .flat_map( | mip_level | std::iter::repeat_n( mip_level, 6 ) )
+      .map( | mip_level | texture.readback_buffer( device, mip_level ) )
+      .collect();
 
-    let specular_2_buffer = device.create_buffer
-    (
-      &wgpu::BufferDescriptor
+      SpecularCube
       {
-        label : None,
-        size : specular_2_texture.mip_memory_size( 0 ) as u64,
-        mapped_at_creation : false,
-        usage : wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST
+        texture,
+        pipeline,
+        buffers,
+        total_mips : cube_total_mips
       }
-    );
+    });
+
+    let specular_2_buffer = specular_2_texture.readback_buffer( device, 0 );
+
+    let compute = settings.compute.then( ||
+    {
+      let targets = ComputeTargets
+      {
+        diffuse : &diffuse_texture,
+        specular : &specular_1_texture,
+        specular_mips : total_mips,
+        lut : &specular_2_texture,
+        specular_cube : specular_cube.as_ref().map( | cube | &cube.texture )
+      };
+      IBLCompute::new( device, settings, env_map, &uniform_buffer, targets )
+    });
 
     Self
     {
-      env_map,
       diffuse_texture,
       specular_1_texture,
       specular_2_texture,
@@ -354,345 +452,279 @@ impl IBLRenderer
       diffuse_buffer,
       specular_1_buffers,
       specular_2_buffer,
-      uniform_buffer,
-      total_mips
+      specular_cube,
+      compute,
+      uniform_stride,
+      total_mips,
+      settings : *settings
     }
   }
 
-  pub fn render_diffuse( &self, encoder : &mut wgpu::CommandEncoder )
+  /// Renders the irradiance, or adds a batch of it when progressive.
+  pub fn render_diffuse( &self, submitter : &mut Submitter, profiler : &GpuProfiler )
   {
-    let diffuse_view = self.diffuse_texture.view();
-
+    let size = self.diffuse_texture.size();
+    let samples = match &self.settings.progressive
     {
-      let mut render_pass = encoder.begin_render_pass
-      (
-        &wgpu::RenderPassDescriptor
-        {
-          label : None,
-          color_attachments : &[
-            Some( wgpu::RenderPassColorAttachment
-            {
-              view : diffuse_view,
-              resolve_target : None,
-              ops : wgpu::Operations
-              {
-                load : wgpu::LoadOp::Clear( wgpu::Color::BLACK ),
-                store : wgpu::StoreOp::Store
-              }
-            })
-          ],
-          depth_stencil_attachment : None,
-          timestamp_writes : None,
-          occlusion_query_set : None
-        }
-      );
+      Some( progressive ) => progressive.diffuse_batch_axis().pow( 2 ) as u64,
+      None => self.settings.diffuse_samples as u64 * self.settings.diffuse_samples as u64
+    };
+    for tile in self.settings.tiling.tiles( size.width, size.height )
+    {
+      if let Some( compute ) = &self.compute
+      {
+        compute.diffuse( submitter.encoder(), profiler, self.diffuse_uniform_offset(), &tile );
+      }
+      else
+      {
+        let timestamp_writes = profiler.render_pass( "diffuse" );
+        self.draw_tile( submitter.encoder(), timestamp_writes, self.diffuse_texture.view(), &self.diffuse_pipeline, self.diffuse_uniform_offset(), &tile );
+      }
+      submitter.add_samples( tile.texels() * samples );
+    }
+  }
 
-      render_pass.set_pipeline( &self.diffuse_pipeline );
-      render_pass.set_bind_group( 0, &self.bind_group, &[] );
-      render_pass.draw( 0..3, 0..1 );
+  /// Copies the irradiance, the specular mips and the specular cube to their readback buffers.
+  pub fn copy_outputs( &self, encoder : &mut wgpu::CommandEncoder )
+  {
+    self.diffuse_buffer.copy_from( encoder, self.diffuse_texture.texture(), 0, 0 );
+    for ( mip_level, buffer ) in self.specular_1_buffers.iter().enumerate()
+    {
+      buffer.copy_from( encoder, self.specular_1_texture.texture(), mip_level as u32, 0 );
     }
 
-    // Copy diffuse texture to the buffer
-    let diffuse_texture = self.diffuse_texture.texture();
-    encoder.copy_texture_to_buffer
-    (
-      wgpu::TexelCopyTextureInfoBase 
-      { 
-        texture: diffuse_texture, 
-        mip_level: 0, 
-        origin: wgpu::Origin3d::ZERO, 
-        aspect: wgpu::TextureAspect::All 
-      }, 
-      wgpu::TexelCopyBufferInfo
-      {
-        buffer : &self.diffuse_buffer,
-        layout : wgpu::TexelCopyBufferLayout
-        {
-          offset : 0,
-          bytes_per_row : Some( diffuse_texture.width() * diffuse_texture.format().block_copy_size( None ).unwrap() ),
-          rows_per_image : None
-        }
-      },
-      wgpu::Extent3d 
-      { 
-        width: diffuse_texture.width(), 
-        height: diffuse_texture.height(), 
-        depth_or_array_layers: 1 
-      }
-    );
+    let Some( cube ) = &self.specular_cube else { return };
+    for ( index, buffer ) in cube.buffers.iter().enumerate()
+    {
+      buffer.copy_from( encoder, cube.texture.texture(), index as u32 / 6, index as u32 % 6 );
+    }
   }
 
-  pub fn render_specular_1( &self, encoder : &mut wgpu::CommandEncoder, queue : &wgpu::Queue )
+  pub fn render_specular_1( &self, submitter : &mut Submitter, profiler : &GpuProfiler )
   {
     for mip_level in 0..self.total_mips
     {
-      queue.write_buffer
-      (
-        &self.uniform_buffer, 
-        0, 
-        bytemuck::cast_slice( &[ mip_level, self.total_mips ] )
-      );
-
-      let view = self.specular_1_texture.create_mip_view( mip_level );
+      self.render_specular_mip( submitter, profiler, mip_level );
+    }
+  }
 
+  fn render_specular_mip( &self, submitter : &mut Submitter, profiler : &GpuProfiler, mip_level : u32 )
+  {
+    let size = self.specular_1_texture.mip_level_size( mip_level );
+    let view = self.specular_1_texture.create_mip_view( mip_level );
+    for tile in self.settings.tiling.tiles( size.width, size.height )
+    {
+      if let Some( compute ) = &self.compute
       {
-        let mut render_pass = encoder.begin_render_pass
-        (
-          &wgpu::RenderPassDescriptor
-          {
-            label : None,
-            color_attachments : &[
-              Some( wgpu::RenderPassColorAttachment
-              {
-                view : &view,
-                resolve_target : None,
-                ops : wgpu::Operations
-                {
-                  load : wgpu::LoadOp::Clear( wgpu::Color::BLACK ),
-                  store : wgpu::StoreOp::Store
-                }
-              })
-            ],
-            depth_stencil_attachment : None,
-            timestamp_writes : None,
-            occlusion_query_set : None
-          }
-        );
-  
-        render_pass.set_pipeline( &self.specular_1_pipeline );
-        render_pass.set_bind_group( 0, &self.bind_group, &[] );
-        render_pass.draw( 0..3, 0..1 );
+        compute.specular( submitter.encoder(), profiler, mip_level, self.uniform_offset( mip_level ), &tile );
       }
-  
-      // Copy diffuse texture to the buffer
-      let wrapper = &self.specular_1_buffers[ mip_level as usize ];
-      let texture = self.specular_1_texture.texture();
-      let size = self.specular_1_texture.mip_level_size( mip_level );
-      encoder.copy_texture_to_buffer
-      (
-        wgpu::TexelCopyTextureInfoBase 
-        { 
-          texture: texture, 
-          mip_level, 
-          origin: wgpu::Origin3d::ZERO, 
-          aspect: wgpu::TextureAspect::All 
-        }, 
-        wgpu::TexelCopyBufferInfo
-        {
-          buffer : &wrapper.buffer,
-          layout : wgpu::TexelCopyBufferLayout
-          {
-            offset : 0,
-            bytes_per_row : Some( wrapper.padded_bytes_per_row ),
-            rows_per_image : None
-          }
-        },
-        size
-      );
+      else
+      {
+        let timestamp_writes = profiler.render_pass( &format!( "specular mip {}", mip_level ) );
+        self.draw_tile( submitter.encoder(), timestamp_writes, &view, &self.specular_1_pipeline, self.uniform_offset( mip_level ), &tile );
+      }
+      submitter.add_samples( tile.texels() * self.specular_batch_samples() );
     }
   }
 
-  pub fn render_specular_2( &self, encoder : &mut wgpu::CommandEncoder )
+  /// Renders the prefiltered specular to the faces of the cube, when `specular_cube_size` is set.
+  pub fn render_specular_cube( &self, submitter : &mut Submitter, profiler : &GpuProfiler )
   {
-    let specular_view = self.specular_2_texture.view();
+    let Some( cube ) = &self.specular_cube else { return };
 
+    for mip_level in 0..cube.total_mips
     {
-      let mut render_pass = encoder.begin_render_pass
-      (
-        &wgpu::RenderPassDescriptor
-        {
-          label : None,
-          color_attachments : &[
-            Some( wgpu::RenderPassColorAttachment
-            {
-              view : specular_view,
-              resolve_target : None,
-              ops : wgpu::Operations
-              {
-                load : wgpu::LoadOp::Clear( wgpu::Color::BLACK ),
-                store : wgpu::StoreOp::Store
-              }
-            })
-          ],
-          depth_stencil_attachment : None,
-          timestamp_writes : None,
-          occlusion_query_set : None
-        }
-      );
+      self.render_specular_cube_mip( submitter, profiler, mip_level );
+    }
+  }
+
+  fn render_specular_cube_mip( &self, submitter : &mut Submitter, profiler : &GpuProfiler, mip_level : u32 )
+  {
+    let Some( cube ) = &self.specular_cube else { return };
 
-      render_pass.set_pipeline( &self.specular_2_pipeline );
-      render_pass.set_bind_group( 0, &self.bind_group, &[] );
-      render_pass.draw( 0..3, 0..1 );
+    let size = cube.texture.mip_level_size( mip_level );
+    let tiles = self.settings.tiling.tiles( size.width, size.height );
+    if let Some( compute ) = &self.compute
+    {
+      for tile in &tiles
+      {
+        compute.specular_cube( submitter.encoder(), profiler, mip_level, self.uniform_offset( self.total_mips + mip_level * 6 ), tile );
+        submitter.add_samples( 6 * tile.texels() * self.specular_batch_samples() );
+      }
+      return;
     }
 
-    // Copy diffuse texture to the buffer
-    let specular_2_texture = self.specular_2_texture.texture();
-    encoder.copy_texture_to_buffer
-    (
-      wgpu::TexelCopyTextureInfoBase 
-      { 
-        texture: specular_2_texture, 
-        mip_level: 0, 
-        origin: wgpu::Origin3d::ZERO, 
-        aspect: wgpu::TextureAspect::All 
-      }, 
-      wgpu::TexelCopyBufferInfo
+    for face in 0..6
+    {
+      let view = cube.texture.create_layer_view( face, mip_level );
+      for tile in &tiles
       {
-        buffer : &self.specular_2_buffer,
-        layout : wgpu::TexelCopyBufferLayout
-        {
-          offset : 0,
-          bytes_per_row : Some( specular_2_texture.width() * specular_2_texture.format().block_copy_size( None ).unwrap() ),
-          rows_per_image : None
-        }
-      },
-      wgpu::Extent3d 
-      { 
-        width: specular_2_texture.width(), 
-        height: specular_2_texture.height(), 
-        depth_or_array_layers: 1 
+        let timestamp_writes = profiler.render_pass( &format!( "specular cube mip {}", mip_level ) );
+        self.draw_tile( submitter.encoder(), timestamp_writes, &view, &cube.pipeline, self.uniform_offset( self.total_mips + mip_level * 6 + face ), tile );
+        submitter.add_samples( tile.texels() * self.settings.specular_samples as u64 );
       }
-    );
+    }
   }
 
-  pub async fn save_diffuse( &self, device : &wgpu::Device )
+  /// Samples per texel of one specular pass, a single batch when progressive.
+  fn specular_batch_samples( &self ) -> u64
   {
-    let ( sender, reciever ) = flume::bounded( 1 );
+    self.settings.progressive.map_or( self.settings.specular_samples, | progressive | progressive.batch_samples ) as u64
+  }
 
-    self.diffuse_buffer.map_async
+  /// Starts the refinement of the irradiance, the specular mips and the specular cube mips, when `BakeSettings::progressive` is set.
+  pub fn refinement( &self, device : &wgpu::Device ) -> Option< Refinement >
+  {
+    let progressive = self.settings.progressive?;
+    let diffuse_batches = progressive.diffuse_batches( &self.settings );
+    let specular_batches = progressive.specular_batches( &self.settings );
+    let output = | name : String, max_batches : u32 | OutputProgress { name, batches : 0, max_batches, noise : None };
+
+    let outputs = std::iter::once( output( "diffuse".to_owned(), diffuse_batches ) )
+    .chain( ( 0..self.total_mips ).map( | mip_level | output( format!( "specular mip {}", mip_level ), specular_batches ) ) )
+    .chain
     (
-      wgpu::MapMode::Read, 
-      .., 
-      move | r | sender.send( r ).unwrap()
-    );
+      self.specular_cube.iter()
+      .flat_map( | cube | 0..cube.total_mips )
+      .map( | mip_level | output( format!( "specular cube mip {}", mip_level ), specular_batches ) )
+    )
+    .collect();
 
-    device.poll( wgpu::PollType::wait() ).unwrap();
-    reciever.recv_async().await.unwrap().unwrap();
+    Some( Refinement::new( device, progressive, outputs ) )
+  }
 
-    use image::ImageEncoder;
+  /// Adds the next batch to every output of `refinement` that is still refining and estimates their noise.
+  pub async fn refine( &self, device : &wgpu::Device, queue : &wgpu::Queue, profiler : &GpuProfiler, refinement : &mut Refinement ) -> anyhow::Result< () >
+  {
+    let Some( compute ) = &self.compute else { return Ok( () ) };
+    let active = refinement.active();
+    compute.set_batch( queue, refinement.round() );
 
+    let mut submitter = Submitter::new( device, queue, &self.settings.tiling );
+    for &index in &active
     {
-      let size = self.diffuse_texture.size();
-      let view = self.diffuse_buffer.get_mapped_range( .. );
+      let index = index as u32;
+      match index
+      {
+        0 => self.render_diffuse( &mut submitter, profiler ),
+        _ if index <= self.total_mips => self.render_specular_mip( &mut submitter, profiler, index - 1 ),
+        _ => self.render_specular_cube_mip( &mut submitter, profiler, index - 1 - self.total_mips )
+      }
+      compute.estimate_noise( submitter.encoder(), index as usize, refinement.readback() );
+    }
+    submitter.finish();
+
+    refinement.end_round( device, &active ).await
+  }
 
-      // HDR type only support RGBF32, so we need to remove the alpha channel
-      let data = view.iter().enumerate().filter( | ( i, _v ) |
+  pub fn render_specular_2( &self, submitter : &mut Submitter, profiler : &GpuProfiler )
+  {
+    let size = self.specular_2_texture.size();
+    for tile in self.settings.tiling.tiles( size.width, size.height )
+    {
+      if let Some( compute ) = &self.compute
       {
-        let rem = i % 16;
-        rem != 15 && rem != 14 && rem != 13 && rem != 12 
-      })
-      .map( | ( _i, v ) |
+        compute.lut( submitter.encoder(), profiler, self.diffuse_uniform_offset() + self.uniform_stride, &tile );
+      }
+      else
       {
-        *v
-      })
-      .collect::< Vec< u8 > >();
-
-      let file = File::create( "result/diffuse.hdr" ).unwrap();
-      
-      let encoder = image::codecs::hdr::HdrEncoder::new( file );
-      encoder.write_image( &data, size.width, size.height, image::ExtendedColorType::Rgb32F ).unwrap();
+        let timestamp_writes = profiler.render_pass( "lut" );
+        self.draw_tile( submitter.encoder(), timestamp_writes, self.specular_2_texture.view(), &self.specular_2_pipeline, self.diffuse_uniform_offset() + self.uniform_stride, &tile );
+      }
+      submitter.add_samples( tile.texels() * self.settings.lut_samples as u64 );
     }
 
+    self.specular_2_buffer.copy_from( submitter.encoder(), self.specular_2_texture.texture(), 0, 0 );
   }
 
-  pub async fn save_specular_2( &self, device : &wgpu::Device )
+  /// Draws the full screen triangle of `pipeline` to `view`, limited to `tile` by the scissor rect.
+  /// The first tile clears the whole target, the following ones keep what the previous tiles wrote.
+  fn draw_tile
+  (
+    &self,
+    encoder : &mut wgpu::CommandEncoder,
+    timestamp_writes : Option< wgpu::RenderPassTimestampWrites >,
+    view : &wgpu::TextureView,
+    pipeline : &wgpu::RenderPipeline,
+    uniform_offset : u32,
+    tile : &Tile
+  )
   {
-    let ( sender, reciever ) = flume::bounded( 1 );
-
-    self.specular_2_buffer.map_async
+    let mut render_pass = encoder.begin_render_pass
     (
-      wgpu::MapMode::Read, 
-      .., 
-      move | r | sender.send( r ).unwrap()
+      &wgpu::RenderPassDescriptor
+      {
+        label : None,
+        color_attachments : &[
+          Some( wgpu::RenderPassColorAttachment
+          {
+            view,
+            resolve_target : None,
+            ops : wgpu::Operations
+            {
+              load : if tile.is_first() { wgpu::LoadOp::Clear( wgpu::Color::BLACK ) } else { wgpu::LoadOp::Load },
+              store : wgpu::StoreOp::Store
+            }
+          })
+        ],
+        depth_stencil_attachment : None,
+        timestamp_writes,
+        occlusion_query_set : None
+      }
     );
 
-    device.poll( wgpu::PollType::wait() ).unwrap();
-    reciever.recv_async().await.unwrap().unwrap();
-
-    use image::ImageEncoder;
+    render_pass.set_pipeline( pipeline );
+    render_pass.set_bind_group( 0, &self.bind_group, &[ uniform_offset ] );
+    render_pass.set_scissor_rect( tile.x, tile.y, tile.width, tile.height );
+    render_pass.draw( 0..3, 0..1 );
+  }
 
+  /// Readback buffers of every output, to be mapped together with [`readback::map_buffers`].
+  /// The LUT is only included when `lut` is set.
+  pub fn readback_buffers( &self, lut : bool ) -> Vec< &wgpu::Buffer >
+  {
+    let mut buffers = vec![ self.diffuse_buffer.buffer() ];
+    buffers.extend( self.specular_1_buffers.iter().map( ReadbackBuffer::buffer ) );
+    buffers.extend( self.specular_cube.iter().flat_map( | cube | cube.buffers.iter().map( ReadbackBuffer::buffer ) ) );
+    if lut
     {
-      let size = self.specular_2_texture.size();
-      let view = self.specular_2_buffer.get_mapped_range( .. );
-
-      // HDR type only support RGBF32, so we need to remove the alpha channel
-      let data = view.iter().enumerate().filter( | ( i, _v ) |
-      {
-        let rem = i % 16;
-        rem != 15 && rem != 14 && rem != 13 && rem != 12 
-      })
-      .map( | ( _i, v ) |
-      {
-        *v
-      })
-      .collect::< Vec< u8 > >();
-
-      let file = File::create( "result/specular_2.hdr" ).unwrap();
-      
-      let encoder = image::codecs::hdr::HdrEncoder::new( file );
-      encoder.write_image( &data, size.width, size.height, image::ExtendedColorType::Rgb32F ).unwrap();
-
-      // let file = File::create( "result/specular_2.png" ).unwrap();
-
-      // let encoder = image::codecs::png::PngEncoder::new( file );
-      // encoder.write_image( &view, size.width, size.height, image::ExtendedColorType::Rgba8 ).unwrap();
+      buffers.push( self.specular_2_buffer.buffer() );
     }
+    buffers
   }
 
-  pub async fn save_specular_1( &self, device : &wgpu::Device )
+  /// Takes the outputs out of the buffers of [`IBLRenderer::readback_buffers`] once they are mapped.
+  /// The LUT is only read when no `brdf_lut` from an earlier bake is given.
+  pub fn take_outputs( &self, brdf_lut : Option< &RawImage > ) -> BakeOutputs< RawImage >
   {
-    use image::ImageEncoder;
-    let ( sender, reciever ) = flume::bounded( 1 );
-
-    for mip_level in 0..self.total_mips
+    let diffuse = self.diffuse_buffer.take( "diffuse" );
+    let specular = self.specular_1_buffers.iter().enumerate()
+    .map( | ( mip_level, buffer ) | buffer.take( format!( "specular_1_{}", mip_level ) ) )
+    .collect();
+    let specular_cube = self.specular_cube.as_ref().map( | cube | readback::take_cube( &cube.buffers, "specular_cube" ) );
+    let brdf_lut = match brdf_lut
     {
-      let sender = sender.clone();
-      let wrapper = &self.specular_1_buffers[ mip_level as usize ];
-      let buffer = &wrapper.buffer;
-      buffer.map_async
-      (
-        wgpu::MapMode::Read, 
-        .., 
-        move | r | sender.send( r ).unwrap()
-      );
-
-      device.poll( wgpu::PollType::wait() ).unwrap();
-      reciever.recv_async().await.unwrap().unwrap();
+      Some( brdf_lut ) => brdf_lut.clone(),
+      None => self.specular_2_buffer.take( "specular_2" )
+    };
 
-      {
-        let size = self.specular_1_texture.mip_level_size( mip_level );
-        let view = buffer.get_mapped_range( .. );
-
-        let mut data = Vec::with_capacity( ( wrapper.unpadded_bytes_per_row * wrapper.num_rows ) as usize );
-        for row in 0..wrapper.num_rows
-        {
-          let start = ( wrapper.padded_bytes_per_row * row ) as usize;
-          let end = start + wrapper.unpadded_bytes_per_row as usize;
-          data.extend_from_slice( &view[ start..end ] );
-        }
-
-        // HDR type only support RGBF32, so we need to remove the alpha channel
-        let data = data.iter().enumerate().filter( | ( i, _v ) |
-        {
-          let rem = i % 16;
-          rem != 15 && rem != 14 && rem != 13 && rem != 12 
-        })
-        .map( | ( _i, v ) |
-        {
-          *v
-        })
-        .collect::< Vec< u8 > >();
+    BakeOutputs { diffuse, specular, brdf_lut, specular_cube, environment_cube : None, environment : None }
+  }
 
-        let file = File::create( format!( "result/specular_1_{}.hdr", mip_level ) ).unwrap();
-        
-        let encoder = image::codecs::hdr::HdrEncoder::new( file );
-        encoder.write_image( &data, size.width, size.height, image::ExtendedColorType::Rgb32F ).unwrap();
+  /// The prefiltered specular cube, when `specular_cube_size` is set.
+  pub fn specular_cube_texture( &self ) -> Option< &Texture >
+  {
+    self.specular_cube.as_ref().map( | cube | &cube.texture )
+  }
 
-        // let file = File::create( format!( "result/specular_1_{}.png", mip_level ) ).unwrap();
+  fn uniform_offset( &self, index : u32 ) -> u32
+  {
+    index * self.uniform_stride
+  }
 
-        // let encoder = image::codecs::png::PngEncoder::new( file );
-        // encoder.write_image( &view, size.width, size.height, image::ExtendedColorType::Rgba8 ).unwrap();
-      }
-    }
+  /// Offset of the uniform of the diffuse pass, after every specular mip and cube face. The LUT one follows it.
+  fn diffuse_uniform_offset( &self ) -> u32
+  {
+    self.uniform_offset( self.total_mips + self.specular_cube.as_ref().map_or( 0, | cube | cube.total_mips * 6 ) )
   }
-}
\ No newline at end of file
+}
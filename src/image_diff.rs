@@ -0,0 +1,134 @@
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::output::OutputImage;
+
+/// Differences below this fraction of the brightest channel of the reference count as relative to it,
+/// so the relative error doesn't blow up on black texels.
+const RELATIVE_FLOOR : f32 = 0.01;
+
+/// Stops of the false colors of [`save_heatmap`], from no error to the full scale.
+const HEATMAP_COLORS : [ [ f32; 3 ]; 5 ] =
+[
+  [ 0.0, 0.0, 0.0 ],
+  [ 0.0, 0.0, 1.0 ],
+  [ 0.0, 1.0, 1.0 ],
+  [ 1.0, 1.0, 0.0 ],
+  [ 1.0, 0.0, 0.0 ]
+];
+
+/// Differences between an image and the reference it is compared to.
+#[ derive( Clone, Copy, Debug ) ]
+pub struct ImageDiff
+{
+  /// Root mean square of the differences of every channel
+  pub rmse : f32,
+  /// In dB, with the brightest channel of the reference as the peak. Infinite for identical images
+  pub psnr : f32,
+  /// Largest difference of a channel over its reference value, see [`relative_errors`]
+  pub max_relative_error : f32,
+  /// Texel of `max_relative_error`
+  pub max_error_at : ( u32, u32 )
+}
+
+impl ImageDiff
+{
+  pub fn new( image : &OutputImage, reference : &OutputImage ) -> anyhow::Result< Self >
+  {
+    let errors = relative_errors( image, reference )?;
+
+    let squared_sum = image.data.iter().zip( &reference.data ).map( | ( a, b ) | ( ( a - b ) as f64 ).powi( 2 ) ).sum::< f64 >();
+    let rmse = ( squared_sum / image.data.len().max( 1 ) as f64 ).sqrt() as f32;
+    let ( max_index, max_relative_error ) = errors.iter().copied().enumerate()
+    .fold( ( 0, 0.0 ), | max, ( i, error ) | if error > max.1 { ( i, error ) } else { max } );
+
+    Ok( Self
+    {
+      rmse,
+      // Also infinite for identical black images, whose peak is 0 too
+      psnr : if rmse == 0.0 { f32::INFINITY } else { 20.0 * ( peak( reference ) / rmse ).log10() },
+      max_relative_error,
+      max_error_at : ( max_index as u32 % image.width, max_index as u32 / image.width )
+    })
+  }
+}
+
+/// Rec. 709 luminance of linear RGB.
+pub fn luminance( rgb : [ f32; 3 ] ) -> f32
+{
+  0.2126 * rgb[ 0 ] + 0.7152 * rgb[ 1 ] + 0.0722 * rgb[ 2 ]
+}
+
+fn peak( image : &OutputImage ) -> f32
+{
+  image.data.iter().fold( 0.0f32, | peak, value | peak.max( value.abs() ) )
+}
+
+/// Largest error of the channels of every texel over the reference value, or over [`RELATIVE_FLOOR`]
+/// times the peak of the reference when that is larger. NaNs and infinities are infinitely wrong.
+pub fn relative_errors( image : &OutputImage, reference : &OutputImage ) -> anyhow::Result< Vec< f32 > >
+{
+  if ( image.width, image.height ) != ( reference.width, reference.height )
+  {
+    anyhow::bail!
+    (
+      "{} is {}x{} but its reference is {}x{}",
+      image.name, image.width, image.height, reference.width, reference.height
+    );
+  }
+
+  let floor = ( RELATIVE_FLOOR * peak( reference ) ).max( f32::MIN_POSITIVE );
+  Ok( image.data.chunks_exact( 3 ).zip( reference.data.chunks_exact( 3 ) ).map( | ( a, b ) |
+  {
+    a.iter().zip( b ).fold( 0.0f32, | max, ( a, b ) |
+    {
+      let error = ( a - b ).abs() / b.abs().max( floor );
+      if error.is_finite() { max.max( error ) } else { f32::INFINITY }
+    })
+  })
+  .collect() )
+}
+
+/// Writes the relative errors as a false color PNG, black where the images match and red from `scale` up.
+pub fn save_heatmap( path : &Path, image : &OutputImage, reference : &OutputImage, scale : f32 ) -> anyhow::Result< () >
+{
+  let errors = relative_errors( image, reference )?;
+  let heatmap = image::RgbImage::from_fn( image.width, image.height, | x, y |
+  {
+    let t = ( errors[ ( y * image.width + x ) as usize ] / scale ).clamp( 0.0, 1.0 ) * ( HEATMAP_COLORS.len() - 1 ) as f32;
+    let stop = ( t as usize ).min( HEATMAP_COLORS.len() - 2 );
+    let ( from, to ) = ( HEATMAP_COLORS[ stop ], HEATMAP_COLORS[ stop + 1 ] );
+    image::Rgb( std::array::from_fn( | c | ( ( from[ c ] + ( to[ c ] - from[ c ] ) * ( t - stop as f32 ) ) * 255.0 ).round() as u8 ) )
+  });
+
+  if let Some( dir ) = path.parent()
+  {
+    std::fs::create_dir_all( dir ).with_context( || format!( "Failed to create {}", dir.display() ) )?;
+  }
+  heatmap.save( path ).with_context( || format!( "Failed to write {}", path.display() ) )
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+
+  #[ test ]
+  fn metrics()
+  {
+    let reference = OutputImage::new( "reference", 2, 1, vec![ 1.0, 1.0, 1.0, 0.0, 0.0, 0.0 ] );
+    let same = ImageDiff::new( &reference, &reference ).unwrap();
+    assert!( same.rmse == 0.0 && same.psnr.is_infinite() && same.max_relative_error == 0.0 );
+
+    // One channel off by 0.6: RMSE of sqrt( 0.36 / 6 ), and relative to the 1% floor on the black texel
+    let image = OutputImage::new( "image", 2, 1, vec![ 1.0, 1.0, 1.0, 0.0, 0.6, 0.0 ] );
+    let diff = ImageDiff::new( &image, &reference ).unwrap();
+    assert!( ( diff.rmse - 0.06f32.sqrt() ).abs() < 1e-6 );
+    assert!( ( diff.psnr - 20.0 * ( 1.0 / 0.06f32.sqrt() ).log10() ).abs() < 1e-4 );
+    assert!( ( diff.max_relative_error - 60.0 ).abs() < 1e-3 && diff.max_error_at == ( 1, 0 ) );
+
+    let nan = OutputImage::new( "nan", 2, 1, vec![ f32::NAN, 1.0, 1.0, 0.0, 0.0, 0.0 ] );
+    assert!( ImageDiff::new( &nan, &reference ).unwrap().max_relative_error.is_infinite() );
+  }
+}
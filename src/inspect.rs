@@ -0,0 +1,545 @@
+use std::f32::consts::PI;
+
+use glam::{DVec3, Vec3};
+use wgpu::util::DeviceExt;
+
+use crate::{baker::SourceImage, image_diff, readback, texture::Texture};
+
+pub const SHADER_SOURCE : &str = include_str!( "shaders/inspect.wgsl" );
+
+/// Bins of the luminance histogram: one per stop from 2^-16 to 2^16, one for everything darker, black included,
+/// and one for everything brighter.
+pub const HISTOGRAM_BINS : usize = 34;
+const LOWEST_STOP : i32 = -16;
+
+/// Fraction of the texels the suggested clamp may cut, the brightest ones.
+pub const FIREFLY_FRACTION : f32 = 0.0001;
+
+/// Texels from this fraction of the largest luminance up make the dominant light.
+pub const LIGHT_FRACTION : f32 = 0.5;
+
+const WORKGROUP_SIZE : u32 = 16;
+
+/// Statistics of an equirectangular input, as `inspect` reports them.
+#[ derive( Clone, Debug ) ]
+pub struct SourceStats
+{
+  pub width : u32,
+  pub height : u32,
+  /// Smallest luminance above zero, 0 when every texel is black
+  pub min_luminance : f32,
+  pub max_luminance : f32,
+  /// Over the texels without NaN or infinite channels
+  pub mean_luminance : f32,
+  pub nan_count : u32,
+  pub infinite_count : u32,
+  /// Texels with a negative channel, which counts as 0 in the luminance
+  pub negative_count : u32,
+  /// Texels per bin, see [`HISTOGRAM_BINS`] and [`bin_range`]
+  pub histogram : [ u32; HISTOGRAM_BINS ],
+  /// Luminance integrated over the sphere
+  pub energy : f32,
+  /// Luminance weighted mean direction of the texels of the dominant light, see [`LIGHT_FRACTION`]
+  pub light_direction : Vec3,
+  /// Steradians the dominant light covers
+  pub light_solid_angle : f32,
+  /// Suggested firefly clamp, see [`FIREFLY_FRACTION`]
+  pub clamp : f32,
+  /// Integrated luminance left when clamping at `clamp`
+  pub clamped_energy : f32
+}
+
+impl SourceStats
+{
+  /// Stops between the smallest luminance above zero and the largest.
+  pub fn dynamic_range( &self ) -> f32
+  {
+    if self.min_luminance > 0.0 { ( self.max_luminance / self.min_luminance ).log2() } else { 0.0 }
+  }
+
+  pub fn print( &self, name : &str )
+  {
+    println!( "{}: {}x{}", name, self.width, self.height );
+    println!( "  luminance      min {:.4e}, max {:.4e}, mean {:.4e}", self.min_luminance, self.max_luminance, self.mean_luminance );
+    println!( "  dynamic range  {:.1} stops", self.dynamic_range() );
+    println!( "  invalid texels {} NaN, {} infinite, {} negative", self.nan_count, self.infinite_count, self.negative_count );
+
+    let direction = self.light_direction;
+    println!
+    (
+      "  dominant light ({:.3}, {:.3}, {:.3}), {:.1}° elevation, {:.1}° azimuth, {:.3e} sr",
+      direction.x, direction.y, direction.z,
+      direction.y.clamp( -1.0, 1.0 ).asin().to_degrees(),
+      direction.z.atan2( direction.x ).to_degrees(),
+      self.light_solid_angle
+    );
+    let kept = if self.energy > 0.0 { self.clamped_energy / self.energy * 100.0 } else { 100.0 };
+    println!( "  suggested clamp {:.4e}, keeping {:.1}% of the energy", self.clamp, kept );
+
+    println!( "  histogram" );
+    let used = | ( _, count ) : &( usize, &u32 ) | **count > 0;
+    let first = self.histogram.iter().enumerate().find( used ).map_or( 0, | ( bin, _ ) | bin );
+    let last = self.histogram.iter().enumerate().rev().find( used ).map_or( 0, | ( bin, _ ) | bin );
+    let largest = self.histogram.iter().copied().max().unwrap_or( 0 ).max( 1 );
+    for bin in first..=last
+    {
+      let ( low, high ) = bin_range( bin );
+      let range = match bin
+      {
+        0 => format!( "< 2^{}", high ),
+        _ if bin == HISTOGRAM_BINS - 1 => format!( ">= 2^{}", low ),
+        _ => format!( "2^{}..2^{}", low, high )
+      };
+      let count = self.histogram[ bin ];
+      let bar = "#".repeat( ( count as u64 * 40 ).div_ceil( largest as u64 ) as usize );
+      println!( "    {:>12} {:>10} {}", range, count, bar );
+    }
+  }
+}
+
+/// Stops the luminance of the texels of `bin` lies between.
+pub fn bin_range( bin : usize ) -> ( i32, i32 )
+{
+  ( bin as i32 + LOWEST_STOP - 1, bin as i32 + LOWEST_STOP )
+}
+
+/// Mirrors `histogram_bin` in `inspect.wgsl`.
+fn histogram_bin( luminance : f32 ) -> usize
+{
+  if luminance <= 0.0
+  {
+    return 0;
+  }
+  let exponent = ( ( luminance.to_bits() >> 23 ) & 0xff ) as i32 - 127;
+  ( exponent - LOWEST_STOP + 1 ).clamp( 0, HISTOGRAM_BINS as i32 - 1 ) as usize
+}
+
+/// Mirrors `threshold_main` in `inspect.wgsl`: the upper edge of the highest bin with more than
+/// [`FIREFLY_FRACTION`] of the valid texels in it or above it, and the largest luminance when there's none.
+fn clamp_threshold( histogram : &[ u32; HISTOGRAM_BINS ], valid : u32, max_luminance : f32 ) -> f32
+{
+  let allowed = ( valid as f32 * FIREFLY_FRACTION ) as u32;
+  let mut above = 0;
+  for bin in ( 0..HISTOGRAM_BINS ).rev()
+  {
+    above += histogram[ bin ];
+    if above > allowed
+    {
+      return 2f32.powi( bin_range( bin ).1 ).min( max_luminance );
+    }
+  }
+  max_luminance
+}
+
+/// Luminance of the texel, `None` when it has a NaN or an infinite channel. Negative channels count as 0.
+fn texel_luminance( rgb : [ f32; 3 ] ) -> Option< f32 >
+{
+  rgb.iter().all( | c | c.is_finite() ).then( || image_diff::luminance( rgb.map( | c | c.max( 0.0 ) ) ) )
+}
+
+fn texel_elevation( y : u32, height : u32 ) -> f32
+{
+  ( 0.5 - ( y as f32 + 0.5 ) / height as f32 ) * PI
+}
+
+fn texel_solid_angle( y : u32, width : u32, height : u32 ) -> f32
+{
+  texel_elevation( y, height ).cos() * ( 2.0 * PI / width as f32 ) * ( PI / height as f32 )
+}
+
+fn texel_direction( x : u32, y : u32, width : u32, height : u32 ) -> Vec3
+{
+  let elevation = texel_elevation( y, height );
+  let azimuth = ( ( x as f32 + 0.5 ) / width as f32 * 2.0 - 1.0 ) * PI;
+  Vec3::new( azimuth.cos() * elevation.cos(), elevation.sin(), azimuth.sin() * elevation.cos() )
+}
+
+/// The statistics computed on the CPU, the same way the GPU passes of [`Inspector`] do.
+pub fn inspect_cpu( source : &SourceImage ) -> SourceStats
+{
+  let ( width, height ) = ( source.width, source.height );
+  let texels = | | source.pixels.chunks_exact( 4 ).enumerate().map( move | ( i, rgba ) |
+  {
+    ( i as u32 % width, i as u32 / width, [ rgba[ 0 ], rgba[ 1 ], rgba[ 2 ] ] )
+  });
+
+  let ( mut nan_count, mut infinite_count, mut negative_count ) = ( 0, 0, 0 );
+  let ( mut min_luminance, mut max_luminance ) = ( f32::INFINITY, 0.0f32 );
+  let mut histogram = [ 0; HISTOGRAM_BINS ];
+  let ( mut luminance_sum, mut energy ) = ( 0.0f64, 0.0f64 );
+  for ( _, y, rgb ) in texels()
+  {
+    let Some( luminance ) = texel_luminance( rgb ) else
+    {
+      if rgb.iter().any( | c | c.is_nan() ) { nan_count += 1 } else { infinite_count += 1 }
+      continue;
+    };
+    if rgb.iter().any( | c | *c < 0.0 )
+    {
+      negative_count += 1;
+    }
+    if luminance > 0.0
+    {
+      min_luminance = min_luminance.min( luminance );
+    }
+    max_luminance = max_luminance.max( luminance );
+    histogram[ histogram_bin( luminance ) ] += 1;
+    luminance_sum += luminance as f64;
+    energy += ( luminance * texel_solid_angle( y, width, height ) ) as f64;
+  }
+
+  let valid = width * height - nan_count - infinite_count;
+  let clamp = clamp_threshold( &histogram, valid, max_luminance );
+  let threshold = max_luminance * LIGHT_FRACTION;
+  let ( mut light, mut light_solid_angle, mut clamped_energy ) = ( DVec3::ZERO, 0.0f64, 0.0f64 );
+  for ( x, y, rgb ) in texels()
+  {
+    let Some( luminance ) = texel_luminance( rgb ) else { continue };
+    let solid_angle = texel_solid_angle( y, width, height );
+    clamped_energy += ( luminance.min( clamp ) * solid_angle ) as f64;
+    if luminance > 0.0 && luminance >= threshold
+    {
+      light += ( texel_direction( x, y, width, height ) * luminance * solid_angle ).as_dvec3();
+      light_solid_angle += solid_angle as f64;
+    }
+  }
+
+  SourceStats
+  {
+    width,
+    height,
+    min_luminance : if min_luminance.is_finite() { min_luminance } else { 0.0 },
+    max_luminance,
+    mean_luminance : ( luminance_sum / valid.max( 1 ) as f64 ) as f32,
+    nan_count,
+    infinite_count,
+    negative_count,
+    histogram,
+    energy : energy as f32,
+    light_direction : light.normalize_or_zero().as_vec3(),
+    light_solid_angle : light_solid_angle as f32,
+    clamp,
+    clamped_energy : clamped_energy as f32
+  }
+}
+
+/// `Stats` in `inspect.wgsl`.
+#[ repr( C ) ]
+#[ derive( Clone, Copy, bytemuck::Pod, bytemuck::Zeroable ) ]
+struct GpuStats
+{
+  min_luminance : u32,
+  max_luminance : u32,
+  nan_count : u32,
+  infinite_count : u32,
+  negative_count : u32,
+  clamp : f32,
+  histogram : [ u32; HISTOGRAM_BINS ]
+}
+
+/// `Partial` in `inspect.wgsl`, the sums of one workgroup.
+#[ repr( C ) ]
+#[ derive( Clone, Copy, bytemuck::Pod, bytemuck::Zeroable ) ]
+struct Partial
+{
+  light : [ f32; 3 ],
+  luminance : f32,
+  energy : f32,
+  clamped_energy : f32,
+  light_solid_angle : f32,
+  padding : f32
+}
+
+/// Computes the [`SourceStats`] of a source texture on the GPU. A first pass reduces the extremes, the
+/// invalid texels and the histogram with atomics and sums the luminance per workgroup, a second picks the
+/// clamp from the histogram and a third sums the dominant light. The partial sums are added up on the CPU.
+pub struct Inspector
+{
+  bind_group_layout : wgpu::BindGroupLayout,
+  reduce_pipeline : wgpu::ComputePipeline,
+  threshold_pipeline : wgpu::ComputePipeline,
+  light_pipeline : wgpu::ComputePipeline
+}
+
+impl Inspector
+{
+  pub fn new( device : &wgpu::Device ) -> Self
+  {
+    let storage_entry = | binding | wgpu::BindGroupLayoutEntry
+    {
+      binding,
+      visibility: wgpu::ShaderStages::COMPUTE,
+      ty: wgpu::BindingType::Buffer
+      {
+        ty: wgpu::BufferBindingType::Storage { read_only: false },
+        has_dynamic_offset: false,
+        min_binding_size: None
+      },
+      count: None
+    };
+    let bind_group_layout = device.create_bind_group_layout
+    (
+      &wgpu::BindGroupLayoutDescriptor
+      {
+        label: None,
+        entries: &
+        [
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Texture
+            {
+              multisampled: false,
+              view_dimension: wgpu::TextureViewDimension::D2,
+              sample_type: wgpu::TextureSampleType::Float { filterable: false }
+            },
+            count: None
+          },
+          storage_entry( 1 ),
+          storage_entry( 2 )
+        ]
+      }
+    );
+
+    let shader = device.create_shader_module
+    (
+      wgpu::ShaderModuleDescriptor
+      {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl( SHADER_SOURCE.into() )
+      }
+    );
+
+    let pipeline_layout = device.create_pipeline_layout
+    (
+      &wgpu::PipelineLayoutDescriptor
+      {
+        label : None,
+        bind_group_layouts : &
+        [
+          &bind_group_layout
+        ],
+        push_constant_ranges : &[]
+      }
+    );
+
+    let pipeline = | entry_point | device.create_compute_pipeline
+    (
+      &wgpu::ComputePipelineDescriptor
+      {
+        label : Some( entry_point ),
+        layout : Some( &pipeline_layout ),
+        module : &shader,
+        entry_point : Some( entry_point ),
+        compilation_options : wgpu::PipelineCompilationOptions::default(),
+        cache : None
+      }
+    );
+
+    Self
+    {
+      reduce_pipeline : pipeline( "reduce_main" ),
+      threshold_pipeline : pipeline( "threshold_main" ),
+      light_pipeline : pipeline( "light_main" ),
+      bind_group_layout
+    }
+  }
+
+  /// Runs the passes on `source`, an equirectangular texture of 32-bit floats, and reads the statistics back.
+  pub async fn inspect( &self, device : &wgpu::Device, queue : &wgpu::Queue, source : &Texture ) -> anyhow::Result< SourceStats >
+  {
+    let size = source.size();
+    let ( groups_x, groups_y ) = ( size.width.div_ceil( WORKGROUP_SIZE ), size.height.div_ceil( WORKGROUP_SIZE ) );
+    let partials_size = ( groups_x * groups_y ) as u64 * std::mem::size_of::< Partial >() as u64;
+
+    let initial_stats = GpuStats { min_luminance : u32::MAX, ..bytemuck::Zeroable::zeroed() };
+    let stats_buffer = device.create_buffer_init
+    (
+      &wgpu::util::BufferInitDescriptor
+      {
+        label : None,
+        contents : bytemuck::bytes_of( &initial_stats ),
+        usage : wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC
+      }
+    );
+    let partials_buffer = device.create_buffer
+    (
+      &wgpu::BufferDescriptor
+      {
+        label : None,
+        size : partials_size,
+        usage : wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation : false
+      }
+    );
+    let readback_buffer = | size | device.create_buffer
+    (
+      &wgpu::BufferDescriptor
+      {
+        label : None,
+        size,
+        usage : wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation : false
+      }
+    );
+    let stats_readback = readback_buffer( std::mem::size_of::< GpuStats >() as u64 );
+    let partials_readback = readback_buffer( partials_size );
+
+    let bind_group = device.create_bind_group
+    (
+      &wgpu::BindGroupDescriptor
+      {
+        label : None,
+        layout : &self.bind_group_layout,
+        entries : &
+        [
+          wgpu::BindGroupEntry
+          {
+            binding : 0,
+            resource : wgpu::BindingResource::TextureView( source.view() )
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 1,
+            resource : stats_buffer.as_entire_binding()
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 2,
+            resource : partials_buffer.as_entire_binding()
+          }
+        ]
+      }
+    );
+
+    let mut encoder = device.create_command_encoder( &wgpu::CommandEncoderDescriptor { label : None } );
+    {
+      let mut compute_pass = encoder.begin_compute_pass
+      (
+        &wgpu::ComputePassDescriptor
+        {
+          label : None,
+          timestamp_writes : None
+        }
+      );
+      compute_pass.set_bind_group( 0, &bind_group, &[] );
+      compute_pass.set_pipeline( &self.reduce_pipeline );
+      compute_pass.dispatch_workgroups( groups_x, groups_y, 1 );
+      compute_pass.set_pipeline( &self.threshold_pipeline );
+      compute_pass.dispatch_workgroups( 1, 1, 1 );
+      compute_pass.set_pipeline( &self.light_pipeline );
+      compute_pass.dispatch_workgroups( groups_x, groups_y, 1 );
+    }
+    encoder.copy_buffer_to_buffer( &stats_buffer, 0, &stats_readback, 0, stats_readback.size() );
+    encoder.copy_buffer_to_buffer( &partials_buffer, 0, &partials_readback, 0, partials_size );
+    queue.submit( Some( encoder.finish() ) );
+
+    readback::map_buffers( device, &[ &stats_readback, &partials_readback ] ).await?;
+    let stats : GpuStats = bytemuck::pod_read_unaligned( &stats_readback.get_mapped_range( .. ) );
+    let partials : Vec< Partial > = bytemuck::pod_collect_to_vec( &partials_readback.get_mapped_range( .. ) );
+    stats_readback.unmap();
+    partials_readback.unmap();
+
+    let sum = | value : fn( &Partial ) -> f32 | partials.iter().map( | partial | value( partial ) as f64 ).sum::< f64 >();
+    let light = partials.iter().map( | partial | Vec3::from( partial.light ).as_dvec3() ).sum::< DVec3 >();
+    let valid = size.width * size.height - stats.nan_count - stats.infinite_count;
+
+    Ok( SourceStats
+    {
+      width : size.width,
+      height : size.height,
+      min_luminance : if stats.min_luminance == u32::MAX { 0.0 } else { f32::from_bits( stats.min_luminance ) },
+      max_luminance : f32::from_bits( stats.max_luminance ),
+      mean_luminance : ( sum( | partial | partial.luminance ) / valid.max( 1 ) as f64 ) as f32,
+      nan_count : stats.nan_count,
+      infinite_count : stats.infinite_count,
+      negative_count : stats.negative_count,
+      histogram : stats.histogram,
+      energy : sum( | partial | partial.energy ) as f32,
+      light_direction : light.normalize_or_zero().as_vec3(),
+      light_solid_angle : sum( | partial | partial.light_solid_angle ) as f32,
+      clamp : stats.clamp,
+      clamped_energy : sum( | partial | partial.clamped_energy ) as f32
+    })
+  }
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+  use crate::gpu;
+
+  /// Dim sky with a small, bright sun above the horizon, a NaN, an infinity and a negative texel.
+  fn source() -> SourceImage
+  {
+    let ( width, height ) = ( 64, 32 );
+    let mut pixels = Vec::new();
+    for y in 0..height
+    {
+      for x in 0..width
+      {
+        let sun = ( 21..23 ).contains( &x ) && ( 10..12 ).contains( &y );
+        let rgb = if sun { [ 1000.0, 900.0, 800.0 ] } else { [ 0.2, 0.3, 0.5 + y as f32 * 0.01 ] };
+        pixels.extend( rgb.into_iter().chain( [ 1.0 ] ) );
+      }
+    }
+    pixels[ 0 ] = f32::NAN;
+    pixels[ 5 ] = f32::INFINITY;
+    pixels[ 8 ] = -1.0;
+    SourceImage { width, height, pixels }
+  }
+
+  #[ test ]
+  fn cpu_statistics()
+  {
+    let stats = inspect_cpu( &source() );
+    assert_eq!( ( stats.nan_count, stats.infinite_count, stats.negative_count ), ( 1, 1, 1 ) );
+    assert_eq!( stats.histogram.iter().sum::< u32 >(), 64 * 32 - 2 );
+    let sun = image_diff::luminance( [ 1000.0, 900.0, 800.0 ] );
+    assert_eq!( ( stats.max_luminance, stats.histogram[ histogram_bin( sun ) ] ), ( sun, 4 ) );
+
+    // The sun covers the 4 texels around the corner at x = 22, y = 11
+    let direction = texel_direction( 22, 11, 64, 32 ).lerp( texel_direction( 21, 10, 64, 32 ), 0.5 ).normalize();
+    assert!( stats.light_direction.dot( direction ) > 0.999, "{:?}", stats.light_direction );
+    let sun_solid_angle = texel_solid_angle( 10, 64, 32 ) * 2.0 + texel_solid_angle( 11, 64, 32 ) * 2.0;
+    assert!( ( stats.light_solid_angle - sun_solid_angle ).abs() < 1e-6 );
+
+    // 4 of the 2046 valid texels is more than the firefly fraction, the sun isn't cut
+    assert_eq!( stats.clamp, stats.max_luminance );
+    assert!( ( stats.clamped_energy - stats.energy ).abs() < stats.energy * 1e-5 );
+  }
+
+  #[ test ]
+  fn clamp_cuts_the_brightest_texels()
+  {
+    let mut histogram = [ 0; HISTOGRAM_BINS ];
+    histogram[ histogram_bin( 1.0 ) ] = 100_000;
+    histogram[ histogram_bin( 5000.0 ) ] = 5;
+    assert_eq!( clamp_threshold( &histogram, 100_005, 5000.0 ), 2.0 );
+    histogram[ histogram_bin( 5000.0 ) ] = 50;
+    assert_eq!( clamp_threshold( &histogram, 100_050, 5000.0 ), 5000.0 );
+  }
+
+  #[ test ]
+  fn gpu_matches_cpu()
+  {
+    let Some( ( device, queue ) ) = gpu::test_device() else
+    {
+      eprintln!( "No suitable GPU adapter, skipping" );
+      return;
+    };
+    let source = source();
+    let expected = inspect_cpu( &source );
+    let stats = pollster::block_on( Inspector::new( &device ).inspect( &device, &queue, &source.to_texture( &device, &queue ) ) ).unwrap();
+
+    assert_eq!( ( stats.nan_count, stats.infinite_count, stats.negative_count ), ( 1, 1, 1 ) );
+    assert_eq!( stats.histogram, expected.histogram );
+    assert_eq!( ( stats.min_luminance, stats.max_luminance, stats.clamp ), ( expected.min_luminance, expected.max_luminance, expected.clamp ) );
+    let close = | a : f32, b : f32 | ( a - b ).abs() <= b.abs() * 1e-4;
+    assert!( close( stats.mean_luminance, expected.mean_luminance ) && close( stats.energy, expected.energy ) );
+    assert!( close( stats.clamped_energy, expected.clamped_energy ) && close( stats.light_solid_angle, expected.light_solid_angle ) );
+    assert!( stats.light_direction.dot( expected.light_direction ) > 0.9999 );
+  }
+}
@@ -1,76 +1,235 @@
-use std::{io::Read, net::TcpListener, sync::Arc};
+use std::{path::Path, sync::Arc};
 use log::debug;
-use winit::{event::{ElementState, Event, KeyEvent, WindowEvent}, event_loop::{self, EventLoop}, keyboard::{KeyCode, PhysicalKey}, window::WindowBuilder};
+use winit::{event::{ElementState, Event, KeyEvent, WindowEvent}, event_loop::EventLoop, keyboard::{KeyCode, PhysicalKey}, window::WindowBuilder};
+
+use crate::{baker::BakeSettings, batch::{BatchJob, Input, ProfileOutput}, cli::{Cli, Command}};
 
 mod state;
-mod hdr_texture;
-mod cube_texture;
 mod cube_map_renderer;
+mod equirect_renderer;
+mod sky_renderer;
 mod camera;
 mod ibl_renderer;
-mod texture_2d;
+mod ibl_compute;
+mod texture;
+mod cube_file;
 mod cube_mipmap_renderer;
+mod cpu_baker;
+mod gpu;
+mod output;
+mod baker;
+mod batch;
+mod cli;
+mod cache;
+mod manifest;
+mod sh;
+mod gltf;
+mod packing;
+mod profiler;
+mod tiling;
+mod progressive;
+mod readback;
+mod sky;
+mod image_diff;
+mod golden;
+mod compare;
+mod inspect;
+
+/// The device to bake on, `None` when `settings.cpu` is set or no adapter can be used, the CPU reference bakes then.
+async fn bake_device( settings : &BakeSettings ) -> Option< ( wgpu::Adapter, wgpu::Device, wgpu::Queue ) >
+{
+  if settings.cpu
+  {
+    return None;
+  }
+  match gpu::create_device( &gpu::create_instance(), None ).await
+  {
+    Ok( device ) => Some( device ),
+    Err( e ) =>
+    {
+      eprintln!( "{:#}, baking on the CPU instead", e );
+      None
+    }
+  }
+}
+
+pub async fn bake( jobs : Vec< BatchJob >, settings : &BakeSettings, force : bool, profile : Option< &ProfileOutput > ) -> anyhow::Result< () >
+{
+  let device = bake_device( settings ).await;
+  let report = match device
+  {
+    Some( ( _adapter, device, queue ) ) =>
+    {
+      gpu::check_output_formats( &device, settings )?;
+      batch::run( &device, &queue, jobs, settings, force, profile.is_some() ).await
+    },
+    None => batch::run_cpu( jobs, &BakeSettings { cpu : true, ..*settings }, force )
+  };
+  report.print();
+  match profile
+  {
+    Some( ProfileOutput::Table ) => report.print_profile(),
+    Some( ProfileOutput::Json( path ) ) => report.save_profile( path )?,
+    None => {}
+  }
+
+  if report.num_failed() > 0
+  {
+    anyhow::bail!( "{} of {} inputs failed", report.num_failed(), report.jobs.len() );
+  }
+  Ok( () )
+}
+
+/// Compares the bake of the regression cases to their goldens under `dir`, or replaces the goldens with `bless`.
+/// Only the backend is taken from `settings`, see [`golden`].
+pub async fn golden( dir : &Path, diff_dir : &Path, bless : bool, settings : &BakeSettings ) -> anyhow::Result< () >
+{
+  let device = bake_device( settings ).await;
+  let compute = settings.compute;
+  match &device
+  {
+    Some( ( _adapter, device, queue ) ) =>
+    {
+      let backend = if compute { "compute" } else { "render" };
+      golden::run( dir, diff_dir, bless, backend, | source, settings | golden::bake_gpu( device, queue, source, &BakeSettings { compute, ..*settings } ) )
+    },
+    None => golden::run( dir, diff_dir, bless, "cpu", cpu_baker::bake )
+  }
+}
 
-pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
-    let event_loop = EventLoop::new().unwrap();
-    let window = WindowBuilder::new()
-    .with_inner_size(winit::dpi::LogicalSize { width: 1600, height: 900})
-    .with_position(winit::dpi::LogicalPosition {x: 150, y: 50})
-    .build(&event_loop).unwrap();
-
-    let window = Arc::new(window);
-    let mut state = state::State::new(window.clone()).await;
-
-    state.render_hdr_to_cube();
-    state.save_ibl_diffuse().await;
-    state.save_ibl_specular_1().await;
-    state.save_ibl_specular_2().await;
-
-    // event_loop.run(move |event, elwt| match event {
-    //     Event::WindowEvent {
-    //         ref event,
-    //         window_id,
-    //     } if window_id == window.id() => if !state.input(event) { 
-    //         match event {
-    //             WindowEvent::CloseRequested
-    //             | WindowEvent::KeyboardInput {
-    //                 event:
-    //                     KeyEvent {
-    //                         state: ElementState::Pressed,
-    //                         physical_key: PhysicalKey::Code(KeyCode::Escape),
-    //                         ..
-    //                     },
-    //                 ..
-    //             } => elwt.exit(),
-    //             //WindowEvent::Resized(new_size) => {state.resize(*new_size);},
-    //             WindowEvent::ScaleFactorChanged { scale_factor, inner_size_writer } => { 
-    //                 debug!("ScaleFactorChanged: {:?}, {:?}", scale_factor, inner_size_writer);
-    //                 //TODO
-
-    //             },
-    //             WindowEvent::RedrawRequested => {
-    //                 state.update();
-    //                 match state.render() {
-    //                     Ok(_) => {}
-    //                     //Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
-    //                     Err(wgpu::SurfaceError::OutOfMemory) => elwt.exit(),
-    //                     Err(e) => eprintln!("Error: {:?}", e),
-    //                 }
-    //             },
-    //             _ => {}
-    //         }
-    //     },
-    //     Event::AboutToWait => {
-    //         window.request_redraw();
-    //     }
-    //     _ => {}
-    // })?;
-
-    Ok(())
+/// Prints the statistics of the image at `input` or of the equirect of its sky, computed on the GPU or on the CPU
+/// like a bake of `settings`.
+pub async fn inspect( input : &Input, settings : &BakeSettings ) -> anyhow::Result< () >
+{
+  let source = match input
+  {
+    Input::File( path ) => baker::SourceImage::load( path )?,
+    Input::Sky( sky ) =>
+    {
+      let ( width, height ) = sky::SKY_EQUIRECT_SIZE;
+      sky.uniform()?.render_equirect( width, height )
+    }
+  };
+  let stats = match bake_device( settings ).await
+  {
+    Some( ( _adapter, device, queue ) ) =>
+    {
+      inspect::Inspector::new( &device ).inspect( &device, &queue, &source.to_texture( &device, &queue ) ).await?
+    },
+    None => inspect::inspect_cpu( &source )
+  };
+  stats.print( &input.to_string() );
+  Ok( () )
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    pollster::block_on(run())?;
-    Ok(())
-}
\ No newline at end of file
+pub async fn view( input : &Input, settings : &BakeSettings ) -> anyhow::Result< () >
+{
+  let event_loop = EventLoop::new()?;
+  let window = WindowBuilder::new()
+  .with_inner_size(winit::dpi::LogicalSize { width: 1600, height: 900})
+  .with_position(winit::dpi::LogicalPosition {x: 150, y: 50})
+  .build(&event_loop)?;
+
+  let window = Arc::new(window);
+  // The progress of the refinement is shown on the specular cube
+  let settings = match settings.progressive
+  {
+    Some( _ ) => BakeSettings { specular_cube_size : settings.specular_cube_size.or( Some( 256 ) ), ..*settings },
+    None => *settings
+  };
+  let mut state = state::State::new( window.clone(), input, &settings ).await?;
+
+  event_loop.run(move |event, elwt| match event {
+      Event::WindowEvent {
+          ref event,
+          window_id,
+      } if window_id == window.id() && !state.input(event) => {
+          match event {
+              WindowEvent::CloseRequested
+              | WindowEvent::KeyboardInput {
+                  event:
+                      KeyEvent {
+                          state: ElementState::Pressed,
+                          physical_key: PhysicalKey::Code(KeyCode::Escape),
+                          ..
+                      },
+                  ..
+              } => elwt.exit(),
+              //WindowEvent::Resized(new_size) => {state.resize(*new_size);},
+              WindowEvent::ScaleFactorChanged { scale_factor, inner_size_writer } => {
+                  debug!("ScaleFactorChanged: {:?}, {:?}", scale_factor, inner_size_writer);
+                  //TODO
+
+              },
+              WindowEvent::RedrawRequested => {
+                  state.update();
+                  match state.render() {
+                      Ok(_) => {}
+                      //Err(wgpu::SurfaceError::Lost) => state.resize(state.size),
+                      Err(wgpu::SurfaceError::OutOfMemory) => elwt.exit(),
+                      Err(e) => eprintln!("Error: {:?}", e),
+                  }
+              },
+              _ => {}
+          }
+      },
+      Event::AboutToWait => {
+          window.request_redraw();
+      }
+      _ => {}
+  })?;
+
+  Ok(())
+}
+
+pub async fn run() -> anyhow::Result< () >
+{
+  let cli = match Cli::parse( std::env::args().skip( 1 ) )
+  {
+    Ok( cli ) => cli,
+    Err( e ) =>
+    {
+      eprint!( "{}", cli::USAGE );
+      return Err( e );
+    }
+  };
+
+  match cli.command
+  {
+    Command::Bake { input, output } =>
+    {
+      bake( vec![ BatchJob { input, output_dir : output } ], &cli.settings, cli.force, cli.profile.as_ref() ).await
+    },
+    Command::Batch { pattern, output } =>
+    {
+      let inputs = batch::collect_inputs( &pattern )?;
+      if inputs.is_empty()
+      {
+        anyhow::bail!( "No inputs match {}", pattern );
+      }
+
+      let jobs = batch::file_jobs( inputs, &output )?;
+      bake( jobs, &cli.settings, cli.force, cli.profile.as_ref() ).await
+    },
+    Command::View { input } => view( &input, &cli.settings ).await,
+    Command::Golden { dir, diff_dir, bless } => golden( &dir, &diff_dir, bless, &cli.settings ).await,
+    Command::Compare { a, b, diff_dir } =>
+    {
+      compare::print( &compare::compare( &a, &b, diff_dir.as_deref() )? );
+      Ok( () )
+    },
+    Command::Inspect { input } => inspect( &input, &cli.settings ).await,
+    Command::CacheClean { dir } =>
+    {
+      let removed = cache::clean( &dir )?;
+      println!( "Removed {} cache entries from {}", removed, dir.display() );
+      Ok( () )
+    }
+  }
+}
+
+fn main() -> anyhow::Result< () >
+{
+  env_logger::init();
+  pollster::block_on( run() )
+}
@@ -0,0 +1,469 @@
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{baker::{BakeOutputs, BakeSettings, CubeContainer, CubeResample, EnvironmentCubeSettings, GltfSettings, OutputLayout}, cube_file, gltf::GLTF_FILE, gpu, ibl_renderer, output::{OutputFormat, OutputImage}, packing::Packing, sky::SkySettings};
+
+pub const MANIFEST_FILE : &str = "manifest.json";
+
+/// Describes every artifact of one bake, so importers don't have to rely on file naming conventions.
+/// It is written after all outputs, which makes it the marker of a finished bake.
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct Manifest
+{
+  pub tool : String,
+  pub tool_version : String,
+  /// See [`crate::cache::cache_key`]
+  pub cache_key : String,
+  pub source : SourceEntry,
+  pub settings : SettingsEntry,
+  /// Processing applied from the source to the written files, in order, as `<operation>: <details>`
+  pub transforms : Vec< String >,
+  pub diffuse : ImageEntry,
+  pub specular : SpecularEntry,
+  pub brdf_lut : LutEntry,
+  #[ serde( default, skip_serializing_if = "Option::is_none" ) ]
+  pub specular_cube : Option< SpecularCubeEntry >,
+  #[ serde( default, skip_serializing_if = "Option::is_none" ) ]
+  pub gltf : Option< GltfEntry >,
+  #[ serde( default, skip_serializing_if = "Option::is_none" ) ]
+  pub environment_cube : Option< EnvironmentCubeEntry >,
+  /// The unfiltered environment as an equirectangular image
+  #[ serde( default, skip_serializing_if = "Option::is_none" ) ]
+  pub environment : Option< ImageEntry >,
+  /// Every file of the bake but the manifest, which has to be there at this size for the bake to count as cached
+  #[ serde( default ) ]
+  pub files : Vec< FileEntry >
+}
+
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct SourceEntry
+{
+  /// File of the image, or `sky:<model>` for a sky
+  pub path : String,
+  /// BLAKE3 of the file content, or of the parameters of the sky
+  pub hash : String,
+  /// Size of the image, 0 for a sky
+  pub width : u32,
+  pub height : u32,
+  #[ serde( default, skip_serializing_if = "Option::is_none" ) ]
+  pub sky : Option< SkyEntry >
+}
+
+/// One written file.
+#[ derive( Serialize, Deserialize, Debug, Clone, PartialEq ) ]
+pub struct FileEntry
+{
+  /// Relative to the manifest
+  pub path : String,
+  pub bytes : u64
+}
+
+/// Parameters of an analytic sky source.
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct SkyEntry
+{
+  /// `preetham`, `hosek-wilkie` or `gradient`
+  pub model : String,
+  /// In degrees, the azimuth is 0 at the center of an equirect
+  pub sun_elevation : f32,
+  pub sun_azimuth : f32,
+  pub turbidity : f32,
+  pub ground_albedo : f32
+}
+
+/// Sample counts are per output texel.
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct SettingsEntry
+{
+  pub cube_size : u32,
+  pub diffuse_samples : u32,
+  pub specular_samples : u32,
+  pub lut_samples : u32,
+  /// `bilinear`, `supersample` or `area`, the source is resampled to the environment cube with it
+  #[ serde( default ) ]
+  pub cube_resample : String,
+  /// Samples along each axis of a cube texel, with `supersample` only
+  #[ serde( default, skip_serializing_if = "Option::is_none" ) ]
+  pub cube_samples : Option< u32 >,
+  /// `box`, `kaiser` or `solid-angle`, the filter of the environment cube mips
+  #[ serde( default ) ]
+  pub mip_filter : String,
+  /// Mapping of the diffuse and specular maps, `equirect` or `octahedral`
+  #[ serde( default ) ]
+  pub layout : String,
+  #[ serde( default, skip_serializing_if = "is_zero" ) ]
+  pub border : u32,
+  /// GPU formats the outputs were rendered in, before they were written in `output_format`
+  #[ serde( default ) ]
+  pub diffuse_format : String,
+  #[ serde( default ) ]
+  pub specular_format : String,
+  #[ serde( default ) ]
+  pub lut_format : String,
+  #[ serde( default ) ]
+  pub output_format : String,
+  /// Range multiplier of the packed PNG formats
+  #[ serde( default, skip_serializing_if = "Option::is_none" ) ]
+  pub range : Option< f32 >
+}
+
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct ImageEntry
+{
+  /// Relative to the manifest
+  pub path : String,
+  pub format : String,
+  /// Range multiplier of the packed PNG formats
+  #[ serde( default, skip_serializing_if = "Option::is_none" ) ]
+  pub range : Option< f32 >,
+  pub width : u32,
+  pub height : u32,
+  /// `equirect` or `octahedral` for environment lookups, `cube_face` for one face of a cube, `cube_cross` for the faces
+  /// of a cube laid out as a horizontal cross, `cube` for a container with every face, `lut` for tables indexed by the UV
+  pub layout : String,
+  /// Texels duplicated around every edge, the mapping covers the rest of the image
+  #[ serde( default, skip_serializing_if = "is_zero" ) ]
+  pub border : u32
+}
+
+fn is_zero( value : &u32 ) -> bool
+{
+  *value == 0
+}
+
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct SpecularMip
+{
+  pub mip : u32,
+  pub roughness : f32,
+  #[ serde( flatten ) ]
+  pub image : ImageEntry
+}
+
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct SpecularEntry
+{
+  /// Perceptual roughness is `mip / mip_count`
+  pub mip_count : u32,
+  pub mips : Vec< SpecularMip >
+}
+
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct SpecularCubeEntry
+{
+  /// Roughness of each mip is the same as for `specular`
+  pub mip_count : u32,
+  pub face_size : u32,
+  /// Faces of every mip, in the order of `face_order`
+  pub mips : Vec< Vec< ImageEntry > >,
+  pub face_order : [ String; 6 ]
+}
+
+/// The unfiltered environment cube, for rendering the background.
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct EnvironmentCubeEntry
+{
+  /// `faces`, `cross`, `ktx2` or `dds`
+  pub container : String,
+  pub mip_count : u32,
+  pub face_size : u32,
+  /// Files of every mip, either the six faces in the order of `face_order` or a single cross.
+  /// Empty when `file` holds the whole cube
+  pub mips : Vec< Vec< ImageEntry > >,
+  /// KTX2 or DDS file with every face and mip
+  #[ serde( default, skip_serializing_if = "Option::is_none" ) ]
+  pub file : Option< ImageEntry >,
+  pub face_order : [ String; 6 ]
+}
+
+/// glTF asset using `EXT_lights_image_based`, its specular images are RGBD PNGs.
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct GltfEntry
+{
+  pub path : String,
+  pub intensity : f32,
+  /// Around the up axis, in degrees
+  pub rotation : f32
+}
+
+#[ derive( Serialize, Deserialize, Debug, Clone ) ]
+pub struct LutEntry
+{
+  #[ serde( flatten ) ]
+  pub image : ImageEntry,
+  /// What the U and V coordinates of the LUT stand for
+  pub u : String,
+  pub v : String,
+  /// Meaning of every channel
+  pub channels : [ String; 3 ]
+}
+
+impl ImageEntry
+{
+  fn new( image : &OutputImage, format : OutputFormat, layout : &str ) -> Self
+  {
+    Self
+    {
+      path : image.file_name( format ),
+      format : format.name(),
+      range : format.range(),
+      width : image.width,
+      height : image.height,
+      layout : layout.into(),
+      border : 0
+    }
+  }
+
+  /// Reads the image back from `dir`, the directory of the manifest. The packed PNGs are decoded with their range.
+  pub fn load( &self, dir : &Path ) -> anyhow::Result< OutputImage >
+  {
+    let path = dir.join( &self.path );
+    let packing = [ Packing::Rgbm, Packing::Rgbd, Packing::Rgbe ].into_iter()
+    .find( | packing | self.format == OutputFormat::Png { packing : *packing, range : 0.0 }.name() );
+    match packing
+    {
+      Some( packing ) => OutputImage::load_png( &path, packing, self.range.unwrap_or( packing.default_range() ) ),
+      None => OutputImage::load( &path )
+    }
+  }
+
+  /// Image with every face and mip of a cube, whose texels are 32-bit floats.
+  fn container( path : &str, container : CubeContainer, face_size : u32 ) -> Self
+  {
+    Self
+    {
+      path : path.into(),
+      format : format!( "{}_rgba32f", container.name() ),
+      range : None,
+      width : face_size,
+      height : face_size,
+      layout : "cube".into(),
+      border : 0
+    }
+  }
+
+  fn environment( image : &OutputImage, format : OutputFormat, layout : OutputLayout ) -> Self
+  {
+    Self { border : layout.border(), ..Self::new( image, format, layout.name() ) }
+  }
+}
+
+/// What happens to the source on the way to the files, see [`Manifest::transforms`].
+fn transforms( source : &SourceEntry, settings : &BakeSettings ) -> Vec< String >
+{
+  let cube = format!( "{0}x{0} faces", settings.cube_size );
+  let mut transforms = vec!
+  [
+    match ( &source.sky, settings.cube_resample )
+    {
+      ( Some( sky ), _ ) => format!( "sky: {} rendered to {}", sky.model, cube ),
+      ( None, CubeResample::Supersample ) => format!( "equirect_to_cube: supersample {0}x{0}, {1}", settings.cube_samples, cube ),
+      ( None, resample ) => format!( "equirect_to_cube: {}, {}", resample.name(), cube )
+    },
+    format!( "cube_mips: {}", settings.mip_filter.name() ),
+    match settings.layout
+    {
+      OutputLayout::Equirect => "layout: equirect".into(),
+      layout => format!( "layout: {}, {} texel border", layout.name(), layout.border() )
+    }
+  ];
+  match settings.output_format
+  {
+    OutputFormat::Png { packing, range } =>
+    {
+      transforms.extend( match packing
+      {
+        Packing::Rgbm | Packing::Rgbd => [ format!( "packing: {}, range {}", packing.name(), range ), format!( "clamp: 0 to {}", range ) ],
+        Packing::Rgbe => [ "packing: rgbe".into(), "clamp: negative to 0".into() ]
+      });
+    },
+    OutputFormat::Hdr => transforms.push( "clamp: negative to 0".into() ),
+    OutputFormat::Exr => {}
+  }
+  transforms
+}
+
+fn face_order() -> [ String; 6 ]
+{
+  [ "+X", "-X", "+Y", "-Y", "+Z", "-Z" ].map( String::from )
+}
+
+impl FileEntry
+{
+  /// Writes `image` to `dir` in `format`.
+  pub fn image( dir : &Path, image : &OutputImage, format : OutputFormat ) -> anyhow::Result< Self >
+  {
+    Ok( Self { path : image.file_name( format ), bytes : image.save( dir, format )? } )
+  }
+}
+
+impl GltfEntry
+{
+  pub fn new( gltf : &GltfSettings ) -> Self
+  {
+    Self
+    {
+      path : GLTF_FILE.into(),
+      intensity : gltf.intensity,
+      rotation : gltf.rotation
+    }
+  }
+}
+
+impl SkyEntry
+{
+  pub fn new( sky : &SkySettings ) -> Self
+  {
+    Self
+    {
+      model : sky.model.name().into(),
+      sun_elevation : sky.sun_elevation,
+      sun_azimuth : sky.sun_azimuth,
+      turbidity : sky.turbidity,
+      ground_albedo : sky.ground_albedo
+    }
+  }
+}
+
+impl EnvironmentCubeEntry
+{
+  fn new( mips : &[ [ OutputImage; 6 ] ], export : EnvironmentCubeSettings, format : OutputFormat ) -> Self
+  {
+    let face_size = mips.first().map_or( 0, | faces | faces[ 0 ].width );
+    let ( mips_entries, file ) = match export.container
+    {
+      CubeContainer::Faces => ( mips.iter().map( | faces | faces.iter().map( | face | ImageEntry::new( face, format, "cube_face" ) ).collect() ).collect(), None ),
+      CubeContainer::Cross =>
+      {
+        let crosses = mips.iter().enumerate().map( | ( mip_level, faces ) |
+        {
+          let size = faces[ 0 ].width;
+          let cross = ImageEntry
+          {
+            path : format!( "{}.{}", cube_file::cross_name( mip_level ), format.extension() ),
+            width : size * 4,
+            height : size * 3,
+            ..ImageEntry::new( &faces[ 0 ], format, "cube_cross" )
+          };
+          vec![ cross ]
+        })
+        .collect();
+        ( crosses, None )
+      },
+      CubeContainer::Ktx2 => ( Vec::new(), Some( ImageEntry::container( cube_file::KTX2_FILE, export.container, face_size ) ) ),
+      CubeContainer::Dds => ( Vec::new(), Some( ImageEntry::container( cube_file::DDS_FILE, export.container, face_size ) ) )
+    };
+
+    Self
+    {
+      container : export.container.name().into(),
+      mip_count : mips.len() as u32,
+      face_size,
+      mips : mips_entries,
+      file,
+      face_order : face_order()
+    }
+  }
+}
+
+impl Manifest
+{
+  pub fn new( source : SourceEntry, cache_key : String, settings : &BakeSettings, outputs : &BakeOutputs ) -> Self
+  {
+    let mip_count = outputs.specular.len() as u32;
+    let mips = outputs.specular.iter().enumerate().map( | ( mip, image ) | SpecularMip
+    {
+      mip : mip as u32,
+      roughness : ibl_renderer::mip_roughness( mip as u32, mip_count ),
+      image : ImageEntry::environment( image, settings.output_format, settings.layout )
+    })
+    .collect();
+    let transforms = transforms( &source, settings );
+
+    Self
+    {
+      tool : env!( "CARGO_PKG_NAME" ).into(),
+      tool_version : env!( "CARGO_PKG_VERSION" ).into(),
+      cache_key,
+      source,
+      settings : SettingsEntry
+      {
+        cube_size : settings.cube_size,
+        diffuse_samples : settings.diffuse_samples * settings.diffuse_samples,
+        specular_samples : settings.specular_samples,
+        lut_samples : settings.lut_samples,
+        cube_resample : settings.cube_resample.name().into(),
+        cube_samples : ( settings.cube_resample == CubeResample::Supersample ).then_some( settings.cube_samples ),
+        mip_filter : settings.mip_filter.name().into(),
+        layout : settings.layout.name().into(),
+        border : settings.layout.border(),
+        diffuse_format : gpu::format_name( settings.diffuse_format ).into(),
+        specular_format : gpu::format_name( settings.specular_format ).into(),
+        lut_format : gpu::format_name( settings.lut_format ).into(),
+        output_format : settings.output_format.name(),
+        range : settings.output_format.range()
+      },
+      transforms,
+      diffuse : ImageEntry::environment( &outputs.diffuse, settings.output_format, settings.layout ),
+      specular : SpecularEntry { mip_count, mips },
+      brdf_lut : LutEntry
+      {
+        image : ImageEntry::new( &outputs.brdf_lut, settings.output_format, "lut" ),
+        u : "NdotV, 0 to 1 left to right".into(),
+        v : "roughness, 0 to 1 top to bottom".into(),
+        channels : [ "F0 scale".into(), "F0 bias".into(), "unused".into() ]
+      },
+      specular_cube : outputs.specular_cube.as_ref().map( | mips | SpecularCubeEntry
+      {
+        mip_count : mips.len() as u32,
+        face_size : mips.first().map_or( 0, | faces | faces[ 0 ].width ),
+        mips : mips.iter().map( | faces | faces.iter().map( | face | ImageEntry::new( face, settings.output_format, "cube_face" ) ).collect() ).collect(),
+        face_order : face_order()
+      }),
+      // Set by the writer once the file is written, see [`Manifest::add_gltf`]
+      gltf : None,
+      environment_cube : outputs.environment_cube.as_ref().zip( settings.environment_cube ).map( | ( mips, export ) |
+      {
+        EnvironmentCubeEntry::new( mips, export, settings.output_format )
+      }),
+      environment : outputs.environment.as_ref().map( | image | ImageEntry::new( image, settings.output_format, "equirect" ) ),
+      files : Vec::new()
+    }
+  }
+
+  /// Records the glTF export, which is only written when the source has irradiance coefficients.
+  pub fn add_gltf( &mut self, gltf : &GltfSettings )
+  {
+    self.gltf = Some( GltfEntry::new( gltf ) );
+    self.transforms.push( format!( "gltf_rotation: {} degrees around +Y", gltf.rotation ) );
+  }
+
+  /// Every file of `files` is in `dir` with the size it was written with.
+  pub fn files_intact( &self, dir : &Path ) -> bool
+  {
+    self.files.iter().all( | file | std::fs::metadata( dir.join( &file.path ) ).is_ok_and( | metadata | metadata.is_file() && metadata.len() == file.bytes ) )
+  }
+
+  pub fn load( dir : &Path ) -> anyhow::Result< Self >
+  {
+    Self::read( &dir.join( MANIFEST_FILE ) )
+  }
+
+  /// Reads the manifest at `path`, which doesn't have to be named [`MANIFEST_FILE`].
+  pub fn read( path : &Path ) -> anyhow::Result< Self >
+  {
+    let file = std::fs::File::open( path ).with_context( || format!( "Failed to open {}", path.display() ) )?;
+    serde_json::from_reader( std::io::BufReader::new( file ) ).with_context( || format!( "Failed to parse {}", path.display() ) )
+  }
+
+  /// Writes the manifest to `dir` and returns the size of the file in bytes.
+  pub fn save( &self, dir : &Path ) -> anyhow::Result< u64 >
+  {
+    let path = dir.join( MANIFEST_FILE );
+    let json = serde_json::to_string_pretty( self )?;
+    std::fs::write( &path, &json ).with_context( || format!( "Failed to write {}", path.display() ) )?;
+    Ok( json.len() as u64 )
+  }
+}
@@ -0,0 +1,190 @@
+use std::{fs::File, io::BufWriter, path::Path};
+
+use anyhow::Context;
+use image::ImageEncoder;
+
+use crate::packing::{self, Packing};
+
+/// File format every output image is written in.
+#[ derive( Clone, Copy, Debug, PartialEq ) ]
+pub enum OutputFormat
+{
+  /// Radiance HDR, 32-bit float texels stored as RGBE
+  Hdr,
+  /// OpenEXR with 32-bit float RGB channels
+  Exr,
+  /// 8-bit RGBA PNG, see [`Packing`]
+  Png { packing : Packing, range : f32 }
+}
+
+impl OutputFormat
+{
+  pub fn extension( self ) -> &'static str
+  {
+    match self
+    {
+      Self::Hdr => "hdr",
+      Self::Exr => "exr",
+      Self::Png { .. } => "png"
+    }
+  }
+
+  /// Name recorded in the manifest.
+  pub fn name( self ) -> String
+  {
+    match self
+    {
+      Self::Hdr => "radiance_hdr".into(),
+      Self::Exr => "openexr".into(),
+      Self::Png { packing, .. } => format!( "png_{}", packing.name() )
+    }
+  }
+
+  /// Range multiplier of RGBM and RGBD, the other formats have none.
+  pub fn range( self ) -> Option< f32 >
+  {
+    match self
+    {
+      Self::Hdr | Self::Exr | Self::Png { packing : Packing::Rgbe, .. } => None,
+      Self::Png { range, .. } => Some( range )
+    }
+  }
+}
+
+/// CPU copy of one baked image, tightly packed RGB 32-bit float texels.
+#[ derive( Clone ) ]
+pub struct OutputImage
+{
+  pub name : String,
+  pub width : u32,
+  pub height : u32,
+  pub data : Vec< f32 >
+}
+
+impl OutputImage 
+{
+  pub fn new( name : impl Into< String >, width : u32, height : u32, data : Vec< f32 > ) -> Self
+  {
+    Self
+    {
+      name : name.into(),
+      width,
+      height,
+      data
+    }
+  }
+
+  pub fn file_name( &self, format : OutputFormat ) -> String
+  {
+    format!( "{}.{}", self.name, format.extension() )
+  }
+
+  /// Reads an HDR or EXR file back, named after the file without its extension.
+  pub fn load( path : &Path ) -> anyhow::Result< Self >
+  {
+    let image = image::open( path ).with_context( || format!( "Failed to read {}", path.display() ) )?.to_rgb32f();
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    Ok( Self::new( name, image.width(), image.height(), image.into_raw() ) )
+  }
+
+  /// Writes `<dir>/<name>.<extension>` and returns the size of the written file in bytes.
+  pub fn save( &self, dir : &Path, format : OutputFormat ) -> anyhow::Result< u64 >
+  {
+    let path = dir.join( self.file_name( format ) );
+    match format
+    {
+      OutputFormat::Hdr => self.save_hdr( &path ),
+      OutputFormat::Exr => self.save_exr( &path ),
+      OutputFormat::Png { packing, range } => self.save_png( &path, packing, range )
+    }
+  }
+
+  fn save_hdr( &self, path : &Path ) -> anyhow::Result< u64 >
+  {
+    let file = File::create( path ).with_context( || format!( "Failed to create {}", path.display() ) )?;
+
+    let encoder = image::codecs::hdr::HdrEncoder::new( BufWriter::new( file ) );
+    encoder.write_image( bytemuck::cast_slice( &self.data ), self.width, self.height, image::ExtendedColorType::Rgb32F )?;
+
+    Ok( std::fs::metadata( path )?.len() )
+  }
+
+  fn save_exr( &self, path : &Path ) -> anyhow::Result< u64 >
+  {
+    let file = File::create( path ).with_context( || format!( "Failed to create {}", path.display() ) )?;
+
+    let encoder = image::codecs::openexr::OpenExrEncoder::new( BufWriter::new( file ) );
+    encoder.write_image( bytemuck::cast_slice( &self.data ), self.width, self.height, image::ExtendedColorType::Rgb32F )?;
+
+    Ok( std::fs::metadata( path )?.len() )
+  }
+
+  /// Reads a PNG written by [`OutputImage::save_png`] back, `packing` and `range` have to be the ones it was written with.
+  pub fn load_png( path : &Path, packing : Packing, range : f32 ) -> anyhow::Result< Self >
+  {
+    let image = image::open( path ).with_context( || format!( "Failed to read {}", path.display() ) )?.to_rgba8();
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    Ok( Self::new( name, image.width(), image.height(), packing::decode( packing, range, image.as_raw() ) ) )
+  }
+
+  /// Writes the image to `path` as a PNG in the `packing` encoding and returns the size of the file in bytes.
+  pub fn save_png( &self, path : &Path, packing : Packing, range : f32 ) -> anyhow::Result< u64 >
+  {
+    let file = File::create( path ).with_context( || format!( "Failed to create {}", path.display() ) )?;
+
+    let encoder = image::codecs::png::PngEncoder::new( BufWriter::new( file ) );
+    encoder.write_image( &packing::encode( packing, range, &self.data ), self.width, self.height, image::ExtendedColorType::Rgba8 )?;
+
+    Ok( std::fs::metadata( path )?.len() )
+  }
+}
+
+/// Converts texels of `format` read back from the GPU to tightly packed RGB 32-bit floats, which
+/// is all the file formats need. Missing channels are zero and alpha is dropped.
+pub fn texels_to_rgb( format : wgpu::TextureFormat, bytes : &[ u8 ] ) -> Vec< f32 >
+{
+  let texel_size = format.block_copy_size( None ).expect( "Readback of a depth or compressed format" ) as usize;
+  let components = format.components() as usize;
+
+  bytes.chunks_exact( texel_size ).flat_map( | texel |
+  {
+    if format == wgpu::TextureFormat::Rg11b10Ufloat
+    {
+      return unpack_rg11b10( u32::from_le_bytes( [ texel[ 0 ], texel[ 1 ], texel[ 2 ], texel[ 3 ] ] ) );
+    }
+
+    let channel_size = texel_size / components;
+    let channel = | i : usize |
+    {
+      if i >= components
+      {
+        return 0.0;
+      }
+      let bytes = &texel[ i * channel_size..( i + 1 ) * channel_size ];
+      match channel_size
+      {
+        4 => f32::from_le_bytes( [ bytes[ 0 ], bytes[ 1 ], bytes[ 2 ], bytes[ 3 ] ] ),
+        2 => half::f16::from_le_bytes( [ bytes[ 0 ], bytes[ 1 ] ] ).to_f32(),
+        _ => panic!( "Readback of {:?} is not supported", format )
+      }
+    };
+    [ channel( 0 ), channel( 1 ), channel( 2 ) ]
+  })
+  .collect()
+}
+
+/// Unsigned floats without sign bit: 6-bit mantissas for R and G, 5-bit for B, 5-bit exponents.
+fn unpack_rg11b10( packed : u32 ) -> [ f32; 3 ]
+{
+  let unpack = | bits : u32, mantissa_bits : u32 |
+  {
+    let mantissa = ( bits & ( ( 1 << mantissa_bits ) - 1 ) ) as f32 / ( 1 << mantissa_bits ) as f32;
+    match bits >> mantissa_bits
+    {
+      0 => mantissa * 2f32.powi( -14 ),
+      31 => if mantissa == 0.0 { f32::INFINITY } else { f32::NAN },
+      exponent => 2f32.powi( exponent as i32 - 15 ) * ( 1.0 + mantissa )
+    }
+  };
+  [ unpack( packed & 0x7ff, 6 ), unpack( ( packed >> 11 ) & 0x7ff, 6 ), unpack( packed >> 22, 5 ) ]
+}
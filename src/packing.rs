@@ -0,0 +1,194 @@
+/// 8-bit RGBA encodings of HDR colors, for targets without float textures.
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum Packing
+{
+  /// Color divided by a shared multiplier stored in alpha, covers `0..range`
+  Rgbm,
+  /// Color multiplied by a shared divider stored in alpha and stored in gamma 2.2, as Babylon.js does.
+  /// With a range of 255 the result is what Babylon decodes.
+  Rgbd,
+  /// Radiance shared exponent, the range is not used
+  Rgbe
+}
+
+impl Packing
+{
+  pub fn name( self ) -> &'static str
+  {
+    match self
+    {
+      Self::Rgbm => "rgbm",
+      Self::Rgbd => "rgbd",
+      Self::Rgbe => "rgbe"
+    }
+  }
+
+  /// Range the encoding is usually used with.
+  pub fn default_range( self ) -> f32
+  {
+    match self
+    {
+      Self::Rgbm => 8.0,
+      Self::Rgbd => 255.0,
+      Self::Rgbe => 1.0
+    }
+  }
+}
+
+const GAMMA : f32 = 2.2;
+
+fn to_u8( value : f32 ) -> u8
+{
+  ( value.clamp( 0.0, 1.0 ) * 255.0 ).round() as u8
+}
+
+fn to_f32( value : u8 ) -> f32
+{
+  value as f32 / 255.0
+}
+
+/// Packs tightly packed RGB texels to RGBA8.
+pub fn encode( packing : Packing, range : f32, rgb : &[ f32 ] ) -> Vec< u8 >
+{
+  rgb.chunks_exact( 3 ).flat_map( | texel |
+  {
+    let color = [ texel[ 0 ].max( 0.0 ), texel[ 1 ].max( 0.0 ), texel[ 2 ].max( 0.0 ) ];
+    let max = color[ 0 ].max( color[ 1 ] ).max( color[ 2 ] );
+    match packing
+    {
+      Packing::Rgbm =>
+      {
+        let m = ( ( max / range ).clamp( 1.0 / 255.0, 1.0 ) * 255.0 ).ceil() / 255.0;
+        let scale = 1.0 / ( m * range );
+        [ to_u8( color[ 0 ] * scale ), to_u8( color[ 1 ] * scale ), to_u8( color[ 2 ] * scale ), to_u8( m ) ]
+      },
+      Packing::Rgbd =>
+      {
+        // Babylon's encoding works on 0..255, other ranges are scaled to it
+        let color = color.map( | c | c * 255.0 / range );
+        let max = ( max * 255.0 / range ).max( 1e-6 );
+        let d = ( ( 255.0 / max ).max( 1.0 ).floor() / 255.0 ).clamp( 0.0, 1.0 );
+        let gamma = | c : f32 | to_u8( ( c * d ).powf( 1.0 / GAMMA ) );
+        [ gamma( color[ 0 ] ), gamma( color[ 1 ] ), gamma( color[ 2 ] ), to_u8( d ) ]
+      },
+      Packing::Rgbe =>
+      {
+        if max < 1e-32
+        {
+          return [ 0; 4 ];
+        }
+        // frexp: max = mantissa * 2^exponent with the mantissa in 0.5..1
+        let exponent = max.log2().floor() as i32 + 1;
+        let scale = 256.0 / 2f32.powi( exponent );
+        let mantissa = | c : f32 | ( c * scale ).floor().min( 255.0 ) as u8;
+        [ mantissa( color[ 0 ] ), mantissa( color[ 1 ] ), mantissa( color[ 2 ] ), ( exponent + 128 ).clamp( 0, 255 ) as u8 ]
+      }
+    }
+  })
+  .collect()
+}
+
+/// Inverse of [`encode`], returns tightly packed RGB texels.
+pub fn decode( packing : Packing, range : f32, rgba : &[ u8 ] ) -> Vec< f32 >
+{
+  rgba.chunks_exact( 4 ).flat_map( | texel |
+  {
+    let color = [ texel[ 0 ], texel[ 1 ], texel[ 2 ] ];
+    match packing
+    {
+      Packing::Rgbm =>
+      {
+        let scale = to_f32( texel[ 3 ] ) * range;
+        color.map( | c | to_f32( c ) * scale )
+      },
+      Packing::Rgbd =>
+      {
+        let d = to_f32( texel[ 3 ] ).max( 1.0 / 255.0 );
+        color.map( | c | to_f32( c ).powf( GAMMA ) / d * range / 255.0 )
+      },
+      Packing::Rgbe =>
+      {
+        if texel[ 3 ] == 0
+        {
+          return [ 0.0; 3 ];
+        }
+        let scale = 2f32.powi( texel[ 3 ] as i32 - 128 - 8 );
+        color.map( | c | ( c as f32 + 0.5 ) * scale )
+      }
+    }
+  })
+  .collect()
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+
+  /// Colors spread over `0..max`, including black, greys and saturated colors.
+  fn test_colors( max : f32 ) -> Vec< f32 >
+  {
+    let mut colors = vec![ 0.0, 0.0, 0.0 ];
+    for i in 0..200
+    {
+      let t = ( i as f32 / 199.0 ).powi( 3 ) * max;
+      colors.extend_from_slice( &[ t, t, t ] );
+      colors.extend_from_slice( &[ t, t * 0.5, t * 0.1 ] );
+      colors.extend_from_slice( &[ 0.0, t * 0.3, t ] );
+    }
+    colors
+  }
+
+  /// Largest error of any channel, relative to the brightest channel of its texel plus `floor`,
+  /// so dark texels aren't judged by their relative error alone.
+  fn max_error( packing : Packing, range : f32, colors : &[ f32 ], floor : f32 ) -> f32
+  {
+    let decoded = decode( packing, range, &encode( packing, range, colors ) );
+    colors.chunks_exact( 3 ).zip( decoded.chunks_exact( 3 ) ).map( | ( expected, actual ) |
+    {
+      let max = expected[ 0 ].max( expected[ 1 ] ).max( expected[ 2 ] );
+      expected.iter().zip( actual ).map( | ( e, a ) | ( e - a ).abs() / ( max + floor ) ).fold( 0.0, f32::max )
+    })
+    .fold( 0.0, f32::max )
+  }
+
+  #[ test ]
+  fn rgbm_error_is_bounded()
+  {
+    for range in [ 1.0, 8.0, 64.0 ]
+    {
+      // Half a step of the color, scaled by a multiplier at most one step above the brightest channel
+      let error = max_error( Packing::Rgbm, range, &test_colors( range ), range / 255.0 );
+      assert!( error <= 0.5 / 255.0 + 1e-4, "range {}: {}", range, error );
+    }
+  }
+
+  #[ test ]
+  fn rgbd_error_is_bounded()
+  {
+    for range in [ 16.0, 255.0 ]
+    {
+      // Half a step in gamma space, up to 2.2 times larger in linear space. Texels darker than
+      // `range / 255` keep a divider of 1 and only have the precision of an 8-bit gamma color
+      let error = max_error( Packing::Rgbd, range, &test_colors( range ), range / 255.0 / 16.0 );
+      assert!( error <= 0.5 / 255.0 * GAMMA * 2.0, "range {}: {}", range, error );
+    }
+  }
+
+  #[ test ]
+  fn rgbe_error_is_bounded()
+  {
+    // The brightest mantissa is at least 128, so the error is below one step of it
+    let error = max_error( Packing::Rgbe, 1.0, &test_colors( 1e4 ), 1e-30 );
+    assert!( error <= 1.0 / 128.0, "{}", error );
+  }
+
+  #[ test ]
+  fn babylon_rgbd_reference()
+  {
+    // Full white in range: divider of 255, color of 1
+    assert_eq!( encode( Packing::Rgbd, 255.0, &[ 1.0, 1.0, 1.0 ] ), [ 255, 255, 255, 255 ] );
+    // Brightest value in range: divider of 1
+    assert_eq!( encode( Packing::Rgbd, 255.0, &[ 255.0, 0.0, 0.0 ] ), [ 255, 0, 0, 1 ] );
+  }
+}
@@ -0,0 +1,232 @@
+use std::cell::{OnceCell, RefCell};
+
+use serde::Serialize;
+
+use crate::readback::map_buffers;
+
+/// Timestamps a query set can hold, two per pass.
+const MAX_QUERIES : u32 = 1024;
+
+/// Query sets allocated at most, one after the other as the passes of a bake fill them.
+/// Tiled and progressive bakes run thousands of passes.
+const MAX_QUERY_SETS : usize = 64;
+
+/// GPU time of one stage of a bake. The passes with the same label are summed, so the batches
+/// of a progressive bake add up to one stage per output.
+#[ derive( Clone, Debug, Serialize ) ]
+pub struct StageTiming
+{
+  pub stage : String,
+  pub passes : u32,
+  pub ms : f64,
+  /// Passes that ran once every query set was full. Their time is missing from `ms`
+  pub untimed : u32
+}
+
+/// One query set and the buffers its timestamps are resolved and read back through.
+struct QueryBlock
+{
+  query_set : wgpu::QuerySet,
+  resolve_buffer : wgpu::Buffer,
+  read_buffer : wgpu::Buffer
+}
+
+struct Queries
+{
+  device : wgpu::Device,
+  blocks : Vec< OnceCell< QueryBlock > >,
+  /// Nanoseconds per timestamp tick
+  period : f32
+}
+
+/// Writes timestamps at the start and end of every pass of a bake. Without `TIMESTAMP_QUERY`,
+/// or when profiling isn't requested, it hands out no timestamp writes and reads nothing back.
+pub struct GpuProfiler
+{
+  queries : Option< Queries >,
+  /// Label of each pass, the pass `i` writes the queries `2 * i` and `2 * i + 1` counted across the query sets
+  labels : RefCell< Vec< String > >,
+  /// Label of each pass that ran without timestamps
+  untimed : RefCell< Vec< String > >
+}
+
+impl QueryBlock
+{
+  fn new( device : &wgpu::Device ) -> Self
+  {
+    let size = MAX_QUERIES as u64 * wgpu::QUERY_SIZE as u64;
+    Self
+    {
+      query_set : device.create_query_set
+      (
+        &wgpu::QuerySetDescriptor
+        {
+          label : Some( "PROFILER_QUERIES" ),
+          ty : wgpu::QueryType::Timestamp,
+          count : MAX_QUERIES
+        }
+      ),
+      resolve_buffer : device.create_buffer
+      (
+        &wgpu::BufferDescriptor
+        {
+          label : None,
+          size,
+          mapped_at_creation : false,
+          usage : wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC
+        }
+      ),
+      read_buffer : device.create_buffer
+      (
+        &wgpu::BufferDescriptor
+        {
+          label : None,
+          size,
+          mapped_at_creation : false,
+          usage : wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST
+        }
+      )
+    }
+  }
+}
+
+impl GpuProfiler
+{
+  pub fn new( device : &wgpu::Device, queue : &wgpu::Queue, enabled : bool ) -> Self
+  {
+    let queries = ( enabled && device.features().contains( wgpu::Features::TIMESTAMP_QUERY ) ).then( || Queries
+    {
+      device : device.clone(),
+      blocks : ( 0..MAX_QUERY_SETS ).map( | _ | OnceCell::new() ).collect(),
+      period : queue.get_timestamp_period()
+    });
+
+    Self { queries, labels : RefCell::new( Vec::new() ), untimed : RefCell::new( Vec::new() ) }
+  }
+
+  /// Reserves the queries of the next pass, allocating a query set when the last one is full.
+  /// `None` when disabled or when every query set is full, the pass is counted as untimed then.
+  fn next_queries( &self, label : &str ) -> Option< ( &wgpu::QuerySet, u32 ) >
+  {
+    let queries = self.queries.as_ref()?;
+    let mut labels = self.labels.borrow_mut();
+    let index = labels.len() as u32 * 2;
+    let Some( block ) = queries.blocks.get( ( index / MAX_QUERIES ) as usize ) else
+    {
+      self.untimed.borrow_mut().push( label.to_owned() );
+      return None;
+    };
+    labels.push( label.to_owned() );
+    Some( ( &block.get_or_init( || QueryBlock::new( &queries.device ) ).query_set, index % MAX_QUERIES ) )
+  }
+
+  pub fn compute_pass( &self, label : &str ) -> Option< wgpu::ComputePassTimestampWrites< '_ > >
+  {
+    self.next_queries( label ).map( | ( query_set, index ) | wgpu::ComputePassTimestampWrites
+    {
+      query_set,
+      beginning_of_pass_write_index : Some( index ),
+      end_of_pass_write_index : Some( index + 1 )
+    })
+  }
+
+  pub fn render_pass( &self, label : &str ) -> Option< wgpu::RenderPassTimestampWrites< '_ > >
+  {
+    self.next_queries( label ).map( | ( query_set, index ) | wgpu::RenderPassTimestampWrites
+    {
+      query_set,
+      beginning_of_pass_write_index : Some( index ),
+      end_of_pass_write_index : Some( index + 1 )
+    })
+  }
+
+  /// Query sets in use and the number of queries written to each.
+  fn used_blocks( &self ) -> Vec< ( &QueryBlock, u32 ) >
+  {
+    let Some( queries ) = &self.queries else { return Vec::new() };
+    let count = self.labels.borrow().len() as u32 * 2;
+    queries.blocks.iter()
+    .map_while( OnceCell::get )
+    .enumerate()
+    .map( | ( i, block ) | ( block, count.saturating_sub( i as u32 * MAX_QUERIES ).min( MAX_QUERIES ) ) )
+    .filter( | ( _, count ) | *count > 0 )
+    .collect()
+  }
+
+  /// Copies the timestamps of every pass recorded so far to the read buffers.
+  pub fn resolve( &self, encoder : &mut wgpu::CommandEncoder )
+  {
+    for ( block, count ) in self.used_blocks()
+    {
+      encoder.resolve_query_set( &block.query_set, 0..count, &block.resolve_buffer, 0 );
+      encoder.copy_buffer_to_buffer( &block.resolve_buffer, 0, &block.read_buffer, 0, count as u64 * wgpu::QUERY_SIZE as u64 );
+    }
+  }
+
+  /// Reads back the timings resolved by [`GpuProfiler::resolve`] and starts over for the next bake.
+  pub async fn read( &self, device : &wgpu::Device ) -> anyhow::Result< Vec< StageTiming > >
+  {
+    let blocks = self.used_blocks();
+    let labels = self.labels.take();
+    let untimed = self.untimed.take();
+    let Some( queries ) = &self.queries else { return Ok( Vec::new() ) };
+
+    let mut timestamps = Vec::new();
+    if !blocks.is_empty()
+    {
+      map_buffers( device, &blocks.iter().map( | ( block, _ ) | &block.read_buffer ).collect::< Vec< _ > >() ).await?;
+      for ( block, count ) in blocks
+      {
+        timestamps.extend( bytemuck::pod_collect_to_vec::< u8, u64 >( &block.read_buffer.get_mapped_range( ..count as u64 * wgpu::QUERY_SIZE as u64 ) ) );
+        block.read_buffer.unmap();
+      }
+    }
+    Ok( stage_timings( labels, untimed, &timestamps, queries.period ) )
+  }
+}
+
+/// Sums the passes of every stage, from the start and end timestamps of the timed passes in `timestamps`.
+fn stage_timings( labels : Vec< String >, untimed : Vec< String >, timestamps : &[ u64 ], period : f32 ) -> Vec< StageTiming >
+{
+  let mut stages : Vec< StageTiming > = Vec::new();
+  let passes = labels.into_iter().zip( timestamps.chunks_exact( 2 ).map( Some ) ).chain( untimed.into_iter().map( | label | ( label, None ) ) );
+  for ( label, pass ) in passes
+  {
+    let stage = match stages.iter().position( | stage | stage.stage == label )
+    {
+      Some( index ) => &mut stages[ index ],
+      None =>
+      {
+        stages.push( StageTiming { stage : label, passes : 0, ms : 0.0, untimed : 0 } );
+        stages.last_mut().unwrap()
+      }
+    };
+    match pass
+    {
+      Some( pass ) =>
+      {
+        stage.passes += 1;
+        stage.ms += pass[ 1 ].saturating_sub( pass[ 0 ] ) as f64 * period as f64 / 1e6;
+      },
+      None => stage.untimed += 1
+    }
+  }
+  stages
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+
+  #[ test ]
+  fn untimed_passes_are_counted()
+  {
+    let labels = [ "diffuse", "specular", "diffuse" ].map( String::from ).to_vec();
+    let untimed = [ "specular", "lut" ].map( String::from ).to_vec();
+    let stages = stage_timings( labels, untimed, &[ 0, 1000, 1000, 3000, 3000, 3500 ], 2.0 );
+
+    let summary = stages.iter().map( | stage | ( stage.stage.as_str(), stage.passes, stage.ms, stage.untimed ) ).collect::< Vec< _ > >();
+    assert_eq!( summary, vec![ ( "diffuse", 2, 0.003, 0 ), ( "specular", 1, 0.004, 1 ), ( "lut", 0, 0.0, 1 ) ] );
+  }
+}
@@ -0,0 +1,183 @@
+use std::time::{Duration, Instant};
+
+use crate::baker::BakeSettings;
+
+/// Bytes of the noise of one output, the `noise` member of `Accumulator` in `ibl_compute.wgsl`.
+pub const NOISE_SIZE : usize = 16;
+
+/// Progressive refinement of the irradiance and specular prefilter. Instead of taking every sample
+/// in one pass, batches of samples are accumulated over many submissions until the estimated noise
+/// of every mip is below `target_noise`, the sample counts of `BakeSettings` are used up or the time
+/// budget runs out. Needs the compute passes.
+#[ derive( Clone, Copy, Debug, PartialEq ) ]
+pub struct ProgressiveSettings
+{
+  /// Samples per texel added by each batch of the specular prefilter. Irradiance batches
+  /// take the nearest square grid.
+  pub batch_samples : u32,
+  /// Relative noise at which a mip stops refining
+  pub target_noise : f32,
+  /// Seconds after which the refinement stops, converged or not
+  pub time_budget : Option< f32 >
+}
+
+impl Default for ProgressiveSettings
+{
+  fn default() -> Self
+  {
+    Self { batch_samples : 64, target_noise : 0.01, time_budget : None }
+  }
+}
+
+impl ProgressiveSettings
+{
+  /// Samples along each axis of the jittered grid of an irradiance batch.
+  pub fn diffuse_batch_axis( &self ) -> u32
+  {
+    ( ( self.batch_samples as f32 ).sqrt().round() as u32 ).max( 1 )
+  }
+
+  /// Batches it takes to reach `BakeSettings::diffuse_samples` squared.
+  pub fn diffuse_batches( &self, settings : &BakeSettings ) -> u32
+  {
+    ( settings.diffuse_samples * settings.diffuse_samples ).div_ceil( self.diffuse_batch_axis().pow( 2 ) )
+  }
+
+  /// Batches it takes to reach `BakeSettings::specular_samples`.
+  pub fn specular_batches( &self, settings : &BakeSettings ) -> u32
+  {
+    settings.specular_samples.div_ceil( self.batch_samples )
+  }
+}
+
+/// Offset of the sample pattern of `batch`, from the R2 sequence. Every batch shifts the same
+/// pattern by it, modulo 1, so the batches fill in each other's gaps.
+pub fn batch_offset( batch : u32 ) -> [ f32; 2 ]
+{
+  // Inverses of the plastic number and of its square
+  let offset = [ 0.754_877_7 * batch as f32, 0.569_840_3 * batch as f32 ];
+  [ offset[ 0 ].fract(), offset[ 1 ].fract() ]
+}
+
+/// Convergence of one refined output.
+#[ derive( Clone, Debug ) ]
+pub struct OutputProgress
+{
+  pub name : String,
+  pub batches : u32,
+  pub max_batches : u32,
+  /// Relative noise estimated from the means of the even and odd batches, once there are two
+  pub noise : Option< f32 >
+}
+
+impl OutputProgress
+{
+  fn is_done( &self, target_noise : f32 ) -> bool
+  {
+    self.batches >= self.max_batches || self.noise.is_some_and( | noise | noise <= target_noise )
+  }
+}
+
+/// State of a progressive bake, advanced one batch per output at a time by [`crate::baker::Baker::refine`].
+/// The outputs are the irradiance, then the specular mips, then the mips of the specular cube.
+pub struct Refinement
+{
+  settings : ProgressiveSettings,
+  outputs : Vec< OutputProgress >,
+  round : u32,
+  start : Instant,
+  /// The noise of every output, written by `compute_noise_main`
+  readback : wgpu::Buffer
+}
+
+impl Refinement
+{
+  pub fn new( device : &wgpu::Device, settings : ProgressiveSettings, outputs : Vec< OutputProgress > ) -> Self
+  {
+    let readback = device.create_buffer
+    (
+      &wgpu::BufferDescriptor
+      {
+        label : None,
+        size : ( outputs.len() * NOISE_SIZE ) as u64,
+        mapped_at_creation : false,
+        usage : wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST
+      }
+    );
+
+    Self { settings, outputs, round : 0, start : Instant::now(), readback }
+  }
+
+  /// Index of the batch the next round adds.
+  pub fn round( &self ) -> u32
+  {
+    self.round
+  }
+
+  pub fn elapsed( &self ) -> Duration
+  {
+    self.start.elapsed()
+  }
+
+  /// Outputs the next round adds a batch to.
+  pub fn active( &self ) -> Vec< usize >
+  {
+    ( 0..self.outputs.len() ).filter( | &i | !self.outputs[ i ].is_done( self.settings.target_noise ) ).collect()
+  }
+
+  /// The outputs only hold a result once every one got a batch, so the time budget can't end the first round.
+  pub fn is_done( &self ) -> bool
+  {
+    self.round > 0 && self.settings.time_budget.is_some_and( | budget | self.elapsed().as_secs_f32() >= budget )
+    || self.active().is_empty()
+  }
+
+  /// Buffer the noise of output `i` is copied to, at `i * NOISE_SIZE`.
+  pub fn readback( &self ) -> &wgpu::Buffer
+  {
+    &self.readback
+  }
+
+  /// Records the batches of `active` and reads back their noise, once the round was submitted.
+  pub async fn end_round( &mut self, device : &wgpu::Device, active : &[ usize ] ) -> anyhow::Result< () >
+  {
+    crate::readback::map_buffer( device, &self.readback ).await?;
+    let noise = bytemuck::pod_collect_to_vec::< u8, f32 >( &self.readback.get_mapped_range( .. ) );
+    self.readback.unmap();
+
+    for &index in active
+    {
+      let output = &mut self.outputs[ index ];
+      output.batches += 1;
+      if output.batches >= 2
+      {
+        output.noise = Some( noise[ index * NOISE_SIZE / 4 ] );
+      }
+    }
+    self.round += 1;
+    Ok( () )
+  }
+
+  /// One line summary, with the worst noise of the outputs that are still refining.
+  pub fn summary( &self ) -> String
+  {
+    let worst = self.outputs.iter()
+    .filter( | output | !output.is_done( self.settings.target_noise ) )
+    .filter_map( | output | output.noise.map( | noise | ( noise, &output.name ) ) )
+    .max_by( | a, b | a.0.total_cmp( &b.0 ) );
+
+    match worst
+    {
+      _ if self.is_done() => format!( "refined in {} batches, {:.1} s", self.round, self.elapsed().as_secs_f32() ),
+      Some( ( noise, name ) ) => format!
+      (
+        "batch {}, noise {:.2}% in {} (target {:.2}%)",
+        self.round,
+        noise * 100.0,
+        name,
+        self.settings.target_noise * 100.0
+      ),
+      None => format!( "batch {}", self.round )
+    }
+  }
+}
@@ -0,0 +1,283 @@
+use anyhow::Context;
+
+use crate::output::{texels_to_rgb, OutputImage};
+
+/// Texels of one output copied out of its mapped readback buffer, still in the GPU format.
+/// Converting them is left to the threads that encode the files.
+#[ derive( Clone ) ]
+pub struct RawImage
+{
+  pub name : String,
+  pub width : u32,
+  pub height : u32,
+  pub format : wgpu::TextureFormat,
+  /// Rows without their padding
+  pub bytes : Vec< u8 >
+}
+
+impl RawImage
+{
+  pub fn decode( self ) -> OutputImage
+  {
+    OutputImage::new( self.name, self.width, self.height, texels_to_rgb( self.format, &self.bytes ) )
+  }
+}
+
+/// Buffer one image is copied to for reading it back. The rows are padded to
+/// `COPY_BYTES_PER_ROW_ALIGNMENT` as texture copies require, whatever the width.
+pub struct ReadbackBuffer
+{
+  buffer : wgpu::Buffer,
+  format : wgpu::TextureFormat,
+  size : wgpu::Extent3d,
+  unpadded_bytes_per_row : u32,
+  padded_bytes_per_row : u32
+}
+
+impl ReadbackBuffer
+{
+  /// `size` is the size of one layer of one mip.
+  pub fn new( device : &wgpu::Device, size : wgpu::Extent3d, format : wgpu::TextureFormat ) -> Self
+  {
+    let ( unpadded_bytes_per_row, padded_bytes_per_row ) = bytes_per_row( size.width, format );
+    let buffer = device.create_buffer
+    (
+      &wgpu::BufferDescriptor
+      {
+        label : None,
+        size : padded_bytes_per_row as u64 * size.height as u64,
+        mapped_at_creation : false,
+        usage : wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST
+      }
+    );
+
+    Self
+    {
+      buffer,
+      format,
+      size : wgpu::Extent3d { depth_or_array_layers : 1, ..size },
+      unpadded_bytes_per_row,
+      padded_bytes_per_row
+    }
+  }
+
+  pub fn buffer( &self ) -> &wgpu::Buffer
+  {
+    &self.buffer
+  }
+
+  /// Records the copy of the layer `layer` of the mip `mip_level` of `texture`, which has the size of the buffer.
+  pub fn copy_from( &self, encoder : &mut wgpu::CommandEncoder, texture : &wgpu::Texture, mip_level : u32, layer : u32 )
+  {
+    encoder.copy_texture_to_buffer
+    (
+      wgpu::TexelCopyTextureInfoBase
+      {
+        texture,
+        mip_level,
+        origin : wgpu::Origin3d { x : 0, y : 0, z : layer },
+        aspect : wgpu::TextureAspect::All
+      },
+      wgpu::TexelCopyBufferInfo
+      {
+        buffer : &self.buffer,
+        layout : wgpu::TexelCopyBufferLayout
+        {
+          offset : 0,
+          bytes_per_row : Some( self.padded_bytes_per_row ),
+          rows_per_image : None
+        }
+      },
+      self.size
+    );
+  }
+
+  /// Copies the texels out of the mapped buffer, without the row padding, and unmaps it.
+  pub fn take( &self, name : impl Into< String > ) -> RawImage
+  {
+    let bytes = strip_padding( &self.buffer.get_mapped_range( .. ), self.unpadded_bytes_per_row, self.padded_bytes_per_row );
+    self.buffer.unmap();
+
+    RawImage { name : name.into(), width : self.size.width, height : self.size.height, format : self.format, bytes }
+  }
+}
+
+/// Bytes of the texels of one row of `width` texels, and the same padded for a texture copy.
+fn bytes_per_row( width : u32, format : wgpu::TextureFormat ) -> ( u32, u32 )
+{
+  let unpadded = width * format.block_copy_size( None ).expect( "Readback of a depth or compressed format" );
+  let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+  ( unpadded, unpadded.div_ceil( alignment ) * alignment )
+}
+
+fn strip_padding( padded : &[ u8 ], unpadded_bytes_per_row : u32, padded_bytes_per_row : u32 ) -> Vec< u8 >
+{
+  padded.chunks_exact( padded_bytes_per_row as usize )
+  .flat_map( | row | &row[ ..unpadded_bytes_per_row as usize ] )
+  .copied()
+  .collect()
+}
+
+/// Takes the faces of every mip of a cube out of `buffers`, which hold the six faces of a mip after another.
+/// The faces are named `<name>_<mip>_<face>`, in the WebGPU face order.
+pub fn take_cube( buffers : &[ ReadbackBuffer ], name : &str ) -> Vec< [ RawImage; 6 ] >
+{
+  buffers.chunks_exact( 6 ).enumerate().map( | ( mip_level, buffers ) |
+  {
+    std::array::from_fn( | face | buffers[ face ].take( format!( "{}_{}_{}", name, mip_level, face ) ) )
+  })
+  .collect()
+}
+
+/// Maps every buffer of `buffers` for reading and waits for all of them with a single poll of the device.
+/// Fails when the device is lost or a buffer can't be mapped.
+pub async fn map_buffers( device : &wgpu::Device, buffers : &[ &wgpu::Buffer ] ) -> anyhow::Result< () >
+{
+  let ( sender, receiver ) = flume::bounded( buffers.len() );
+  for buffer in buffers
+  {
+    let sender = sender.clone();
+    buffer.map_async
+    (
+      wgpu::MapMode::Read,
+      ..,
+      move | r | { let _ = sender.send( r ); }
+    );
+  }
+  drop( sender );
+
+  device.poll( wgpu::PollType::wait() ).context( "Failed to wait for the GPU" )?;
+  for _ in buffers
+  {
+    receiver.recv_async().await.context( "A buffer was dropped before it was mapped" )?.context( "Failed to map a readback buffer" )?;
+  }
+  Ok( () )
+}
+
+pub async fn map_buffer( device : &wgpu::Device, buffer : &wgpu::Buffer ) -> anyhow::Result< () >
+{
+  map_buffers( device, &[ buffer ] ).await
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+  use crate::{baker::{BakeSettings, Baker, SourceImage}, cpu_baker, gpu};
+
+  /// Sizes of every mip of a `width` x `height` texture, down to 1x1.
+  fn mip_sizes( width : u32, height : u32 ) -> Vec< wgpu::Extent3d >
+  {
+    let size = wgpu::Extent3d { width, height, depth_or_array_layers : 1 };
+    ( 0..size.max_mips( wgpu::TextureDimension::D2 ) ).map( | mip | size.mip_level_size( mip, wgpu::TextureDimension::D2 ) ).collect()
+  }
+
+  /// Padded image of texels numbered from 0, the way a copy lays it out in the buffer.
+  fn padded_image( size : wgpu::Extent3d, format : wgpu::TextureFormat ) -> ( Vec< u8 >, Vec< u8 > )
+  {
+    let ( unpadded, padded ) = bytes_per_row( size.width, format );
+    let texels = ( 0..size.height ).flat_map( | y | ( 0..unpadded ).map( move | x | ( y * unpadded + x ) as u8 ) ).collect::< Vec< _ > >();
+    let buffer = texels.chunks_exact( unpadded as usize )
+    .flat_map( | row | row.iter().copied().chain( std::iter::repeat_n( 0xff, ( padded - unpadded ) as usize ) ) )
+    .collect();
+    ( buffer, texels )
+  }
+
+  #[ test ]
+  fn rows_are_padded_for_copies()
+  {
+    for format in [ wgpu::TextureFormat::Rgba32Float, wgpu::TextureFormat::Rgba16Float, wgpu::TextureFormat::Rg11b10Ufloat ]
+    {
+      for size in mip_sizes( 100, 50 ).into_iter().chain( mip_sizes( 512, 512 ) )
+      {
+        let ( unpadded, padded ) = bytes_per_row( size.width, format );
+        assert_eq!( unpadded, size.width * format.block_copy_size( None ).unwrap() );
+        assert_eq!( padded % wgpu::COPY_BYTES_PER_ROW_ALIGNMENT, 0, "{:?} {}x{}", format, size.width, size.height );
+        assert!( padded >= unpadded && padded - unpadded < wgpu::COPY_BYTES_PER_ROW_ALIGNMENT );
+      }
+    }
+  }
+
+  #[ test ]
+  fn padding_is_stripped_from_odd_sizes()
+  {
+    // 100x50 and its mips, down to 1x1, are all narrower than or not a multiple of the alignment
+    for format in [ wgpu::TextureFormat::Rgba32Float, wgpu::TextureFormat::Rgba16Float ]
+    {
+      for size in mip_sizes( 100, 50 )
+      {
+        let ( unpadded, padded ) = bytes_per_row( size.width, format );
+        let ( buffer, texels ) = padded_image( size, format );
+        assert_eq!( strip_padding( &buffer, unpadded, padded ), texels, "{:?} {}x{}", format, size.width, size.height );
+      }
+    }
+  }
+
+  #[ test ]
+  fn one_texel_mip()
+  {
+    let size = *mip_sizes( 100, 50 ).last().unwrap();
+    assert_eq!( ( size.width, size.height ), ( 1, 1 ) );
+
+    let ( unpadded, padded ) = bytes_per_row( size.width, wgpu::TextureFormat::Rgba32Float );
+    assert_eq!( ( unpadded, padded ), ( 16, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT ) );
+    let ( buffer, texels ) = padded_image( size, wgpu::TextureFormat::Rgba32Float );
+    assert_eq!( strip_padding( &buffer, unpadded, padded ), texels );
+  }
+
+  #[ test ]
+  fn odd_sizes_match_the_cpu_bake()
+  {
+    let Some( ( device, queue ) ) = gpu::test_device() else
+    {
+      eprintln!( "No suitable GPU adapter, the readback of a bake is skipped" );
+      return;
+    };
+
+    // Rows of 100 texels aren't a multiple of the copy alignment, and the five specular mips of 16x8 end at 1x1
+    let settings = BakeSettings
+    {
+      cube_size : 16,
+      diffuse_width : 100,
+      diffuse_height : 50,
+      specular_1_width : 16,
+      specular_1_height : 8,
+      specular_2_width : 100,
+      specular_2_height : 50,
+      diffuse_samples : 16,
+      specular_samples : 64,
+      lut_samples : 64,
+      specular_cube_size : Some( 16 ),
+      compute : true,
+      ..BakeSettings::default()
+    };
+    // Different in every row and column, so texels read back at the wrong place stand out
+    let ( width, height ) = ( 64, 32 );
+    let pixels = ( 0..width * height ).flat_map( | i |
+    {
+      let ( x, y ) = ( ( i % width ) as f32 / width as f32, ( i / width ) as f32 / height as f32 );
+      [ 1.0 + 4.0 * x, 1.0 + 4.0 * y, 1.0 + 4.0 * x * y, 1.0 ]
+    })
+    .collect();
+    let source = SourceImage { width, height, pixels };
+
+    let mut baker = Baker::new( &device, &queue, &settings, false );
+    baker.set_source( &device, &source.to_texture( &device, &queue ) );
+    baker.bake( &device, &queue );
+    device.poll( wgpu::PollType::wait() ).unwrap();
+    let gpu = pollster::block_on( baker.read_outputs( &device ) ).unwrap().decode();
+    let cpu = cpu_baker::bake( &source, &settings );
+
+    let sizes = gpu.images().map( | image | ( image.name.clone(), image.width, image.height ) ).collect::< Vec< _ > >();
+    assert!( sizes.contains( &( "diffuse".into(), 100, 50 ) ) && sizes.contains( &( "specular_2".into(), 100, 50 ) ), "{:?}", sizes );
+    assert!( sizes.contains( &( "specular_1_4".into(), 1, 1 ) ) && sizes.contains( &( "specular_cube_4_5".into(), 1, 1 ) ), "{:?}", sizes );
+
+    for ( gpu_image, cpu_image ) in gpu.images().zip( cpu.images() )
+    {
+      assert_eq!( ( &gpu_image.name, gpu_image.width, gpu_image.height ), ( &cpu_image.name, cpu_image.width, cpu_image.height ) );
+      let difference = gpu_image.data.iter().zip( &cpu_image.data ).map( | ( g, c ) | ( g - c ).abs() ).sum::< f32 >();
+      let total = cpu_image.data.iter().map( | c | c.abs() ).sum::< f32 >();
+      assert!( difference < 0.01 * total, "{} differs by {} of its total", gpu_image.name, difference / total );
+    }
+  }
+}
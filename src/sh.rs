@@ -0,0 +1,67 @@
+use std::f32::consts::PI;
+
+use crate::baker::SourceImage;
+
+/// Nine RGB coefficients of the real spherical harmonics up to l = 2,
+/// ordered (0,0), (1,-1), (1,0), (1,1), (2,-2), (2,-1), (2,0), (2,1), (2,2).
+pub type ShCoefficients = [ [ f32; 3 ]; 9 ];
+
+fn basis( x : f32, y : f32, z : f32 ) -> [ f32; 9 ]
+{
+  [
+    0.282095,
+    0.488603 * y,
+    0.488603 * z,
+    0.488603 * x,
+    1.092548 * x * y,
+    1.092548 * y * z,
+    0.315392 * ( 3.0 * z * z - 1.0 ),
+    1.092548 * x * z,
+    0.546274 * ( x * x - y * y )
+  ]
+}
+
+/// Projects the radiance of an equirectangular image on the SH basis. Directions follow
+/// the cube the image is converted to: Y up, `u = 0.5` looking down +X.
+pub fn project_radiance( source : &SourceImage ) -> ShCoefficients
+{
+  let mut coefficients = [ [ 0.0; 3 ]; 9 ];
+  let texel_phi = 2.0 * PI / source.width as f32;
+  let texel_theta = PI / source.height as f32;
+
+  for y in 0..source.height
+  {
+    let elevation = ( 0.5 - ( y as f32 + 0.5 ) / source.height as f32 ) * PI;
+    let solid_angle = elevation.cos() * texel_phi * texel_theta;
+
+    for x in 0..source.width
+    {
+      let phi = ( ( x as f32 + 0.5 ) / source.width as f32 - 0.5 ) * 2.0 * PI;
+      let basis = basis( elevation.cos() * phi.cos(), elevation.sin(), elevation.cos() * phi.sin() );
+
+      let texel = ( ( y * source.width + x ) * 4 ) as usize;
+      let radiance = &source.pixels[ texel..texel + 3 ];
+      for ( coefficient, b ) in coefficients.iter_mut().zip( basis )
+      {
+        for ( c, r ) in coefficient.iter_mut().zip( radiance )
+        {
+          *c += r * b * solid_angle;
+        }
+      }
+    }
+  }
+
+  coefficients
+}
+
+/// Convolves radiance coefficients with the clamped cosine lobe, which gives the irradiance.
+pub fn radiance_to_irradiance( mut coefficients : ShCoefficients ) -> ShCoefficients
+{
+  let bands = [ PI, 2.0 * PI / 3.0, PI / 4.0 ];
+  for ( i, coefficient ) in coefficients.iter_mut().enumerate()
+  {
+    let band = match i { 0 => 0, 1..=3 => 1, _ => 2 };
+    coefficient.iter_mut().for_each( | c | *c *= bands[ band ] );
+  }
+  coefficients
+}
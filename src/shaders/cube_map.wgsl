@@ -9,6 +9,16 @@ struct Face
   right: vec3<f32>,
 };
 
+// 0: one bilinear sample, 1: SAMPLES x SAMPLES bilinear samples, 2: area weighted, the order of `CubeResample`
+override RESAMPLE : u32 = 0u;
+override SAMPLES : u32 = 4u;
+
+const RESAMPLE_SUPERSAMPLE : u32 = 1u;
+const RESAMPLE_AREA : u32 = 2u;
+
+// Most source texels the area filter reads along each axis, larger footprints are read with a stride
+const MAX_AREA_TEXELS : f32 = 16.0;
+
 const tangent_normalizer : vec2f = vec2f( 0.15915, 0.3183 );
 
 
@@ -20,6 +30,38 @@ fn main( @builtin( global_invocation_id ) gid: vec3< u32 > )
     return;
   }
 
+  let size = vec2f( textureDimensions( dst ) );
+  var hdr_sample : vec4f;
+  switch RESAMPLE
+  {
+    case RESAMPLE_SUPERSAMPLE
+    {
+      // Samples spread evenly over the texel
+      hdr_sample = vec4f( 0.0 );
+      for( var i = 0u; i < SAMPLES * SAMPLES; i++ )
+      {
+        let offset = ( vec2f( f32( i % SAMPLES ), f32( i / SAMPLES ) ) + 0.5 ) / f32( SAMPLES );
+        hdr_sample += sampleHDR( src, equirect_uv( texel_direction( gid.z, ( vec2f( gid.xy ) + offset ) / size ) ) );
+      }
+      hdr_sample /= f32( SAMPLES * SAMPLES );
+    }
+    case RESAMPLE_AREA
+    {
+      hdr_sample = sample_area( gid.z, gid.xy, size );
+    }
+    default
+    {
+      // At the texel center
+      hdr_sample = sampleHDR( src, equirect_uv( texel_direction( gid.z, ( vec2f( gid.xy ) + 0.5 ) / size ) ) );
+    }
+  }
+
+  textureStore(  dst, gid.xy, gid.z, hdr_sample );
+}
+
+// Direction through `face_uv` in 0..1 on `face`
+fn texel_direction( face_index : u32, face_uv : vec2f ) -> vec3f
+{
   // https://www.w3.org/TR/webgpu/#coordinate-systems
   // Wwebgpu uses left-handed coordinate system to represent the face of a cube
   // When transforming into the right-handed coordinate system, the Y is flipped
@@ -69,51 +111,105 @@ fn main( @builtin( global_invocation_id ) gid: vec3< u32 > )
     )
   );
 
-  let size = textureDimensions( dst );
-  // Get the YZ coordinates from the invocation ID
-  var face_uv = vec2f( gid.xy );
-  // Normallize it in range 0.0..1.0
-  face_uv /= vec2f( size );
   // Transform to range -1.0..1.0
   let uv2 = face_uv * 2.0 - vec2f( 1.0 );
 
   // The face of the cube to draw to
-  let face = faces[ gid.z ];
+  let face = faces[ face_index ];
   let rot_mat = mat3x3< f32 >( face.forward, face.up, face.right );
 
   // Get the direction vector
   var dir = vec3f( 1.0, uv2 );
   // Rotate it towards the current face of the cube
   dir = rot_mat * dir;
-  dir = normalize( dir );
+  return normalize( dir );
+}
 
+// Coordinates of `dir` in the equirectangular source
+fn equirect_uv( dir : vec3f ) -> vec2f
+{
   // Get the spherical coordinates from the direction
   let longitude = asin( dir.y );
   let latitude = atan2( dir.z, dir.x );
 
-  var hdr_uv = vec2f( latitude, longitude ) * tangent_normalizer + vec2f( 0.5 );
-  
-  let hdr_sample = sampleHDR( src, hdr_uv );
-  textureStore(  dst, gid.xy, gid.z, hdr_sample );
+  return vec2f( latitude, longitude ) * tangent_normalizer + vec2f( 0.5 );
+}
+
+// Average of the source texels under the texel of the cube, weighted by how much of each one it covers.
+// The footprint is approximated by the bounding box of the texel corners in the source, falling back
+// to a bilinear sample when it is smaller than a source texel.
+fn sample_area( face : u32, texel : vec2u, size : vec2f ) -> vec4f
+{
+  let src_size = vec2f( textureDimensions( src ) );
+  let center = equirect_uv( texel_direction( face, ( vec2f( texel ) + 0.5 ) / size ) );
+  var low = center;
+  var high = center;
+  for( var corner = 0u; corner < 4u; corner++ )
+  {
+    let uv = equirect_uv( texel_direction( face, ( vec2f( texel ) + vec2f( f32( corner & 1u ), f32( corner >> 1u ) ) ) / size ) );
+    // On the same side of the seam as the center
+    let u = center.x + fract( uv.x - center.x + 0.5 ) - 0.5;
+    low = min( low, vec2f( u, uv.y ) );
+    high = max( high, vec2f( u, uv.y ) );
+  }
+
+  // The texels of the top and bottom faces around the pole cover every longitude
+  let st = ( vec2f( texel ) + 0.5 ) / size * 2.0 - 1.0;
+  if ( face == 2u || face == 3u ) && all( abs( st ) <= 1.0 / size )
+  {
+    low.x = center.x - 0.5;
+    high.x = center.x + 0.5;
+    if center.y < 0.5 { low.y = 0.0; } else { high.y = 1.0; }
+  }
+
+  low *= src_size;
+  high *= src_size;
+  if all( high - low <= vec2f( 1.0 ) )
+  {
+    return sampleHDR( src, center );
+  }
+
+  // Every visited texel stands for the `step` texels that follow it
+  let step = max( ceil( ( high - low ) / MAX_AREA_TEXELS ), vec2f( 1.0 ) );
+  var sum = vec4f( 0.0 );
+  var total = 0.0;
+  for( var y = floor( low.y ); y < high.y; y += step.y )
+  {
+    let weight_y = min( y + step.y, high.y ) - max( y, low.y );
+    for( var x = floor( low.x ); x < high.x; x += step.x )
+    {
+      let weight = weight_y * ( min( x + step.x, high.x ) - max( x, low.x ) );
+      sum += weight * load_wrapped( src, vec2i( vec2f( x, y ) + floor( step * 0.5 ) ) );
+      total += weight;
+    }
+  }
+  return sum / total;
+}
+
+// Texel of the source at `cell`, the longitude wraps around the seam and the latitude stops at the poles
+fn load_wrapped( src : texture_2d< f32 >, cell : vec2i ) -> vec4f
+{
+  let size = vec2i( textureDimensions( src ) );
+  let x = ( cell.x % size.x + size.x ) % size.x;
+  let y = clamp( cell.y, 0, size.y - 1 );
+  return textureLoad( src, vec2i( x, y ), 0 );
 }
 
+// Bilinear sample of the source, whose texel centers are at half integers of `uv * size`
 fn sampleHDR( src : texture_2d< f32 >, uv : vec2f ) -> vec4f
 {
-  var dimensions = textureDimensions( src );
-  let size = vec2f( dimensions );
-  let big_uv = uv * size;
-  let cell_id = vec2u( floor( big_uv ) );
+  let size = vec2f( textureDimensions( src ) );
+  let big_uv = uv * size - 0.5;
+  let cell_id = vec2i( floor( big_uv ) );
   let offset = fract( big_uv );
 
-  dimensions -= vec2u( 1 );
-
-  let sample1 = textureLoad( src, cell_id + vec2u( 0, 0 ), 0 );
-  let sample2 = textureLoad( src, min( cell_id + vec2u( 1, 0 ), dimensions ), 0 );
-  let sample3 = textureLoad( src, min( cell_id + vec2u( 0, 1 ), dimensions ), 0 );
-  let sample4 = textureLoad( src, min( cell_id + vec2u( 1, 1 ), dimensions ), 0 );
+  let sample1 = load_wrapped( src, cell_id + vec2i( 0, 0 ) );
+  let sample2 = load_wrapped( src, cell_id + vec2i( 1, 0 ) );
+  let sample3 = load_wrapped( src, cell_id + vec2i( 0, 1 ) );
+  let sample4 = load_wrapped( src, cell_id + vec2i( 1, 1 ) );
 
   let mix12 = mix( sample1, sample2, offset.x );
   let mix34 = mix( sample3, sample4, offset.x );
 
   return mix( mix12, mix34, offset.y );
-}
\ No newline at end of file
+}
@@ -0,0 +1,32 @@
+// Renders the environment cube back to an equirectangular image, the inverse of `cube_map.wgsl`.
+// The mapping is the one of the equirect outputs of `ibl.wgsl`, so every equirect image of a bake lines up.
+
+@group( 0 ) @binding( 0 ) var env_map : texture_cube< f32 >;
+@group( 0 ) @binding( 1 ) var env_sampler : sampler;
+@group( 0 ) @binding( 2 ) var dst : texture_storage_2d< rgba32float, write >;
+
+const PI : f32 = 3.1415926535;
+
+@compute @workgroup_size( 16, 16, 1 )
+fn main( @builtin( global_invocation_id ) gid : vec3< u32 > )
+{
+  let size = textureDimensions( dst );
+  if any( gid.xy >= size )
+  {
+    return;
+  }
+
+  var uv = ( vec2f( gid.xy ) + 0.5 ) / vec2f( size );
+  uv.y = 1.0 - uv.y;
+  // vec2f( -PI..PI, -PI/2..PI/2 )
+  let angles = ( uv * 2.0 - vec2f( 1.0 ) ) * vec2f( PI, PI / 2.0 );
+  let dir = vec3f( cos( angles.x ) * cos( angles.y ), sin( angles.y ), sin( angles.x ) * cos( angles.y ) );
+
+  // Mip whose texels are about as wide as the output texel, which narrows towards the poles.
+  // A texel at the center of a cube face spans PI / 2 / cube_size
+  let cube_size = f32( textureDimensions( env_map ).x );
+  let texel_angle = max( 2.0 * PI * cos( angles.y ) / f32( size.x ), PI / f32( size.y ) );
+  let lod = log2( max( texel_angle * cube_size * 2.0 / PI, 1.0 ) );
+
+  textureStore( dst, gid.xy, textureSampleLevel( env_map, env_sampler, dir, lod ) );
+}
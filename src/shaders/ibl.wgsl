@@ -3,7 +3,6 @@
 struct VertexOutput
 {
   @builtin( position ) pos : vec4f,
-  @location( 0 ) uv : vec2f,
 }
 
 @vertex
@@ -14,7 +13,6 @@ fn vertex_main( @builtin( vertex_index ) id : u32 ) -> VertexOutput
 
   var result : VertexOutput;
   result.pos = vec4f( vec2f( x * 4.0 - 1.0, 1.0 - y * 4.0 ), 1.0, 1.0 );
-  result.uv = vec2f( x, y ) * 2.0;
 
   return result;
 }
@@ -22,26 +20,91 @@ fn vertex_main( @builtin( vertex_index ) id : u32 ) -> VertexOutput
 struct Uniform
 {
   mip_level : u32,
-  total_mips : u32
+  total_mips : u32,
+  face : u32,
+  // First entry of the current mip in the specular sample table of the compute passes
+  sample_offset : u32,
+  // Size of the target of the pass, or of the current mip
+  size : vec2u
 }
 
 @group( 0 ) @binding( 0 ) var env_map : texture_cube< f32 >;
 @group( 0 ) @binding( 1 ) var env_sampler : sampler;
 @group( 0 ) @binding( 2 ) var< uniform > uniforms : Uniform ;
 
+// Set from `BakeSettings` when the pipelines are created
+override DIFFUSE_SAMPLES : u32 = 50u;
+override SPECULAR_SAMPLES : u32 = 512u;
+override LUT_SAMPLES : u32 = 1024u;
+// `OutputLayout` of the diffuse and specular maps, 0 for equirect and 1 for octahedral
+override LAYOUT : u32 = 0u;
+// Texels duplicated around every edge of an octahedral map, so bilinear filtering wraps correctly
+const OCTAHEDRAL_BORDER : f32 = 1.0;
+
 
 const PI : f32 = 3.1415926535;
 
-@fragment
-fn fragment_diffuse_main( in : VertexOutput ) -> @location( 0 ) vec4f
-{ 
-  var uv = vec2f( in.uv.x, 1.0 - in.uv.y ); 
+// Direction the output texel stands for, in the layout selected by `LAYOUT`
+fn output_direction( in : VertexOutput ) -> vec3f
+{
+  return texel_direction( in.pos.xy, vec2f( uniforms.size ) );
+}
+
+// Position of the output texel center in the target, 0 to 1
+fn output_uv( in : VertexOutput ) -> vec2f
+{
+  return in.pos.xy / vec2f( uniforms.size );
+}
+
+// Same as `output_direction`, for the texel centered at `pos` of a `size` target
+fn texel_direction( pos : vec2f, size : vec2f ) -> vec3f
+{
+  if( LAYOUT == 1u )
+  {
+    return octahedral_direction( pos, size );
+  }
+
+  var uv = pos / size;
+  uv.y = 1.0 - uv.y;
   // -1.0..1.0
   uv = uv * 2.0 - vec2f( 1.0 );
   // vec2f( -PI..PI, -PI/2..PI/2 )
   uv *= vec2f( PI, PI / 2.0 );
-  var normal = vec3f( cos( uv.x ) * cos( uv.y ), sin( uv.y ), sin( uv.x ) * cos( uv.y ) );
-  normal = normalize( normal );
+  let dir = vec3f( cos( uv.x ) * cos( uv.y ), sin( uv.y ), sin( uv.x ) * cos( uv.y ) );
+  return normalize( dir );
+}
+
+// Octahedral map with +Y at the center, U going towards +X and V towards +Z.
+// Border texels take the value of the interior texel mirrored across the middle of the edge,
+// which is the texel bilinear filtering would reach by wrapping over the octahedron
+fn octahedral_direction( pos : vec2f, size : vec2f ) -> vec3f
+{
+  let interior = size - vec2f( 2.0 * OCTAHEDRAL_BORDER );
+  var texel = floor( pos ) - vec2f( OCTAHEDRAL_BORDER );
+
+  if( texel.x < 0.0 || texel.x >= interior.x )
+  {
+    texel = vec2f( clamp( texel.x, 0.0, interior.x - 1.0 ), interior.y - 1.0 - texel.y );
+  }
+  if( texel.y < 0.0 || texel.y >= interior.y )
+  {
+    texel = vec2f( interior.x - 1.0 - texel.x, clamp( texel.y, 0.0, interior.y - 1.0 ) );
+  }
+
+  let e = ( texel + 0.5 ) / interior * 2.0 - vec2f( 1.0 );
+  var dir = vec3f( e.x, 1.0 - abs( e.x ) - abs( e.y ), e.y );
+  if( dir.y < 0.0 )
+  {
+    let signs = select( vec2f( -1.0 ), vec2f( 1.0 ), dir.xz >= vec2f( 0.0 ) );
+    dir = vec3f( ( 1.0 - abs( dir.z ) ) * signs.x, dir.y, ( 1.0 - abs( dir.x ) ) * signs.y );
+  }
+  return normalize( dir );
+}
+
+@fragment
+fn fragment_diffuse_main( in : VertexOutput ) -> @location( 0 ) vec4f
+{ 
+  let normal = output_direction( in );
 
   var up = vec3f( 0.0, 1.0, 0.0 );
   // if( 1.0 - abs( normal.y ) < 1e-5 )
@@ -54,11 +117,15 @@ fn fragment_diffuse_main( in : VertexOutput ) -> @location( 0 ) vec4f
 
   let TBN = mat3x3< f32 >( up, normal, right );
 
-  let NUM_SAMPLES_X : f32 = 50.0;
-  let NUM_SAMPLES_Y : f32 = 50.0;
+  let NUM_SAMPLES_X : f32 = f32( DIFFUSE_SAMPLES );
+  let NUM_SAMPLES_Y : f32 = f32( DIFFUSE_SAMPLES );
   let DELTA_X : f32 = 1.0 / NUM_SAMPLES_X;
   let DELTA_Y : f32 = 1.0 / NUM_SAMPLES_Y;
 
+  // Each sample reads the mip whose texels cover its solid angle, as `sample_lod` in `ibl_compute.rs` does
+  let env_dim = f32( textureDimensions( env_map ).x );
+  let saTexel = 4.0 * PI / ( 6.0 * env_dim * env_dim );
+
   var result = vec4f( 0.0 );
 
   for( var x = 0.0; x < 1.0; x += DELTA_X )
@@ -69,7 +136,9 @@ fn fragment_diffuse_main( in : VertexOutput ) -> @location( 0 ) vec4f
       var sample_dir = normalize( vec3f( sin( uv.x ) * sin( uv.y ), cos( uv.y ), cos( uv.x ) * sin( uv.y ) ) );
       sample_dir = TBN * sample_dir;
 
-      result += textureSample( env_map, env_sampler, sample_dir ) * cos( uv.y ) * sin( uv.y );
+      let saSample = 2.0 * PI * DELTA_X * PI / 2.0 * DELTA_Y * sin( uv.y );
+      let mipLevel = max( 0.5 * log2( saSample / saTexel ), 0.0 );
+      result += textureSampleLevel( env_map, env_sampler, sample_dir, mipLevel ) * cos( uv.y ) * sin( uv.y );
     }
   }
 
@@ -81,18 +150,41 @@ fn fragment_diffuse_main( in : VertexOutput ) -> @location( 0 ) vec4f
 @fragment
 fn fragment_specular_1_main( in : VertexOutput ) -> @location( 0 ) vec4f
 {
-  var uv = vec2f( in.uv.x, 1.0 - in.uv.y ); 
-  // -1.0..1.0
-  uv = uv * 2.0 - vec2f( 1.0 );
-  // vec2f( -PI..PI, -PI/2..PI/2 )
-  uv *= vec2f( PI, PI / 2.0 );
-  var N = vec3f( cos( uv.x ) * cos( uv.y ), sin( uv.y ), sin( uv.x ) * cos( uv.y ) );
-  N = normalize( N );
+  return vec4f( prefilter( output_direction( in ) ), 1.0 );
+}
+
+// Same as `fragment_specular_1_main`, but renders the face `uniforms.face` of a cube map
+@fragment
+fn fragment_specular_cube_main( in : VertexOutput ) -> @location( 0 ) vec4f
+{
+  return vec4f( prefilter( cube_direction( uniforms.face, output_uv( in ) ) ), 1.0 );
+}
+
+// Direction a cube map lookup uses for the texel at `uv` of `face`, in the WebGPU face order +X, -X, +Y, -Y, +Z, -Z
+fn cube_direction( face : u32, uv : vec2f ) -> vec3f
+{
+  let st = uv * 2.0 - vec2f( 1.0 );
+  var dir : vec3f;
+  switch face
+  {
+    case 0u { dir = vec3f( 1.0, -st.y, -st.x ); }
+    case 1u { dir = vec3f( -1.0, -st.y, st.x ); }
+    case 2u { dir = vec3f( st.x, 1.0, st.y ); }
+    case 3u { dir = vec3f( st.x, -1.0, -st.y ); }
+    case 4u { dir = vec3f( st.x, -st.y, 1.0 ); }
+    default { dir = vec3f( -st.x, -st.y, -1.0 ); }
+  }
+  return normalize( dir );
+}
+
+// GGX prefiltered radiance around N, for the roughness of the current mip
+fn prefilter( N : vec3f ) -> vec3f
+{
   let V = N;
 
   let roughness = f32( uniforms.mip_level ) / f32( uniforms.total_mips );
   let alpha = roughness * roughness;
-  let NUM_SAMPLES = u32( 512 );
+  let NUM_SAMPLES = SPECULAR_SAMPLES;
 
   var result = vec3f( 0.0 );
   var total_weight = 0.0;
@@ -127,19 +219,18 @@ fn fragment_specular_1_main( in : VertexOutput ) -> @location( 0 ) vec4f
     }
   }
 
-  result /= f32( NUM_SAMPLES );
-  result /= total_weight;
-  return vec4f( result, 1.0 );
+  return result / total_weight;
 }
 
 @fragment
 fn fragment_specular_2_main( in : VertexOutput ) -> @location( 0 ) vec4f
 {
-  let roughness = in.uv.y;
-  let dotNV = in.uv.x;
+  let uv = output_uv( in );
+  let roughness = uv.y;
+  let dotNV = uv.x;
 
   let alpha = roughness * roughness;
-  let NUM_SAMPLES = u32( 1024 );
+  let NUM_SAMPLES = LUT_SAMPLES;
 
   let N = vec3f( 0.0, 1.0, 0.0 );
   let V = vec3f( 0.0, dotNV, sqrt( 1.0 - dotNV * dotNV ) );
@@ -159,9 +250,9 @@ fn fragment_specular_2_main( in : VertexOutput ) -> @location( 0 ) vec4f
 
     if( dotNL > 0.0 )
     {
-      let G = V_GGX_SmithCorrelated( alpha, dotNL, dotNV );
-      //let G = GeometrySmith( dotNV, dotNL, alpha );
-      let BRDF = G * dotVH / ( dotNH * dotNV );
+      let Vis = V_GGX_SmithCorrelated( alpha, dotNL, dotNV );
+      // D * Vis * NdotL over the pdf of L, D * NdotH / ( 4 * VdotH )
+      let BRDF = 4.0 * Vis * dotNL * dotVH / dotNH;
 
       let Fp5 = pow( 1.0 - dotVH, 5.0 );
       result.x += BRDF * ( 1.0 - Fp5 );
@@ -0,0 +1,308 @@
+// Compute versions of the passes of `ibl.wgsl`, which this file is appended to.
+// The `*_STORAGE_FORMAT` names are replaced with the formats of the outputs when the module is created.
+
+struct Sample
+{
+  // Tangent space, Y is the normal
+  direction : vec3f,
+  lod : f32,
+  weight : f32
+}
+
+// Origin of the tile of the output the dispatch covers
+struct Tile
+{
+  origin : vec2u
+}
+
+@group( 0 ) @binding( 3 ) var< storage, read > diffuse_samples : array< Sample >;
+@group( 0 ) @binding( 4 ) var< storage, read > specular_samples : array< Sample >;
+// Hammersley points of the BRDF LUT
+@group( 0 ) @binding( 5 ) var< storage, read > lut_samples : array< vec2f >;
+@group( 0 ) @binding( 6 ) var< uniform > tile : Tile;
+
+// Progressive passes, see `progressive.rs`. Each batch takes the next `*_BATCH` entries of the sample tables.
+override DIFFUSE_BATCH : u32 = 64u;
+override SPECULAR_BATCH : u32 = 64u;
+
+struct Batch
+{
+  index : u32
+}
+
+// Sums of the even and odd batches of every texel, interleaved, with the weights in w.
+// `noise` is written by `compute_noise_main`.
+struct Accumulator
+{
+  noise : vec4f,
+  texels : array< vec4f >
+}
+
+@group( 0 ) @binding( 7 ) var< uniform > batch : Batch;
+@group( 1 ) @binding( 4 ) var< storage, read_write > accumulator : Accumulator;
+
+@group( 1 ) @binding( 0 ) var diffuse_output : texture_storage_2d< DIFFUSE_STORAGE_FORMAT, write >;
+@group( 1 ) @binding( 1 ) var specular_output : texture_storage_2d< SPECULAR_STORAGE_FORMAT, write >;
+@group( 1 ) @binding( 2 ) var lut_output : texture_storage_2d< LUT_STORAGE_FORMAT, write >;
+@group( 1 ) @binding( 3 ) var specular_cube_output : texture_storage_2d_array< SPECULAR_STORAGE_FORMAT, write >;
+
+// Samples are staged in workgroup memory, one per invocation
+const WORKGROUP_SIZE : u32 = 8u;
+const SHARED_SAMPLES : u32 = 64u;
+
+var< workgroup > shared_samples : array< Sample, SHARED_SAMPLES >;
+var< workgroup > shared_lut_samples : array< vec2f, SHARED_SAMPLES >;
+var< workgroup > shared_noise : array< f32, SHARED_SAMPLES >;
+
+// Rotates tangent space directions around `N`
+fn tangent_frame( N : vec3f ) -> mat3x3< f32 >
+{
+  var up = vec3f( 0.0, 1.0, 0.0 );
+  if( abs( N.y ) > 0.999 )
+  {
+    up = vec3f( 1.0, 0.0, 0.0 );
+  }
+  let forward = normalize( cross( up, N ) );
+  let right = normalize( cross( N, forward ) );
+  return mat3x3< f32 >( right, N, forward );
+}
+
+// Weighted sum of the environment over the samples `offset..offset + count` of `table`,
+// returned with the sum of the weights in `w`
+fn integrate( N : vec3f, local : u32, table : u32, offset : u32, count : u32 ) -> vec4f
+{
+  let frame = tangent_frame( N );
+  var result = vec4f( 0.0 );
+
+  for( var base = 0u; base < count; base += SHARED_SAMPLES )
+  {
+    workgroupBarrier();
+    if( base + local < count )
+    {
+      if( table == 0u )
+      {
+        shared_samples[ local ] = diffuse_samples[ offset + base + local ];
+      }
+      else
+      {
+        shared_samples[ local ] = specular_samples[ offset + base + local ];
+      }
+    }
+    workgroupBarrier();
+
+    let num_shared = min( SHARED_SAMPLES, count - base );
+    for( var i = 0u; i < num_shared; i += 1u )
+    {
+      let s = shared_samples[ i ];
+      if( s.weight > 0.0 )
+      {
+        let radiance = textureSampleLevel( env_map, env_sampler, frame * s.direction, s.lod ).rgb;
+        result += vec4f( radiance * s.weight, s.weight );
+      }
+    }
+  }
+
+  return result;
+}
+
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn compute_diffuse_main( @builtin( global_invocation_id ) gid : vec3u, @builtin( local_invocation_index ) local : u32 )
+{
+  let size = textureDimensions( diffuse_output );
+  let texel = tile.origin + gid.xy;
+  let N = texel_direction( vec2f( texel ) + 0.5, vec2f( size ) );
+
+  let count = DIFFUSE_SAMPLES * DIFFUSE_SAMPLES;
+  let result = PI * integrate( N, local, 0u, 0u, count ).rgb / f32( count );
+
+  if( all( texel < size ) )
+  {
+    textureStore( diffuse_output, texel, vec4f( result, 1.0 ) );
+  }
+}
+
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn compute_specular_main( @builtin( global_invocation_id ) gid : vec3u, @builtin( local_invocation_index ) local : u32 )
+{
+  let size = textureDimensions( specular_output );
+  let texel = tile.origin + gid.xy;
+  let N = texel_direction( vec2f( texel ) + 0.5, vec2f( size ) );
+
+  let sum = integrate( N, local, 1u, uniforms.sample_offset, SPECULAR_SAMPLES );
+
+  if( all( texel < size ) )
+  {
+    textureStore( specular_output, texel, vec4f( sum.rgb / sum.w, 1.0 ) );
+  }
+}
+
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn compute_specular_cube_main( @builtin( global_invocation_id ) gid : vec3u, @builtin( local_invocation_index ) local : u32 )
+{
+  let size = textureDimensions( specular_cube_output );
+  let texel = tile.origin + gid.xy;
+  let N = cube_direction( gid.z, ( vec2f( texel ) + 0.5 ) / vec2f( size ) );
+
+  let sum = integrate( N, local, 1u, uniforms.sample_offset, SPECULAR_SAMPLES );
+
+  if( all( texel < size ) )
+  {
+    textureStore( specular_cube_output, texel, gid.z, vec4f( sum.rgb / sum.w, 1.0 ) );
+  }
+}
+
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn compute_lut_main( @builtin( global_invocation_id ) gid : vec3u, @builtin( local_invocation_index ) local : u32 )
+{
+  let size = textureDimensions( lut_output );
+  let texel = tile.origin + gid.xy;
+  let uv = ( vec2f( texel ) + 0.5 ) / vec2f( size );
+  let roughness = uv.y;
+  let dotNV = uv.x;
+
+  let alpha = roughness * roughness;
+  let N = vec3f( 0.0, 1.0, 0.0 );
+  let V = vec3f( 0.0, dotNV, sqrt( 1.0 - dotNV * dotNV ) );
+
+  var result = vec2f( 0.0 );
+
+  for( var base = 0u; base < LUT_SAMPLES; base += SHARED_SAMPLES )
+  {
+    workgroupBarrier();
+    if( base + local < LUT_SAMPLES )
+    {
+      shared_lut_samples[ local ] = lut_samples[ base + local ];
+    }
+    workgroupBarrier();
+
+    let num_shared = min( SHARED_SAMPLES, LUT_SAMPLES - base );
+    for( var i = 0u; i < num_shared; i += 1u )
+    {
+      let H = importance_sample( shared_lut_samples[ i ], N, alpha );
+
+      let dotVH = saturate( dot( V, H ) );
+      let L = normalize( 2.0 * dotVH * H - V );
+
+      let dotNL = saturate( L.y );
+      let dotNH = saturate( H.y );
+
+      if( dotNL > 0.0 )
+      {
+        let Vis = V_GGX_SmithCorrelated( alpha, dotNL, dotNV );
+        let BRDF = 4.0 * Vis * dotNL * dotVH / dotNH;
+
+        let Fp5 = pow( 1.0 - dotVH, 5.0 );
+        result.x += BRDF * ( 1.0 - Fp5 );
+        result.y += BRDF * Fp5;
+      }
+    }
+  }
+
+  if( all( texel < size ) )
+  {
+    textureStore( lut_output, texel, vec4f( result / f32( LUT_SAMPLES ), 0.0, 1.0 ) );
+  }
+}
+
+// Adds the current batch to the even or odd sum of the texel `index` of the accumulator and
+// returns the mean of every batch so far. The first two batches overwrite what the last bake left.
+fn accumulate( index : u32, sample : vec4f ) -> vec4f
+{
+  let slot = 2u * index + ( batch.index & 1u );
+  if( batch.index < 2u )
+  {
+    accumulator.texels[ slot ] = sample;
+    if( batch.index == 0u )
+    {
+      accumulator.texels[ slot + 1u ] = vec4f( 0.0 );
+    }
+  }
+  else
+  {
+    accumulator.texels[ slot ] += sample;
+  }
+
+  let sum = accumulator.texels[ 2u * index ] + accumulator.texels[ 2u * index + 1u ];
+  return vec4f( sum.rgb / sum.w, 1.0 );
+}
+
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn compute_diffuse_progressive_main( @builtin( global_invocation_id ) gid : vec3u, @builtin( local_invocation_index ) local : u32 )
+{
+  let size = textureDimensions( diffuse_output );
+  let texel = tile.origin + gid.xy;
+  let N = texel_direction( vec2f( texel ) + 0.5, vec2f( size ) );
+
+  // Every batch is a complete estimate of the irradiance, so each one weighs 1
+  let sum = integrate( N, local, 0u, batch.index * DIFFUSE_BATCH, DIFFUSE_BATCH );
+  let sample = vec4f( PI * sum.rgb / f32( DIFFUSE_BATCH ), 1.0 );
+
+  if( all( texel < size ) )
+  {
+    textureStore( diffuse_output, texel, accumulate( texel.y * size.x + texel.x, sample ) );
+  }
+}
+
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn compute_specular_progressive_main( @builtin( global_invocation_id ) gid : vec3u, @builtin( local_invocation_index ) local : u32 )
+{
+  let size = textureDimensions( specular_output );
+  let texel = tile.origin + gid.xy;
+  let N = texel_direction( vec2f( texel ) + 0.5, vec2f( size ) );
+
+  let sum = integrate( N, local, 1u, uniforms.sample_offset + batch.index * SPECULAR_BATCH, SPECULAR_BATCH );
+
+  if( all( texel < size ) )
+  {
+    textureStore( specular_output, texel, accumulate( texel.y * size.x + texel.x, sum ) );
+  }
+}
+
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn compute_specular_cube_progressive_main( @builtin( global_invocation_id ) gid : vec3u, @builtin( local_invocation_index ) local : u32 )
+{
+  let size = textureDimensions( specular_cube_output );
+  let texel = tile.origin + gid.xy;
+  let N = cube_direction( gid.z, ( vec2f( texel ) + 0.5 ) / vec2f( size ) );
+
+  let sum = integrate( N, local, 1u, uniforms.sample_offset + batch.index * SPECULAR_BATCH, SPECULAR_BATCH );
+
+  if( all( texel < size ) )
+  {
+    textureStore( specular_cube_output, texel, gid.z, accumulate( ( gid.z * size.y + texel.y ) * size.x + texel.x, sum ) );
+  }
+}
+
+// Mean relative difference of the luminance of the even and odd batch means. The two halves
+// differ by about twice the error of the mean of all the batches, relative to it.
+@compute @workgroup_size( SHARED_SAMPLES, 1, 1 )
+fn compute_noise_main( @builtin( local_invocation_index ) local : u32 )
+{
+  let count = arrayLength( &accumulator.texels ) / 2u;
+  let luminance = vec3f( 0.2126, 0.7152, 0.0722 );
+
+  var sum = 0.0;
+  for( var i = local; i < count; i += SHARED_SAMPLES )
+  {
+    let even = accumulator.texels[ 2u * i ];
+    let odd = accumulator.texels[ 2u * i + 1u ];
+    let e = dot( even.rgb / max( even.w, 1e-6 ), luminance );
+    let o = dot( odd.rgb / max( odd.w, 1e-6 ), luminance );
+    sum += abs( e - o ) / max( e + o, 1e-6 );
+  }
+  shared_noise[ local ] = sum;
+
+  for( var stride = SHARED_SAMPLES / 2u; stride > 0u; stride /= 2u )
+  {
+    workgroupBarrier();
+    if( local < stride )
+    {
+      shared_noise[ local ] += shared_noise[ local + stride ];
+    }
+  }
+
+  if( local == 0u )
+  {
+    accumulator.noise = vec4f( shared_noise[ 0 ] / f32( count ), 0.0, 0.0, 0.0 );
+  }
+}
@@ -0,0 +1,244 @@
+@group( 0 ) @binding( 0 ) var source : texture_2d< f32 >;
+@group( 0 ) @binding( 1 ) var< storage, read_write > stats : Stats;
+@group( 0 ) @binding( 2 ) var< storage, read_write > partials : array< Partial >;
+
+// `GpuStats` in `inspect.rs`. The luminances are stored as their bits, which order like the positive floats they are
+struct Stats
+{
+  min_luminance : atomic< u32 >,
+  max_luminance : atomic< u32 >,
+  nan_count : atomic< u32 >,
+  infinite_count : atomic< u32 >,
+  negative_count : atomic< u32 >,
+  // Written by `threshold_main`
+  clamp : f32,
+  histogram : array< atomic< u32 >, HISTOGRAM_BINS >
+};
+
+// Sums of the texels of one workgroup, `Partial` in `inspect.rs`
+struct Partial
+{
+  light : vec3f,
+  luminance : f32,
+  energy : f32,
+  clamped_energy : f32,
+  light_solid_angle : f32
+};
+
+// `HISTOGRAM_BINS` in `inspect.rs`: one bin per stop from 2^-16 to 2^16, and one below and above
+const HISTOGRAM_BINS : u32 = 34u;
+const LOWEST_STOP : i32 = -16;
+// `FIREFLY_FRACTION` and `LIGHT_FRACTION` in `inspect.rs`
+const FIREFLY_FRACTION : f32 = 0.0001;
+const LIGHT_FRACTION : f32 = 0.5;
+const WORKGROUP_SIZE : u32 = 16u;
+const PI : f32 = 3.14159265359;
+
+var< workgroup > shared_sums : array< vec4f, 256 >;
+var< workgroup > shared_min : atomic< u32 >;
+var< workgroup > shared_max : atomic< u32 >;
+var< workgroup > shared_histogram : array< atomic< u32 >, HISTOGRAM_BINS >;
+
+// Channels that are NaN or infinite, the exponent is all ones then
+fn special_channels( color : vec3f ) -> vec3< bool >
+{
+  return ( bitcast< vec3u >( color ) & vec3u( 0x7f800000u ) ) == vec3u( 0x7f800000u );
+}
+
+// Luminance of the texel, or -1 when it has a NaN or an infinite channel. Negative channels count as 0
+fn texel_luminance( color : vec3f ) -> f32
+{
+  if( any( special_channels( color ) ) )
+  {
+    return -1.0;
+  }
+  return dot( max( color, vec3f( 0.0 ) ), vec3f( 0.2126, 0.7152, 0.0722 ) );
+}
+
+// Mirrors `histogram_bin` in `inspect.rs`, the stop is read from the exponent so both agree exactly
+fn histogram_bin( luminance : f32 ) -> u32
+{
+  let exponent = i32( ( bitcast< u32 >( luminance ) >> 23u ) & 0xffu ) - 127;
+  if( luminance <= 0.0 )
+  {
+    return 0u;
+  }
+  return u32( clamp( exponent - LOWEST_STOP + 1, 0, i32( HISTOGRAM_BINS ) - 1 ) );
+}
+
+// Solid angle of the equirect texel and its direction, row 0 at the top
+fn texel_solid_angle( texel : vec2u, size : vec2u ) -> f32
+{
+  let elevation = ( 0.5 - ( f32( texel.y ) + 0.5 ) / f32( size.y ) ) * PI;
+  return cos( elevation ) * ( 2.0 * PI / f32( size.x ) ) * ( PI / f32( size.y ) );
+}
+
+fn texel_direction( texel : vec2u, size : vec2u ) -> vec3f
+{
+  let elevation = ( 0.5 - ( f32( texel.y ) + 0.5 ) / f32( size.y ) ) * PI;
+  let azimuth = ( ( f32( texel.x ) + 0.5 ) / f32( size.x ) * 2.0 - 1.0 ) * PI;
+  return vec3f( cos( azimuth ) * cos( elevation ), sin( elevation ), sin( azimuth ) * cos( elevation ) );
+}
+
+// Adds up `shared_sums`, the total ends up in the first element
+fn reduce_shared_sums( local : u32 )
+{
+  for( var stride = WORKGROUP_SIZE * WORKGROUP_SIZE / 2u; stride > 0u; stride /= 2u )
+  {
+    workgroupBarrier();
+    if( local < stride )
+    {
+      shared_sums[ local ] += shared_sums[ local + stride ];
+    }
+  }
+  workgroupBarrier();
+}
+
+fn partial_index( wid : vec3u, groups : vec3u ) -> u32
+{
+  return wid.y * groups.x + wid.x;
+}
+
+// Extremes, invalid texels and histogram through atomics, luminance and energy sums per workgroup
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn reduce_main
+(
+  @builtin( global_invocation_id ) gid : vec3u,
+  @builtin( local_invocation_index ) local : u32,
+  @builtin( workgroup_id ) wid : vec3u,
+  @builtin( num_workgroups ) groups : vec3u
+)
+{
+  if( local == 0u )
+  {
+    atomicStore( &shared_min, 0xffffffffu );
+    atomicStore( &shared_max, 0u );
+  }
+  if( local < HISTOGRAM_BINS )
+  {
+    atomicStore( &shared_histogram[ local ], 0u );
+  }
+  workgroupBarrier();
+
+  let size = textureDimensions( source );
+  var sums = vec4f( 0.0 );
+  if( all( gid.xy < size ) )
+  {
+    let color = textureLoad( source, gid.xy, 0 ).rgb;
+    let luminance = texel_luminance( color );
+    if( luminance < 0.0 )
+    {
+      let mantissa = ( bitcast< vec3u >( color ) & vec3u( 0x007fffffu ) ) != vec3u( 0u );
+      if( any( special_channels( color ) & mantissa ) )
+      {
+        atomicAdd( &stats.nan_count, 1u );
+      }
+      else
+      {
+        atomicAdd( &stats.infinite_count, 1u );
+      }
+    }
+    else
+    {
+      if( any( color < vec3f( 0.0 ) ) )
+      {
+        atomicAdd( &stats.negative_count, 1u );
+      }
+      if( luminance > 0.0 )
+      {
+        atomicMin( &shared_min, bitcast< u32 >( luminance ) );
+      }
+      atomicMax( &shared_max, bitcast< u32 >( luminance ) );
+      atomicAdd( &shared_histogram[ histogram_bin( luminance ) ], 1u );
+      sums = vec4f( luminance, luminance * texel_solid_angle( gid.xy, size ), 0.0, 0.0 );
+    }
+  }
+  shared_sums[ local ] = sums;
+  reduce_shared_sums( local );
+
+  if( local == 0u )
+  {
+    atomicMin( &stats.min_luminance, atomicLoad( &shared_min ) );
+    atomicMax( &stats.max_luminance, atomicLoad( &shared_max ) );
+    let index = partial_index( wid, groups );
+    partials[ index ].luminance = shared_sums[ 0 ].x;
+    partials[ index ].energy = shared_sums[ 0 ].y;
+  }
+  if( local < HISTOGRAM_BINS )
+  {
+    let count = atomicLoad( &shared_histogram[ local ] );
+    if( count > 0u )
+    {
+      atomicAdd( &stats.histogram[ local ], count );
+    }
+  }
+}
+
+// Mirrors `clamp_threshold` in `inspect.rs`: the upper edge of the highest bin with more than
+// `FIREFLY_FRACTION` of the valid texels in it or above it, and the largest luminance when there's none
+@compute @workgroup_size( 1, 1, 1 )
+fn threshold_main()
+{
+  let size = textureDimensions( source );
+  let valid = size.x * size.y - atomicLoad( &stats.nan_count ) - atomicLoad( &stats.infinite_count );
+  let allowed = u32( f32( valid ) * FIREFLY_FRACTION );
+  let max_luminance = bitcast< f32 >( atomicLoad( &stats.max_luminance ) );
+
+  var clamp_luminance = max_luminance;
+  var above = 0u;
+  for( var bin = i32( HISTOGRAM_BINS ) - 1; bin >= 0; bin-- )
+  {
+    above += atomicLoad( &stats.histogram[ bin ] );
+    if( above > allowed )
+    {
+      clamp_luminance = min( exp2( f32( bin + LOWEST_STOP ) ), max_luminance );
+      break;
+    }
+  }
+  stats.clamp = clamp_luminance;
+}
+
+// Direction and solid angle of the texels above `LIGHT_FRACTION` of the largest luminance, and the energy left with the clamp
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn light_main
+(
+  @builtin( global_invocation_id ) gid : vec3u,
+  @builtin( local_invocation_index ) local : u32,
+  @builtin( workgroup_id ) wid : vec3u,
+  @builtin( num_workgroups ) groups : vec3u
+)
+{
+  let size = textureDimensions( source );
+  let threshold = bitcast< f32 >( atomicLoad( &stats.max_luminance ) ) * LIGHT_FRACTION;
+
+  var light = vec4f( 0.0 );
+  var clamped_energy = 0.0;
+  if( all( gid.xy < size ) )
+  {
+    let luminance = texel_luminance( textureLoad( source, gid.xy, 0 ).rgb );
+    let solid_angle = texel_solid_angle( gid.xy, size );
+    if( luminance >= 0.0 )
+    {
+      clamped_energy = min( luminance, stats.clamp ) * solid_angle;
+    }
+    if( luminance > 0.0 && luminance >= threshold )
+    {
+      light = vec4f( texel_direction( gid.xy, size ) * luminance * solid_angle, solid_angle );
+    }
+  }
+
+  shared_sums[ local ] = light;
+  reduce_shared_sums( local );
+  let light_sum = shared_sums[ 0 ];
+
+  shared_sums[ local ] = vec4f( clamped_energy, 0.0, 0.0, 0.0 );
+  reduce_shared_sums( local );
+
+  if( local == 0u )
+  {
+    let index = partial_index( wid, groups );
+    partials[ index ].light = light_sum.xyz;
+    partials[ index ].light_solid_angle = light_sum.w;
+    partials[ index ].clamped_energy = shared_sums[ 0 ].x;
+  }
+}
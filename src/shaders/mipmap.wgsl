@@ -1,29 +1,226 @@
+// Downsamples the six faces of the environment cube, in the spirit of AMD's single pass downsampler.
+// With the box and solid angle filters each workgroup reduces a 32x32 tile of the source mip to
+// up to four mips through workgroup memory. The Kaiser filter reads a 4x4 footprint that crosses
+// the tiles, so it writes a single mip per dispatch.
 
+struct Uniforms
+{
+  // Mips written by this dispatch, the outputs past it are bound to a dummy texture
+  mip_count : u32
+}
+
+// 0: box, 1: Kaiser, 2: solid angle, the order of `MipFilter`
+override FILTER : u32 = 0u;
+
+const FILTER_BOX : u32 = 0u;
+const FILTER_SOLID_ANGLE : u32 = 2u;
+
+@group( 0 ) @binding( 0 ) var source : texture_2d_array< f32 >;
+@group( 0 ) @binding( 1 ) var< uniform > uniforms : Uniforms;
+@group( 0 ) @binding( 2 ) var output_1 : texture_storage_2d_array< rgba32float, write >;
+@group( 0 ) @binding( 3 ) var output_2 : texture_storage_2d_array< rgba32float, write >;
+@group( 0 ) @binding( 4 ) var output_3 : texture_storage_2d_array< rgba32float, write >;
+@group( 0 ) @binding( 5 ) var output_4 : texture_storage_2d_array< rgba32float, write >;
+
+const WORKGROUP_SIZE : u32 = 8u;
+// Texels of the first output covered by a workgroup along each axis
+const TILE_SIZE : u32 = 16u;
 
-struct VertexOutput
+// Sums of the weighted colors in rgb and of the weights in w. `tile` holds the first and
+// third outputs of the tile, `quarter_tile` the second.
+var< workgroup > tile : array< vec4f, 256 >;
+var< workgroup > quarter_tile : array< vec4f, 64 >;
+
+// Solid angle of a texel relative to the one at the center of the face, which is
+// proportional to ( 1 + u² + v² )^-3/2 with u and v in -1..1
+fn texel_weight( texel : vec2u, size : vec2u ) -> f32
 {
-  @builtin( position ) pos : vec4f,
-  @location( 0 ) uv : vec2f,
+  if( FILTER != FILTER_SOLID_ANGLE )
+  {
+    return 1.0;
+  }
+  let uv = ( vec2f( texel ) + 0.5 ) / vec2f( size ) * 2.0 - 1.0;
+  let d = 1.0 + dot( uv, uv );
+  return 1.0 / ( d * sqrt( d ) );
 }
 
-@vertex
-fn vertex_main( @builtin( vertex_index ) id : u32 ) -> VertexOutput
+// Direction through `st` in -1..1 on `face`, unnormalized. Same orientation as `cube_direction` in `ibl.wgsl`,
+// which is the one of the cube sampler.
+fn face_direction( face : u32, st : vec2f ) -> vec3f
 {
-  let x = f32( id / 2 );
-  let y = f32( id % 2 );
+  switch face
+  {
+    case 0u { return vec3f( 1.0, -st.y, -st.x ); }
+    case 1u { return vec3f( -1.0, -st.y, st.x ); }
+    case 2u { return vec3f( st.x, 1.0, st.y ); }
+    case 3u { return vec3f( st.x, -1.0, -st.y ); }
+    case 4u { return vec3f( st.x, -st.y, 1.0 ); }
+    default { return vec3f( -st.x, -st.y, -1.0 ); }
+  }
+}
 
-  var result : VertexOutput;
-  result.pos = vec4f( vec2f( x * 4.0 - 1.0, 1.0 - y * 4.0 ), 1.0, 1.0 );
-  result.uv = vec2f( x, y ) * 2.0;
+struct FaceCoord
+{
+  face : u32,
+  st : vec2f
+}
 
-  return result;
+// Face of the major axis of `dir` and the coordinates of `dir` on it. Inverse of `face_direction`.
+fn direction_face( dir : vec3f ) -> FaceCoord
+{
+  let a = abs( dir );
+  if( a.x >= a.y && a.x >= a.z )
+  {
+    if( dir.x > 0.0 ) { return FaceCoord( 0u, vec2f( -dir.z, -dir.y ) / a.x ); }
+    return FaceCoord( 1u, vec2f( dir.z, -dir.y ) / a.x );
+  }
+  if( a.y >= a.z )
+  {
+    if( dir.y > 0.0 ) { return FaceCoord( 2u, vec2f( dir.x, dir.z ) / a.y ); }
+    return FaceCoord( 3u, vec2f( dir.x, -dir.z ) / a.y );
+  }
+  if( dir.z > 0.0 ) { return FaceCoord( 4u, vec2f( dir.x, -dir.y ) / a.z ); }
+  return FaceCoord( 5u, vec2f( -dir.x, -dir.y ) / a.z );
 }
 
-@group( 0 ) @binding( 0 ) var t : texture_2d< f32 >;
-@group( 0 ) @binding( 1 ) var s : sampler;
+// Texel of the source mip, continued on the neighboring face when it lies past an edge of `face`.
+// This lets footprints that cross an edge be filtered with the texels the cube sampler blends with.
+fn fetch( texel : vec2i, face : u32, size : vec2u ) -> vec3f
+{
+  if( all( texel >= vec2i( 0 ) ) && all( texel < vec2i( size ) ) )
+  {
+    return textureLoad( source, texel, face, 0 ).rgb;
+  }
+
+  let st = ( vec2f( texel ) + 0.5 ) / vec2f( size ) * 2.0 - 1.0;
+  let neighbor = direction_face( face_direction( face, st ) );
+  let neighbor_texel = vec2i( floor( ( neighbor.st * 0.5 + 0.5 ) * vec2f( size ) ) );
+  return textureLoad( source, clamp( neighbor_texel, vec2i( 0 ), vec2i( size ) - 1 ), neighbor.face, 0 ).rgb;
+}
+
+fn load_weighted( texel : vec2u, face : u32, size : vec2u ) -> vec4f
+{
+  let weight = texel_weight( min( texel, size - 1u ), size );
+  return vec4f( fetch( vec2i( texel ), face, size ) * weight, weight );
+}
 
-@fragment
-fn fragment_main( in : VertexOutput ) -> @location( 0 ) vec4f
+fn resolve( sum : vec4f ) -> vec4f
 {
-  return textureSample( t, s, in.uv );
-}
\ No newline at end of file
+  return vec4f( sum.rgb / sum.w, 1.0 );
+}
+
+fn store( level : u32, texel : vec2u, face : u32, sum : vec4f )
+{
+  switch( level )
+  {
+    case 0u:
+    {
+      if( all( texel < textureDimensions( output_1 ) ) ) { textureStore( output_1, texel, face, resolve( sum ) ); }
+    }
+    case 1u:
+    {
+      if( all( texel < textureDimensions( output_2 ) ) ) { textureStore( output_2, texel, face, resolve( sum ) ); }
+    }
+    case 2u:
+    {
+      if( all( texel < textureDimensions( output_3 ) ) ) { textureStore( output_3, texel, face, resolve( sum ) ); }
+    }
+    default:
+    {
+      if( all( texel < textureDimensions( output_4 ) ) ) { textureStore( output_4, texel, face, resolve( sum ) ); }
+    }
+  }
+}
+
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn downsample_main( @builtin( workgroup_id ) group : vec3u, @builtin( local_invocation_id ) local : vec3u )
+{
+  let face = group.z;
+  let size = textureDimensions( source );
+
+  // First output: every invocation reduces four 2x2 blocks of the source
+  for( var i = 0u; i < 4u; i += 1u )
+  {
+    let tile_texel = local.xy * 2u + vec2u( i & 1u, i >> 1u );
+    let texel = group.xy * TILE_SIZE + tile_texel;
+    let sum = load_weighted( texel * 2u, face, size )
+    + load_weighted( texel * 2u + vec2u( 1u, 0u ), face, size )
+    + load_weighted( texel * 2u + vec2u( 0u, 1u ), face, size )
+    + load_weighted( texel * 2u + vec2u( 1u, 1u ), face, size );
+
+    store( 0u, texel, face, sum );
+    tile[ tile_texel.y * TILE_SIZE + tile_texel.x ] = sum;
+  }
+
+  if( uniforms.mip_count < 2u )
+  {
+    return;
+  }
+  workgroupBarrier();
+
+  // Second output: one texel per invocation
+  {
+    let t = local.xy * 2u;
+    let sum = tile[ t.y * TILE_SIZE + t.x ] + tile[ t.y * TILE_SIZE + t.x + 1u ]
+    + tile[ ( t.y + 1u ) * TILE_SIZE + t.x ] + tile[ ( t.y + 1u ) * TILE_SIZE + t.x + 1u ];
+
+    store( 1u, group.xy * ( TILE_SIZE / 2u ) + local.xy, face, sum );
+    quarter_tile[ local.y * 8u + local.x ] = sum;
+  }
+
+  if( uniforms.mip_count < 3u )
+  {
+    return;
+  }
+  workgroupBarrier();
+
+  // Third output: the first 4x4 invocations
+  if( all( local.xy < vec2u( 4u ) ) )
+  {
+    let t = local.xy * 2u;
+    let sum = quarter_tile[ t.y * 8u + t.x ] + quarter_tile[ t.y * 8u + t.x + 1u ]
+    + quarter_tile[ ( t.y + 1u ) * 8u + t.x ] + quarter_tile[ ( t.y + 1u ) * 8u + t.x + 1u ];
+
+    store( 2u, group.xy * ( TILE_SIZE / 4u ) + local.xy, face, sum );
+    tile[ local.y * 4u + local.x ] = sum;
+  }
+
+  if( uniforms.mip_count < 4u )
+  {
+    return;
+  }
+  workgroupBarrier();
+
+  // Fourth output: the first 2x2 invocations
+  if( all( local.xy < vec2u( 2u ) ) )
+  {
+    let t = local.xy * 2u;
+    let sum = tile[ t.y * 4u + t.x ] + tile[ t.y * 4u + t.x + 1u ]
+    + tile[ ( t.y + 1u ) * 4u + t.x ] + tile[ ( t.y + 1u ) * 4u + t.x + 1u ];
+
+    store( 3u, group.xy * ( TILE_SIZE / 8u ) + local.xy, face, sum );
+  }
+}
+
+// Kaiser windowed sinc with a radius of two source texels and alpha = 4, sampled at the
+// centers of the source texels at 0.5 and 1.5 from the destination texel, normalized
+const KAISER_WEIGHTS = array< f32, 4 >( 0.054027, 0.445973, 0.445973, 0.054027 );
+
+@compute @workgroup_size( WORKGROUP_SIZE, WORKGROUP_SIZE, 1 )
+fn downsample_kaiser_main( @builtin( global_invocation_id ) gid : vec3u )
+{
+  let face = gid.z;
+  let size = textureDimensions( source );
+  let origin = vec2i( gid.xy * 2u ) - 1;
+
+  var sum = vec3f( 0.0 );
+  for( var y = 0; y < 4; y += 1 )
+  {
+    for( var x = 0; x < 4; x += 1 )
+    {
+      sum += fetch( origin + vec2i( x, y ), face, size ) * KAISER_WEIGHTS[ x ] * KAISER_WEIGHTS[ y ];
+    }
+  }
+
+  store( 0u, gid.xy, face, vec4f( sum, 1.0 ) );
+}
@@ -0,0 +1,203 @@
+@group( 0 ) @binding( 0 ) var dst : texture_storage_2d_array< rgba32float, write >;
+@group( 0 ) @binding( 1 ) var< uniform > sky : Sky;
+
+// `SkyUniform` in `sky.rs`, which documents the coefficients of every model
+struct Sky
+{
+  sun_direction : vec3f,
+  model : u32,
+  ground_albedo : f32,
+  coefficients : array< vec4f, 8 >
+};
+
+struct Face
+{
+  forward: vec3<f32>,
+  up: vec3<f32>,
+  right: vec3<f32>,
+};
+
+// The order of `SkyModel`
+const MODEL_PREETHAM : u32 = 0u;
+const MODEL_HOSEK_WILKIE : u32 = 1u;
+
+@compute @workgroup_size(16, 16, 1)
+fn main( @builtin( global_invocation_id ) gid: vec3< u32 > )
+{
+  if gid.x >= u32( textureDimensions( dst ).x )
+  {
+    return;
+  }
+
+  let size = vec2f( textureDimensions( dst ) );
+  let face_dir = texel_direction( gid.z, ( vec2f( gid.xy ) + 0.5 ) / size );
+  // The faces are written with Y flipped, up is +Y once the cube is sampled
+  let dir = vec3f( face_dir.x, -face_dir.y, face_dir.z );
+  // Angle covered by a texel at the center of a face
+  let texel_angle = 2.0 / size.x;
+
+  var radiance : vec3f;
+  if dir.y >= 0.0
+  {
+    radiance = sky_radiance( dir, texel_angle, true );
+  }
+  else
+  {
+    // The ground reflects its albedo of the sky right above the horizon
+    let horizontal = vec3f( dir.x, 0.0, dir.z );
+    let horizon = select( vec3f( 1.0, 0.0, 0.0 ), normalize( horizontal ), length( horizontal ) > 1e-4 );
+    radiance = sky.ground_albedo * sky_radiance( horizon, texel_angle, false );
+  }
+
+  textureStore( dst, gid.xy, gid.z, vec4f( max( radiance, vec3f( 0.0 ) ), 1.0 ) );
+}
+
+fn coefficient( index : u32 ) -> f32
+{
+  return sky.coefficients[ index / 4u ][ index % 4u ];
+}
+
+fn coefficient3( index : u32 ) -> vec3f
+{
+  return vec3f( coefficient( index ), coefficient( index + 1u ), coefficient( index + 2u ) );
+}
+
+// Radiance of the sky in `dir`, which is above the horizon. `sun` adds the sun disk of the gradient.
+// `SkyUniform::radiance` in `sky.rs` mirrors it for the CPU baker
+fn sky_radiance( dir : vec3f, texel_angle : f32, sun : bool ) -> vec3f
+{
+  let cos_theta = max( dir.y, 0.0 );
+  let cos_gamma = clamp( dot( dir, sky.sun_direction ), -1.0, 1.0 );
+  // Stays accurate for the small angles of the sun disk
+  let gamma = atan2( length( cross( dir, sky.sun_direction ) ), dot( dir, sky.sun_direction ) );
+
+  switch sky.model
+  {
+    case MODEL_PREETHAM
+    {
+      return preetham( cos_theta, gamma, cos_gamma );
+    }
+    case MODEL_HOSEK_WILKIE
+    {
+      return vec3f( hosek_wilkie( 0u, cos_theta, gamma, cos_gamma ), hosek_wilkie( 1u, cos_theta, gamma, cos_gamma ), hosek_wilkie( 2u, cos_theta, gamma, cos_gamma ) );
+    }
+    default
+    {
+      return gradient( cos_theta, gamma, cos_gamma, texel_angle, sun );
+    }
+  }
+}
+
+// Perez function of the coefficients starting at `first`
+fn perez( first : u32, cos_theta : f32, gamma : f32, cos_gamma : f32 ) -> f32
+{
+  let a = coefficient( first );
+  let b = coefficient( first + 1u );
+  let c = coefficient( first + 2u );
+  let d = coefficient( first + 3u );
+  let e = coefficient( first + 4u );
+  return ( 1.0 + a * exp( b / max( cos_theta, 1e-3 ) ) ) * ( 1.0 + c * exp( d * gamma ) + e * cos_gamma * cos_gamma );
+}
+
+fn preetham( cos_theta : f32, gamma : f32, cos_gamma : f32 ) -> vec3f
+{
+  let luminance = coefficient( 15u ) * perez( 0u, cos_theta, gamma, cos_gamma );
+  let x = coefficient( 16u ) * perez( 5u, cos_theta, gamma, cos_gamma );
+  let y = coefficient( 17u ) * perez( 10u, cos_theta, gamma, cos_gamma );
+
+  // Yxy to XYZ, then to linear sRGB
+  let xyz = vec3f( x / y, 1.0, ( 1.0 - x - y ) / y ) * luminance;
+  let xyz_to_rgb = mat3x3f
+  (
+    vec3f( 3.2406, -0.9689, 0.0557 ),
+    vec3f( -1.5372, 1.8758, -0.2040 ),
+    vec3f( -0.4986, 0.0415, 1.0570 )
+  );
+  return xyz_to_rgb * xyz;
+}
+
+// `ArHosekSkyModel_GetRadianceInternal` of the reference implementation for one channel
+fn hosek_wilkie( channel : u32, cos_theta : f32, gamma : f32, cos_gamma : f32 ) -> f32
+{
+  let first = channel * 9u;
+  let exp_m = exp( coefficient( first + 4u ) * gamma );
+  let ray_m = cos_gamma * cos_gamma;
+  let g = coefficient( first + 8u );
+  let mie_m = ( 1.0 + cos_gamma * cos_gamma ) / pow( 1.0 + g * g - 2.0 * g * cos_gamma, 1.5 );
+  let zenith = sqrt( cos_theta );
+
+  let value = ( 1.0 + coefficient( first ) * exp( coefficient( first + 1u ) / ( cos_theta + 0.01 ) ) )
+  * ( coefficient( first + 2u ) + coefficient( first + 3u ) * exp_m + coefficient( first + 5u ) * ray_m + coefficient( first + 6u ) * mie_m + coefficient( first + 7u ) * zenith );
+  return value * coefficient( 27u + channel );
+}
+
+fn gradient( cos_theta : f32, gamma : f32, cos_gamma : f32, texel_angle : f32, sun : bool ) -> vec3f
+{
+  var radiance = mix( coefficient3( 3u ), coefficient3( 0u ), sqrt( cos_theta ) );
+  radiance += coefficient3( 11u ) * pow( max( cos_gamma, 0.0 ), coefficient( 10u ) );
+  if sun
+  {
+    // A disk smaller than a texel is widened to it, keeping its irradiance, and its edge is smoothed over a texel
+    let sun_radius = coefficient( 9u );
+    let radius = max( sun_radius, texel_angle );
+    let scale = ( 1.0 - cos( sun_radius ) ) / ( 1.0 - cos( radius ) );
+    let coverage = clamp( ( radius - gamma ) / texel_angle + 0.5, 0.0, 1.0 );
+    radiance += coefficient3( 6u ) * scale * coverage;
+  }
+  return radiance;
+}
+
+// Direction through `face_uv` in 0..1 on `face`, the same as in `cube_map.wgsl`
+fn texel_direction( face_index : u32, face_uv : vec2f ) -> vec3f
+{
+  var faces : array< Face, 6 > = array
+  (
+    // +X
+    Face
+    (
+      vec3f( 1.0, 0.0, 0.0 ),
+      vec3f( 0.0, 0.0, -1.0 ),
+      vec3f( 0.0, 1.0, 0.0 )
+    ),
+    // -X
+    Face
+    (
+      vec3f( -1.0, 0.0, 0.0 ),
+      vec3f( 0.0, 0.0, 1.0 ),
+      vec3f( 0.0, 1.0, 0.0 )
+    ),
+    // +Y
+    Face
+    (
+      vec3f( 0.0, -1.0, 0.0 ),
+      vec3f( 1.0, 0.0, 0.0 ),
+      vec3f( 0.0, 0.0, 1.0 )
+    ),
+    // -Y
+    Face
+    (
+      vec3f( 0.0, 1.0, 0.0 ),
+      vec3f( 1.0, 0.0, 0.0 ),
+      vec3f( 0.0, 0.0, -1.0 )
+    ),
+    // +Z
+    Face
+    (
+      vec3f( 0.0, 0.0, 1.0 ),
+      vec3f( 1.0, 0.0, 0.0 ),
+      vec3f( 0.0, 1.0, 0.0 )
+    ),
+    // -Z
+    Face
+    (
+      vec3f( 0.0, 0.0, -1.0 ),
+      vec3f( -1.0, 0.0, 0.0 ),
+      vec3f( 0.0, 1.0, 0.0 )
+    )
+  );
+
+  let uv2 = face_uv * 2.0 - vec2f( 1.0 );
+  let face = faces[ face_index ];
+  let rot_mat = mat3x3< f32 >( face.forward, face.up, face.right );
+  return normalize( rot_mat * vec3f( 1.0, uv2 ) );
+}
@@ -0,0 +1,555 @@
+use std::{f64::consts::{FRAC_PI_2, PI}, path::PathBuf};
+
+use anyhow::{bail, Context};
+use glam::{Mat3, Vec3};
+
+use crate::baker::SourceImage;
+
+/// Size of the equirect a sky is rendered to on the CPU, for `inspect` and the SH of the glTF light.
+pub const SKY_EQUIRECT_SIZE : ( u32, u32 ) = ( 2048, 1024 );
+
+/// Analytic sky the environment cube can be rendered from instead of an image.
+/// The order matches `MODEL` in `sky.wgsl`.
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum SkyModel
+{
+  /// Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight", 1999
+  Preetham,
+  /// Hosek and Wilkie, "An Analytic Model for Full Spectral Sky-Dome Radiance", 2012, RGB version
+  HosekWilkie,
+  /// Blend from the horizon to the zenith color, with a sun disk and its glow
+  Gradient
+}
+
+impl SkyModel
+{
+  pub fn name( self ) -> &'static str
+  {
+    match self
+    {
+      Self::Preetham => "preetham",
+      Self::HosekWilkie => "hosek-wilkie",
+      Self::Gradient => "gradient"
+    }
+  }
+}
+
+/// Sun positions of a day. The sun rises at -90° of azimuth, culminates at 0° and sets at 90°.
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum TimeOfDay
+{
+  Sunrise,
+  Morning,
+  Noon,
+  Afternoon,
+  Sunset
+}
+
+impl TimeOfDay
+{
+  pub fn name( self ) -> &'static str
+  {
+    match self
+    {
+      Self::Sunrise => "sunrise",
+      Self::Morning => "morning",
+      Self::Noon => "noon",
+      Self::Afternoon => "afternoon",
+      Self::Sunset => "sunset"
+    }
+  }
+
+  /// Elevation and azimuth of the sun, in degrees.
+  pub fn sun( self ) -> ( f32, f32 )
+  {
+    match self
+    {
+      Self::Sunrise => ( 2.0, -90.0 ),
+      Self::Morning => ( 25.0, -60.0 ),
+      Self::Noon => ( 65.0, 0.0 ),
+      Self::Afternoon => ( 35.0, 50.0 ),
+      Self::Sunset => ( 2.0, 90.0 )
+    }
+  }
+}
+
+/// Parameters of a sky source.
+#[ derive( Clone, Debug, PartialEq ) ]
+pub struct SkySettings
+{
+  pub model : SkyModel,
+  /// Degrees above the horizon. Preetham and Hosek-Wilkie only hold for a sun above the horizon,
+  /// lower ones are clamped to it
+  pub sun_elevation : f32,
+  /// Degrees around the up axis, 0 is the center of an equirect and 90 a quarter of it to the right
+  pub sun_azimuth : f32,
+  /// Haze of the atmosphere, from 1 for a clear sky to 10
+  pub turbidity : f32,
+  /// Reflectance of the ground below the horizon
+  pub ground_albedo : f32,
+  /// `ArHosekSkyModelData_RGB.h` of the reference implementation, which holds the coefficients
+  /// of Hosek-Wilkie. The tables don't ship with the tool, so they are read from this file
+  pub hosek_data : Option< PathBuf >
+}
+
+impl Default for SkySettings
+{
+  fn default() -> Self
+  {
+    Self
+    {
+      model : SkyModel::Preetham,
+      sun_elevation : 45.0,
+      sun_azimuth : 0.0,
+      turbidity : 3.0,
+      ground_albedo : 0.3,
+      hosek_data : None
+    }
+  }
+}
+
+/// The `Sky` uniform of `sky.wgsl`. What `coefficients` holds depends on the model:
+///
+/// - Preetham: the Perez coefficients A to E of the luminance, then of the x and y chromaticities,
+///   then the zenith luminance and chromaticities divided by the Perez function at the zenith
+/// - Hosek-Wilkie: the nine coefficients of the red, green and blue channels, then the radiance of each
+/// - Gradient: the zenith and horizon colors, the sun radiance, its angular radius, the exponent of
+///   its glow and the color of the glow
+#[ repr( C ) ]
+#[ derive( Clone, Copy, Debug, Default, bytemuck::NoUninit ) ]
+pub struct SkyUniform
+{
+  sun_direction : [ f32; 3 ],
+  model : u32,
+  ground_albedo : f32,
+  padding : [ f32; 3 ],
+  coefficients : [ f32; 32 ]
+}
+
+impl SkySettings
+{
+  /// Name of the sky in reports, in place of a file name.
+  pub fn name( &self ) -> String
+  {
+    format!( "sky:{}", self.model.name() )
+  }
+
+  /// Evaluates the coefficients of the model for the sun position, reading the Hosek-Wilkie data when needed.
+  pub fn uniform( &self ) -> anyhow::Result< SkyUniform >
+  {
+    let elevation = ( self.sun_elevation as f64 ).to_radians();
+    let azimuth = ( self.sun_azimuth as f64 ).to_radians();
+    let turbidity = self.turbidity as f64;
+    let mut uniform = SkyUniform
+    {
+      // Y is up, like the directions the cube is sampled with
+      sun_direction : [ elevation.cos() * azimuth.cos(), elevation.sin(), elevation.cos() * azimuth.sin() ].map( | v | v as f32 ),
+      model : self.model as u32,
+      ground_albedo : self.ground_albedo,
+      ..Default::default()
+    };
+
+    let coefficients = match self.model
+    {
+      SkyModel::Preetham => preetham( elevation.max( 0.0 ), turbidity ),
+      SkyModel::HosekWilkie =>
+      {
+        let path = self.hosek_data.as_ref().context( "The Hosek-Wilkie sky needs --hosek-data" )?;
+        let source = std::fs::read_to_string( path ).with_context( || format!( "Failed to open {}", path.display() ) )?;
+        let data = HosekData::parse( &source ).with_context( || format!( "Failed to parse {}", path.display() ) )?;
+        data.coefficients( elevation.max( 0.0 ), turbidity, self.ground_albedo as f64 )
+      },
+      SkyModel::Gradient => gradient( elevation, turbidity )
+    };
+    for ( dst, src ) in uniform.coefficients.iter_mut().zip( coefficients )
+    {
+      *dst = src as f32;
+    }
+    Ok( uniform )
+  }
+}
+
+impl SkyUniform
+{
+  /// Radiance in `dir`, with Y up, as `main` in `sky.wgsl` renders it to a texel covering `texel_angle`.
+  pub fn radiance( &self, dir : Vec3, texel_angle : f32 ) -> Vec3
+  {
+    let radiance = if dir.y >= 0.0
+    {
+      self.sky_radiance( dir, texel_angle, true )
+    }
+    else
+    {
+      // The ground reflects its albedo of the sky right above the horizon
+      let horizontal = Vec3::new( dir.x, 0.0, dir.z );
+      let horizon = if horizontal.length() > 1e-4 { horizontal.normalize() } else { Vec3::X };
+      self.ground_albedo * self.sky_radiance( horizon, texel_angle, false )
+    };
+    radiance.max( Vec3::ZERO )
+  }
+
+  /// Equirect of the sky, row 0 looking up and the center looking along +X like the images the tool takes.
+  pub fn render_equirect( &self, width : u32, height : u32 ) -> SourceImage
+  {
+    let texel_angle = 2.0 * std::f32::consts::PI / width as f32;
+    let pixels = ( 0..width * height ).flat_map( | i |
+    {
+      let elevation = ( 0.5 - ( ( i / width ) as f32 + 0.5 ) / height as f32 ) * std::f32::consts::PI;
+      let azimuth = ( ( ( i % width ) as f32 + 0.5 ) / width as f32 * 2.0 - 1.0 ) * std::f32::consts::PI;
+      let dir = Vec3::new( azimuth.cos() * elevation.cos(), elevation.sin(), azimuth.sin() * elevation.cos() );
+      let [ r, g, b ] = self.radiance( dir, texel_angle ).to_array();
+      [ r, g, b, 1.0 ]
+    })
+    .collect();
+    SourceImage { width, height, pixels }
+  }
+
+  fn coefficient3( &self, index : usize ) -> Vec3
+  {
+    Vec3::from_slice( &self.coefficients[ index..index + 3 ] )
+  }
+
+  /// `sky_radiance` of `sky.wgsl`, `dir` is above the horizon.
+  fn sky_radiance( &self, dir : Vec3, texel_angle : f32, sun : bool ) -> Vec3
+  {
+    let sun_direction = Vec3::from_array( self.sun_direction );
+    let cos_theta = dir.y.max( 0.0 );
+    let cos_gamma = dir.dot( sun_direction ).clamp( -1.0, 1.0 );
+    let gamma = dir.cross( sun_direction ).length().atan2( dir.dot( sun_direction ) );
+
+    match self.model
+    {
+      model if model == SkyModel::Preetham as u32 => self.preetham_radiance( cos_theta, gamma, cos_gamma ),
+      model if model == SkyModel::HosekWilkie as u32 => Vec3::from_array( [ 0, 1, 2 ].map( | channel | self.hosek_wilkie_radiance( channel, cos_theta, gamma, cos_gamma ) ) ),
+      _ => self.gradient_radiance( cos_theta, gamma, cos_gamma, texel_angle, sun )
+    }
+  }
+
+  fn perez_distribution( &self, first : usize, cos_theta : f32, gamma : f32, cos_gamma : f32 ) -> f32
+  {
+    let [ a, b, c, d, e ] = std::array::from_fn( | i | self.coefficients[ first + i ] );
+    ( 1.0 + a * ( b / cos_theta.max( 1e-3 ) ).exp() ) * ( 1.0 + c * ( d * gamma ).exp() + e * cos_gamma * cos_gamma )
+  }
+
+  fn preetham_radiance( &self, cos_theta : f32, gamma : f32, cos_gamma : f32 ) -> Vec3
+  {
+    let luminance = self.coefficients[ 15 ] * self.perez_distribution( 0, cos_theta, gamma, cos_gamma );
+    let x = self.coefficients[ 16 ] * self.perez_distribution( 5, cos_theta, gamma, cos_gamma );
+    let y = self.coefficients[ 17 ] * self.perez_distribution( 10, cos_theta, gamma, cos_gamma );
+
+    // Yxy to XYZ, then to linear sRGB
+    let xyz = Vec3::new( x / y, 1.0, ( 1.0 - x - y ) / y ) * luminance;
+    let xyz_to_rgb = Mat3::from_cols
+    (
+      Vec3::new( 3.2406, -0.9689, 0.0557 ),
+      Vec3::new( -1.5372, 1.8758, -0.2040 ),
+      Vec3::new( -0.4986, 0.0415, 1.0570 )
+    );
+    xyz_to_rgb * xyz
+  }
+
+  fn hosek_wilkie_radiance( &self, channel : usize, cos_theta : f32, gamma : f32, cos_gamma : f32 ) -> f32
+  {
+    let c = &self.coefficients[ channel * 9..][ ..9 ];
+    let exp_m = ( c[ 4 ] * gamma ).exp();
+    let ray_m = cos_gamma * cos_gamma;
+    let g = c[ 8 ];
+    let mie_m = ( 1.0 + cos_gamma * cos_gamma ) / ( 1.0 + g * g - 2.0 * g * cos_gamma ).powf( 1.5 );
+    let zenith = cos_theta.sqrt();
+
+    let value = ( 1.0 + c[ 0 ] * ( c[ 1 ] / ( cos_theta + 0.01 ) ).exp() ) * ( c[ 2 ] + c[ 3 ] * exp_m + c[ 5 ] * ray_m + c[ 6 ] * mie_m + c[ 7 ] * zenith );
+    value * self.coefficients[ 27 + channel ]
+  }
+
+  fn gradient_radiance( &self, cos_theta : f32, gamma : f32, cos_gamma : f32, texel_angle : f32, sun : bool ) -> Vec3
+  {
+    let mut radiance = self.coefficient3( 3 ).lerp( self.coefficient3( 0 ), cos_theta.sqrt() );
+    radiance += self.coefficient3( 11 ) * cos_gamma.max( 0.0 ).powf( self.coefficients[ 10 ] );
+    if sun
+    {
+      // A disk smaller than a texel is widened to it, keeping its irradiance, and its edge is smoothed over a texel
+      let sun_radius = self.coefficients[ 9 ];
+      let radius = sun_radius.max( texel_angle );
+      let scale = ( 1.0 - sun_radius.cos() ) / ( 1.0 - radius.cos() );
+      let coverage = ( ( radius - gamma ) / texel_angle + 0.5 ).clamp( 0.0, 1.0 );
+      radiance += self.coefficient3( 6 ) * scale * coverage;
+    }
+    radiance
+  }
+}
+
+/// Perez function, the relative sky distribution of Preetham, at zenith angle `theta` and angle `gamma` from the sun.
+fn perez( [ a, b, c, d, e ] : [ f64; 5 ], theta : f64, gamma : f64 ) -> f64
+{
+  ( 1.0 + a * ( b / theta.cos() ).exp() ) * ( 1.0 + c * ( d * gamma ).exp() + e * gamma.cos().powi( 2 ) )
+}
+
+fn preetham( elevation : f64, turbidity : f64 ) -> Vec< f64 >
+{
+  let t = turbidity;
+  let luminance = [ 0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703 ];
+  let x = [ -0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452 ];
+  let y = [ -0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529 ];
+
+  // Zenith luminance in kcd/m² and chromaticities, from the zenith angle of the sun
+  let sun_theta = FRAC_PI_2 - elevation;
+  let chi = ( 4.0 / 9.0 - t / 120.0 ) * ( PI - 2.0 * sun_theta );
+  let zenith_luminance = ( 4.0453 * t - 4.9710 ) * chi.tan() - 0.2155 * t + 2.4192;
+  let polynomial = | [ a, b, c ] : [ [ f64; 4 ]; 3 ] |
+  {
+    let theta = [ sun_theta.powi( 3 ), sun_theta.powi( 2 ), sun_theta, 1.0 ];
+    let dot = | row : [ f64; 4 ] | row.iter().zip( theta ).map( | ( r, t ) | r * t ).sum::< f64 >();
+    t * t * dot( a ) + t * dot( b ) + dot( c )
+  };
+  let zenith_x = polynomial
+  ([
+    [ 0.00166, -0.00375, 0.00209, 0.0 ],
+    [ -0.02903, 0.06377, -0.03202, 0.00394 ],
+    [ 0.11693, -0.21196, 0.06052, 0.25886 ]
+  ]);
+  let zenith_y = polynomial
+  ([
+    [ 0.00275, -0.00610, 0.00317, 0.0 ],
+    [ -0.04214, 0.08970, -0.04153, 0.00516 ],
+    [ 0.15346, -0.26756, 0.06670, 0.26688 ]
+  ]);
+
+  let mut coefficients = [ luminance, x, y ].concat();
+  coefficients.extend
+  (
+    [ ( luminance, zenith_luminance ), ( x, zenith_x ), ( y, zenith_y ) ]
+    .map( | ( perez_coefficients, zenith ) | zenith / perez( perez_coefficients, 0.0, sun_theta ) )
+  );
+  coefficients
+}
+
+/// Hosek-Wilkie RGB coefficients of `ArHosekSkyModelData_RGB.h`, for each channel.
+struct HosekData
+{
+  /// Nine coefficients for 6 control points of the solar elevation, 10 turbidities and 2 albedos
+  configurations : [ Vec< f64 >; 3 ],
+  /// Radiance for 6 control points of the solar elevation, 10 turbidities and 2 albedos
+  radiances : [ Vec< f64 >; 3 ]
+}
+
+impl HosekData
+{
+  fn parse( source : &str ) -> anyhow::Result< Self >
+  {
+    let array = | name : &str, len : usize | -> anyhow::Result< Vec< f64 > >
+    {
+      let values = parse_c_array( source, name )?;
+      if values.len() != len
+      {
+        bail!( "Expected {} values in {}, found {}", len, name, values.len() );
+      }
+      Ok( values )
+    };
+
+    Ok( Self
+    {
+      configurations : [ array( "datasetRGB1", 9 * 6 * 10 * 2 )?, array( "datasetRGB2", 9 * 6 * 10 * 2 )?, array( "datasetRGB3", 9 * 6 * 10 * 2 )? ],
+      radiances : [ array( "datasetRGBRad1", 6 * 10 * 2 )?, array( "datasetRGBRad2", 6 * 10 * 2 )?, array( "datasetRGBRad3", 6 * 10 * 2 )? ]
+    })
+  }
+
+  /// Coefficients of every channel followed by their radiances, interpolated like `arhosek_rgb_skymodelstate_alloc_init`.
+  fn coefficients( &self, elevation : f64, turbidity : f64, albedo : f64 ) -> Vec< f64 >
+  {
+    let mut coefficients = self.configurations.iter().flat_map( | data | hosek_cook( data, 9, elevation, turbidity, albedo ) ).collect::< Vec< _ > >();
+    coefficients.extend( self.radiances.iter().flat_map( | data | hosek_cook( data, 1, elevation, turbidity, albedo ) ) );
+    coefficients
+  }
+}
+
+/// Interpolates `count` values of `data` between the quintic Bézier control points of the solar elevation,
+/// then linearly between the two nearest turbidities and the two albedos.
+fn hosek_cook( data : &[ f64 ], count : usize, elevation : f64, turbidity : f64, albedo : f64 ) -> Vec< f64 >
+{
+  let turbidity = turbidity.clamp( 1.0, 10.0 );
+  let low = turbidity.floor() as usize;
+  let remainder = turbidity - low as f64;
+  let s = ( elevation / FRAC_PI_2 ).clamp( 0.0, 1.0 ).cbrt();
+  let bernstein = [ 1.0, 5.0, 10.0, 10.0, 5.0, 1.0 ].into_iter().enumerate().map( | ( i, binomial ) | binomial * ( 1.0 - s ).powi( 5 - i as i32 ) * s.powi( i as i32 ) ).collect::< Vec< _ > >();
+
+  let block = count * 6;
+  let mut values = vec![ 0.0; count ];
+  for ( albedo_weight, albedo_offset ) in [ ( 1.0 - albedo, 0 ), ( albedo, block * 10 ) ]
+  {
+    for ( turbidity_weight, index ) in [ ( 1.0 - remainder, low - 1 ), ( remainder, low ) ]
+    {
+      if index >= 10
+      {
+        continue;
+      }
+      let control_points = &data[ albedo_offset + block * index..][ ..block ];
+      for ( i, value ) in values.iter_mut().enumerate()
+      {
+        *value += albedo_weight * turbidity_weight * bernstein.iter().enumerate().map( | ( point, b ) | b * control_points[ point * count + i ] ).sum::< f64 >();
+      }
+    }
+  }
+  values
+}
+
+/// Numbers between the braces of the C array `name[]`, comments are skipped.
+fn parse_c_array( source : &str, name : &str ) -> anyhow::Result< Vec< f64 > >
+{
+  let start = source.find( &format!( "{}[]", name ) ).with_context( || format!( "Missing array {}", name ) )?;
+  let body = &source[ start.. ];
+  let ( Some( open ), Some( close ) ) = ( body.find( '{' ), body.find( '}' ) ) else { bail!( "Unterminated array {}", name ) };
+
+  let mut text = String::new();
+  let mut rest = &body[ open + 1..close ];
+  while let Some( comment ) = rest.find( "/*" )
+  {
+    text.push_str( &rest[ ..comment ] );
+    rest = rest[ comment..].split_once( "*/" ).map_or( "", | ( _, after ) | after );
+  }
+  text.push_str( rest );
+
+  text.lines()
+  .flat_map( | line | line.split( "//" ).next().unwrap_or_default().split( ',' ) )
+  .map( str::trim )
+  .filter( | value | !value.is_empty() )
+  .map( | value | value.parse::< f64 >().with_context( || format!( "Invalid number {} in {}", value, name ) ) )
+  .collect()
+}
+
+/// Colors of the gradient sky, which warms up and dims as the sun sets.
+fn gradient( elevation : f64, turbidity : f64 ) -> Vec< f64 >
+{
+  let smoothstep = | low : f64, high : f64, x : f64 | { let t = ( ( x - low ) / ( high - low ) ).clamp( 0.0, 1.0 ); t * t * ( 3.0 - 2.0 * t ) };
+  let mix = | a : [ f64; 3 ], b : [ f64; 3 ], t : f64 | [ 0, 1, 2 ].map( | i | a[ i ] + ( b[ i ] - a[ i ] ) * t );
+  let scale = | a : [ f64; 3 ], s : f64 | a.map( | v | v * s );
+
+  let sun_height = elevation.sin();
+  let day = smoothstep( -0.1, 0.1, sun_height ).max( 0.02 );
+  let warmth = 1.0 - smoothstep( 0.0, 0.35, sun_height );
+  let haze = ( ( turbidity - 1.0 ) / 9.0 ).clamp( 0.0, 1.0 );
+
+  let horizon = mix( mix( [ 0.65, 0.8, 0.95 ], [ 1.0, 0.5, 0.25 ], warmth ), [ 0.9, 0.9, 0.9 ], haze * 0.5 );
+  let zenith = mix( [ 0.12, 0.3, 0.7 ], horizon, haze * 0.5 );
+  let sun_color = mix( [ 1.0, 0.95, 0.9 ], [ 1.0, 0.45, 0.15 ], warmth );
+
+  // The disk is twice as large as the sun, so it still covers a few texels of small cubes.
+  // Its irradiance is about five times the one of the sky, as on a clear day
+  let sun_radius = 0.5f64.to_radians();
+  let sun_solid_angle = 2.0 * PI * ( 1.0 - sun_radius.cos() );
+  let sun_irradiance = 8.0 * ( 1.0 - 0.5 * haze ) * day;
+
+  [
+    scale( zenith, day ).as_slice(),
+    &scale( horizon, day ),
+    &scale( sun_color, sun_irradiance / sun_solid_angle ),
+    &[ sun_radius, 8.0 - 6.0 * haze ],
+    &scale( sun_color, 0.5 * day )
+  ]
+  .concat()
+}
+
+/// Header laid out like `ArHosekSkyModelData_RGB.h`, written once to the temporary directory for the tests.
+/// Its coefficients aren't the ones of the model but keep the sky smooth and positive, blue at the zenith
+/// and brighter around the sun.
+#[ cfg( test ) ]
+pub fn test_hosek_data() -> PathBuf
+{
+  static PATH : std::sync::OnceLock< PathBuf > = std::sync::OnceLock::new();
+  PATH.get_or_init( ||
+  {
+    let mut source = String::from( "// Synthetic data for the tests\n" );
+    for channel in 0..3
+    {
+      let mut configurations = format!( "double datasetRGB{}[] =\n{{\n", channel + 1 );
+      let mut radiances = format!( "double datasetRGBRad{}[] =\n{{\n", channel + 1 );
+      for albedo in 0..2
+      {
+        for turbidity in 0..10
+        {
+          configurations.push_str( &format!( "\t// albedo {}, turbidity {}\n", albedo, turbidity + 1 ) );
+          radiances.push_str( &format!( "\t// albedo {}, turbidity {}\n", albedo, turbidity + 1 ) );
+          let t = turbidity as f64;
+          for point in 0..6
+          {
+            let k = point as f64;
+            for value in [ -1.0 - 0.02 * t, -0.3, 0.4 + 0.05 * k, 1.0 + 0.1 * t, -2.5, 0.1, 0.3 + 0.05 * t, 0.5, 0.65 ]
+            {
+              configurations.push_str( &format!( "\t{:.6e},\n", value ) );
+            }
+            let radiance = [ 1.0, 1.4, 2.2 ][ channel ] * ( 0.5 + 0.1 * k ) * ( 1.0 + 0.2 * albedo as f64 );
+            radiances.push_str( &format!( "\t{:.6e},\n", radiance ) );
+          }
+        }
+      }
+      source.push_str( &configurations );
+      source.push_str( "};\n\n" );
+      source.push_str( &radiances );
+      source.push_str( "};\n\n" );
+    }
+
+    let path = std::env::temp_dir().join( format!( "IBLConverter-hosek-{}.h", std::process::id() ) );
+    std::fs::write( &path, source ).unwrap();
+    path
+  })
+  .clone()
+}
+
+#[ cfg( test ) ]
+mod tests
+{
+  use super::*;
+
+  #[ test ]
+  fn parses_c_arrays()
+  {
+    let source = "/* header */\ndouble datasetA[] =\n{\n\t// first\n\t1.5e+000, -2.000000e-001, /* skipped, 9 */\n\t3,\n};\ndouble datasetB[] = { 4 };";
+    assert_eq!( parse_c_array( source, "datasetA" ).unwrap(), vec![ 1.5, -0.2, 3.0 ] );
+    assert_eq!( parse_c_array( source, "datasetB" ).unwrap(), vec![ 4.0 ] );
+    assert!( parse_c_array( source, "datasetC" ).is_err() );
+    assert!( HosekData::parse( source ).is_err() );
+  }
+
+  #[ test ]
+  fn cooks_between_elevations_turbidities_and_albedos()
+  {
+    // Control points spaced evenly along a Bézier curve give back its parameter, the cube root of the elevation
+    // over 90°, and the 100 x albedo + 10 x turbidity index offsets are interpolated linearly
+    let data = ( 0..2 ).flat_map( | albedo | ( 0..10 ).flat_map( move | turbidity | ( 0..6 ).map( move | point |
+      100.0 * albedo as f64 + 10.0 * turbidity as f64 + point as f64 / 5.0 ) ) )
+    .collect::< Vec< _ > >();
+    for ( elevation, turbidity, albedo ) in [ ( FRAC_PI_2 / 8.0, 3.25, 0.5 ), ( FRAC_PI_2, 10.0, 0.0 ), ( 0.0, 1.0, 1.0 ), ( FRAC_PI_2 / 27.0, 7.5, 0.3 ) ]
+    {
+      let expected = 100.0 * albedo + 10.0 * ( turbidity - 1.0 ) + ( elevation / FRAC_PI_2 ).cbrt();
+      let [ actual ] = hosek_cook( &data, 1, elevation, turbidity, albedo )[ .. ] else { unreachable!() };
+      assert!( ( actual - expected ).abs() < 1e-9, "{} instead of {} at {}, {}, {}", actual, expected, elevation, turbidity, albedo );
+    }
+  }
+
+  #[ test ]
+  fn hosek_wilkie_radiance_follows_the_reference()
+  {
+    let sky = SkySettings { model : SkyModel::HosekWilkie, sun_elevation : 30.0, hosek_data : Some( test_hosek_data() ), ..SkySettings::default() };
+    let uniform = sky.uniform().unwrap();
+    let sun = Vec3::from_array( uniform.sun_direction );
+    for dir in [ Vec3::Y, sun, Vec3::new( 1.0, 0.2, -0.5 ).normalize(), Vec3::new( -1.0, 0.01, 0.0 ).normalize() ]
+    {
+      // `ArHosekSkyModel_GetRadianceInternal` times the radiance of the channel, in double precision
+      let ( cos_theta, cos_gamma ) = ( dir.y as f64, dir.dot( sun ).clamp( -1.0, 1.0 ) as f64 );
+      let gamma = cos_gamma.acos();
+      let expected = Vec3::from_array( [ 0, 1, 2 ].map( | channel |
+      {
+        let c = uniform.coefficients[ channel * 9..][ ..9 ].iter().map( | &c | c as f64 ).collect::< Vec< _ > >();
+        let mie = ( 1.0 + cos_gamma * cos_gamma ) / ( 1.0 + c[ 8 ] * c[ 8 ] - 2.0 * c[ 8 ] * cos_gamma ).powf( 1.5 );
+        let value = ( 1.0 + c[ 0 ] * ( c[ 1 ] / ( cos_theta + 0.01 ) ).exp() )
+          * ( c[ 2 ] + c[ 3 ] * ( c[ 4 ] * gamma ).exp() + c[ 5 ] * cos_gamma * cos_gamma + c[ 6 ] * mie + c[ 7 ] * cos_theta.sqrt() );
+        ( value * uniform.coefficients[ 27 + channel ] as f64 ) as f32
+      }));
+      let actual = uniform.radiance( dir, 0.01 );
+      assert!( ( actual - expected ).abs().max_element() < 1e-3 * expected.max_element(), "{} instead of {} in {}", actual, expected, dir );
+    }
+
+    // Blue at the zenith, brighter around the sun
+    let zenith = uniform.radiance( Vec3::Y, 0.01 );
+    assert!( zenith.z > zenith.x && uniform.radiance( sun, 0.01 ).z > zenith.z, "{}", zenith );
+  }
+}
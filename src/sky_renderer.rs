@@ -0,0 +1,154 @@
+use std::rc::Rc;
+
+use crate::{profiler::GpuProfiler, sky::SkyUniform, texture::Texture};
+
+pub const SHADER_SOURCE : &str = include_str!( "shaders/sky.wgsl" );
+
+/// Renders an analytic sky to the first mip of the environment cube, in place of [`crate::cube_map_renderer::CubeMapRenderer`].
+pub struct SkyRenderer
+{
+  cube_texture : Rc< Texture >,
+  buffer : wgpu::Buffer,
+  bind_group : wgpu::BindGroup,
+  pipeline : wgpu::ComputePipeline
+}
+
+impl SkyRenderer
+{
+  pub fn new( cube_texture : Rc< Texture >, device : &wgpu::Device ) -> Self
+  {
+    let buffer = device.create_buffer
+    (
+      &wgpu::BufferDescriptor
+      {
+        label : None,
+        size : std::mem::size_of::< SkyUniform >() as u64,
+        usage : wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::UNIFORM,
+        mapped_at_creation : false
+      }
+    );
+
+    let bind_group_layout = device.create_bind_group_layout
+    (
+      &wgpu::BindGroupLayoutDescriptor
+      {
+        label: None,
+        entries: &
+        [
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 0,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture
+            {
+              access: wgpu::StorageTextureAccess::WriteOnly,
+              format: cube_texture.format(),
+              view_dimension: wgpu::TextureViewDimension::D2Array
+            },
+            count: None
+          },
+          wgpu::BindGroupLayoutEntry
+          {
+            binding: 1,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer
+            {
+              ty: wgpu::BufferBindingType::Uniform,
+              has_dynamic_offset: false,
+              min_binding_size: None
+            },
+            count: None
+          }
+        ]
+      }
+    );
+
+    let cube_view = cube_texture.create_mip_array_view( 0 );
+    let bind_group = device.create_bind_group
+    (
+      &wgpu::BindGroupDescriptor
+      {
+        label : None,
+        layout : &bind_group_layout,
+        entries : &
+        [
+          wgpu::BindGroupEntry
+          {
+            binding : 0,
+            resource : wgpu::BindingResource::TextureView( &cube_view )
+          },
+          wgpu::BindGroupEntry
+          {
+            binding : 1,
+            resource : buffer.as_entire_binding()
+          }
+        ]
+      }
+    );
+
+    let shader = device.create_shader_module
+    (
+      wgpu::ShaderModuleDescriptor
+      {
+        label: None,
+        source: wgpu::ShaderSource::Wgsl( SHADER_SOURCE.into() )
+      }
+    );
+
+    let pipeline_layout = device.create_pipeline_layout
+    (
+      &wgpu::PipelineLayoutDescriptor
+      {
+        label : None,
+        bind_group_layouts : &
+        [
+          &bind_group_layout
+        ],
+        push_constant_ranges : &[]
+      }
+    );
+
+    let pipeline = device.create_compute_pipeline
+    (
+      &wgpu::ComputePipelineDescriptor
+      {
+        label : None,
+        layout : Some( &pipeline_layout ),
+        module : &shader,
+        entry_point : None,
+        compilation_options : wgpu::PipelineCompilationOptions::default(),
+        cache : None
+      }
+    );
+
+    Self
+    {
+      cube_texture,
+      buffer,
+      bind_group,
+      pipeline
+    }
+  }
+
+  /// Sky of the following renders.
+  pub fn set_sky( &self, queue : &wgpu::Queue, sky : &SkyUniform )
+  {
+    queue.write_buffer( &self.buffer, 0, bytemuck::bytes_of( sky ) );
+  }
+
+  pub fn render( &self, encoder : &mut wgpu::CommandEncoder, profiler : &GpuProfiler )
+  {
+    let num_groups = self.cube_texture.size().width.div_ceil( 16 );
+    let mut compute_pass = encoder.begin_compute_pass
+    (
+      &wgpu::ComputePassDescriptor
+      {
+        label : None,
+        timestamp_writes : profiler.compute_pass( "sky" )
+      }
+    );
+    compute_pass.set_pipeline( &self.pipeline );
+    compute_pass.set_bind_group( 0, &self.bind_group, &[] );
+    compute_pass.dispatch_workgroups( num_groups, num_groups, 6 );
+  }
+}
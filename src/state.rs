@@ -1,51 +1,35 @@
-use std::{rc::Rc, sync::Arc};
+use std::sync::Arc;
 
-use image::{GenericImageView, ImageReader};
-use winit::{event::WindowEvent, window::Window};
+use winit::{event::{ElementState, KeyEvent, WindowEvent}, keyboard::{KeyCode, PhysicalKey}, window::Window};
 
-use crate::{camera::Uniform, cube_map_renderer::CubeMapRenderer, cube_mipmap_renderer::CubeMipmapRenderer, cube_texture::CubeTexture, ibl_renderer::IBLRenderer, texture_2d::Texture2D};
+use crate::{baker::{BakeSettings, Baker, SourceImage}, batch::Input, camera::Uniform, gpu, progressive::Refinement};
 
 pub struct State {
   pub device: wgpu::Device,
   pub queue: wgpu::Queue,
-  pub window: Arc<Window>,
   pub surface: wgpu::Surface< 'static >,
   pipeline : wgpu::RenderPipeline,
-  hdr_texture : Rc< Texture2D >,
-  cube_texture : Rc< CubeTexture >,
-  cm_renderer : CubeMapRenderer,
   uniform : Uniform,
-  pub bind_group : wgpu::BindGroup,
-  pub bind_group_layout : wgpu::BindGroupLayout,
-  surface_format : wgpu::TextureFormat,
-  cube_mipmap_renderer : CubeMipmapRenderer,
-  ibl_renderer : IBLRenderer
+  /// The environment cube, followed by every mip of the specular cube when there is one
+  bind_groups : Vec< wgpu::BindGroup >,
+  /// Index in `bind_groups` of the cube on screen, switched with the up and down arrows
+  shown : usize,
+  window : Arc< Window >,
+  baker : Baker,
+  /// Refined a batch per frame until done, when `BakeSettings::progressive` is set
+  refinement : Option< Refinement >
 }
 
 impl State {
-  pub async fn new( window: Arc<Window> ) -> Self
+  pub async fn new( window: Arc<Window>, input : &Input, settings : &BakeSettings ) -> anyhow::Result< Self >
   {
-    let instance = wgpu::Instance::new
-    (
-      &wgpu::InstanceDescriptor 
-      {
-        backends: wgpu::Backends::all(),
-        ..Default::default()
-      }
-    );
+    let instance = gpu::create_instance();
 
     let window_size = window.inner_size();
-    let surface = instance.create_surface( window.clone() ).unwrap();
+    let surface = instance.create_surface( window.clone() )?;
 
-    let adapter = instance.request_adapter
-    (
-      &wgpu::RequestAdapterOptions 
-      {
-        power_preference: wgpu::PowerPreference::default(),
-        compatible_surface: Some( &surface ),
-        force_fallback_adapter: false,
-      },
-    ).await.unwrap();
+    let ( adapter, device, queue ) = gpu::create_device( &instance, Some( &surface ) ).await?;
+    gpu::check_output_formats( &device, settings )?;
 
     // Surface configuration
     let surface_caps = surface.get_capabilities( &adapter );
@@ -65,46 +49,18 @@ impl State {
       view_formats: vec![],
     };
 
-    let ( device, queue ) = adapter.request_device
-    ( 
-      &wgpu::DeviceDescriptor
-      {
-        required_features : wgpu::Features::FLOAT32_FILTERABLE,
-        ..Default::default()
-      } 
-    ).await.unwrap();
     surface.configure( &device, &config );
 
-    //let image = ImageReader::open( "assets/rogland_clear_night_4k.hdr" ).unwrap().decode().unwrap();
-    //let image = ImageReader::open( "D:/VS_projects/Rust/IBLConverter/assets/rogland_clear_night_4k.hdr" ).unwrap().decode().unwrap();
-    //let image = ImageReader::open( "D:/VS_projects/Rust/IBLConverter/assets/autumn_field_puresky_4k.hdr" ).unwrap().decode().unwrap();
-    //let image = ImageReader::open( "D:/VS_projects/Rust/IBLConverter/assets/passendorf_snow_4k.hdr" ).unwrap().decode().unwrap();
-    //let image = ImageReader::open( "D:/VS_projects/Rust/IBLConverter/assets/metro_noord_4k.hdr" ).unwrap().decode().unwrap();
-    //let image = ImageReader::open( "D:/VS_projects/Rust/IBLConverter/assets/kloppenheim_06_puresky_4k.hdr" ).unwrap().decode().unwrap();
-    let image = ImageReader::open( "D:/VS_projects/Rust/IBLConverter/assets/the_sky_is_on_fire_4k.hdr" ).unwrap().decode().unwrap();
-    let image = image.to_rgba32f();
-    let ( img_width, img_height ) = image.dimensions();
-    let pixels = image.into_vec();
+    let mut baker = Baker::new( &device, &queue, settings, false );
+    match input
+    {
+      Input::File( path ) => baker.set_source( &device, &SourceImage::load( path )?.to_texture( &device, &queue ) ),
+      Input::Sky( sky ) => baker.set_sky( &queue, &sky.uniform()? )
+    }
+    let refinement = baker.bake( &device, &queue );
 
-    let hdr_texture = Rc::new( Texture2D::new( &device, wgpu::TextureFormat::Rgba32Float, img_width, img_height, false ) );
-    hdr_texture.write_pixels( &queue, &pixels );
-    let cube_texture = Rc::new( CubeTexture::new( &device, 1024, 1024 ) );
     let uniform = Uniform::new( &device, window_size.width as f32, window_size.height as f32 );
 
-    let cm_renderer = CubeMapRenderer::new( cube_texture.clone(), hdr_texture.clone(), &device );
-    let cube_mipmap_renderer = CubeMipmapRenderer::new( &device, cube_texture.clone() );
-    let ibl_renderer = IBLRenderer::new
-    ( 
-      &device, cube_texture.clone(), 
-      wgpu::TextureFormat::Rgba32Float, 
-      512, 
-      512, 
-      512,
-      512,
-      512, 
-      512
-    );
-
     let bind_group_layout = device.create_bind_group_layout
     (
       &wgpu::BindGroupLayoutDescriptor 
@@ -138,7 +94,7 @@ impl State {
       }
     );
 
-    let bind_group = device.create_bind_group
+    let create_bind_group = | view : &wgpu::TextureView, sampler : &wgpu::Sampler | device.create_bind_group
     (
       &wgpu::BindGroupDescriptor
       {
@@ -149,16 +105,26 @@ impl State {
           wgpu::BindGroupEntry
           {
             binding : 0,
-            resource : wgpu::BindingResource::TextureView( cube_texture.view_cube() )
+            resource : wgpu::BindingResource::TextureView( view )
           },
           wgpu::BindGroupEntry
           {
             binding : 1,
-            resource : wgpu::BindingResource::Sampler( &cube_texture.sampler() )
+            resource : wgpu::BindingResource::Sampler( sampler )
           },
         ]
       }
     );
+    let cube_texture = baker.cube_texture();
+    let mut bind_groups = vec![ create_bind_group( cube_texture.view(), cube_texture.sampler() ) ];
+    if let Some( specular_cube ) = baker.specular_cube_texture()
+    {
+      bind_groups.extend
+      (
+        ( 0..specular_cube.texture().mip_level_count() )
+        .map( | mip_level | create_bind_group( &specular_cube.create_mip_cube_view( mip_level ), specular_cube.sampler() ) )
+      );
+    }
 
     let main_shader = device.create_shader_module
     (
@@ -223,64 +189,56 @@ impl State {
     );
 
 
-    Self
-    {
-      device,
-      queue,
-      window,
-      surface,
-      pipeline,
-      hdr_texture,
-      cm_renderer,
-      cube_texture,
-      uniform,
-      bind_group,
-      bind_group_layout,
-      surface_format,
-      cube_mipmap_renderer,
-      ibl_renderer
-    }
+    Ok
+    (
+      Self
+      {
+        device,
+        queue,
+        surface,
+        pipeline,
+        uniform,
+        bind_groups,
+        shown : 0,
+        window,
+        baker,
+        refinement
+      }
+    )
   }
 
-  pub fn input( &mut self, _event: &WindowEvent ) -> bool 
+  pub fn input( &mut self, event: &WindowEvent ) -> bool 
   {
-    false
+    let WindowEvent::KeyboardInput { event : KeyEvent { state : ElementState::Pressed, physical_key : PhysicalKey::Code( key ), .. }, .. } = event
+    else { return false };
+
+    match key
+    {
+      KeyCode::ArrowUp => self.shown = ( self.shown + 1 ).min( self.bind_groups.len() - 1 ),
+      KeyCode::ArrowDown => self.shown = self.shown.saturating_sub( 1 ),
+      _ => return false
+    }
+    true
   }
 
   pub fn update( &mut self ) 
   {
     self.uniform.update( &self.queue );
-  }
-
-  pub fn render_hdr_to_cube( &mut self )
-  {
-    let mut encoder = self.device.create_command_encoder( &wgpu::CommandEncoderDescriptor::default() );
-
-    self.cm_renderer.render( &mut encoder );
-    self.cube_mipmap_renderer.generate_mipmaps( &self.device, &mut encoder );
-    self.ibl_renderer.render_diffuse( &mut encoder );
-    self.ibl_renderer.render_specular_1( &mut encoder, &self.queue );
-    self.ibl_renderer.render_specular_2( &mut encoder );
-
-    self.queue.submit( std::iter::once( encoder.finish() ) );
-  }
-
-  pub async fn save_ibl_diffuse( &self )
-  {
-    self.ibl_renderer.save_diffuse( &self.device ).await;
-  }
 
-  pub async fn save_ibl_specular_1( &self )
-  {
-    self.ibl_renderer.save_specular_1( &self.device ).await;
-  }
-
-  pub async fn save_ibl_specular_2( &self )
-  {
-    self.ibl_renderer.save_specular_2( &self.device ).await;
+    if let Some( refinement ) = self.refinement.as_mut().filter( | refinement | !refinement.is_done() )
+    {
+      match pollster::block_on( self.baker.refine( &self.device, &self.queue, refinement ) )
+      {
+        Ok( () ) => self.window.set_title( &refinement.summary() ),
+        Err( e ) =>
+        {
+          self.window.set_title( &format!( "Refinement failed: {:#}", e ) );
+          self.refinement = None;
+        }
+      }
+    }
   }
 
-
   pub fn render( &mut self ) -> Result< (), wgpu::SurfaceError > 
   {
     let output = self.surface.get_current_texture()?;
@@ -316,7 +274,7 @@ impl State {
       render_pass.set_pipeline( &self.pipeline );
       
       render_pass.set_bind_group( 0, &self.uniform.bind_group, &[] );
-      render_pass.set_bind_group( 1, &self.bind_group, &[] );
+      render_pass.set_bind_group( 1, &self.bind_groups[ self.shown ], &[] );
 
       render_pass.draw( 0..3, 0..1 );
     }
@@ -0,0 +1,279 @@
+use crate::readback::ReadbackBuffer;
+
+/// Shape of a [`Texture`], which sets the dimension of its default view.
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub enum TextureKind
+{
+  D2,
+  /// Six square layers in the WebGPU face order
+  Cube,
+  Array( u32 )
+}
+
+impl TextureKind
+{
+  pub fn layers( self ) -> u32
+  {
+    match self
+    {
+      Self::D2 => 1,
+      Self::Cube => 6,
+      Self::Array( layers ) => layers
+    }
+  }
+
+  fn view_dimension( self ) -> wgpu::TextureViewDimension
+  {
+    match self
+    {
+      Self::D2 => wgpu::TextureViewDimension::D2,
+      Self::Cube => wgpu::TextureViewDimension::Cube,
+      Self::Array( _ ) => wgpu::TextureViewDimension::D2Array
+    }
+  }
+}
+
+/// Describes a [`Texture`]. By default it is a single mip 2D `Rgba32Float` texture that can be
+/// sampled, uploaded to and read back.
+pub struct TextureBuilder< 'a >
+{
+  label : Option< &'a str >,
+  width : u32,
+  height : u32,
+  kind : TextureKind,
+  format : wgpu::TextureFormat,
+  /// The full chain when `None`
+  mip_level_count : Option< u32 >,
+  usage : wgpu::TextureUsages,
+  storage_if_supported : bool
+}
+
+impl< 'a > TextureBuilder< 'a >
+{
+  pub fn label( self, label : &'a str ) -> Self
+  {
+    Self { label : Some( label ), ..self }
+  }
+
+  pub fn cube( self ) -> Self
+  {
+    Self { kind : TextureKind::Cube, ..self }
+  }
+
+  pub fn array( self, layers : u32 ) -> Self
+  {
+    Self { kind : TextureKind::Array( layers ), ..self }
+  }
+
+  pub fn format( self, format : wgpu::TextureFormat ) -> Self
+  {
+    Self { format, ..self }
+  }
+
+  /// At most `count` mips, fewer when the texture is too small for them.
+  pub fn mips( self, count : u32 ) -> Self
+  {
+    Self { mip_level_count : Some( count ), ..self }
+  }
+
+  /// Every mip down to 1x1.
+  pub fn full_mips( self ) -> Self
+  {
+    Self { mip_level_count : None, ..self }
+  }
+
+  /// Usages on top of the default sampling and copies.
+  pub fn usage( self, usage : wgpu::TextureUsages ) -> Self
+  {
+    Self { usage : self.usage | usage, ..self }
+  }
+
+  /// Adds `STORAGE_BINDING` when the format allows it, so compute passes can write to it.
+  pub fn storage_if_supported( self ) -> Self
+  {
+    Self { storage_if_supported : true, ..self }
+  }
+
+  pub fn build( self, device : &wgpu::Device ) -> Texture
+  {
+    let size = wgpu::Extent3d { width : self.width, height : self.height, depth_or_array_layers : self.kind.layers() };
+    let max_mips = wgpu::Extent3d { depth_or_array_layers : 1, ..size }.max_mips( wgpu::TextureDimension::D2 );
+    let storage = self.storage_if_supported
+    && self.format.guaranteed_format_features( device.features() ).allowed_usages.contains( wgpu::TextureUsages::STORAGE_BINDING );
+
+    let texture = device.create_texture
+    (
+      &wgpu::TextureDescriptor
+      {
+        label : self.label,
+        size,
+        mip_level_count : self.mip_level_count.map_or( max_mips, | count | count.clamp( 1, max_mips ) ),
+        sample_count : 1,
+        dimension : wgpu::TextureDimension::D2,
+        format : self.format,
+        usage : if storage { self.usage | wgpu::TextureUsages::STORAGE_BINDING } else { self.usage },
+        view_formats : &[]
+      }
+    );
+
+    let view = texture.create_view
+    (
+      &wgpu::TextureViewDescriptor
+      {
+        dimension : Some( self.kind.view_dimension() ),
+        ..Default::default()
+      }
+    );
+
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor
+    {
+      label : None,
+      address_mode_u: wgpu::AddressMode::ClampToEdge,
+      address_mode_v: wgpu::AddressMode::ClampToEdge,
+      address_mode_w: wgpu::AddressMode::ClampToEdge,
+      mag_filter: wgpu::FilterMode::Linear,
+      min_filter: wgpu::FilterMode::Linear,
+      mipmap_filter: wgpu::FilterMode::Linear,
+      ..Default::default()
+    });
+
+    Texture
+    {
+      texture,
+      view,
+      sampler,
+      format : self.format,
+      size
+    }
+  }
+}
+
+/// 2D, cube or array texture, with a linear sampler and a view of every mip and layer.
+pub struct Texture
+{
+  texture : wgpu::Texture,
+  view : wgpu::TextureView,
+  sampler : wgpu::Sampler,
+  format : wgpu::TextureFormat,
+  size : wgpu::Extent3d
+}
+
+impl Texture
+{
+  pub fn builder< 'a >( width : u32, height : u32 ) -> TextureBuilder< 'a >
+  {
+    TextureBuilder
+    {
+      label : None,
+      width,
+      height,
+      kind : TextureKind::D2,
+      format : wgpu::TextureFormat::Rgba32Float,
+      mip_level_count : Some( 1 ),
+      usage : wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST | wgpu::TextureUsages::COPY_SRC,
+      storage_if_supported : false
+    }
+  }
+
+  pub fn texture( &self ) -> &wgpu::Texture { &self.texture }
+
+  /// Every mip and layer, as a cube for cube textures.
+  pub fn view( &self ) -> &wgpu::TextureView { &self.view }
+
+  pub fn sampler( &self ) -> &wgpu::Sampler { &self.sampler }
+
+  pub fn format( &self ) -> wgpu::TextureFormat { self.format }
+
+  /// Size of mip 0, with the layers in `depth_or_array_layers`.
+  pub fn size( &self ) -> wgpu::Extent3d { self.size }
+
+  pub fn mip_count( &self ) -> u32 { self.texture.mip_level_count() }
+
+  pub fn mip_level_size( &self, mip_level : u32 ) -> wgpu::Extent3d
+  {
+    self.size.mip_level_size( mip_level, wgpu::TextureDimension::D2 )
+  }
+
+  /// The first layer of one mip.
+  pub fn create_mip_view( &self, mip_level : u32 ) -> wgpu::TextureView
+  {
+    self.create_layer_view( 0, mip_level )
+  }
+
+  /// One layer, or face, of one mip.
+  pub fn create_layer_view( &self, layer : u32, mip_level : u32 ) -> wgpu::TextureView
+  {
+    self.texture.create_view
+    (
+      &wgpu::TextureViewDescriptor
+      {
+        base_mip_level : mip_level,
+        mip_level_count : Some( 1 ),
+        base_array_layer : layer,
+        array_layer_count : Some( 1 ),
+        dimension : Some( wgpu::TextureViewDimension::D2 ),
+        ..Default::default()
+      }
+    )
+  }
+
+  /// Every layer of one mip, for storage writes.
+  pub fn create_mip_array_view( &self, mip_level : u32 ) -> wgpu::TextureView
+  {
+    self.texture.create_view
+    (
+      &wgpu::TextureViewDescriptor
+      {
+        base_mip_level : mip_level,
+        mip_level_count : Some( 1 ),
+        dimension : Some( wgpu::TextureViewDimension::D2Array ),
+        ..Default::default()
+      }
+    )
+  }
+
+  /// Cube view of a single mip, to look at it without the others.
+  pub fn create_mip_cube_view( &self, mip_level : u32 ) -> wgpu::TextureView
+  {
+    self.texture.create_view
+    (
+      &wgpu::TextureViewDescriptor
+      {
+        base_mip_level : mip_level,
+        mip_level_count : Some( 1 ),
+        dimension : Some( wgpu::TextureViewDimension::Cube ),
+        ..Default::default()
+      }
+    )
+  }
+
+  /// Uploads every layer of one mip from tightly packed texels, layer after layer.
+  pub fn write_mip( &self, queue : &wgpu::Queue, mip_level : u32, data : &[ u8 ] )
+  {
+    let size = self.mip_level_size( mip_level );
+    queue.write_texture
+    (
+      wgpu::TexelCopyTextureInfoBase
+      {
+        texture: &self.texture,
+        mip_level,
+        origin: wgpu::Origin3d::ZERO,
+        aspect: wgpu::TextureAspect::All
+      },
+      data,
+      wgpu::TexelCopyBufferLayout
+      {
+        offset: 0,
+        bytes_per_row: Some( size.width * self.format.block_copy_size( None ).unwrap() ),
+        rows_per_image: Some( size.height )
+      },
+      size
+    );
+  }
+
+  /// Buffer one layer of `mip_level` can be copied to, see [`ReadbackBuffer::copy_from`].
+  pub fn readback_buffer( &self, device : &wgpu::Device, mip_level : u32 ) -> ReadbackBuffer
+  {
+    ReadbackBuffer::new( device, self.mip_level_size( mip_level ), self.format )
+  }
+}
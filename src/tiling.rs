@@ -0,0 +1,109 @@
+/// How the filtering passes are split so that no single draw or submission runs long enough
+/// to trip the GPU watchdog of the OS. It doesn't change the outputs.
+#[ derive( Clone, Copy, Debug, Default, PartialEq, Eq ) ]
+pub struct Tiling
+{
+  /// Edge of the square tiles the outputs are rendered in, whole outputs when `None`
+  pub tile_size : Option< u32 >,
+  /// Environment samples taken by the passes of one submission, unlimited when `None`
+  pub samples_per_submit : Option< u64 >
+}
+
+/// Region of an output rendered by one pass, at `column` and `row` of the grid of tiles.
+#[ derive( Clone, Copy, Debug, PartialEq, Eq ) ]
+pub struct Tile
+{
+  pub column : u32,
+  pub row : u32,
+  pub x : u32,
+  pub y : u32,
+  pub width : u32,
+  pub height : u32
+}
+
+impl Tile
+{
+  pub fn is_first( &self ) -> bool
+  {
+    self.column == 0 && self.row == 0
+  }
+
+  pub fn texels( &self ) -> u64
+  {
+    self.width as u64 * self.height as u64
+  }
+}
+
+impl Tiling
+{
+  /// Tiles covering a `width` x `height` output row by row, a single one when tiling is off.
+  pub fn tiles( &self, width : u32, height : u32 ) -> Vec< Tile >
+  {
+    let tile_size = self.tile_size.unwrap_or( width.max( height ) ).max( 1 );
+    let mut tiles = Vec::new();
+    for row in 0..height.div_ceil( tile_size )
+    {
+      for column in 0..width.div_ceil( tile_size )
+      {
+        let ( x, y ) = ( column * tile_size, row * tile_size );
+        tiles.push( Tile { column, row, x, y, width : tile_size.min( width - x ), height : tile_size.min( height - y ) } );
+      }
+    }
+    tiles
+  }
+
+  /// Tiles along each axis of the largest output of `max_size` texels.
+  pub fn columns( &self, max_size : u32 ) -> u32
+  {
+    self.tile_size.map_or( 1, | tile_size | max_size.div_ceil( tile_size ) )
+  }
+}
+
+/// Records the passes of a bake and submits them whenever `Tiling::samples_per_submit`
+/// environment samples were recorded, so long bakes are spread over many submissions.
+pub struct Submitter< 'a >
+{
+  device : &'a wgpu::Device,
+  queue : &'a wgpu::Queue,
+  encoder : wgpu::CommandEncoder,
+  budget : Option< u64 >,
+  samples : u64
+}
+
+impl< 'a > Submitter< 'a >
+{
+  pub fn new( device : &'a wgpu::Device, queue : &'a wgpu::Queue, tiling : &Tiling ) -> Self
+  {
+    Self
+    {
+      device,
+      queue,
+      encoder : device.create_command_encoder( &wgpu::CommandEncoderDescriptor::default() ),
+      budget : tiling.samples_per_submit,
+      samples : 0
+    }
+  }
+
+  pub fn encoder( &mut self ) -> &mut wgpu::CommandEncoder
+  {
+    &mut self.encoder
+  }
+
+  /// Accounts for the samples of the passes recorded last and submits once the budget is spent.
+  pub fn add_samples( &mut self, samples : u64 )
+  {
+    self.samples += samples;
+    if self.budget.is_some_and( | budget | self.samples >= budget )
+    {
+      let encoder = std::mem::replace( &mut self.encoder, self.device.create_command_encoder( &wgpu::CommandEncoderDescriptor::default() ) );
+      self.queue.submit( std::iter::once( encoder.finish() ) );
+      self.samples = 0;
+    }
+  }
+
+  /// Submits everything recorded since the last submission.
+  pub fn finish( self )
+  {
+    self.queue.submit( std::iter::once( self.encoder.finish() ) );
+  }
+}